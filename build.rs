@@ -0,0 +1,54 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::compile_protos("proto/premium.proto")?;
+    }
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha());
+    println!("cargo:rustc-env=BUILD_EPOCH={}", build_epoch()?);
+    Ok(())
+}
+
+/// Seconds since the epoch the build started at, or `SOURCE_DATE_EPOCH` for
+/// reproducible builds. Only changes when this script runs again, i.e. when
+/// the sources or the commit do.
+fn build_epoch() -> Result<u64, Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    for path in ["src", "premium-core/src", "Cargo.toml"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => Ok(epoch.trim().parse()?),
+        Err(_) => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+/// The commit being built: `GIT_SHA` when the build sets it, e.g. from a CI
+/// variable, otherwise asked of git, `unknown` outside a checkout.
+fn git_sha() -> String {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    for path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    std::env::var("GIT_SHA")
+        .ok()
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            String::from_utf8(output.stdout).ok()
+        })
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,98 @@
+//! Benchmarks of the steps every quote goes through. Run with
+//! `cargo bench -p premium-core` and compare against a saved baseline with
+//! `-- --save-baseline main` / `-- --baseline main`.
+
+use async_std::task;
+use chrono::NaiveDate;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use premium_core::config::{StorageBackend, StorageConfig};
+use premium_core::frequency::{self, PaymentFrequency};
+use premium_core::matrix::{self, MatrixRow};
+use premium_core::money::{Currency, Money};
+use premium_core::premium::{calculate_age_on, calculate_premium, HealthRequest};
+use premium_core::{bands, store};
+
+const CODES: [&str; 4] = ["1A", "2A", "3A", "4A"];
+const SUMS_INSURED: [&str; 5] = ["100000", "200000", "300000", "500000", "1000000"];
+
+fn age(c: &mut Criterion) {
+    let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+    c.bench_function("calculate_age", |b| {
+        b.iter(|| calculate_age_on(black_box("1977-09-14"), today))
+    });
+}
+
+fn band(c: &mut Criterion) {
+    c.bench_function("band_resolution", |b| {
+        b.iter(|| {
+            let age = black_box(47);
+            (
+                bands::score(black_box("1A"), age),
+                matrix::band(black_box("500000"), None),
+            )
+        })
+    });
+}
+
+fn breakdown(c: &mut Criterion) {
+    let premium = Money::parse("18450.50", Currency::new("INR")).unwrap();
+    c.bench_function("breakdown", |b| {
+        b.iter(|| frequency::breakdown(black_box(PaymentFrequency::Monthly), &premium))
+    });
+}
+
+/// A matrix the size of the bundled workbook's, in the memory store.
+fn load_matrix() {
+    store::configure(&StorageConfig {
+        backend: StorageBackend::Memory,
+        ..StorageConfig::default()
+    })
+    .unwrap();
+    let mut rows = Vec::new();
+    for code in CODES {
+        for sum_insured in SUMS_INSURED {
+            for score in bands::scores(code) {
+                rows.push(MatrixRow {
+                    key: format!("{}:{}", code, sum_insured),
+                    code: code.to_string(),
+                    sum_insured: sum_insured.to_string(),
+                    deductible: None,
+                    gender: None,
+                    age_band: bands::label(code, score).unwrap_or_default(),
+                    premium: 1000 * score,
+                    score,
+                });
+            }
+        }
+    }
+    task::block_on(store::write_version(&rows)).unwrap();
+}
+
+fn lookup(c: &mut Criterion) {
+    load_matrix();
+    c.bench_function("memory_store_lookup", |b| {
+        b.iter(|| {
+            task::block_on(store::premium(
+                None,
+                black_box("3A"),
+                "500000",
+                black_box(4),
+            ))
+        })
+    });
+    c.bench_function("quote", |b| {
+        b.iter_batched(
+            || HealthRequest {
+                code: "3A".to_string(),
+                sum_insured: "500000".to_string(),
+                date_of_birth: "1977-09-14".to_string(),
+                ..HealthRequest::default()
+            },
+            |request| task::block_on(calculate_premium(request)).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(pricing, age, band, breakdown, lookup);
+criterion_main!(pricing);
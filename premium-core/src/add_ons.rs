@@ -0,0 +1,257 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use log::{error, info};
+use serde::Serialize;
+
+use crate::config::{AddOnsConfig, MatrixConfig};
+use crate::matrix::{find_column, read_workbook, RowError, Sheet};
+use crate::money::{Currency, Money};
+use crate::premium::{HealthRequest, PremiumError};
+
+/// A cover bought on top of the base policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OptionalCover {
+    MaternityCover,
+    OpdCover,
+    RestoreBenefit,
+}
+
+impl OptionalCover {
+    pub const ALL: [OptionalCover; 3] = [
+        OptionalCover::MaternityCover,
+        OptionalCover::OpdCover,
+        OptionalCover::RestoreBenefit,
+    ];
+
+    /// The request field that asks for the cover.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptionalCover::MaternityCover => "maternityCover",
+            OptionalCover::OpdCover => "opdCover",
+            OptionalCover::RestoreBenefit => "restoreBenefit",
+        }
+    }
+
+    fn from_cell(cell: &str) -> Option<OptionalCover> {
+        OptionalCover::ALL
+            .into_iter()
+            .find(|cover| cover.name().eq_ignore_ascii_case(cell))
+    }
+}
+
+/// An optional cover priced into a quote, itemized in the response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddOnPremium {
+    pub cover: OptionalCover,
+    pub premium: Money,
+}
+
+/// Annual add-on premiums by product, cover and sum insured; an empty sum
+/// insured prices the cover for every band of the product.
+type Prices = HashMap<(String, OptionalCover, String), String>;
+
+static PRICES: RwLock<Option<Prices>> = RwLock::new(None);
+
+/// Reads the add-on prices from the configured worksheet, replacing any
+/// loaded before. Nothing is loaded when `addOns.path` is not set, and no
+/// optional cover is then offered.
+pub fn load(config: &AddOnsConfig, matrix: &MatrixConfig) -> anyhow::Result<usize, PremiumError> {
+    let Some(path) = &config.path else {
+        return Ok(0);
+    };
+    let workbook = MatrixConfig {
+        path: path.clone(),
+        sheets: vec![config.sheet.clone()],
+        ..matrix.clone()
+    };
+    let sheets = read_workbook(&workbook, None)?;
+    let prices = match sheets.first().map(parse_prices) {
+        Some(Ok(prices)) => prices,
+        Some(Err(errors)) => {
+            for err in &errors {
+                error!(
+                    "options sheet {} row {} {}",
+                    err.sheet, err.row, err.message
+                );
+            }
+            return Err(PremiumError::InvalidInput);
+        }
+        None => HashMap::new(),
+    };
+    info!("loaded {} add-on prices from {}", prices.len(), path);
+    let count = prices.len();
+    *PRICES.write().unwrap_or_else(|err| err.into_inner()) = Some(prices);
+    Ok(count)
+}
+
+/// Parses `code`, `option` (`maternityCover`, `opdCover` or
+/// `restoreBenefit`), the annual `premium` of the cover and an optional
+/// `sumInsured`. A row without a sum insured prices the cover for the bands
+/// of the product no other row names.
+pub fn parse_prices(sheet: &Sheet) -> Result<Prices, Vec<RowError>> {
+    let error = |row: usize, message: &str| RowError {
+        sheet: sheet.name.clone(),
+        row,
+        message: message.to_string(),
+    };
+    let Some((header, body)) = sheet.rows.split_first() else {
+        return Err(vec![error(1, "header row is missing")]);
+    };
+    let find = |name| find_column(header, name);
+    let columns = (find("code"), find("option"), find("premium"));
+    let (Some(code), Some(option), Some(premium)) = columns else {
+        return Err(vec![error(
+            1,
+            "missing required columns: code, option, premium",
+        )]);
+    };
+    let sum_insured = find("sumInsured");
+
+    let mut prices = HashMap::new();
+    let mut errors = Vec::new();
+    for (index, row) in body.iter().enumerate() {
+        let number = index + 2;
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .map_or("", |value| value.trim())
+        };
+        if row.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let Some(cover) = OptionalCover::from_cell(cell(Some(option))) else {
+            errors.push(error(
+                number,
+                "option must be maternityCover, opdCover or restoreBenefit",
+            ));
+            continue;
+        };
+        let amount = cell(Some(premium));
+        if !amount.parse::<f64>().is_ok_and(|amount| amount >= 0.0) {
+            errors.push(error(number, "premium is not an amount"));
+            continue;
+        }
+        let key = (
+            cell(Some(code)).to_string(),
+            cover,
+            cell(sum_insured).to_string(),
+        );
+        if key.0.is_empty() {
+            errors.push(error(number, "code is empty"));
+            continue;
+        }
+        let duplicate = format!("duplicate {} for {} {}", cover.name(), key.0, key.2);
+        match prices.entry(key) {
+            Entry::Occupied(_) => errors.push(error(number, &duplicate)),
+            Entry::Vacant(entry) => {
+                entry.insert(amount.to_string());
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(prices),
+        false => Err(errors),
+    }
+}
+
+/// The premium of each optional cover `input` asks for, in the product's
+/// currency. Fails with `CoverNotOffered` for a cover the worksheet does not
+/// price for the product and sum insured.
+pub fn price(input: &HealthRequest) -> anyhow::Result<Vec<AddOnPremium>, PremiumError> {
+    let covers = input.optional_covers();
+    if covers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let prices = PRICES.read().unwrap_or_else(|err| err.into_inner());
+    let empty = HashMap::new();
+    price_with(prices.as_ref().unwrap_or(&empty), input, &covers)
+}
+
+fn price_with(
+    prices: &Prices,
+    input: &HealthRequest,
+    covers: &[OptionalCover],
+) -> anyhow::Result<Vec<AddOnPremium>, PremiumError> {
+    let currency = Currency::for_product(&input.code);
+    covers
+        .iter()
+        .map(|cover| {
+            let key = |sum_insured: &str| (input.code.clone(), *cover, sum_insured.to_string());
+            let amount = prices
+                .get(&key(&input.sum_insured))
+                .or_else(|| prices.get(&key("")))
+                .ok_or_else(|| {
+                    error!(
+                        "{} is not priced for {} {}",
+                        cover.name(),
+                        input.code,
+                        input.sum_insured
+                    );
+                    PremiumError::CoverNotOffered(cover.name().to_string())
+                })?;
+            Ok(AddOnPremium {
+                cover: *cover,
+                premium: Money::parse(amount, currency.clone())?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_price() {
+        let rows = [
+            vec!["code", "option", "sumInsured", "premium"],
+            vec!["1A", "maternityCover", "", "1200"],
+            vec!["1A", "maternityCover", "500000", "1800"],
+            vec!["1A", "OPDCOVER", "", "450.50"],
+            vec!["1A", "dental", "", "100"],
+        ];
+        let sheet = Sheet {
+            name: "options".to_string(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .collect(),
+        };
+        let errors = parse_prices(&sheet).unwrap_err();
+        assert_eq!(errors[0].row, 5);
+        let sheet = Sheet {
+            rows: sheet.rows[..4].to_vec(),
+            ..sheet
+        };
+        let prices = parse_prices(&sheet).unwrap();
+
+        let input = |sum_insured: &str| HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: sum_insured.to_string(),
+            maternity_cover: true,
+            opd_cover: true,
+            ..HealthRequest::default()
+        };
+        let priced = |input: &HealthRequest| {
+            price_with(&prices, input, &input.optional_covers()).map(|add_ons| {
+                add_ons
+                    .iter()
+                    .map(|add_on| add_on.premium.to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(priced(&input("100000")).unwrap(), ["1200", "450.50"]);
+        assert_eq!(priced(&input("500000")).unwrap(), ["1800", "450.50"]);
+        let restore = HealthRequest {
+            restore_benefit: true,
+            ..input("100000")
+        };
+        assert!(matches!(
+            priced(&restore),
+            Err(PremiumError::CoverNotOffered(cover)) if cover == "restoreBenefit"
+        ));
+    }
+}
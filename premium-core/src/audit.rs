@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, warn};
+use redis::RedisResult;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::commission::Commission;
+use crate::config::{AuditConfig, AuditSink};
+use crate::connection::{conn_read, conn_write, redis_error};
+use crate::discounts::AppliedDiscount;
+use crate::money::Money;
+use crate::ped::ConditionLoading;
+use crate::premium::{HealthRequest, PremiumError};
+use crate::pricing_rules::RuleAdjustment;
+
+const STREAM_KEY: &str = "{premium}:audit";
+const BATCH: usize = 500;
+
+static CONFIG: OnceLock<AuditConfig> = OnceLock::new();
+/// Serializes appends so records from concurrent quotes never interleave.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Selects the audit sink; quotes are not audited when this is never called.
+pub fn configure(config: &AuditConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("audit sink already configured");
+    }
+}
+
+fn config() -> &'static AuditConfig {
+    CONFIG.get_or_init(AuditConfig::default)
+}
+
+/// Who asked for a quote, as far as the front end can tell.
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    pub id: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Everything needed to reproduce a quoted price.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord<'a> {
+    pub timestamp: String,
+    pub caller: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+    pub request: &'a HealthRequest,
+    pub matrix_version: u64,
+    pub age: i32,
+    pub score: i32,
+    /// Premium read from the matrix before any adjustment.
+    pub matrix_premium: &'a Money,
+    pub short_period_percent: Option<u32>,
+    pub discounts: &'a [AppliedDiscount],
+    pub loading_percent: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub condition_loadings: &'a [ConditionLoading],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referral_loading_percent: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub rule_adjustments: &'a [RuleAdjustment],
+    pub premium: &'a str,
+    pub currency: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commission: Option<&'a Commission>,
+}
+
+pub fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Appends the record to the configured sink. A quote whose record cannot be
+/// written fails rather than going out unaudited.
+pub async fn record(record: &AuditRecord<'_>) -> anyhow::Result<(), PremiumError> {
+    let config = config();
+    if config.sink == AuditSink::Off {
+        return Ok(());
+    }
+    let line = serde_json::to_string(record).map_err(|err| {
+        error!("cannot serialize audit record {}", err);
+        PremiumError::InternalServer
+    })?;
+    match config.sink {
+        AuditSink::Off => Ok(()),
+        AuditSink::File => append_file(&config.path, &line),
+        AuditSink::Redis => append_stream(config.stream_max_len, &line).await,
+    }
+}
+
+fn append_file(path: &str, line: &str) -> anyhow::Result<(), PremiumError> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|err| {
+            error!("cannot append audit record to {} {}", path, err);
+            PremiumError::InternalServer
+        })
+}
+
+async fn append_stream(max_len: u64, line: &str) -> anyhow::Result<(), PremiumError> {
+    let mut conn = conn_write().await?;
+    let result: RedisResult<String> = redis::cmd("XADD")
+        .arg(STREAM_KEY)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(max_len)
+        .arg("*")
+        .arg("record")
+        .arg(line)
+        .query(&mut conn);
+    result.map(|_| ()).map_err(|err| {
+        error!("Redis error while appending audit record {}", err);
+        redis_error(&err)
+    })
+}
+
+/// Query parameters of the audit endpoint; every filter is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AuditFilter {
+    pub code: Option<String>,
+    pub caller: Option<String>,
+    pub matrix_version: Option<u64>,
+    /// RFC 3339 bounds on the record timestamp, inclusive.
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+}
+
+struct Bounds {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(100).clamp(1, 1000)
+    }
+
+    fn bounds(&self) -> anyhow::Result<Bounds, PremiumError> {
+        let parse = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| {
+                    DateTime::parse_from_rfc3339(value)
+                        .map(|time| time.with_timezone(&Utc))
+                        .map_err(|_| PremiumError::InvalidInput)
+                })
+                .transpose()
+        };
+        Ok(Bounds {
+            from: parse(&self.from)?,
+            to: parse(&self.to)?,
+        })
+    }
+
+    fn matches(&self, bounds: &Bounds, record: &Value) -> bool {
+        let text = |value: &Value| value.as_str().map(str::to_string);
+        let timestamp = record["timestamp"]
+            .as_str()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|time| time.with_timezone(&Utc));
+        self.code
+            .as_ref()
+            .is_none_or(|code| text(&record["request"]["code"]).as_ref() == Some(code))
+            && self
+                .caller
+                .as_ref()
+                .is_none_or(|caller| text(&record["caller"]).as_ref() == Some(caller))
+            && self
+                .matrix_version
+                .is_none_or(|version| record["matrixVersion"].as_u64() == Some(version))
+            && bounds
+                .from
+                .is_none_or(|from| timestamp.is_some_and(|time| time >= from))
+            && bounds
+                .to
+                .is_none_or(|to| timestamp.is_some_and(|time| time <= to))
+    }
+}
+
+/// Matching records, newest first.
+pub async fn records(filter: &AuditFilter) -> anyhow::Result<Vec<Value>, PremiumError> {
+    let bounds = filter.bounds()?;
+    let config = config();
+    match config.sink {
+        AuditSink::Off => Ok(Vec::new()),
+        AuditSink::File => read_file(&config.path, filter, &bounds),
+        AuditSink::Redis => read_stream(filter, &bounds).await,
+    }
+}
+
+fn read_file(
+    path: &str,
+    filter: &AuditFilter,
+    bounds: &Bounds,
+) -> anyhow::Result<Vec<Value>, PremiumError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            error!("cannot read audit records from {} {}", path, err);
+            return Err(PremiumError::InternalServer);
+        }
+    };
+    let mut records: Vec<Value> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .filter(|record| filter.matches(bounds, record))
+        .collect();
+    records.reverse();
+    records.truncate(filter.limit());
+    Ok(records)
+}
+
+/// Walks the stream backwards in batches until enough records match.
+async fn read_stream(
+    filter: &AuditFilter,
+    bounds: &Bounds,
+) -> anyhow::Result<Vec<Value>, PremiumError> {
+    let mut conn = conn_read().await?;
+    let mut end = bounds
+        .to
+        .map_or("+".to_string(), |to| to.timestamp_millis().to_string());
+    let start = bounds
+        .from
+        .map_or("-".to_string(), |from| from.timestamp_millis().to_string());
+    let mut records = Vec::new();
+    loop {
+        let result: RedisResult<Vec<(String, HashMap<String, String>)>> = redis::cmd("XREVRANGE")
+            .arg(STREAM_KEY)
+            .arg(&end)
+            .arg(&start)
+            .arg("COUNT")
+            .arg(BATCH)
+            .query(&mut conn);
+        let entries = result.map_err(|err| {
+            error!("Redis error while reading audit records {}", err);
+            redis_error(&err)
+        })?;
+        let fetched = entries.len();
+        for (id, fields) in entries {
+            end = format!("({}", id);
+            let record = fields
+                .get("record")
+                .and_then(|value| serde_json::from_str::<Value>(value).ok());
+            if let Some(record) = record.filter(|record| filter.matches(bounds, record)) {
+                records.push(record);
+                if records.len() == filter.limit() {
+                    return Ok(records);
+                }
+            }
+        }
+        if fetched < BATCH {
+            return Ok(records);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read_file_filters() {
+        let path = std::env::temp_dir().join(format!("premium-audit-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let lines = [
+            json!({"timestamp": "2024-05-01T10:00:00.000Z", "caller": "web", "request": {"code": "1A"}, "matrixVersion": 1}),
+            json!({"timestamp": "2024-05-02T10:00:00.000Z", "caller": "agent", "request": {"code": "1A"}, "matrixVersion": 2}),
+            json!({"timestamp": "2024-05-03T10:00:00.000Z", "caller": "web", "request": {"code": "2B"}, "matrixVersion": 2}),
+        ];
+        for line in &lines {
+            append_file(path, &line.to_string()).unwrap();
+        }
+
+        let read = |filter: AuditFilter| {
+            let bounds = filter.bounds().unwrap();
+            read_file(path, &filter, &bounds).unwrap()
+        };
+        let all = read(AuditFilter::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0]["request"]["code"], "2B");
+
+        let web = read(AuditFilter {
+            caller: Some("web".to_string()),
+            code: Some("1A".to_string()),
+            ..AuditFilter::default()
+        });
+        assert_eq!(web, vec![lines[0].clone()]);
+
+        let window = read(AuditFilter {
+            from: Some("2024-05-02T00:00:00Z".to_string()),
+            matrix_version: Some(2),
+            limit: Some(1),
+            ..AuditFilter::default()
+        });
+        assert_eq!(window, vec![lines[2].clone()]);
+
+        let bad = AuditFilter {
+            to: Some("yesterday".to_string()),
+            ..AuditFilter::default()
+        };
+        assert!(bad.bounds().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::OnceLock;
+
+use log::warn;
+
+use crate::config::{AgeBand, AgeBandConfig};
+
+static BANDS: OnceLock<AgeBandConfig> = OnceLock::new();
+
+/// Sets the age bands; the original 18-35, 36-45, ... 71+ bands apply when
+/// this is never called.
+pub fn configure(config: &AgeBandConfig) {
+    if BANDS.set(config.clone()).is_err() {
+        warn!("age bands already configured");
+    }
+}
+
+fn bands() -> &'static AgeBandConfig {
+    BANDS.get_or_init(AgeBandConfig::default)
+}
+
+/// The matrix score for `age` under the product's bands, 0 when no band
+/// covers it.
+pub fn score(product_code: &str, age: i32) -> i32 {
+    let config = bands();
+    let bands = config.products.get(product_code).unwrap_or(&config.default);
+    band_score(bands, age)
+}
+
+/// Every score the product's bands can yield, in band order.
+pub fn scores(product_code: &str) -> Vec<i32> {
+    let config = bands();
+    let bands = config.products.get(product_code).unwrap_or(&config.default);
+    let mut scores: Vec<i32> = Vec::with_capacity(bands.len());
+    for band in bands {
+        if !scores.contains(&band.score) {
+            scores.push(band.score);
+        }
+    }
+    scores
+}
+
+/// The age range of the product's first band with `score`, written the way
+/// the matrix workbook does, e.g. `18-35` or `71+`.
+pub fn label(product_code: &str, score: i32) -> Option<String> {
+    let config = bands();
+    let bands = config.products.get(product_code).unwrap_or(&config.default);
+    bands
+        .iter()
+        .find(|band| band.score == score)
+        .map(|band| match band.max_age {
+            Some(max_age) => format!("{}-{}", band.min_age, max_age),
+            None => format!("{}+", band.min_age),
+        })
+}
+
+fn band_score(bands: &[AgeBand], age: i32) -> i32 {
+    bands
+        .iter()
+        .find(|band| age >= band.min_age && band.max_age.is_none_or(|max_age| age <= max_age))
+        .map_or(0, |band| band.score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::premium::calculate_age_on;
+    use chrono::{Datelike, Duration, NaiveDate};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_band_score() {
+        let bands = AgeBandConfig::default().default;
+        let scores: Vec<i32> = [17, 18, 35, 36, 45, 46, 60, 65, 70, 71, 99]
+            .iter()
+            .map(|age| band_score(&bands, *age))
+            .collect();
+        assert_eq!(scores, vec![0, 1, 1, 2, 2, 3, 4, 5, 6, 7, 7]);
+
+        let config: AgeBandConfig = serde_json::from_str(
+            r#"{"products": {"2B": [{"minAge": 0, "maxAge": 17, "score": 1}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(band_score(&config.products["2B"], 5), 1);
+        assert_eq!(config.default, bands);
+    }
+
+    /// Contiguous bands from `first` scored 1, 2, ... with the given widths;
+    /// the last one open-ended when `open`.
+    fn contiguous(first: i32, widths: &[i32], open: bool) -> Vec<AgeBand> {
+        let mut min_age = first;
+        let mut bands: Vec<AgeBand> = widths
+            .iter()
+            .zip(1..)
+            .map(|(width, score)| {
+                let band = AgeBand {
+                    min_age,
+                    max_age: Some(min_age + width - 1),
+                    score,
+                };
+                min_age += width;
+                band
+            })
+            .collect();
+        if open {
+            if let Some(last) = bands.last_mut() {
+                last.max_age = None;
+            }
+        }
+        bands
+    }
+
+    proptest! {
+        #[test]
+        fn prop_band_boundaries(
+            first in 0..30i32,
+            widths in prop::collection::vec(1..20i32, 1..8),
+            open: bool,
+            age in -1000..1000i32,
+        ) {
+            let bands = contiguous(first, &widths, open);
+            prop_assert_eq!(band_score(&bands, first - 1), 0);
+            for (index, band) in bands.iter().enumerate() {
+                prop_assert_eq!(band_score(&bands, band.min_age), band.score);
+                if let Some(max_age) = band.max_age {
+                    prop_assert_eq!(band_score(&bands, max_age), band.score);
+                    let next = bands.get(index + 1).map_or(0, |next| next.score);
+                    prop_assert_eq!(band_score(&bands, max_age + 1), next);
+                }
+            }
+            let score = band_score(&bands, age);
+            prop_assert!(score == 0 || bands.iter().any(|band| band.score == score));
+        }
+
+        #[test]
+        fn prop_score_changes_at_36(days in 36_525..73_049i64) {
+            let on = NaiveDate::from_ymd_opt(1900, 1, 1).unwrap() + Duration::days(days);
+            prop_assume!((on.month(), on.day()) != (2, 29));
+            let bands = AgeBandConfig::default().default;
+            let turned_36 = on.with_year(on.year() - 36).unwrap();
+            let score = |dob: NaiveDate| {
+                band_score(&bands, calculate_age_on(&dob.format("%Y-%m-%d").to_string(), on).unwrap())
+            };
+            prop_assert_eq!(score(turned_36), 2);
+            prop_assert_eq!(score(turned_36 + Duration::days(1)), 1);
+        }
+    }
+}
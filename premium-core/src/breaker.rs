@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::config::CircuitBreakerConfig;
+use crate::premium::PremiumError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probes: u32 },
+}
+
+/// Stops calls to a failing dependency for a while once enough consecutive
+/// calls have failed, then lets a few probes through to see whether it has
+/// recovered before closing again.
+pub struct CircuitBreaker {
+    /// The dependency, as logged.
+    name: &'static str,
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            name,
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_millis(self.config.open_ms)
+    }
+
+    /// Admits a call, or fails fast with the seconds to wait while open.
+    pub fn allow(&self) -> anyhow::Result<(), PremiumError> {
+        if self.config.failure_threshold == 0 {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(unavailable(until - now));
+                }
+                *state = State::HalfOpen { probes: 1 };
+                Ok(())
+            }
+            State::HalfOpen { probes } if probes < self.config.half_open_probes.max(1) => {
+                *state = State::HalfOpen { probes: probes + 1 };
+                Ok(())
+            }
+            State::HalfOpen { .. } => Err(unavailable(self.open_for())),
+        }
+    }
+
+    /// `closed`, `open` or `halfOpen`, as reported by diagnostics.
+    pub fn state(&self) -> &'static str {
+        match *self.state.lock().unwrap_or_else(|err| err.into_inner()) {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "halfOpen",
+        }
+    }
+
+    pub fn success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if matches!(*state, State::HalfOpen { .. }) {
+            warn!("{} circuit closed", self.name);
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn failure(&self) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            State::HalfOpen { .. } => self.config.failure_threshold,
+            State::Open { .. } => return,
+        };
+        *state = if failures >= self.config.failure_threshold {
+            warn!(
+                "{} circuit opened for {}ms after {} failures",
+                self.name, self.config.open_ms, failures
+            );
+            State::Open {
+                until: Instant::now() + self.open_for(),
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
+
+/// Retry-After is whole seconds, so any wait rounds up to at least one.
+fn unavailable(wait: Duration) -> PremiumError {
+    PremiumError::Unavailable(wait.as_millis().div_ceil(1000).max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_half_open_close() {
+        let breaker = CircuitBreaker::new(
+            "redis",
+            CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_ms: 50,
+                half_open_probes: 1,
+            },
+        );
+        breaker.failure();
+        assert!(breaker.allow().is_ok());
+        breaker.failure();
+        assert!(matches!(breaker.allow(), Err(PremiumError::Unavailable(1))));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_err());
+        breaker.failure();
+        assert!(breaker.allow().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow().is_ok());
+        breaker.success();
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_ok());
+    }
+}
@@ -0,0 +1,503 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{ready, Context, Poll};
+
+use async_std::channel::{self, Receiver};
+use async_std::stream::Stream;
+use async_std::task::JoinHandle;
+use chrono::Local;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::audit::Caller;
+use crate::config::BulkConfig;
+use crate::export;
+use crate::premium::{quote_for, ErrorResponse, HealthRequest, HealthResponse, PremiumError};
+use crate::schema;
+use crate::tenant;
+
+static CONFIG: OnceLock<BulkConfig> = OnceLock::new();
+
+/// Jobs in submission order, with the tenant that submitted each.
+static JOBS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Sets the row limit, concurrency and retention of bulk jobs; the defaults
+/// apply when this is never called.
+pub fn configure(config: &BulkConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("bulk quoting already configured");
+    }
+}
+
+fn config() -> &'static BulkConfig {
+    CONFIG.get_or_init(BulkConfig::default)
+}
+
+/// Columns read from an uploaded file, by their JSON field names. A
+/// `reference` column is copied to the result to match rows up.
+const COLUMNS: [&str; 7] = [
+    "code",
+    "sumInsured",
+    "dateOfBirth",
+    "deductible",
+    "zone",
+    "gender",
+    "paymentFrequency",
+];
+const REQUIRED: [&str; 3] = ["code", "sumInsured", "dateOfBirth"];
+const RESULT_HEADER: [&str; 10] = [
+    "row",
+    "reference",
+    "code",
+    "sumInsured",
+    "dateOfBirth",
+    "premium",
+    "currency",
+    "matrixVersion",
+    "errorCode",
+    "error",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkJob {
+    pub id: String,
+    pub state: JobState,
+    pub rows: usize,
+    pub processed: usize,
+    pub quoted: usize,
+    pub failed: usize,
+    pub submitted_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+}
+
+struct Entry {
+    tenant: Option<String>,
+    job: BulkJob,
+    result: Option<Vec<u8>>,
+}
+
+/// One row of a streamed result: its quote, or why it has none.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RowQuote {
+    pub row: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub reference: String,
+    #[serde(flatten)]
+    pub quote: Option<HealthResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+/// A data row of the upload: its line number, reference and fields.
+#[derive(Debug, Clone, PartialEq)]
+struct Applicant {
+    row: usize,
+    reference: String,
+    fields: Map<String, Value>,
+}
+
+/// Reads the uploaded CSV and starts quoting its rows in the background.
+/// Fails with `Validation` for a file without the required columns, and
+/// with `InvalidInput` for one without rows or with more than `maxRows`.
+pub fn submit(body: &str, caller: &Caller) -> anyhow::Result<BulkJob, PremiumError> {
+    let applicants = accepted(body)?;
+    let job = BulkJob {
+        id: format!("{:016x}", fastrand::u64(..)),
+        state: JobState::Running,
+        rows: applicants.len(),
+        processed: 0,
+        quoted: 0,
+        failed: 0,
+        submitted_at: Local::now().to_rfc3339(),
+        finished_at: None,
+    };
+    jobs().push(Entry {
+        tenant: tenant::current(),
+        job: job.clone(),
+        result: None,
+    });
+    info!("bulk job {} started with {} rows", job.id, job.rows);
+    let (id, caller) = (job.id.clone(), caller.clone());
+    async_std::task::spawn(tenant::scope(tenant::current(), async move {
+        run(id, applicants, caller).await;
+    }));
+    Ok(job)
+}
+
+/// Quotes the uploaded CSV while the caller reads the result, one JSON line
+/// per row in file order. Rows are quoted `concurrency` at a time and no
+/// further ahead than the reader, so a large file is never held in full.
+/// Fails like `submit`.
+pub fn stream(body: &str, caller: &Caller) -> anyhow::Result<Lines, PremiumError> {
+    let applicants = accepted(body)?;
+    let concurrency = config().concurrency.max(1);
+    let (sender, receiver) = channel::bounded(concurrency);
+    info!("bulk stream started with {} rows", applicants.len());
+    let caller = caller.clone();
+    async_std::task::spawn(tenant::scope(tenant::current(), async move {
+        for chunk in applicants.chunks(concurrency) {
+            for quote in spawn_quotes(chunk, &caller) {
+                let (applicant, quote) = quote.await;
+                let row = RowQuote {
+                    row: applicant.row,
+                    reference: applicant.reference,
+                    error: quote.as_ref().err().map(ErrorResponse::from),
+                    quote: quote.ok(),
+                };
+                let mut line = serde_json::to_vec(&row).unwrap_or_default();
+                line.push(b'\n');
+                if sender.send(line).await.is_err() {
+                    warn!("bulk stream closed by the caller at row {}", row.row);
+                    return;
+                }
+            }
+        }
+    }));
+    Ok(Lines {
+        receiver,
+        line: Vec::new(),
+        read: 0,
+    })
+}
+
+/// The lines of a streamed result, readable as each row is quoted.
+pub struct Lines {
+    receiver: Receiver<Vec<u8>>,
+    line: Vec<u8>,
+    read: usize,
+}
+
+impl async_std::io::Read for Lines {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let available = ready!(async_std::io::BufRead::poll_fill_buf(self.as_mut(), cx))?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        async_std::io::BufRead::consume(self, read);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl async_std::io::BufRead for Lines {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        while this.read == this.line.len() {
+            match ready!(Pin::new(&mut this.receiver).poll_next(cx)) {
+                Some(line) => (this.line, this.read) = (line, 0),
+                None => return Poll::Ready(Ok(&[])),
+            }
+        }
+        Poll::Ready(Ok(&this.line[this.read..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        self.get_mut().read += amount;
+    }
+}
+
+/// The progress of one of the current tenant's jobs.
+pub fn job(id: &str) -> anyhow::Result<BulkJob, PremiumError> {
+    with_entry(id, |entry| entry.job.clone())
+}
+
+/// The result file of a finished job, `None` while it is still running.
+pub fn result(id: &str) -> anyhow::Result<Option<Vec<u8>>, PremiumError> {
+    with_entry(id, |entry| entry.result.clone())
+}
+
+fn jobs() -> std::sync::MutexGuard<'static, Vec<Entry>> {
+    JOBS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn with_entry<T>(id: &str, read: impl FnOnce(&Entry) -> T) -> anyhow::Result<T, PremiumError> {
+    let tenant = tenant::current();
+    jobs()
+        .iter()
+        .find(|entry| entry.job.id == id && entry.tenant == tenant)
+        .map(read)
+        .ok_or_else(|| PremiumError::JobNotFound(id.to_string()))
+}
+
+fn update(id: &str, update: impl FnOnce(&mut Entry)) {
+    if let Some(entry) = jobs().iter_mut().find(|entry| entry.job.id == id) {
+        update(entry);
+    }
+}
+
+async fn run(id: String, applicants: Vec<Applicant>, caller: Caller) {
+    let mut rows = Vec::with_capacity(applicants.len());
+    for chunk in applicants.chunks(config().concurrency.max(1)) {
+        let mut quoted = 0;
+        for quote in spawn_quotes(chunk, &caller) {
+            let (applicant, quote) = quote.await;
+            quoted += usize::from(quote.is_ok());
+            rows.push(result_row(&applicant, &quote));
+        }
+        update(&id, |entry| {
+            entry.job.processed += chunk.len();
+            entry.job.quoted += quoted;
+            entry.job.failed += chunk.len() - quoted;
+        });
+    }
+    let body = export::csv(&(&RESULT_HEADER, rows));
+    update(&id, |entry| {
+        entry.job.state = JobState::Done;
+        entry.job.finished_at = Some(Local::now().to_rfc3339());
+        entry.result = Some(body);
+        info!(
+            "bulk job {} done, {} quoted and {} failed",
+            id, entry.job.quoted, entry.job.failed
+        );
+    });
+    prune();
+}
+
+/// Starts quoting each row of a chunk, to be awaited in order.
+fn spawn_quotes(
+    chunk: &[Applicant],
+    caller: &Caller,
+) -> Vec<JoinHandle<(Applicant, anyhow::Result<HealthResponse, PremiumError>)>> {
+    chunk
+        .iter()
+        .cloned()
+        .map(|applicant| {
+            let caller = caller.clone();
+            async_std::task::spawn(tenant::scope(tenant::current(), async move {
+                let quote = quote(&applicant, &caller).await;
+                (applicant, quote)
+            }))
+        })
+        .collect()
+}
+
+/// Drops the oldest finished jobs beyond `retainedJobs`.
+fn prune() {
+    let mut jobs = jobs();
+    let finished = jobs
+        .iter()
+        .filter(|entry| entry.job.state == JobState::Done)
+        .count();
+    let mut excess = finished.saturating_sub(config().retained_jobs);
+    jobs.retain(|entry| {
+        let drop = excess > 0 && entry.job.state == JobState::Done;
+        excess -= usize::from(drop);
+        !drop
+    });
+}
+
+/// Checks a row against the quote request schema and quotes it.
+async fn quote(
+    applicant: &Applicant,
+    caller: &Caller,
+) -> anyhow::Result<HealthResponse, PremiumError> {
+    let body = Value::Object(applicant.fields.clone());
+    schema::validate(&body)?;
+    let request: HealthRequest = serde_json::from_value(body).map_err(|err| {
+        error!("bulk row {} is not a quote request {}", applicant.row, err);
+        PremiumError::InvalidInput
+    })?;
+    quote_for(request, caller).await
+}
+
+fn result_row(
+    applicant: &Applicant,
+    quote: &anyhow::Result<HealthResponse, PremiumError>,
+) -> Vec<String> {
+    let field = |name: &str| {
+        applicant
+            .fields
+            .get(name)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut row = vec![
+        applicant.row.to_string(),
+        applicant.reference.clone(),
+        field("code"),
+        field("sumInsured"),
+        field("dateOfBirth"),
+    ];
+    match quote {
+        Ok(quote) => row.extend([
+            quote.premium.clone(),
+            quote.currency.clone(),
+            quote.matrix_version.to_string(),
+            String::new(),
+            String::new(),
+        ]),
+        Err(err) => row.extend([
+            String::new(),
+            String::new(),
+            String::new(),
+            err.code().to_string(),
+            err.to_string(),
+        ]),
+    }
+    row
+}
+
+/// The rows of an upload that has some, but no more than `maxRows`.
+fn accepted(body: &str) -> anyhow::Result<Vec<Applicant>, PremiumError> {
+    let applicants = applicants(body)?;
+    let config = config();
+    if applicants.is_empty() || applicants.len() > config.max_rows {
+        error!(
+            "bulk file has {} rows, at most {} are accepted",
+            applicants.len(),
+            config.max_rows
+        );
+        return Err(PremiumError::InvalidInput);
+    }
+    Ok(applicants)
+}
+
+/// The data rows of the upload, keyed by its header. Blank cells are left
+/// out, so they read as absent fields.
+fn applicants(body: &str) -> anyhow::Result<Vec<Applicant>, PremiumError> {
+    let mut records = records(body.trim_start_matches('\u{feff}')).into_iter();
+    let Some((_, header)) = records.next() else {
+        error!("bulk file is empty");
+        return Err(PremiumError::InvalidInput);
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|cell| cell.trim().eq_ignore_ascii_case(name))
+    };
+    if let Some(missing) = REQUIRED.iter().find(|name| column(name).is_none()) {
+        error!("bulk file has no {} column", missing);
+        return Err(PremiumError::Validation {
+            field: missing.to_string(),
+            message: "the column is required".to_string(),
+        });
+    }
+    let columns: Vec<(&str, usize)> = COLUMNS
+        .iter()
+        .filter_map(|name| column(name).map(|index| (*name, index)))
+        .collect();
+    let reference = column("reference");
+    Ok(records
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell.trim().is_empty()))
+        .map(|(row, cells)| {
+            let cell = |index: usize| cells.get(index).map_or("", |cell| cell.trim());
+            Applicant {
+                row,
+                reference: reference.map(cell).unwrap_or_default().to_string(),
+                fields: columns
+                    .iter()
+                    .filter(|(_, index)| !cell(*index).is_empty())
+                    .map(|(name, index)| (name.to_string(), Value::from(cell(*index))))
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+/// RFC 4180 records with the line each starts on. Quoted cells may hold
+/// commas, doubled quotes and line breaks.
+fn records(body: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let (mut record, mut cell) = (Vec::new(), String::new());
+    let (mut line, mut start, mut quoted) = (1, 1, false);
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut cell)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut cell));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c);
+            }
+        }
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((start, record));
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applicants() {
+        let body = "\u{feff}Reference,code,sumInsured,dateOfBirth,ignored\r\n\
+                    L-1,1A,100000,1980-01-01,x\r\n\
+                    \r\n\
+                    \"L,2\",1A,,\"1990-\"\"05\"\"\"\n";
+        let applicants = applicants(body).unwrap();
+        assert_eq!(applicants.len(), 2);
+        assert_eq!(applicants[0].row, 2);
+        assert_eq!(applicants[0].reference, "L-1");
+        assert_eq!(
+            Value::Object(applicants[0].fields.clone()),
+            serde_json::json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": "1980-01-01"})
+        );
+        assert_eq!(applicants[1].row, 4);
+        assert_eq!(applicants[1].reference, "L,2");
+        assert_eq!(applicants[1].fields.get("sumInsured"), None);
+        assert_eq!(applicants[1].fields["dateOfBirth"], "1990-\"05\"");
+
+        assert!(matches!(
+            super::applicants("code,dateOfBirth\n1A,1980-01-01\n"),
+            Err(PremiumError::Validation { field, .. }) if field == "sumInsured"
+        ));
+
+        let row = result_row(&applicants[1], &Err(PremiumError::InvalidInput));
+        assert_eq!(row[0], "4");
+        assert_eq!(row[8], "002");
+    }
+
+    #[test]
+    fn test_lines() {
+        use async_std::io::ReadExt;
+
+        let (sender, receiver) = channel::bounded(1);
+        let mut lines = Lines {
+            receiver,
+            line: Vec::new(),
+            read: 0,
+        };
+        async_std::task::block_on(async {
+            async_std::task::spawn(async move {
+                for line in ["{\"row\":2}\n", "{\"row\":3}\n"] {
+                    sender.send(line.as_bytes().to_vec()).await.unwrap();
+                }
+            });
+            let mut body = String::new();
+            lines.read_to_string(&mut body).await.unwrap();
+            assert_eq!(body, "{\"row\":2}\n{\"row\":3}\n");
+        });
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::RwLock;
+
+use crate::config::ChannelPricingConfig;
+
+static FACTORS: RwLock<Option<ChannelPricingConfig>> = RwLock::new(None);
+
+/// Sets the price factors of each sales channel, replacing any set before.
+/// Every channel is priced at the base rates when this is never called.
+pub fn configure(config: &ChannelPricingConfig) {
+    *FACTORS.write().unwrap_or_else(|err| err.into_inner()) = Some(config.clone());
+}
+
+/// Percent of the premium charged for `product_code` through `channel`,
+/// named in any case: the product's own factor for the channel over the
+/// channel's. None, for the base rates, when neither is set or the channel
+/// is not listed.
+pub fn percent(channel: Option<&str>, product_code: &str) -> Option<u32> {
+    let channel = channel?;
+    let factors = FACTORS.read().unwrap_or_else(|err| err.into_inner());
+    percent_in(factors.as_ref()?, channel, product_code)
+}
+
+fn percent_in(config: &ChannelPricingConfig, channel: &str, product_code: &str) -> Option<u32> {
+    let (_, factors) = config
+        .channels
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(channel))?;
+    factors
+        .products
+        .get(product_code)
+        .copied()
+        .or(factors.percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelFactors;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_percent() {
+        let config = ChannelPricingConfig {
+            channels: HashMap::from([
+                (
+                    "online".to_string(),
+                    ChannelFactors {
+                        percent: Some(95),
+                        products: HashMap::from([("2B".to_string(), 90)]),
+                    },
+                ),
+                (
+                    "banca".to_string(),
+                    ChannelFactors {
+                        percent: None,
+                        products: HashMap::from([("1A".to_string(), 105)]),
+                    },
+                ),
+            ]),
+        };
+        assert_eq!(percent_in(&config, "Online", "1A"), Some(95));
+        assert_eq!(percent_in(&config, "online", "2B"), Some(90));
+        assert_eq!(percent_in(&config, "banca", "1A"), Some(105));
+        assert_eq!(percent_in(&config, "banca", "2B"), None);
+        assert_eq!(percent_in(&config, "agency", "1A"), None);
+    }
+}
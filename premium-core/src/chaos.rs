@@ -0,0 +1,109 @@
+use std::io;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use log::warn;
+use redis::{RedisError, RedisResult};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChaosConfig;
+
+/// Current fault injection settings, as read and replaced through the chaos
+/// endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChaosSettings {
+    pub latency_ms: u64,
+    pub latency_percent: u32,
+    pub failure_percent: u32,
+}
+
+/// `None` while fault injection is off, which it is unless configured.
+static SETTINGS: RwLock<Option<ChaosSettings>> = RwLock::new(None);
+
+/// Turns fault injection on with the configured starting settings.
+pub fn configure(config: &ChaosConfig) {
+    if config.enabled {
+        warn!("chaos fault injection is enabled");
+        set(ChaosSettings {
+            latency_ms: config.latency_ms,
+            latency_percent: config.latency_percent,
+            failure_percent: config.failure_percent,
+        });
+    }
+}
+
+/// The settings in force, `None` while injection is off.
+pub fn settings() -> Option<ChaosSettings> {
+    *SETTINGS.read().unwrap_or_else(|err| err.into_inner())
+}
+
+pub fn set(settings: ChaosSettings) {
+    warn!("chaos settings changed to {:?}", settings);
+    *SETTINGS.write().unwrap_or_else(|err| err.into_inner()) = Some(settings);
+}
+
+/// True for `percent` out of every hundred calls, on average.
+fn roll(percent: u32) -> bool {
+    percent > 0 && fastrand::u32(0..100) < percent
+}
+
+/// The delay to add before a redis command, for `latencyPercent` of them.
+pub(crate) fn latency() -> Option<Duration> {
+    delay(settings()?)
+}
+
+fn delay(settings: ChaosSettings) -> Option<Duration> {
+    match roll(settings.latency_percent) {
+        true => {
+            warn!("chaos: delaying redis command by {}ms", settings.latency_ms);
+            Some(Duration::from_millis(settings.latency_ms))
+        }
+        false => None,
+    }
+}
+
+/// Fails `failurePercent` of redis commands with a reset connection, which
+/// the retry policy treats as transient and the circuit breaker counts as a
+/// redis failure once retries run out.
+pub(crate) fn fault() -> RedisResult<()> {
+    failure(settings())
+}
+
+fn failure(settings: Option<ChaosSettings>) -> RedisResult<()> {
+    match settings {
+        Some(settings) if roll(settings.failure_percent) => {
+            warn!("chaos: failing redis command");
+            Err(RedisError::from(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "chaos: injected redis failure",
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::is_transient;
+
+    #[test]
+    fn test_faults_are_transient_redis_errors() {
+        assert!(roll(100));
+        assert!(!roll(0));
+        // the settings are passed in rather than set, as setting them would
+        // inject faults into the redis tests running alongside
+        let always = ChaosSettings {
+            latency_ms: 50,
+            latency_percent: 100,
+            failure_percent: 100,
+        };
+        assert_eq!(delay(always), Some(Duration::from_millis(50)));
+        let err = failure(Some(always)).unwrap_err();
+        assert!(is_transient(&err));
+        assert_eq!(delay(ChaosSettings::default()), None);
+        assert!(failure(Some(ChaosSettings::default())).is_ok());
+        assert!(failure(None).is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, OnceLock};
+use std::task::Poll;
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::warn;
+
+use crate::config::{ClockConfig, LeapDayBirthday};
+
+#[derive(Default)]
+struct Calendar {
+    zone: Option<Tz>,
+    leap_day_birthdays: LeapDayBirthday,
+}
+
+static CALENDAR: OnceLock<Calendar> = OnceLock::new();
+
+/// Sets the zone the system clock's dates are taken in and when leap day
+/// birthdays fall; the server's local zone and 1 March when this is never
+/// called. Fails on a zone name that is not in the IANA database.
+pub fn configure(config: &ClockConfig) -> anyhow::Result<()> {
+    let zone = match &config.time_zone {
+        Some(name) => Some(
+            name.parse::<Tz>()
+                .map_err(|_| anyhow::anyhow!("unknown time zone {}", name))?,
+        ),
+        None => None,
+    };
+    let calendar = Calendar {
+        zone,
+        leap_day_birthdays: config.leap_day_birthdays,
+    };
+    if CALENDAR.set(calendar).is_err() {
+        warn!("clock already configured");
+    }
+    Ok(())
+}
+
+fn calendar() -> &'static Calendar {
+    CALENDAR.get_or_init(Calendar::default)
+}
+
+/// The day someone born on `date_of_birth` has their birthday in `year`.
+/// A 29 February birthday falls on the configured day outside leap years.
+pub fn birthday(date_of_birth: NaiveDate, year: i32) -> Option<NaiveDate> {
+    birthday_under(calendar().leap_day_birthdays, date_of_birth, year)
+}
+
+fn birthday_under(
+    leap_day_birthdays: LeapDayBirthday,
+    date_of_birth: NaiveDate,
+    year: i32,
+) -> Option<NaiveDate> {
+    date_of_birth
+        .with_year(year)
+        .or_else(|| match leap_day_birthdays {
+            LeapDayBirthday::February28 => NaiveDate::from_ymd_opt(year, 2, 28),
+            LeapDayBirthday::March1 => NaiveDate::from_ymd_opt(year, 3, 1),
+        })
+}
+
+/// Where quotes get today's date from: ages, discount validity and how far
+/// back `asOf` may go.
+pub trait Clock: Send + Sync {
+    fn today(&self) -> NaiveDate;
+}
+
+/// The calendar date in the configured time zone, used outside a
+/// [`scope`].
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&self) -> NaiveDate {
+        match calendar().zone {
+            Some(zone) => date_in(zone, Utc::now()),
+            None => Local::now().date_naive(),
+        }
+    }
+}
+
+fn date_in(zone: Tz, now: DateTime<Utc>) -> NaiveDate {
+    now.with_timezone(&zone).date_naive()
+}
+
+/// When `date` starts in the configured time zone, or the server's local
+/// zone when none is configured, so every instance draws the line between
+/// two days at the same instant. `None` when the zone skips midnight.
+pub fn start_of_day(date: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    match calendar().zone {
+        Some(zone) => start_in(zone, date),
+        None => start_in(Local, date),
+    }
+}
+
+fn start_in<Z: TimeZone>(zone: Z, date: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    zone.from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|start| start.fixed_offset())
+}
+
+/// Always the same date, for tests and replaying a quote as of a day.
+pub struct FixedClock(pub NaiveDate);
+
+impl Clock for FixedClock {
+    fn today(&self) -> NaiveDate {
+        self.0
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// Restores the clock of the enclosing scope, also when a poll panics.
+struct Restore(Option<Arc<dyn Clock>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.set(self.0.take());
+    }
+}
+
+/// Runs `future` with dates read from `clock`. Like tenants, work handed to
+/// other tasks has to scope itself again.
+pub async fn scope<F: Future>(clock: Arc<dyn Clock>, future: F) -> F::Output {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| -> Poll<F::Output> {
+        let _restore = Restore(CURRENT.replace(Some(clock.clone())));
+        future.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// Today by the clock of the running scope, the system clock outside one.
+pub fn today() -> NaiveDate {
+    CURRENT
+        .with_borrow(|clock| clock.as_ref().map(|clock| clock.today()))
+        .unwrap_or_else(|| SystemClock.today())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    #[test]
+    fn test_scope() {
+        let day = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        task::block_on(async {
+            let scoped = scope(Arc::new(FixedClock(day)), async {
+                task::yield_now().await;
+                today()
+            })
+            .await;
+            assert_eq!(scoped, day);
+        });
+    }
+
+    #[test]
+    fn test_time_zone() {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let kolkata: Tz = "Asia/Kolkata".parse().unwrap();
+        assert_eq!(
+            date_in(kolkata, now),
+            NaiveDate::from_ymd_opt(2024, 1, 2).unwrap()
+        );
+        assert_eq!(
+            date_in(Tz::America__New_York, now),
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+        );
+        let new_year = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+        assert_eq!(
+            start_in(kolkata, new_year).unwrap(),
+            DateTime::parse_from_rfc3339("2025-12-31T18:30:00Z").unwrap()
+        );
+        assert_eq!(
+            start_in(Tz::America__New_York, new_year).unwrap(),
+            DateTime::parse_from_rfc3339("2026-01-01T05:00:00Z").unwrap()
+        );
+        assert!(configure(&ClockConfig {
+            time_zone: Some("Mars/Olympus".to_string()),
+            ..ClockConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_leap_day_birthdays() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let leap_day = date(2000, 2, 29);
+        for (policy, expected) in [
+            (LeapDayBirthday::February28, date(2023, 2, 28)),
+            (LeapDayBirthday::March1, date(2023, 3, 1)),
+        ] {
+            assert_eq!(birthday_under(policy, leap_day, 2023), Some(expected));
+            assert_eq!(
+                birthday_under(policy, leap_day, 2024),
+                Some(date(2024, 2, 29))
+            );
+            assert_eq!(
+                birthday_under(policy, date(1990, 7, 4), 2023),
+                Some(date(2023, 7, 4))
+            );
+        }
+    }
+}
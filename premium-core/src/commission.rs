@@ -0,0 +1,100 @@
+use std::sync::RwLock;
+
+use log::error;
+use serde::Serialize;
+
+use crate::config::CommissionConfig;
+use crate::money::Money;
+use crate::premium::{HealthRequest, PremiumError};
+
+static CHANNELS: RwLock<Option<CommissionConfig>> = RwLock::new(None);
+
+/// Sets the commission of each channel, replacing any set before, so rates
+/// can change on a reload. No channel is known when this is never called.
+pub fn configure(config: &CommissionConfig) {
+    *CHANNELS.write().unwrap_or_else(|err| err.into_inner()) = Some(config.clone());
+}
+
+/// What a quote pays the channel it was sold through.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Commission {
+    pub channel: String,
+    pub percent: f64,
+    /// The premium before tax the commission is paid on.
+    pub base: Money,
+    pub amount: Money,
+}
+
+/// The commission on `premium` for the channel `input` names, the product's
+/// own percent for the channel over the channel's. Quotes naming no channel
+/// pay none; an unknown channel fails with `Validation`.
+pub fn compute(
+    input: &HealthRequest,
+    premium: &Money,
+) -> anyhow::Result<Option<Commission>, PremiumError> {
+    let Some(channel) = &input.channel else {
+        return Ok(None);
+    };
+    let channels = CHANNELS.read().unwrap_or_else(|err| err.into_inner());
+    let Some(rates) = channels
+        .as_ref()
+        .and_then(|config| config.channels.get(channel))
+    else {
+        error!("quote names unknown channel {}", channel);
+        return Err(PremiumError::Validation {
+            field: "channel".to_string(),
+            message: format!("unknown channel {}", channel),
+        });
+    };
+    let percent = rates
+        .products
+        .get(&input.code)
+        .copied()
+        .unwrap_or(rates.percent);
+    Ok(Some(Commission {
+        channel: channel.clone(),
+        percent,
+        base: premium.clone(),
+        amount: premium.scale(percent / 100.0),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelCommission;
+    use crate::money::Currency;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_compute() {
+        configure(&CommissionConfig {
+            channels: HashMap::from([(
+                "BANCA01".to_string(),
+                ChannelCommission {
+                    percent: 15.0,
+                    products: HashMap::from([("2B".to_string(), 7.5)]),
+                },
+            )]),
+        });
+        let premium = Money::parse("12345", Currency::new("INR")).unwrap();
+        let input = |code: &str, channel: Option<&str>| HealthRequest {
+            code: code.to_string(),
+            channel: channel.map(str::to_string),
+            ..HealthRequest::default()
+        };
+        let amount = |code, channel| {
+            compute(&input(code, channel), &premium)
+                .unwrap()
+                .map(|commission| commission.amount.to_string())
+        };
+        assert_eq!(amount("1A", Some("BANCA01")).as_deref(), Some("1851.75"));
+        assert_eq!(amount("2B", Some("BANCA01")).as_deref(), Some("925.88"));
+        assert_eq!(amount("1A", None), None);
+        assert!(matches!(
+            compute(&input("1A", Some("AGENCY")), &premium),
+            Err(PremiumError::Validation { field, .. }) if field == "channel"
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use crate::audit::Caller;
+use crate::frequency::PaymentFrequency;
+use crate::matrix::Gender;
+use crate::premium::{quote_for, ErrorResponse, HealthRequest, HealthResponse, PremiumError};
+use crate::products;
+use crate::tenant;
+
+/// One applicant quoted for several sums insured of a product.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CompareRequest {
+    pub code: String,
+    pub date_of_birth: String,
+    /// The bands to quote; every sum insured of the product when empty.
+    pub sums_insured: Vec<String>,
+    pub deductible: Option<String>,
+    pub zone: Option<String>,
+    pub gender: Option<Gender>,
+    pub maternity_cover: bool,
+    pub opd_cover: bool,
+    pub restore_benefit: bool,
+    pub payment_frequency: Option<PaymentFrequency>,
+    pub discount_codes: Vec<String>,
+    pub declared_conditions: Vec<String>,
+    pub height_cm: Option<f64>,
+    pub weight_kg: Option<f64>,
+}
+
+impl CompareRequest {
+    fn quote_request(&self, sum_insured: &str) -> HealthRequest {
+        HealthRequest {
+            code: self.code.clone(),
+            sum_insured: sum_insured.to_string(),
+            date_of_birth: self.date_of_birth.clone(),
+            deductible: self.deductible.clone(),
+            zone: self.zone.clone(),
+            gender: self.gender,
+            maternity_cover: self.maternity_cover,
+            opd_cover: self.opd_cover,
+            restore_benefit: self.restore_benefit,
+            payment_frequency: self.payment_frequency,
+            discount_codes: self.discount_codes.clone(),
+            declared_conditions: self.declared_conditions.clone(),
+            height_cm: self.height_cm,
+            weight_kg: self.weight_kg,
+            ..HealthRequest::default()
+        }
+    }
+}
+
+/// The quote for one sum insured, or why it has none.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandQuote {
+    pub sum_insured: String,
+    #[serde(flatten)]
+    pub quote: Option<HealthResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareResponse {
+    pub code: String,
+    /// Ordered by sum insured.
+    pub quotes: Vec<BandQuote>,
+}
+
+/// Quotes every requested band concurrently. A band that cannot be priced
+/// carries its error; the comparison fails only when no band is priced.
+pub async fn compare(
+    input: CompareRequest,
+    caller: &Caller,
+) -> anyhow::Result<CompareResponse, PremiumError> {
+    let offered = match input.sums_insured.is_empty() {
+        true => products::catalog()
+            .await?
+            .into_iter()
+            .find(|product| product.code == input.code)
+            .map(|product| product.sums_insured)
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+    let bands = bands(offered, &input.sums_insured);
+    if bands.is_empty() {
+        return Err(PremiumError::RiskCalculation);
+    }
+
+    let quotes: Vec<_> = bands
+        .into_iter()
+        .map(|sum_insured| {
+            let request = input.quote_request(&sum_insured);
+            let caller = caller.clone();
+            let quote = async_std::task::spawn(tenant::scope(tenant::current(), async move {
+                quote_for(request, &caller).await
+            }));
+            (sum_insured, quote)
+        })
+        .collect();
+    let mut results = Vec::with_capacity(quotes.len());
+    for (sum_insured, quote) in quotes {
+        results.push((sum_insured, quote.await));
+    }
+    if results.iter().all(|(_, result)| result.is_err()) {
+        let (_, first) = results.swap_remove(0);
+        return Err(first.expect_err("every band failed"));
+    }
+    Ok(CompareResponse {
+        code: input.code,
+        quotes: results
+            .into_iter()
+            .map(|(sum_insured, result)| match result {
+                Ok(quote) => BandQuote {
+                    sum_insured,
+                    quote: Some(quote),
+                    error: None,
+                },
+                Err(err) => BandQuote {
+                    sum_insured,
+                    quote: None,
+                    error: Some(ErrorResponse::from(&err)),
+                },
+            })
+            .collect(),
+    })
+}
+
+/// The requested sums insured, otherwise the offered ones, without repeats
+/// and in ascending order.
+fn bands(offered: Vec<String>, requested: &[String]) -> Vec<String> {
+    let mut bands = match requested.is_empty() {
+        true => offered,
+        false => requested.to_vec(),
+    };
+    bands.sort_by(|a, b| {
+        let amount = |sum: &str| sum.parse::<f64>().unwrap_or(f64::MAX);
+        amount(a).total_cmp(&amount(b)).then_with(|| a.cmp(b))
+    });
+    bands.dedup();
+    bands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bands() {
+        let sums = |sums: &[&str]| sums.iter().map(|sum| sum.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            bands(sums(&["500000", "100000", "1000000"]), &[]),
+            sums(&["100000", "500000", "1000000"])
+        );
+        assert_eq!(
+            bands(Vec::new(), &sums(&["300000", "200000", "300000"])),
+            sums(&["200000", "300000"])
+        );
+        assert!(bands(Vec::new(), &[]).is_empty());
+    }
+}
@@ -0,0 +1,967 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PremiumCacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_entries: u64,
+}
+
+impl Default for PremiumCacheConfig {
+    fn default() -> Self {
+        PremiumCacheConfig {
+            enabled: false,
+            ttl_secs: 60,
+            max_entries: 1000,
+        }
+    }
+}
+
+/// Share of the annual premium charged for policies shorter than a year.
+/// A period is charged at the first rate whose `months` covers it, counting
+/// a part month as a whole one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ShortPeriodConfig {
+    pub rates: Vec<ShortRate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ShortRate {
+    pub months: u32,
+    pub percent: u32,
+}
+
+impl Default for ShortPeriodConfig {
+    fn default() -> Self {
+        let rates = [
+            (1, 15),
+            (2, 20),
+            (3, 30),
+            (4, 40),
+            (5, 50),
+            (6, 60),
+            (7, 70),
+            (8, 75),
+            (9, 80),
+            (10, 85),
+            (11, 90),
+            (12, 100),
+        ];
+        ShortPeriodConfig {
+            rates: rates
+                .into_iter()
+                .map(|(months, percent)| ShortRate { months, percent })
+                .collect(),
+        }
+    }
+}
+
+/// Age bands mapping a policyholder's age to the matrix score, with
+/// overrides for products priced on different bands.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AgeBandConfig {
+    pub default: Vec<AgeBand>,
+    /// Bands keyed by product code.
+    pub products: HashMap<String, Vec<AgeBand>>,
+}
+
+/// Ages `minAge` to `maxAge` inclusive; no `maxAge` leaves the band open.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgeBand {
+    pub min_age: i32,
+    pub max_age: Option<i32>,
+    pub score: i32,
+}
+
+impl Default for AgeBandConfig {
+    fn default() -> Self {
+        let bands = [
+            (18, Some(35), 1),
+            (36, Some(45), 2),
+            (46, Some(55), 3),
+            (56, Some(60), 4),
+            (61, Some(65), 5),
+            (66, Some(70), 6),
+            (71, None, 7),
+        ];
+        AgeBandConfig {
+            default: bands
+                .into_iter()
+                .map(|(min_age, max_age, score)| AgeBand {
+                    min_age,
+                    max_age,
+                    score,
+                })
+                .collect(),
+            products: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSink {
+    #[default]
+    Off,
+    /// Appends one JSON record per line to `path`.
+    File,
+    /// Appends to the `{premium}:audit` redis stream.
+    Redis,
+}
+
+/// Where a record of every quote is kept for reproducing it later.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AuditConfig {
+    pub sink: AuditSink,
+    pub path: String,
+    /// Approximate number of entries the redis stream is trimmed to.
+    pub stream_max_len: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            sink: AuditSink::Off,
+            path: "./premium_audit.jsonl".to_string(),
+            stream_max_len: 1_000_000,
+        }
+    }
+}
+
+/// Worksheet holding discount codes; none are offered without `path`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DiscountConfig {
+    pub path: Option<String>,
+    pub sheet: String,
+}
+
+impl Default for DiscountConfig {
+    fn default() -> Self {
+        DiscountConfig {
+            path: None,
+            sheet: "discounts".to_string(),
+        }
+    }
+}
+
+/// Worksheet of rules for declared pre-existing conditions; every declared
+/// condition is referred without `path`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PedConfig {
+    pub path: Option<String>,
+    pub sheet: String,
+}
+
+impl Default for PedConfig {
+    fn default() -> Self {
+        PedConfig {
+            path: None,
+            sheet: "ped_rules".to_string(),
+        }
+    }
+}
+
+/// Worksheet of optional cover prices per product and sum insured; no cover
+/// is offered without `path`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AddOnsConfig {
+    pub path: Option<String>,
+    pub sheet: String,
+}
+
+impl Default for AddOnsConfig {
+    fn default() -> Self {
+        AddOnsConfig {
+            path: None,
+            sheet: "options".to_string(),
+        }
+    }
+}
+
+/// Worksheet of expression rules loading or discounting quotes; none apply
+/// without `path`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PricingRulesConfig {
+    pub path: Option<String>,
+    pub sheet: String,
+}
+
+impl Default for PricingRulesConfig {
+    fn default() -> Self {
+        PricingRulesConfig {
+            path: None,
+            sheet: "pricing_rules".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateTestMode {
+    /// Prices `splitPercent` of quotes from the candidate version.
+    #[default]
+    Split,
+    /// Prices every quote from both versions and returns the active one.
+    Shadow,
+}
+
+/// A candidate matrix version priced alongside the active one before it is
+/// activated; off without `candidateVersion`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RateTestConfig {
+    pub candidate_version: Option<u64>,
+    pub mode: RateTestMode,
+    pub split_percent: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagSource {
+    /// Flags come from this file alone, changed by a reload.
+    #[default]
+    Config,
+    /// Settings in the `{premium}:flags` redis hash override this file, so a
+    /// change reaches every instance.
+    Redis,
+}
+
+/// Who a feature flag is on for. Requests of the listed tenants only, when
+/// any are listed, and of those `percent` out of a hundred, bucketed by a
+/// key of the request so the same request always gets the same answer.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FlagSetting {
+    pub enabled: bool,
+    pub percent: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tenants: Vec<String>,
+}
+
+impl Default for FlagSetting {
+    fn default() -> Self {
+        FlagSetting {
+            enabled: true,
+            percent: 100,
+            tenants: Vec::new(),
+        }
+    }
+}
+
+/// Feature flags by name; a flag that is not listed keeps its default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FlagsConfig {
+    pub source: FlagSource,
+    /// How often flags are read again from redis.
+    pub refresh_secs: u64,
+    pub flags: HashMap<String, FlagSetting>,
+}
+
+impl Default for FlagsConfig {
+    fn default() -> Self {
+        FlagsConfig {
+            source: FlagSource::Config,
+            refresh_secs: 30,
+            flags: HashMap::new(),
+        }
+    }
+}
+
+/// How long a quote is honoured after it is issued.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct QuoteExpiryConfig {
+    pub validity_secs: u64,
+}
+
+impl Default for QuoteExpiryConfig {
+    fn default() -> Self {
+        QuoteExpiryConfig {
+            validity_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Fault injection into redis commands, for exercising gateway retries and
+/// circuit breakers in staging. The percentages are the starting settings
+/// and can be changed at runtime through the chaos endpoint, which exists
+/// only while this is enabled.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub latency_ms: u64,
+    /// Share of redis commands delayed by `latency_ms`.
+    pub latency_percent: u32,
+    /// Share of redis commands failed as if the connection had dropped.
+    pub failure_percent: u32,
+}
+
+/// Whether the service starts in maintenance mode, quoting nothing until it
+/// is switched off.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+}
+
+/// Checks run at startup before the service reports ready.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PreflightConfig {
+    pub enabled: bool,
+    /// Matrix version that must be loaded; any active version will do when
+    /// absent.
+    pub expected_version: Option<u64>,
+    /// Matrix premiums to read into the local cache for every age band.
+    pub hot_products: Vec<HotProduct>,
+    /// Wait between runs until every check passes.
+    pub retry_interval_ms: u64,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        PreflightConfig {
+            enabled: false,
+            expected_version: None,
+            hot_products: Vec::new(),
+            retry_interval_ms: 5000,
+        }
+    }
+}
+
+/// The calendar quotes are dated by, so pods in different regions agree on
+/// a policyholder's age around midnight.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ClockConfig {
+    /// IANA zone "today" is taken in, e.g. `Asia/Kolkata`; the server's local
+    /// zone when absent.
+    pub time_zone: Option<String>,
+    pub leap_day_birthdays: LeapDayBirthday,
+}
+
+/// How quote requests may write `dateOfBirth`, tried in order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DateOfBirthConfig {
+    pub formats: Vec<DateOfBirthFormat>,
+}
+
+impl Default for DateOfBirthConfig {
+    fn default() -> Self {
+        DateOfBirthConfig {
+            formats: vec![DateOfBirthFormat::Iso],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum DateOfBirthFormat {
+    /// `YYYY-MM-DD`.
+    #[serde(rename = "iso")]
+    Iso,
+    #[serde(rename = "dd/mm/yyyy")]
+    DayMonthYear,
+    /// Seconds since 1970-01-01 UTC, negative before it.
+    #[serde(rename = "epoch")]
+    Epoch,
+}
+
+/// The day someone born on 29 February gets a year older outside leap years.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum LeapDayBirthday {
+    #[serde(rename = "feb28")]
+    February28,
+    #[default]
+    #[serde(rename = "mar1")]
+    March1,
+}
+
+/// Background check that the active matrix version's keys are all present
+/// and not about to expire.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StalenessConfig {
+    /// Seconds between checks; 0 never checks.
+    pub interval_secs: u64,
+    /// Time left on the first key to expire below which the matrix counts as
+    /// expiring.
+    pub expiry_warning_secs: u64,
+}
+
+impl Default for StalenessConfig {
+    fn default() -> Self {
+        StalenessConfig {
+            interval_secs: 300,
+            expiry_warning_secs: 86_400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HotProduct {
+    pub code: String,
+    pub sums_insured: Vec<String>,
+}
+
+/// Settings per product code, given here or read from `path`: a `.json`
+/// file of settings keyed by code, or a workbook with one product per row of
+/// `sheet`. Entries read from `path` replace the ones given here.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ProductRegistryConfig {
+    pub path: Option<String>,
+    pub sheet: String,
+    pub products: HashMap<String, ProductSettings>,
+}
+
+impl Default for ProductRegistryConfig {
+    fn default() -> Self {
+        ProductRegistryConfig {
+            path: None,
+            sheet: "products".to_string(),
+            products: HashMap::new(),
+        }
+    }
+}
+
+/// What a product overrides; anything left out falls back to the service
+/// wide settings.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ProductSettings {
+    /// Tax charged on top of the quoted premium.
+    pub tax_percent: Option<u32>,
+    pub min_age: Option<i32>,
+    pub max_age: Option<i32>,
+    /// Percent of the matrix premium charged per zone; quotes for the product
+    /// must then name one of these zones.
+    pub zone_factors: HashMap<String, u32>,
+    /// Rounds the amount charged, after loadings and tax, to a multiple of
+    /// this many whole currency units.
+    pub rounding: Option<u32>,
+    /// Which way `rounding` goes.
+    pub rounding_mode: RoundingMode,
+    /// ISO 4217 code of the product's premiums.
+    pub currency: Option<String>,
+    /// Sums insured the product is sold with; any band in the matrix when
+    /// empty.
+    pub sums_insured: Vec<String>,
+    /// Cache-Control of the product's quotes, in place of the route's.
+    pub cache_control: Option<String>,
+    /// Sanity bounds, in whole currency units, on the annual premium of the
+    /// matrix with the zone, channel, loadings and pricing rules applied. A
+    /// premium outside them points at a bad matrix cell or loading and is
+    /// refused rather than quoted.
+    pub premium_floor: Option<u32>,
+    pub premium_ceiling: Option<u32>,
+    /// The filed minimum annual premium, in whole currency units. Quotes
+    /// discounted below it are raised to it rather than refused.
+    pub minimum_premium: Option<u32>,
+}
+
+/// Which multiple a product's premium is rounded to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RoundingMode {
+    /// The nearest multiple, halves rounded up.
+    #[default]
+    Nearest,
+    /// The next multiple up, e.g. 12,301 to 12,400 in hundreds.
+    Up,
+    /// The multiple below.
+    Down,
+}
+
+/// Entry ages accepted for new quotes, with overrides per product code.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EligibilityConfig {
+    pub default: EntryAges,
+    pub products: HashMap<String, EntryAges>,
+}
+
+/// Inclusive entry age range; no `maxAge` accepts any age from `minAge`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EntryAges {
+    pub min_age: i32,
+    pub max_age: Option<i32>,
+}
+
+/// Rules referring quotes to an underwriter or declining them. Quotes no rule
+/// matches are accepted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UnderwritingConfig {
+    pub rules: Vec<UnderwritingRule>,
+    /// Service deciding the quotes the rules refer.
+    pub referral: ReferralConfig,
+}
+
+/// An external underwriting service asked to decide referred quotes. Quotes
+/// stay referred when neither `url` nor `stub` is set, and whenever the
+/// service fails, times out or its circuit is open.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReferralConfig {
+    /// Where referred requests are POSTed.
+    pub url: Option<String>,
+    /// Sent with every referral, such as credentials.
+    pub headers: HashMap<String, String>,
+    pub timeout_ms: u64,
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Decides every referral this way without calling `url`, for tests and
+    /// environments without the service.
+    pub stub: Option<ReferralDecision>,
+}
+
+impl Default for ReferralConfig {
+    fn default() -> Self {
+        ReferralConfig {
+            url: None,
+            headers: HashMap::new(),
+            timeout_ms: 2000,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            stub: None,
+        }
+    }
+}
+
+/// What the underwriting service decided about a referred quote.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferralDecision {
+    pub outcome: ReferralOutcome,
+    /// Loading added to the premium of an accepted quote.
+    #[serde(default)]
+    pub loading_percent: u32,
+    /// Why, for a quote still referred or declined.
+    #[serde(default)]
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ReferralOutcome {
+    Accept,
+    Refer,
+    Decline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UnderwritingFactor {
+    Age,
+    /// Body mass index from `heightCm` and `weightKg`.
+    Bmi,
+    SumInsured,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleOutcome {
+    Refer,
+    Decline,
+}
+
+/// Matches quotes whose `factor` is below `min` or above `max`, for the
+/// listed `products` or, when none are listed, every product.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnderwritingRule {
+    pub factor: UnderwritingFactor,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    pub outcome: RuleOutcome,
+    #[serde(default)]
+    pub products: Vec<String>,
+}
+
+impl Default for EntryAges {
+    fn default() -> Self {
+        EntryAges {
+            min_age: 18,
+            max_age: None,
+        }
+    }
+}
+
+/// Currencies premiums are quoted in. Matrix premiums of products missing
+/// from `products` are in `default`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CurrencyConfig {
+    pub default: String,
+    /// ISO 4217 code keyed by product code.
+    pub products: HashMap<String, String>,
+    /// Decimal places for codes the ISO table does not cover.
+    pub minor_units: HashMap<String, u32>,
+}
+
+impl Default for CurrencyConfig {
+    fn default() -> Self {
+        CurrencyConfig {
+            default: "INR".to_string(),
+            products: HashMap::new(),
+            minor_units: HashMap::new(),
+        }
+    }
+}
+
+/// Percent loadings added to the annual premium when it is paid in
+/// installments; annual payment carries none.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PaymentFrequencyConfig {
+    pub semi_annual: u32,
+    pub quarterly: u32,
+    pub monthly: u32,
+}
+
+impl Default for PaymentFrequencyConfig {
+    fn default() -> Self {
+        PaymentFrequencyConfig {
+            semi_annual: 2,
+            quarterly: 3,
+            monthly: 5,
+        }
+    }
+}
+
+/// Price factors of the sales channels named by the `X-Channel` header, such
+/// as `online`, `agency` or `banca`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChannelPricingConfig {
+    pub channels: HashMap<String, ChannelFactors>,
+}
+
+/// Percent of the premium charged through a channel, e.g. 95 for 5% off
+/// online, with overrides per product code. A product with neither is priced
+/// at the base rates.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChannelFactors {
+    pub percent: Option<u32>,
+    pub products: HashMap<String, u32>,
+}
+
+/// Commission paid to distribution channels on the premium before tax, by
+/// channel code.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CommissionConfig {
+    pub channels: HashMap<String, ChannelCommission>,
+}
+
+/// A channel's commission percent, which may be fractional, with overrides
+/// per product code.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChannelCommission {
+    pub percent: f64,
+    pub products: HashMap<String, f64>,
+}
+
+/// Group quotes: the largest census accepted and the discount slabs applied
+/// to the aggregate premium by member count.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GroupConfig {
+    pub max_members: usize,
+    pub discounts: Vec<GroupDiscount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupDiscount {
+    pub min_members: usize,
+    pub percent: u32,
+}
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        let discounts = [(50, 5), (100, 10), (250, 15)];
+        GroupConfig {
+            max_members: 1000,
+            discounts: discounts
+                .into_iter()
+                .map(|(min_members, percent)| GroupDiscount {
+                    min_members,
+                    percent,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Limits of bulk CSV quote jobs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BulkConfig {
+    pub max_rows: usize,
+    /// Rows of a job quoted at once.
+    pub concurrency: usize,
+    /// Finished jobs kept for download; older ones are dropped.
+    pub retained_jobs: usize,
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        BulkConfig {
+            max_rows: 10_000,
+            concurrency: 8,
+            retained_jobs: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    Standalone,
+    #[default]
+    Sentinel,
+    Cluster,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Redis,
+    Postgres,
+    /// Keeps the matrix in process, loaded from `matrix.path` at startup.
+    Memory,
+}
+
+/// Where the premium matrix is kept.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub postgres: PostgresConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PostgresConfig {
+    /// Connection URL; `DATABASE_URL` is used when absent.
+    pub url: Option<String>,
+    pub max_connections: u32,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        PostgresConfig {
+            url: None,
+            max_connections: 5,
+        }
+    }
+}
+
+/// Redis topology. Without `sentinels` the sentinel on `redissvc:26379` is
+/// asked for the master and reads go to `redissvc:6380`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RedisConfig {
+    pub mode: RedisMode,
+    /// Standalone server URL; `redis://{redissvc}:6379` when absent.
+    pub url: Option<String>,
+    /// Replica endpoint for lookups in sentinel mode.
+    pub read_url: Option<String>,
+    /// Sentinel `host:port` addresses, tried in order.
+    pub sentinels: Vec<String>,
+    pub master_name: String,
+    /// Cluster seed node URLs.
+    pub cluster_nodes: Vec<String>,
+    /// ACL user sent with AUTH; credentials in a URL take precedence.
+    pub username: Option<String>,
+    /// AUTH password; `REDIS_PASSWORD` is used when absent.
+    pub password: Option<String>,
+    /// Upgrades every `redis://` address, including sentinels, to `rediss://`.
+    pub tls: bool,
+    /// PEM bundle trusted instead of the system roots.
+    pub ca_cert_path: Option<String>,
+    /// PEM client certificate and key for mutual TLS.
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+    /// Connect, read and write timeout per redis call; 0 waits forever.
+    pub timeout_ms: u64,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub retry: RetryConfig,
+    /// Rows sent per pipeline when loading a matrix version.
+    pub load_batch_size: usize,
+    /// Connections writing pipelines at the same time during a load.
+    pub load_concurrency: usize,
+    /// Expiry set on the keys of each loaded matrix version; they never
+    /// expire when absent.
+    pub key_ttl_secs: Option<u64>,
+}
+
+impl RedisConfig {
+    pub fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.timeout_ms)).filter(|timeout| !timeout.is_zero())
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            mode: RedisMode::Sentinel,
+            url: None,
+            read_url: None,
+            sentinels: Vec::new(),
+            master_name: "redis-premium-master".to_string(),
+            cluster_nodes: Vec::new(),
+            username: None,
+            password: None,
+            tls: false,
+            ca_cert_path: None,
+            client_cert_path: None,
+            client_key_path: None,
+            timeout_ms: 5000,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            retry: RetryConfig::default(),
+            load_batch_size: 1000,
+            load_concurrency: 4,
+            key_ttl_secs: None,
+        }
+    }
+}
+
+/// Fails redis calls fast with 503 once `failureThreshold` consecutive calls
+/// have failed, for `openMs`, then lets `halfOpenProbes` calls test redis
+/// before closing again. A threshold of 0 disables the breaker.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub open_ms: u64,
+    pub half_open_probes: u32,
+}
+
+/// Attempts per repeatable redis command, including the first, and the
+/// exponential backoff between them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 50,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            open_ms: 30_000,
+            half_open_probes: 1,
+        }
+    }
+}
+
+/// Where the premium workbook is read from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MatrixConfig {
+    pub path: String,
+    /// Sheets to load; every sheet in the workbook when empty.
+    pub sheets: Vec<String>,
+    /// Treats each sheet name as the product code of its rows.
+    pub product_sheets: bool,
+    /// Settings for loads that name a remote `sourceUrl`.
+    pub source: SourceConfig,
+    /// Reloads the matrix when the workbook at `path` changes.
+    pub watch: bool,
+    pub watch_debounce_ms: u64,
+    /// Parsed rows handed to the store at a time while loading.
+    pub chunk_rows: usize,
+}
+
+impl Default for MatrixConfig {
+    fn default() -> Self {
+        MatrixConfig {
+            path: "./premium_tables.xlsx".to_string(),
+            sheets: vec!["matrix".to_string()],
+            product_sheets: false,
+            source: SourceConfig::default(),
+            watch: false,
+            watch_debounce_ms: 2000,
+            chunk_rows: 10_000,
+        }
+    }
+}
+
+#[cfg(test)]
+impl MatrixConfig {
+    /// The workbook bundled at the repository root, whatever directory the
+    /// tests run from.
+    pub(crate) fn bundled() -> Self {
+        MatrixConfig {
+            path: concat!(env!("CARGO_MANIFEST_DIR"), "/../premium_tables.xlsx").to_string(),
+            ..MatrixConfig::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SourceConfig {
+    pub max_bytes: u64,
+    pub timeout_secs: u64,
+    /// Extra headers, such as `Authorization`, sent to https sources.
+    pub headers: HashMap<String, String>,
+    pub s3: S3Config,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        SourceConfig {
+            max_bytes: 50 * 1024 * 1024,
+            timeout_secs: 60,
+            headers: HashMap::new(),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+/// S3 credentials; unset values fall back to the `AWS_*` env vars.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct S3Config {
+    pub region: Option<String>,
+    /// Path-style endpoint for S3 compatible stores such as MinIO.
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+}
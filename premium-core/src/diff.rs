@@ -0,0 +1,123 @@
+use serde::Serialize;
+
+use crate::premium::PremiumError;
+use crate::store::{self, VersionPremiums};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffEntry {
+    /// `code:sumInsured` of the matrix row.
+    pub key: String,
+    pub score: i32,
+    pub premium: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedPremium {
+    pub key: String,
+    pub score: i32,
+    pub old_premium: i32,
+    pub new_premium: i32,
+}
+
+/// What changed between two loaded matrix versions, in key and score order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixDiff {
+    pub from: u64,
+    pub to: u64,
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<ChangedPremium>,
+    pub unchanged: usize,
+}
+
+/// Compares version `from` against version `to`; either missing is
+/// `VersionNotFound`.
+pub async fn diff(from: u64, to: u64) -> anyhow::Result<MatrixDiff, PremiumError> {
+    let old = store::version_premiums(from).await?;
+    let new = store::version_premiums(to).await?;
+    Ok(compare(from, to, &old, &new))
+}
+
+pub fn compare(from: u64, to: u64, old: &VersionPremiums, new: &VersionPremiums) -> MatrixDiff {
+    let entry = |(key, score): &(String, i32), premium: i32| DiffEntry {
+        key: key.clone(),
+        score: *score,
+        premium,
+    };
+    let mut diff = MatrixDiff {
+        from,
+        to,
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+        unchanged: 0,
+    };
+    for (key, premium) in old {
+        match new.get(key) {
+            None => diff.removed.push(entry(key, *premium)),
+            Some(new_premium) if new_premium != premium => diff.changed.push(ChangedPremium {
+                key: key.0.clone(),
+                score: key.1,
+                old_premium: *premium,
+                new_premium: *new_premium,
+            }),
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    for (key, premium) in new {
+        if !old.contains_key(key) {
+            diff.added.push(entry(key, *premium));
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn premiums(entries: &[(&str, i32, i32)]) -> VersionPremiums {
+        entries
+            .iter()
+            .map(|(key, score, premium)| ((key.to_string(), *score), *premium))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let old = premiums(&[
+            ("1A:100000", 1, 1200),
+            ("1A:100000", 2, 1500),
+            ("1A:200000", 1, 2000),
+        ]);
+        let new = premiums(&[
+            ("1A:100000", 1, 1200),
+            ("1A:100000", 2, 1650),
+            ("1A:300000", 1, 2800),
+        ]);
+        let diff = compare(1, 2, &old, &new);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(
+            diff.changed,
+            vec![ChangedPremium {
+                key: "1A:100000".to_string(),
+                score: 2,
+                old_premium: 1500,
+                new_premium: 1650,
+            }]
+        );
+        assert_eq!(
+            diff.removed,
+            vec![DiffEntry {
+                key: "1A:200000".to_string(),
+                score: 1,
+                premium: 2000,
+            }]
+        );
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].key, "1A:300000");
+    }
+}
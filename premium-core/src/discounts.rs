@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::NaiveDate;
+use log::{error, info};
+use serde::Serialize;
+
+use crate::clock;
+use crate::config::{DiscountConfig, MatrixConfig};
+use crate::matrix::{find_column, read_workbook, RowError, Sheet};
+use crate::money::Money;
+use crate::premium::PremiumError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscountKind {
+    Percent(u32),
+    /// Whole units of the premium's currency.
+    Flat(i64),
+}
+
+/// A named code from the discounts worksheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discount {
+    pub code: String,
+    pub kind: DiscountKind,
+    pub valid_from: Option<NaiveDate>,
+    pub valid_to: Option<NaiveDate>,
+    /// Whether the code may be combined with other codes on one quote.
+    pub stackable: bool,
+}
+
+impl Discount {
+    fn is_valid_on(&self, date: NaiveDate) -> bool {
+        self.valid_from.is_none_or(|from| date >= from) && self.valid_to.is_none_or(|to| date <= to)
+    }
+
+    fn amount(&self, premium: &Money) -> Money {
+        match self.kind {
+            DiscountKind::Percent(percent) => premium.percent(percent),
+            DiscountKind::Flat(amount) => {
+                let scale = 10_i64.pow(premium.currency().minor_units);
+                Money::from_minor(amount * scale, premium.currency().clone())
+            }
+        }
+    }
+}
+
+/// A discount taken off a quote, itemized in the response.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedDiscount {
+    pub code: String,
+    pub amount: Money,
+}
+
+static DISCOUNTS: RwLock<Option<HashMap<String, Discount>>> = RwLock::new(None);
+
+/// Reads the discount codes from the configured worksheet, replacing any
+/// loaded before. Nothing is loaded when `discounts.path` is not set.
+pub fn load(config: &DiscountConfig, matrix: &MatrixConfig) -> anyhow::Result<usize, PremiumError> {
+    let Some(path) = &config.path else {
+        return Ok(0);
+    };
+    let workbook = MatrixConfig {
+        path: path.clone(),
+        sheets: vec![config.sheet.clone()],
+        ..matrix.clone()
+    };
+    let sheets = read_workbook(&workbook, None)?;
+    let discounts = match sheets.first().map(parse_discounts) {
+        Some(Ok(discounts)) => discounts,
+        Some(Err(errors)) => {
+            for err in &errors {
+                error!(
+                    "discount sheet {} row {} {}",
+                    err.sheet, err.row, err.message
+                );
+            }
+            return Err(PremiumError::InvalidInput);
+        }
+        None => HashMap::new(),
+    };
+    info!("loaded {} discount codes from {}", discounts.len(), path);
+    let count = discounts.len();
+    *DISCOUNTS.write().unwrap_or_else(|err| err.into_inner()) = Some(discounts);
+    Ok(count)
+}
+
+/// Parses `code`, `type` (`percent` or `flat`), `amount` and the optional
+/// `validFrom`, `validTo` (`YYYY-MM-DD`) and `stackable` columns. Codes are
+/// matched case insensitively and are stackable unless marked otherwise.
+pub fn parse_discounts(sheet: &Sheet) -> Result<HashMap<String, Discount>, Vec<RowError>> {
+    let error = |row: usize, message: &str| RowError {
+        sheet: sheet.name.clone(),
+        row,
+        message: message.to_string(),
+    };
+    let Some((header, body)) = sheet.rows.split_first() else {
+        return Err(vec![error(1, "header row is missing")]);
+    };
+    let find = |name| find_column(header, name);
+    let (Some(code), Some(kind), Some(amount)) = (find("code"), find("type"), find("amount"))
+    else {
+        return Err(vec![error(
+            1,
+            "missing required columns: code, type, amount",
+        )]);
+    };
+    let (valid_from, valid_to, stackable) = (find("validFrom"), find("validTo"), find("stackable"));
+
+    let mut discounts = HashMap::new();
+    let mut errors = Vec::new();
+    for (index, row) in body.iter().enumerate() {
+        let number = index + 2;
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .map_or("", |value| value.trim())
+        };
+        if row.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let date = |column| match cell(column) {
+            "" => Ok(None),
+            value => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some),
+        };
+        let kind = match (
+            cell(Some(kind)).to_ascii_lowercase().as_str(),
+            cell(Some(amount)),
+        ) {
+            ("percent", amount) => amount.parse().ok().map(DiscountKind::Percent),
+            ("flat", amount) => amount.parse().ok().map(DiscountKind::Flat),
+            _ => None,
+        };
+        let discount = match (kind, date(valid_from), date(valid_to)) {
+            (Some(kind), Ok(valid_from), Ok(valid_to)) => Discount {
+                code: cell(Some(code)).to_ascii_uppercase(),
+                kind,
+                valid_from,
+                valid_to,
+                stackable: !matches!(
+                    cell(stackable).to_ascii_lowercase().as_str(),
+                    "false" | "no" | "n" | "0"
+                ),
+            },
+            (None, _, _) => {
+                errors.push(error(
+                    number,
+                    "type must be percent or flat with a whole amount",
+                ));
+                continue;
+            }
+            _ => {
+                errors.push(error(number, "validity dates must be YYYY-MM-DD"));
+                continue;
+            }
+        };
+        if discount.code.is_empty() {
+            errors.push(error(number, "code is empty"));
+        } else if discounts.contains_key(&discount.code) {
+            errors.push(error(number, &format!("duplicate code {}", discount.code)));
+        } else {
+            discounts.insert(discount.code.clone(), discount);
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(discounts),
+        false => Err(errors),
+    }
+}
+
+/// Prices `codes` against `premium` today. Unknown codes, codes outside
+/// their validity window and a non-stackable code combined with others fail
+/// with `DiscountNotApplicable`.
+pub fn apply(
+    codes: &[String],
+    premium: &Money,
+) -> anyhow::Result<Vec<AppliedDiscount>, PremiumError> {
+    if codes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let discounts = DISCOUNTS.read().unwrap_or_else(|err| err.into_inner());
+    let empty = HashMap::new();
+    let discounts = discounts.as_ref().unwrap_or(&empty);
+    apply_on(discounts, codes, premium, clock::today())
+}
+
+fn apply_on(
+    discounts: &HashMap<String, Discount>,
+    codes: &[String],
+    premium: &Money,
+    today: NaiveDate,
+) -> anyhow::Result<Vec<AppliedDiscount>, PremiumError> {
+    let mut applied = Vec::with_capacity(codes.len());
+    for code in codes {
+        let not_applicable = || PremiumError::DiscountNotApplicable(code.clone());
+        let discount = discounts
+            .get(&code.to_ascii_uppercase())
+            .filter(|discount| discount.is_valid_on(today))
+            .ok_or_else(not_applicable)?;
+        if !discount.stackable && codes.len() > 1 {
+            return Err(not_applicable());
+        }
+        if applied
+            .iter()
+            .any(|applied: &AppliedDiscount| applied.code == discount.code)
+        {
+            return Err(not_applicable());
+        }
+        applied.push(AppliedDiscount {
+            code: discount.code.clone(),
+            amount: discount.amount(premium),
+        });
+    }
+    Ok(applied)
+}
+
+/// `premium` less every applied discount, never below zero.
+pub fn net(premium: &Money, applied: &[AppliedDiscount]) -> Money {
+    let net = applied.iter().fold(premium.clone(), |net, discount| {
+        net - discount.amount.clone()
+    });
+    match net.is_negative() {
+        true => Money::from_minor(0, premium.currency().clone()),
+        false => net,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    #[test]
+    fn test_parse_and_apply_discounts() {
+        let rows: &[&[&str]] = &[
+            &[
+                "Code",
+                "Type",
+                "Amount",
+                "Valid From",
+                "Valid To",
+                "Stackable",
+            ],
+            &["online", "percent", "5", "", "", ""],
+            &["staff", "percent", "20", "", "", "no"],
+            &["DIWALI", "flat", "100", "2024-10-01", "2024-11-15", "yes"],
+        ];
+        let sheet = Sheet {
+            name: "discounts".to_string(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|value| value.to_string()).collect())
+                .collect(),
+        };
+        let discounts = parse_discounts(&sheet).unwrap();
+        assert_eq!(discounts.len(), 3);
+        assert!(!discounts["STAFF"].stackable);
+
+        let premium = Money::parse("750", Currency::new("INR")).unwrap();
+        let date = |value| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        let codes = |codes: &[&str]| {
+            codes
+                .iter()
+                .map(|code| code.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let applied = apply_on(
+            &discounts,
+            &codes(&["online", "diwali"]),
+            &premium,
+            date("2024-10-20"),
+        )
+        .unwrap();
+        let amounts: Vec<String> = applied.iter().map(|item| item.amount.to_string()).collect();
+        assert_eq!(amounts, vec!["37.50", "100"]);
+        assert_eq!(net(&premium, &applied).to_string(), "612.50");
+
+        let apply = |list: &[&str], on| apply_on(&discounts, &codes(list), &premium, date(on));
+        assert!(apply(&["diwali"], "2024-12-01").is_err());
+        assert!(apply(&["staff", "online"], "2024-10-20").is_err());
+        assert!(apply(&["staff"], "2024-10-20").is_ok());
+        assert!(matches!(
+            apply(&["unknown"], "2024-10-20"),
+            Err(PremiumError::DiscountNotApplicable(code)) if code == "unknown"
+        ));
+
+        let bad = Sheet {
+            name: "discounts".to_string(),
+            rows: vec![
+                vec!["code".to_string(), "type".to_string(), "amount".to_string()],
+                vec!["X".to_string(), "other".to_string(), "5".to_string()],
+            ],
+        };
+        assert_eq!(parse_discounts(&bad).unwrap_err()[0].row, 2);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveDate};
+use log::warn;
+
+use crate::config::{DateOfBirthConfig, DateOfBirthFormat};
+
+static CONFIG: OnceLock<DateOfBirthConfig> = OnceLock::new();
+
+/// Sets the accepted `dateOfBirth` formats; only `YYYY-MM-DD` when this is
+/// never called.
+pub fn configure(config: &DateOfBirthConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("date of birth formats already configured");
+    }
+}
+
+fn config() -> &'static DateOfBirthConfig {
+    CONFIG.get_or_init(DateOfBirthConfig::default)
+}
+
+/// The date `text` names in the first configured format that reads it.
+pub fn parse(text: &str) -> Option<NaiveDate> {
+    parse_in(&config().formats, text)
+}
+
+fn parse_in(formats: &[DateOfBirthFormat], text: &str) -> Option<NaiveDate> {
+    let text = text.trim();
+    formats.iter().find_map(|format| match format {
+        DateOfBirthFormat::Iso => NaiveDate::parse_from_str(text, "%Y-%m-%d").ok(),
+        DateOfBirthFormat::DayMonthYear => NaiveDate::parse_from_str(text, "%d/%m/%Y").ok(),
+        DateOfBirthFormat::Epoch => text
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|at| at.date_naive()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_in() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+        let all = [
+            DateOfBirthFormat::Iso,
+            DateOfBirthFormat::DayMonthYear,
+            DateOfBirthFormat::Epoch,
+        ];
+        assert_eq!(parse_in(&all, "1977-09-14"), date(1977, 9, 14));
+        assert_eq!(parse_in(&all, "14/09/1977"), date(1977, 9, 14));
+        assert_eq!(parse_in(&all, "243043200"), date(1977, 9, 14));
+        assert_eq!(parse_in(&all, "-86400"), date(1969, 12, 31));
+        assert_eq!(parse_in(&all, "31/02/1977"), None);
+        assert_eq!(parse_in(&[DateOfBirthFormat::Iso], "14/09/1977"), None);
+        assert_eq!(parse_in(&[DateOfBirthFormat::Iso], "243043200"), None);
+    }
+}
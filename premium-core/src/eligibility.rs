@@ -0,0 +1,67 @@
+use std::sync::OnceLock;
+
+use log::warn;
+
+use crate::config::{EligibilityConfig, EntryAges};
+use crate::premium::PremiumError;
+use crate::products;
+
+static ENTRY_AGES: OnceLock<EligibilityConfig> = OnceLock::new();
+
+/// Sets the entry ages; every product accepts ages from 18 when this is
+/// never called.
+pub fn configure(config: &EligibilityConfig) {
+    if ENTRY_AGES.set(config.clone()).is_err() {
+        warn!("entry ages already configured");
+    }
+}
+
+/// The product's ages from the product registry, falling back to the
+/// eligibility config for any it does not set.
+fn entry_ages(product_code: &str) -> EntryAges {
+    let config = ENTRY_AGES.get_or_init(EligibilityConfig::default);
+    let ages = config
+        .products
+        .get(product_code)
+        .copied()
+        .unwrap_or(config.default);
+    let settings = products::settings(product_code);
+    EntryAges {
+        min_age: settings.min_age.unwrap_or(ages.min_age),
+        max_age: settings.max_age.or(ages.max_age),
+    }
+}
+
+/// Fails with `AgeNotEligible` when `age` is outside the product's entry
+/// ages.
+pub fn check(product_code: &str, age: i32) -> anyhow::Result<(), PremiumError> {
+    check_ages(entry_ages(product_code), age)
+}
+
+fn check_ages(ages: EntryAges, age: i32) -> anyhow::Result<(), PremiumError> {
+    if age < ages.min_age || ages.max_age.is_some_and(|max_age| age > max_age) {
+        return Err(PremiumError::AgeNotEligible(age));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_ages() {
+        let ages = EntryAges {
+            min_age: 18,
+            max_age: Some(65),
+        };
+        assert!(check_ages(ages, 18).is_ok());
+        assert!(check_ages(ages, 65).is_ok());
+        assert!(matches!(
+            check_ages(ages, 17),
+            Err(PremiumError::AgeNotEligible(17))
+        ));
+        assert!(check_ages(ages, 66).is_err());
+        assert!(check_ages(EntryAges::default(), 99).is_ok());
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::money::Money;
+use crate::premium::{price, HealthRequest, PremiumError};
+use crate::short_period::parse_date;
+
+/// A mid-term change to a policy: the quote parameters it was issued with,
+/// the changed parameters and the date the change takes effect.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndorsementRequest {
+    pub original: HealthRequest,
+    pub changed: HealthRequest,
+    pub policy_start_date: String,
+    pub policy_end_date: String,
+    pub effective_date: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Adjustment {
+    Additional,
+    Refund,
+    None,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndorsementResponse {
+    pub currency: String,
+    pub original_premium: Money,
+    pub changed_premium: Money,
+    /// Amount due for the rest of the period, negative for a refund.
+    pub premium: Money,
+    pub adjustment: Adjustment,
+}
+
+/// Prices both sides of the endorsement for the policy period and charges or
+/// refunds the difference pro rata for the days left from `effectiveDate`.
+pub async fn calculate_endorsement(
+    input: EndorsementRequest,
+) -> anyhow::Result<EndorsementResponse, PremiumError> {
+    let start = parse_date(&input.policy_start_date)?;
+    let end = parse_date(&input.policy_end_date)?;
+    let effective = parse_date(&input.effective_date)?;
+    let share = remaining_share(start, end, effective)?;
+
+    let period = (input.policy_start_date, input.policy_end_date);
+    let original_premium = price(&for_period(input.original, &period)).await?.premium;
+    let changed_premium = price(&for_period(input.changed, &period)).await?.premium;
+    // A change of product cannot move the policy to another currency.
+    if original_premium.currency() != changed_premium.currency() {
+        return Err(PremiumError::InvalidInput);
+    }
+    let premium = difference(&original_premium, &changed_premium, share);
+    let adjustment = match premium {
+        ref premium if premium.is_negative() => Adjustment::Refund,
+        ref premium if premium.is_zero() => Adjustment::None,
+        _ => Adjustment::Additional,
+    };
+    Ok(EndorsementResponse {
+        currency: premium.currency().code.clone(),
+        original_premium,
+        changed_premium,
+        premium,
+        adjustment,
+    })
+}
+
+fn for_period(request: HealthRequest, (start, end): &(String, String)) -> HealthRequest {
+    HealthRequest {
+        policy_start_date: Some(start.clone()),
+        policy_end_date: Some(end.clone()),
+        ..request
+    }
+}
+
+/// Fraction of the policy period still to run on `effective`.
+fn remaining_share(
+    start: NaiveDate,
+    end: NaiveDate,
+    effective: NaiveDate,
+) -> anyhow::Result<f64, PremiumError> {
+    if end <= start || effective < start || effective >= end {
+        return Err(PremiumError::InvalidInput);
+    }
+    let total = (end - start).num_days() as f64;
+    let remaining = (end - effective).num_days() as f64;
+    Ok(remaining / total)
+}
+
+fn difference(original: &Money, changed: &Money, share: f64) -> Money {
+    (changed.clone() - original.clone()).scale(share)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    #[test]
+    fn test_pro_rata_difference() {
+        let date = |value| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        let money = |value| Money::parse(value, Currency::new("INR")).unwrap();
+        let (start, end) = (date("2023-01-01"), date("2024-01-01"));
+
+        let share = remaining_share(start, end, date("2023-07-02")).unwrap();
+        let difference = |original, changed| difference(&money(original), &money(changed), share);
+        assert_eq!(difference("750", "1000").to_string(), "125.34");
+        assert_eq!(difference("1000", "750").to_string(), "-125.34");
+        assert!(difference("750", "750").is_zero());
+
+        let share = remaining_share(start, end, start).unwrap();
+        assert_eq!(
+            super::difference(&money("750"), &money("1000"), share),
+            money("250")
+        );
+
+        assert!(remaining_share(start, end, end).is_err());
+        assert!(remaining_share(start, end, date("2022-12-31")).is_err());
+    }
+}
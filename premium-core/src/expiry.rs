@@ -0,0 +1,117 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::QuoteExpiryConfig;
+use crate::money::{Currency, Money};
+use crate::premium::{quote, HealthRequest, PremiumError};
+
+static CONFIG: OnceLock<QuoteExpiryConfig> = OnceLock::new();
+
+/// Sets how long quotes are honoured; seven days when this is never called.
+pub fn configure(config: &QuoteExpiryConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("quote expiry already configured");
+    }
+}
+
+fn validity() -> Duration {
+    let config = CONFIG.get_or_init(QuoteExpiryConfig::default);
+    Duration::seconds(config.validity_secs as i64)
+}
+
+/// Expiry stamp for a quote issued now.
+pub fn expires_at() -> String {
+    (Utc::now() + validity()).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// A previously issued quote: the request it answered and what it returned.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevalidationRequest {
+    pub request: HealthRequest,
+    pub premium: String,
+    pub matrix_version: u64,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteStatus {
+    Honored,
+    /// The active matrix version now prices the request differently.
+    Changed,
+    Expired,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevalidationResponse {
+    pub honored: bool,
+    pub status: QuoteStatus,
+    pub quoted_premium: String,
+    pub quoted_matrix_version: u64,
+    /// What the request is priced at today.
+    pub premium: String,
+    pub matrix_version: u64,
+    pub currency: String,
+}
+
+/// Reprices the quoted request and reports whether the quote still stands.
+/// A quote past `expiresAt` is never honoured; otherwise it is honoured for
+/// as long as the premium is unchanged, even under a newer matrix version.
+pub async fn revalidate(
+    input: RevalidationRequest,
+) -> anyhow::Result<RevalidationResponse, PremiumError> {
+    let expires_at =
+        DateTime::parse_from_rfc3339(&input.expires_at).map_err(|_| PremiumError::InvalidInput)?;
+    let current = quote(input.request).await?;
+    let currency = Currency::new(&current.currency);
+    let quoted =
+        Money::parse(&input.premium, currency.clone()).map_err(|_| PremiumError::InvalidInput)?;
+    let premium = Money::parse(&current.premium, currency)?;
+
+    let status = status(
+        expires_at.with_timezone(&Utc),
+        Utc::now(),
+        quoted == premium,
+    );
+    Ok(RevalidationResponse {
+        honored: status == QuoteStatus::Honored,
+        status,
+        quoted_premium: input.premium,
+        quoted_matrix_version: input.matrix_version,
+        premium: current.premium,
+        matrix_version: current.matrix_version,
+        currency: current.currency,
+    })
+}
+
+fn status(expires_at: DateTime<Utc>, now: DateTime<Utc>, unchanged: bool) -> QuoteStatus {
+    if now >= expires_at {
+        QuoteStatus::Expired
+    } else if unchanged {
+        QuoteStatus::Honored
+    } else {
+        QuoteStatus::Changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_status() {
+        let now = Utc::now();
+        let later = now + Duration::hours(1);
+        assert_eq!(status(later, now, true), QuoteStatus::Honored);
+        assert_eq!(status(later, now, false), QuoteStatus::Changed);
+        assert_eq!(status(now, now, true), QuoteStatus::Expired);
+
+        let stamp = DateTime::parse_from_rfc3339(&expires_at()).unwrap();
+        assert!(stamp.with_timezone(&Utc) > now + Duration::days(6));
+    }
+}
@@ -0,0 +1,244 @@
+use serde::Serialize;
+
+use crate::bands;
+use crate::discounts::{self, AppliedDiscount};
+use crate::frequency::{self, Breakdown};
+use crate::money::Money;
+use crate::premium::{price, pricing_version, HealthRequest, PremiumError, Priced};
+use crate::pricing_rules;
+use crate::products;
+use crate::quote_cache;
+use crate::store;
+
+/// One step from the matrix premium to the quoted premium.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Factor {
+    pub name: &'static str,
+    pub detail: String,
+    /// The premium once this factor is applied.
+    pub premium: Money,
+}
+
+/// How a quote was derived, from the date of birth to the final premium.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    pub code: String,
+    pub sum_insured: String,
+    pub date_of_birth: String,
+    pub age: i32,
+    /// The configured band the age falls in, when one does.
+    pub age_band: Option<String>,
+    pub score: i32,
+    /// Where the matrix premium was read from; it may have come from the
+    /// premium cache instead, see `cached`.
+    pub storage_key: String,
+    pub cached: bool,
+    pub matrix_version: u64,
+    pub matrix_premium: Money,
+    pub currency: String,
+    pub factors: Vec<Factor>,
+    pub premium: Money,
+    /// Tax on `premium` and the two together, for taxed products.
+    pub tax: Option<Money>,
+    pub total_premium: Option<Money>,
+    /// What rounding the amount charged to the product's round figure
+    /// added, as the quote reports it.
+    pub rounding_adjustment: Option<Money>,
+}
+
+/// Prices `input` the way a quote does and reports every step. Nothing is
+/// audited, as no quote is given.
+pub async fn explain(input: &HealthRequest) -> anyhow::Result<Explanation, PremiumError> {
+    let score = bands::score(&input.code, input.age()?);
+    let cached = quote_cache::get(&quote_cache::key(
+        pricing_version(input).await?,
+        &input.code,
+        &input.band(),
+        score,
+    ))
+    .is_some();
+    let priced = price(input).await?;
+    let applied = discounts::apply(&input.discount_codes, &priced.premium)?;
+    let settings = products::settings(&input.code);
+    let (discounted, uplift) =
+        products::apply_minimum(&settings, &discounts::net(&priced.premium, &applied));
+    let breakdown = input
+        .payment_frequency
+        .map(|frequency| frequency::breakdown(frequency, &discounted));
+    let factors = factors(&priced, &applied, uplift.as_ref(), breakdown.as_ref());
+    let premium = factors
+        .last()
+        .map_or(priced.premium.clone(), |factor| factor.premium.clone());
+    let charge = products::charge(&settings, &premium);
+    Ok(Explanation {
+        code: input.code.clone(),
+        sum_insured: input.sum_insured.clone(),
+        date_of_birth: input.date_of_birth.clone(),
+        age: priced.age,
+        age_band: bands::label(&input.code, priced.score),
+        score: priced.score,
+        storage_key: store::location(priced.matrix_version, &input.code, &priced.band),
+        cached,
+        matrix_version: priced.matrix_version,
+        currency: priced.matrix_premium.currency().code.clone(),
+        matrix_premium: priced.matrix_premium.clone(),
+        factors,
+        premium: charge.premium,
+        tax: charge.tax,
+        total_premium: charge.total_premium,
+        rounding_adjustment: charge.rounding_adjustment,
+    })
+}
+
+fn factors(
+    priced: &Priced,
+    applied: &[AppliedDiscount],
+    uplift: Option<&Money>,
+    breakdown: Option<&Breakdown>,
+) -> Vec<Factor> {
+    let mut factors = Vec::new();
+    let mut premium = priced.matrix_premium.clone();
+    if let Some(percent) = priced.short_period_percent {
+        premium = premium.percent(percent);
+        factors.push(Factor {
+            name: "shortPeriod",
+            detail: format!("{}% of the annual premium for the policy period", percent),
+            premium: premium.clone(),
+        });
+    }
+    if let Some(percent) = priced.zone_percent {
+        premium = premium.percent(percent);
+        factors.push(Factor {
+            name: "zone",
+            detail: format!("{}% of the premium for the zone", percent),
+            premium: premium.clone(),
+        });
+    }
+    if let Some(percent) = priced.channel_percent {
+        premium = premium.percent(percent);
+        factors.push(Factor {
+            name: "channel",
+            detail: format!("{}% of the premium for the sales channel", percent),
+            premium: premium.clone(),
+        });
+    }
+    // Loadings, and then rule adjustments, are added together before they
+    // are applied, so each step shows the running total applied.
+    let mut loading = 0;
+    for condition in &priced.condition_loadings {
+        loading += condition.loading_percent;
+        factors.push(Factor {
+            name: "condition",
+            detail: format!(
+                "{}% loading for declared condition {}",
+                condition.loading_percent, condition.condition
+            ),
+            premium: premium.percent(100 + loading),
+        });
+    }
+    if let Some(percent) = priced.referral_loading_percent {
+        loading += percent;
+        factors.push(Factor {
+            name: "referral",
+            detail: format!("{}% loading from the underwriting service", percent),
+            premium: premium.percent(100 + loading),
+        });
+    }
+    if loading > 0 {
+        premium = premium.percent(100 + loading);
+    }
+    for (index, adjustment) in priced.rule_adjustments.iter().enumerate() {
+        factors.push(Factor {
+            name: "rule",
+            detail: format!(
+                "{:+}% from pricing rule {}",
+                adjustment.percent, adjustment.name
+            ),
+            premium: pricing_rules::apply(&premium, &priced.rule_adjustments[..=index]),
+        });
+    }
+    let mut premium = pricing_rules::apply(&premium, &priced.rule_adjustments);
+    for add_on in &priced.add_ons {
+        premium = premium + add_on.premium.clone();
+        factors.push(Factor {
+            name: "addOn",
+            detail: format!("{} adds {}", add_on.cover.name(), add_on.premium),
+            premium: premium.clone(),
+        });
+    }
+    for (index, discount) in applied.iter().enumerate() {
+        factors.push(Factor {
+            name: "discount",
+            detail: format!("{} takes off {}", discount.code, discount.amount),
+            premium: discounts::net(&priced.premium, &applied[..=index]),
+        });
+    }
+    if let Some(uplift) = uplift {
+        factors.push(Factor {
+            name: "minimumPremium",
+            detail: format!("raised by {} to the product's minimum premium", uplift),
+            premium: discounts::net(&priced.premium, applied) + uplift.clone(),
+        });
+    }
+    if let Some(breakdown) = breakdown {
+        factors.push(Factor {
+            name: "paymentFrequency",
+            detail: format!(
+                "{}% loading, paid in {} installments of {}",
+                breakdown.loading_percent, breakdown.installments, breakdown.installment_premium
+            ),
+            premium: breakdown.annualized_premium.clone(),
+        });
+    }
+    factors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frequency::PaymentFrequency;
+    use crate::money::Currency;
+
+    #[test]
+    fn test_factors() {
+        let inr = |amount: &str| Money::parse(amount, Currency::new("INR")).unwrap();
+        let priced = Priced {
+            premium: inr("450"),
+            matrix_version: 2,
+            band: "100000".to_string(),
+            matrix_premium: inr("1000"),
+            age: 40,
+            score: 2,
+            short_period_percent: Some(50),
+            zone_percent: None,
+            channel_percent: Some(90),
+            condition_loadings: Vec::new(),
+            referral_loading_percent: None,
+            rule_adjustments: Vec::new(),
+            add_ons: Vec::new(),
+        };
+        let applied = vec![AppliedDiscount {
+            code: "LOYAL10".to_string(),
+            amount: inr("50"),
+        }];
+        let breakdown = frequency::breakdown(PaymentFrequency::Annual, &inr("430"));
+        let factors = factors(&priced, &applied, Some(&inr("30")), Some(&breakdown));
+        let steps: Vec<(&str, String)> = factors
+            .iter()
+            .map(|factor| (factor.name, factor.premium.to_string()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("shortPeriod", "500".to_string()),
+                ("channel", "450".to_string()),
+                ("discount", "400".to_string()),
+                ("minimumPremium", "430".to_string()),
+                ("paymentFrequency", "430".to_string()),
+            ]
+        );
+        assert_eq!(factors[2].detail, "LOYAL10 takes off 50");
+    }
+}
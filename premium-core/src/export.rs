@@ -0,0 +1,200 @@
+use log::error;
+use rust_xlsxwriter::{Workbook, XlsxError};
+use serde::Deserialize;
+
+use crate::bands;
+use crate::matrix::band_parts;
+use crate::premium::PremiumError;
+use crate::store::{self, VersionPremiums};
+
+const HEADER: [&str; 5] = ["code", "sumInsured", "ageBand", "score", "premium"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// The active matrix as a file, and the version it was read from.
+pub struct MatrixExport {
+    pub version: u64,
+    pub body: Vec<u8>,
+}
+
+/// Reconstructs the active matrix from the store as one `matrix` sheet with
+/// a code column, so the file loads again under the default matrix settings.
+/// Age bands are not stored, so they are written from the configured bands.
+/// A `deductible` column is added when the version has top-up bands, and a
+/// `gender` column when it has gendered rates.
+pub async fn export(format: ExportFormat) -> anyhow::Result<MatrixExport, PremiumError> {
+    let Some(version) = store::versions().await?.active else {
+        error!("no premium matrix version is active to export");
+        return Err(PremiumError::RiskCalculation);
+    };
+    let (header, rows) = rows(&store::version_premiums(version).await?);
+    let rows = (header.as_slice(), rows);
+    let body = match format {
+        ExportFormat::Csv => csv(&rows),
+        ExportFormat::Xlsx => xlsx(&rows).map_err(|err| {
+            error!("error writing matrix workbook {}", err);
+            PremiumError::InternalServer
+        })?,
+    };
+    Ok(MatrixExport { version, body })
+}
+
+/// The header and rows of the export.
+fn rows(premiums: &VersionPremiums) -> (Vec<&'static str>, Vec<Vec<String>>) {
+    let bands: Vec<_> = premiums
+        .keys()
+        .map(|(key, _)| band_parts(key.split_once(':').map_or("", |(_, band)| band)))
+        .collect();
+    let top_up = bands.iter().any(|(_, deductible, _)| deductible.is_some());
+    let gendered = bands.iter().any(|(_, _, gender)| gender.is_some());
+    let rows = premiums
+        .iter()
+        .zip(bands)
+        .map(
+            |(((key, score), premium), (sum_insured, deductible, gender))| {
+                let code = key.split_once(':').map_or(key.as_str(), |(code, _)| code);
+                let mut row = vec![code.to_string(), sum_insured.to_string()];
+                if top_up {
+                    row.push(deductible.unwrap_or_default().to_string());
+                }
+                if gendered {
+                    row.push(gender.map_or("", |gender| gender.as_str()).to_string());
+                }
+                row.extend([
+                    bands::label(code, *score).unwrap_or_default(),
+                    score.to_string(),
+                    premium.to_string(),
+                ]);
+                row
+            },
+        )
+        .collect();
+    let mut header = HEADER[..2].to_vec();
+    if top_up {
+        header.push("deductible");
+    }
+    if gendered {
+        header.push("gender");
+    }
+    header.extend(&HEADER[2..]);
+    (header, rows)
+}
+
+/// RFC 4180 text of a header and rows, CRLF terminated.
+pub(crate) fn csv((header, rows): &(&[&str], Vec<Vec<String>>)) -> Vec<u8> {
+    let field = |value: &str| {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let mut out = header.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|value| field(value)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out.into_bytes()
+}
+
+fn xlsx((header, rows): &(&[&str], Vec<Vec<String>>)) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("matrix")?;
+    // Score and premium are the last two columns.
+    let numbers = header.len() - 2;
+    for (column, name) in header.iter().enumerate() {
+        sheet.write_string(0, column as u16, *name)?;
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let line = index as u32 + 1;
+        for (column, value) in row.iter().enumerate() {
+            // Score and premium are whole numbers, written as numbers.
+            match value.parse::<i32>() {
+                Ok(number) if column >= numbers => {
+                    sheet.write_number(line, column as u16, number)?
+                }
+                _ => sheet.write_string(line, column as u16, value)?,
+            };
+        }
+    }
+    workbook.save_to_buffer()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv() {
+        let premiums: VersionPremiums = [
+            (("1A:100000".to_string(), 2), 1500),
+            (("1A:100000".to_string(), 1), 1200),
+        ]
+        .into_iter()
+        .collect();
+        let export = |premiums: &VersionPremiums| {
+            let (header, rows) = rows(premiums);
+            String::from_utf8(csv(&(header.as_slice(), rows))).unwrap()
+        };
+        let csv = export(&premiums);
+        assert_eq!(
+            csv,
+            "code,sumInsured,ageBand,score,premium\r\n\
+             1A,100000,18-35,1,1200\r\n\
+             1A,100000,36-45,2,1500\r\n"
+        );
+
+        let premiums: VersionPremiums = [
+            (("1A:100000".to_string(), 1), 1200),
+            (("TU:500000:300000".to_string(), 1), 120),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            export(&premiums),
+            "code,sumInsured,deductible,ageBand,score,premium\r\n\
+             1A,100000,,18-35,1,1200\r\n\
+             TU,500000,300000,18-35,1,120\r\n"
+        );
+
+        let premiums: VersionPremiums = [
+            (("1A:100000".to_string(), 1), 1200),
+            (("1A:100000:female".to_string(), 1), 1100),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            export(&premiums),
+            "code,sumInsured,gender,ageBand,score,premium\r\n\
+             1A,100000,,18-35,1,1200\r\n\
+             1A,100000,female,18-35,1,1100\r\n"
+        );
+    }
+}
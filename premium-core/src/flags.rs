@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use redis::RedisResult;
+use serde::Serialize;
+
+use crate::config::{FlagSetting, FlagSource, FlagsConfig};
+use crate::connection::{conn_read, conn_write, redis_error};
+use crate::premium::PremiumError;
+use crate::{rate_test, tenant};
+
+/// Hash of flag settings as JSON, by flag name, shared by every instance.
+const FLAGS_KEY: &str = "{premium}:flags";
+
+/// A behaviour that can be switched per request without a redeploy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Flag {
+    /// Shadow rate tests price quotes from the candidate version too.
+    ShadowPricing,
+    /// Quotes that give a gender are priced from gendered rates when the
+    /// matrix has them.
+    GenderedRates,
+    /// The v1 quote routes answer with the v2 quote shape.
+    V2Quotes,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [Flag::ShadowPricing, Flag::GenderedRates, Flag::V2Quotes];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flag::ShadowPricing => "shadowPricing",
+            Flag::GenderedRates => "genderedRates",
+            Flag::V2Quotes => "v2Quotes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Flag> {
+        Flag::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+
+    /// The setting of a flag nothing configures: behaviour that predates
+    /// the flag stays on, new behaviour stays off.
+    fn default_setting(&self) -> FlagSetting {
+        FlagSetting {
+            enabled: !matches!(self, Flag::V2Quotes),
+            ..FlagSetting::default()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Flags {
+    config: FlagsConfig,
+    /// Settings read from redis, over those of the config.
+    overrides: HashMap<Flag, FlagSetting>,
+}
+
+static FLAGS: RwLock<Option<Flags>> = RwLock::new(None);
+
+/// Replaces the configured flag settings, keeping any read from redis until
+/// the next refresh. Unknown flag names are logged and ignored.
+pub fn configure(config: &FlagsConfig) {
+    for name in config.flags.keys() {
+        if Flag::from_name(name).is_none() {
+            warn!("unknown feature flag {} ignored", name);
+        }
+    }
+    let mut flags = FLAGS.write().unwrap_or_else(|err| err.into_inner());
+    let overrides = match (config.source, flags.take()) {
+        (FlagSource::Redis, Some(flags)) => flags.overrides,
+        _ => HashMap::new(),
+    };
+    *flags = Some(Flags {
+        config: config.clone(),
+        overrides,
+    });
+}
+
+fn setting(flag: Flag) -> FlagSetting {
+    let flags = FLAGS.read().unwrap_or_else(|err| err.into_inner());
+    let Some(flags) = flags.as_ref() else {
+        return flag.default_setting();
+    };
+    flags
+        .overrides
+        .get(&flag)
+        .or_else(|| flags.config.flags.get(flag.name()))
+        .cloned()
+        .unwrap_or_else(|| flag.default_setting())
+}
+
+/// Whether `flag` is on for the request `key` stands for, in the current
+/// tenant. Each flag buckets requests its own way, and raising `percent`
+/// only adds requests to those it was on for.
+pub fn enabled(flag: Flag, key: &str) -> bool {
+    let key = format!("{}|{}", flag.name(), key);
+    applies(&setting(flag), tenant::current().as_deref(), &key)
+}
+
+fn applies(setting: &FlagSetting, tenant: Option<&str>, key: &str) -> bool {
+    setting.enabled
+        && (setting.tenants.is_empty()
+            || tenant.is_some_and(|tenant| setting.tenants.iter().any(|name| name == tenant)))
+        && rate_test::bucket(key) < setting.percent
+}
+
+/// The setting every flag has now, by name.
+pub fn settings() -> BTreeMap<&'static str, FlagSetting> {
+    Flag::ALL
+        .into_iter()
+        .map(|flag| (flag.name(), setting(flag)))
+        .collect()
+}
+
+/// Changes a flag while serving. With the redis source the setting is
+/// written to redis for every instance to pick up; otherwise it lasts until
+/// the next reload or restart of this instance.
+pub async fn set(flag: Flag, setting: FlagSetting) -> anyhow::Result<(), PremiumError> {
+    let source = {
+        let flags = FLAGS.read().unwrap_or_else(|err| err.into_inner());
+        flags
+            .as_ref()
+            .map_or(FlagSource::Config, |flags| flags.config.source)
+    };
+    if source == FlagSource::Redis {
+        let json = serde_json::to_string(&setting).map_err(|_| PremiumError::InternalServer)?;
+        let mut conn = conn_write().await?;
+        let result: RedisResult<()> = redis::cmd("HSET")
+            .arg(FLAGS_KEY)
+            .arg(flag.name())
+            .arg(json)
+            .query(&mut conn);
+        result.map_err(|err| {
+            error!("Redis error while setting flag {} {}", flag.name(), err);
+            redis_error(&err)
+        })?;
+    }
+    info!("feature flag {} set to {:?}", flag.name(), setting);
+    let mut flags = FLAGS.write().unwrap_or_else(|err| err.into_inner());
+    flags
+        .get_or_insert_with(Flags::default)
+        .overrides
+        .insert(flag, setting);
+    Ok(())
+}
+
+/// Reads the flag settings kept in redis, replacing those read before.
+/// Settings that do not parse are logged and skipped.
+pub async fn refresh() -> anyhow::Result<usize, PremiumError> {
+    let mut conn = conn_read().await?;
+    let result: RedisResult<HashMap<String, String>> =
+        redis::cmd("HGETALL").arg(FLAGS_KEY).query(&mut conn);
+    let stored = result.map_err(|err| {
+        error!("Redis error while reading flags {}", err);
+        redis_error(&err)
+    })?;
+    let overrides = parse_overrides(&stored);
+    let count = overrides.len();
+    let mut flags = FLAGS.write().unwrap_or_else(|err| err.into_inner());
+    flags.get_or_insert_with(Flags::default).overrides = overrides;
+    Ok(count)
+}
+
+fn parse_overrides(stored: &HashMap<String, String>) -> HashMap<Flag, FlagSetting> {
+    stored
+        .iter()
+        .filter_map(|(name, json)| {
+            let Some(flag) = Flag::from_name(name) else {
+                warn!("unknown feature flag {} in redis ignored", name);
+                return None;
+            };
+            match serde_json::from_str(json) {
+                Ok(setting) => Some((flag, setting)),
+                Err(err) => {
+                    error!("feature flag {} in redis does not parse {}", name, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// With the redis source, reads flags from redis now and every
+/// `refreshSecs` until the process exits; never when 0.
+pub async fn watch(config: FlagsConfig) {
+    if config.source != FlagSource::Redis || config.refresh_secs == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(config.refresh_secs);
+    loop {
+        if let Err(err) = refresh().await {
+            warn!("feature flags not refreshed {}", err);
+        }
+        async_std::task::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applies() {
+        let setting = |enabled, percent, tenants: &[&str]| FlagSetting {
+            enabled,
+            percent,
+            tenants: tenants.iter().map(|tenant| tenant.to_string()).collect(),
+        };
+        assert!(applies(&setting(true, 100, &[]), None, "1A"));
+        assert!(!applies(&setting(false, 100, &[]), None, "1A"));
+        assert!(!applies(&setting(true, 0, &[]), None, "1A"));
+        assert!(applies(&setting(true, 100, &["acme"]), Some("acme"), "1A"));
+        assert!(!applies(&setting(true, 100, &["acme"]), None, "1A"));
+
+        let half = setting(true, 50, &[]);
+        let on = (0..1000)
+            .filter(|key| applies(&half, None, &key.to_string()))
+            .count();
+        assert!((400..600).contains(&on), "{}", on);
+        let key = "1A|100000|1977-09-14";
+        assert_eq!(applies(&half, None, key), applies(&half, None, key));
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        let stored = HashMap::from([
+            ("v2Quotes".to_string(), r#"{"percent": 10}"#.to_string()),
+            ("shadowPricing".to_string(), "on".to_string()),
+            ("interpolation".to_string(), "{}".to_string()),
+        ]);
+        let overrides = parse_overrides(&stored);
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[&Flag::V2Quotes].percent, 10);
+        assert!(overrides[&Flag::V2Quotes].enabled);
+        assert_eq!(Flag::from_name("genderedRates"), Some(Flag::GenderedRates));
+        assert!(!Flag::V2Quotes.default_setting().enabled);
+    }
+}
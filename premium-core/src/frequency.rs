@@ -0,0 +1,109 @@
+use std::sync::OnceLock;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::PaymentFrequencyConfig;
+use crate::money::Money;
+
+static LOADINGS: OnceLock<PaymentFrequencyConfig> = OnceLock::new();
+
+/// Sets the installment loadings; the defaults apply when this is never
+/// called.
+pub fn configure(config: &PaymentFrequencyConfig) {
+    if LOADINGS.set(config.clone()).is_err() {
+        warn!("payment frequency loadings already configured");
+    }
+}
+
+fn loadings() -> &'static PaymentFrequencyConfig {
+    LOADINGS.get_or_init(PaymentFrequencyConfig::default)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PaymentFrequency {
+    Annual,
+    SemiAnnual,
+    Quarterly,
+    Monthly,
+}
+
+impl PaymentFrequency {
+    pub fn installments(self) -> u32 {
+        match self {
+            PaymentFrequency::Annual => 1,
+            PaymentFrequency::SemiAnnual => 2,
+            PaymentFrequency::Quarterly => 4,
+            PaymentFrequency::Monthly => 12,
+        }
+    }
+
+    fn loading(self, config: &PaymentFrequencyConfig) -> u32 {
+        match self {
+            PaymentFrequency::Annual => 0,
+            PaymentFrequency::SemiAnnual => config.semi_annual,
+            PaymentFrequency::Quarterly => config.quarterly,
+            PaymentFrequency::Monthly => config.monthly,
+        }
+    }
+}
+
+/// How an installment premium is made up, returned with quotes that name a
+/// `paymentFrequency`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Breakdown {
+    pub payment_frequency: PaymentFrequency,
+    pub base_premium: Money,
+    pub loading_percent: u32,
+    /// Base premium plus the frequency loading, paid over the year.
+    pub annualized_premium: Money,
+    pub installments: u32,
+    pub installment_premium: Money,
+}
+
+pub fn breakdown(frequency: PaymentFrequency, premium: &Money) -> Breakdown {
+    calculate(frequency, premium, loadings())
+}
+
+fn calculate(
+    frequency: PaymentFrequency,
+    premium: &Money,
+    config: &PaymentFrequencyConfig,
+) -> Breakdown {
+    let loading_percent = frequency.loading(config);
+    let annualized = premium.percent(100 + loading_percent);
+    let installments = frequency.installments();
+    Breakdown {
+        payment_frequency: frequency,
+        base_premium: premium.clone(),
+        loading_percent,
+        installment_premium: annualized.split(installments),
+        annualized_premium: annualized,
+        installments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    #[test]
+    fn test_breakdown() {
+        let config = PaymentFrequencyConfig::default();
+        let premium = Money::parse("750", Currency::new("INR")).unwrap();
+        let monthly = calculate(PaymentFrequency::Monthly, &premium, &config);
+        assert_eq!(monthly.annualized_premium.to_string(), "787.50");
+        assert_eq!(monthly.installments, 12);
+        assert_eq!(monthly.installment_premium.to_string(), "65.63");
+
+        let annual = calculate(PaymentFrequency::Annual, &premium, &config);
+        assert_eq!(annual.annualized_premium, premium);
+        assert_eq!(annual.installment_premium, premium);
+
+        let frequency: PaymentFrequency = serde_json::from_str("\"semi-annual\"").unwrap();
+        assert_eq!(frequency, PaymentFrequency::SemiAnnual);
+    }
+}
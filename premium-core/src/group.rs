@@ -0,0 +1,128 @@
+use std::sync::OnceLock;
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::GroupConfig;
+use crate::matrix::Gender;
+use crate::money::{Currency, Money};
+use crate::premium::{price, HealthRequest, PremiumError};
+
+static CONFIG: OnceLock<GroupConfig> = OnceLock::new();
+
+/// Sets the census limit and discount slabs; the defaults apply when this is
+/// never called.
+pub fn configure(config: &GroupConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("group pricing already configured");
+    }
+}
+
+fn config() -> &'static GroupConfig {
+    CONFIG.get_or_init(GroupConfig::default)
+}
+
+/// An employer census quoted under one product code.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupRequest {
+    pub code: String,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMember {
+    pub member_id: String,
+    pub sum_insured: String,
+    pub date_of_birth: String,
+    #[serde(default)]
+    pub gender: Option<Gender>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberPremium {
+    pub member_id: String,
+    pub premium: Money,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupResponse {
+    pub currency: String,
+    pub members: Vec<MemberPremium>,
+    /// Sum of the member premiums before the group discount.
+    pub gross_premium: Money,
+    pub discount_percent: u32,
+    pub discount: Money,
+    pub premium: Money,
+}
+
+/// Prices every member and applies the discount slab for the group size. The
+/// whole quote fails when any member cannot be priced.
+pub async fn calculate_group(input: GroupRequest) -> anyhow::Result<GroupResponse, PremiumError> {
+    let config = config();
+    if input.members.is_empty() || input.members.len() > config.max_members {
+        return Err(PremiumError::InvalidInput);
+    }
+
+    let mut members = Vec::with_capacity(input.members.len());
+    let currency = Currency::for_product(&input.code);
+    let mut gross = Money::from_minor(0, currency.clone());
+    for member in input.members {
+        let request = HealthRequest {
+            code: input.code.clone(),
+            sum_insured: member.sum_insured,
+            date_of_birth: member.date_of_birth,
+            gender: member.gender,
+            ..HealthRequest::default()
+        };
+        let premium = price(&request)
+            .await
+            .inspect_err(|err| {
+                error!("cannot price group member {} {}", member.member_id, err);
+            })?
+            .premium;
+        gross = gross + premium.clone();
+        members.push(MemberPremium {
+            member_id: member.member_id,
+            premium,
+        });
+    }
+
+    let discount_percent = discount_percent(config, members.len());
+    let discount = gross.percent(discount_percent);
+    Ok(GroupResponse {
+        currency: currency.code,
+        members,
+        premium: gross.clone() - discount.clone(),
+        gross_premium: gross,
+        discount_percent,
+        discount,
+    })
+}
+
+/// Percent off for the highest slab the group size reaches.
+fn discount_percent(config: &GroupConfig, members: usize) -> u32 {
+    config
+        .discounts
+        .iter()
+        .filter(|slab| members >= slab.min_members)
+        .max_by_key(|slab| slab.min_members)
+        .map_or(0, |slab| slab.percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discount_percent() {
+        let config = GroupConfig::default();
+        assert_eq!(discount_percent(&config, 10), 0);
+        assert_eq!(discount_percent(&config, 50), 5);
+        assert_eq!(discount_percent(&config, 249), 10);
+        assert_eq!(discount_percent(&config, 500), 15);
+    }
+}
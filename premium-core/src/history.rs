@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bands;
+use crate::matrix;
+use crate::money::{Currency, Money};
+use crate::premium::PremiumError;
+use crate::store;
+
+/// The matrix cell to trace: a product, sum insured and age band, with the
+/// deductible of a top-up product.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryQuery {
+    pub code: String,
+    pub sum_insured: String,
+    /// The matrix score, or the band as the workbook writes it, e.g. `36-45`.
+    pub band: String,
+    #[serde(default)]
+    pub deductible: Option<String>,
+}
+
+/// The cell's premium in one matrix version.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub version: u64,
+    pub loaded_at: String,
+    pub active: bool,
+    /// `None` when the version does not price the cell.
+    pub premium: Option<Money>,
+    /// Difference from the previous version that priced the cell.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<Money>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_percent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumHistory {
+    pub code: String,
+    pub sum_insured: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deductible: Option<String>,
+    pub band: Option<String>,
+    pub score: i32,
+    pub currency: String,
+    /// Oldest version first.
+    pub versions: Vec<HistoryEntry>,
+}
+
+/// The premium of one matrix cell in every loaded version, with the change
+/// from one version to the next. Fails with `InvalidInput` for a band the
+/// product does not have.
+pub async fn history(query: HistoryQuery) -> anyhow::Result<PremiumHistory, PremiumError> {
+    let score = score_of(&query.code, &query.band).ok_or(PremiumError::InvalidInput)?;
+    let band = matrix::band(&query.sum_insured, query.deductible.as_deref());
+    let currency = Currency::for_product(&query.code);
+    let mut versions = store::versions().await?.versions;
+    versions.sort_by_key(|version| version.version);
+
+    let mut premiums = Vec::with_capacity(versions.len());
+    for version in &versions {
+        let premium = match store::premium(Some(version.version), &query.code, &band, score).await {
+            Ok(premium) => Some(Money::parse(&premium.premium, currency.clone())?),
+            Err(PremiumError::RiskCalculation) => None,
+            Err(err) => return Err(err),
+        };
+        premiums.push(premium);
+    }
+    Ok(PremiumHistory {
+        band: bands::label(&query.code, score),
+        versions: entries(
+            versions
+                .into_iter()
+                .zip(premiums)
+                .map(|(version, premium)| {
+                    (version.version, version.loaded_at, version.active, premium)
+                }),
+        ),
+        currency: currency.code,
+        code: query.code,
+        sum_insured: query.sum_insured,
+        deductible: query.deductible,
+        score,
+    })
+}
+
+fn entries(
+    versions: impl Iterator<Item = (u64, String, bool, Option<Money>)>,
+) -> Vec<HistoryEntry> {
+    let mut previous: Option<Money> = None;
+    let mut entries = Vec::new();
+    for (version, loaded_at, active, premium) in versions {
+        let (change, change_percent) = match (&previous, &premium) {
+            (Some(before), Some(after)) => {
+                let percent = match before.minor() {
+                    0 => None,
+                    minor => Some(
+                        ((after.minor() - minor) as f64 * 10_000.0 / minor as f64).round() / 100.0,
+                    ),
+                };
+                (Some(after.clone() - before.clone()), percent)
+            }
+            _ => (None, None),
+        };
+        if premium.is_some() {
+            previous = premium.clone();
+        }
+        entries.push(HistoryEntry {
+            version,
+            loaded_at,
+            active,
+            premium,
+            change,
+            change_percent,
+        });
+    }
+    entries
+}
+
+/// The score `band` names: a score the product's bands yield, or the label
+/// of one of its bands.
+fn score_of(code: &str, band: &str) -> Option<i32> {
+    let scores = bands::scores(code);
+    match band.trim().parse::<i32>() {
+        Ok(score) => scores.contains(&score).then_some(score),
+        Err(_) => scores
+            .into_iter()
+            .find(|score| bands::label(code, *score).as_deref() == Some(band.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_entries() {
+        assert_eq!(score_of("1A", "3"), Some(3));
+        assert_eq!(score_of("1A", "36-45"), Some(2));
+        assert_eq!(score_of("1A", "71+"), Some(7));
+        assert_eq!(score_of("1A", "9"), None);
+
+        let inr = |amount: &str| Some(Money::parse(amount, Currency::new("INR")).unwrap());
+        let loaded = |day: u32| format!("2026-10-{:02}T00:00:00+05:30", day);
+        let entries = entries(
+            [
+                (1, loaded(1), false, inr("750")),
+                (2, loaded(2), false, None),
+                (3, loaded(3), true, inr("800")),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(entries[1].change, None);
+        assert_eq!(entries[2].change, inr("50"));
+        assert_eq!(entries[2].change_percent, Some(6.67));
+    }
+}
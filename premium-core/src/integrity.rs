@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::matrix::MatrixRow;
+use crate::premium::PremiumError;
+use crate::store::{self, KeyCounts, VersionPremiums};
+
+/// Row count and checksum of one product's rows in a matrix version.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductDigest {
+    pub rows: usize,
+    pub checksum: String,
+}
+
+/// Digests by product code.
+pub type Digests = BTreeMap<String, ProductDigest>;
+
+/// Digests rows as they are written. Each row's `key|score|premium` is
+/// hashed with SHA-256 and the hashes of a product are XORed, so the
+/// checksum does not depend on the order rows arrive in. A load rejects
+/// duplicate rows, which would otherwise cancel out.
+#[derive(Debug, Default)]
+pub struct Digester {
+    products: BTreeMap<String, (usize, [u8; 32])>,
+}
+
+impl Digester {
+    pub fn add(&mut self, key: &str, score: i32, premium: i32) {
+        let code = key.split(':').next().unwrap_or_default();
+        let (rows, checksum) = self.products.entry(code.to_string()).or_default();
+        let hash = Sha256::digest(format!("{}|{}|{}", key, score, premium));
+        for (byte, hashed) in checksum.iter_mut().zip(hash) {
+            *byte ^= hashed;
+        }
+        *rows += 1;
+    }
+
+    pub fn add_rows(&mut self, rows: &[MatrixRow]) {
+        for row in rows {
+            self.add(&row.key, row.score, row.premium);
+        }
+    }
+
+    pub fn finish(self) -> Digests {
+        self.products
+            .into_iter()
+            .map(|(code, (rows, checksum))| {
+                let checksum = hex::encode(checksum);
+                (code, ProductDigest { rows, checksum })
+            })
+            .collect()
+    }
+}
+
+/// The digests of premiums read back from storage.
+pub fn digest_premiums(premiums: &VersionPremiums) -> Digests {
+    let mut digester = Digester::default();
+    for ((key, score), premium) in premiums {
+        digester.add(key, *score, *premium);
+    }
+    digester.finish()
+}
+
+/// A product's digest recorded at load against the one counted now.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductCheck {
+    pub code: String,
+    /// Keys of the product in storage now; 0 when it is missing.
+    pub keys: usize,
+    pub loaded: Option<ProductDigest>,
+    pub live: Option<ProductDigest>,
+    pub matches: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub version: u64,
+    /// Whether digests were recorded when the version was loaded. Versions
+    /// loaded before they were only have their live digests.
+    pub recorded: bool,
+    pub consistent: bool,
+    pub products: Vec<ProductCheck>,
+}
+
+/// Recounts the active version and compares each product with what was
+/// loaded, so manual edits and partial loads show up. Fails with
+/// `InternalServer` when no version is active.
+pub async fn check() -> anyhow::Result<IntegrityReport, PremiumError> {
+    let Some(version) = store::versions().await?.active else {
+        error!("no premium matrix version is active to check");
+        return Err(PremiumError::InternalServer);
+    };
+    let loaded = store::digests(version).await?;
+    let live = digest_premiums(&store::version_premiums(version).await?);
+    let keys = store::key_counts().await?;
+    Ok(compare(version, loaded, live, &keys))
+}
+
+fn compare(
+    version: u64,
+    loaded: Option<Digests>,
+    mut live: Digests,
+    keys: &KeyCounts,
+) -> IntegrityReport {
+    let recorded = loaded.is_some();
+    let mut loaded = loaded.unwrap_or_default();
+    let codes: BTreeSet<String> = loaded
+        .keys()
+        .chain(live.keys())
+        .chain(keys.keys())
+        .cloned()
+        .collect();
+    let products: Vec<ProductCheck> = codes
+        .into_iter()
+        .map(|code| {
+            let (loaded, live) = (loaded.remove(&code), live.remove(&code));
+            ProductCheck {
+                matches: !recorded || loaded == live,
+                keys: keys.get(&code).copied().unwrap_or_default(),
+                code,
+                loaded,
+                live,
+            }
+        })
+        .collect();
+    IntegrityReport {
+        version,
+        recorded,
+        consistent: products.iter().all(|product| product.matches),
+        products,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digests() {
+        let premiums: VersionPremiums = [
+            (("1A:100000".to_string(), 1), 750),
+            (("1A:100000".to_string(), 2), 900),
+            (("2B:500000:25000".to_string(), 1), 300),
+        ]
+        .into_iter()
+        .collect();
+        let mut digester = Digester::default();
+        digester.add("1A:100000", 2, 900);
+        digester.add("2B:500000:25000", 1, 300);
+        digester.add("1A:100000", 1, 750);
+        let loaded = digester.finish();
+        assert_eq!(loaded, digest_premiums(&premiums));
+        assert_eq!(loaded["1A"].rows, 2);
+        assert_eq!(loaded["2B"].rows, 1);
+
+        let keys: KeyCounts = [("1A".to_string(), 1), ("2B".to_string(), 1)]
+            .into_iter()
+            .collect();
+        let report = compare(3, Some(loaded.clone()), digest_premiums(&premiums), &keys);
+        assert!(report.recorded && report.consistent);
+        assert_eq!(report.products[1].keys, 1);
+
+        let mut edited = premiums.clone();
+        edited.insert(("1A:100000".to_string(), 2), 950);
+        edited.remove(&("2B:500000:25000".to_string(), 1));
+        let report = compare(3, Some(loaded), digest_premiums(&edited), &KeyCounts::new());
+        assert!(!report.consistent);
+        assert_eq!(report.products[0].live.as_ref().unwrap().rows, 2);
+        assert!(!report.products[0].matches);
+        assert_eq!(report.products[1].live, None);
+        assert_eq!(report.products[1].keys, 0);
+
+        let report = compare(3, None, digest_premiums(&edited), &KeyCounts::new());
+        assert!(!report.recorded && report.consistent);
+    }
+}
@@ -0,0 +1,86 @@
+//! Premium calculation, matrix loading and matrix storage for the premium-rs
+//! service, usable without going through its HTTP API.
+//!
+//! Configure the process wide settings once, then quote:
+//!
+//! ```
+//! use premium_core::config::{MatrixConfig, StorageBackend, StorageConfig};
+//! use premium_core::premium::{calculate_premium, load, HealthRequest};
+//!
+//! # async fn quote() -> anyhow::Result<()> {
+//! let storage = StorageConfig {
+//!     backend: StorageBackend::Memory,
+//!     ..StorageConfig::default()
+//! };
+//! premium_core::store::configure(&storage)?;
+//! let matrix = MatrixConfig {
+//!     path: "premium_tables.xlsx".to_string(),
+//!     ..MatrixConfig::default()
+//! };
+//! # let matrix = MatrixConfig {
+//! #     path: concat!(env!("CARGO_MANIFEST_DIR"), "/../premium_tables.xlsx").to_string(),
+//! #     ..matrix
+//! # };
+//! assert!(load(&matrix, None).await?.valid);
+//! let premium = calculate_premium(HealthRequest {
+//!     code: "1A".to_string(),
+//!     sum_insured: "100000".to_string(),
+//!     date_of_birth: "1977-09-14".to_string(),
+//!     ..HealthRequest::default()
+//! })
+//! .await?;
+//! assert!(premium.parse::<f64>()? > 0.0);
+//! # Ok(())
+//! # }
+//! # async_std::task::block_on(quote()).unwrap();
+//! ```
+//!
+//! Ages and discount validity are reckoned from [`clock::today`]. Wrap a call
+//! in [`clock::scope`] with a [`clock::FixedClock`] to price as of a fixed
+//! date, e.g. in tests.
+
+pub mod add_ons;
+pub mod audit;
+pub mod bands;
+pub mod breaker;
+pub mod bulk;
+pub mod channels;
+pub mod chaos;
+pub mod clock;
+pub mod commission;
+pub mod compare;
+pub mod config;
+pub mod connection;
+pub mod diff;
+pub mod discounts;
+pub mod dob;
+pub mod eligibility;
+pub mod endorsement;
+pub mod expiry;
+pub mod explain;
+pub mod export;
+pub mod flags;
+pub mod frequency;
+pub mod group;
+pub mod history;
+pub mod integrity;
+pub mod maintenance;
+pub mod matrix;
+pub mod money;
+pub mod ped;
+pub mod preflight;
+pub mod premium;
+pub mod pricing_rules;
+pub mod products;
+pub mod quote_cache;
+pub mod rate_test;
+pub mod referral;
+pub mod retry;
+pub mod schema;
+pub mod short_period;
+pub mod single_flight;
+pub mod source;
+pub mod staleness;
+pub mod store;
+pub mod tenant;
+pub mod underwriting;
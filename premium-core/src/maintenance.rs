@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::warn;
+
+use crate::config::MaintenanceConfig;
+use crate::premium::PremiumError;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts the service in or out of maintenance mode.
+pub fn configure(config: &MaintenanceConfig) {
+    set(config.enabled);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Switches maintenance mode, in which nothing is priced while matrix loads
+/// and version changes carry on.
+pub fn set(enabled: bool) {
+    if ENABLED.swap(enabled, Ordering::Relaxed) != enabled {
+        warn!("maintenance mode {}", if enabled { "on" } else { "off" });
+    }
+}
+
+/// Fails with `PricingUnavailable` while in maintenance mode.
+pub fn check() -> anyhow::Result<(), PremiumError> {
+    match enabled() {
+        true => Err(PremiumError::PricingUnavailable),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_toggle() {
+        assert!(check().is_ok());
+        set(true);
+        assert!(matches!(check(), Err(PremiumError::PricingUnavailable)));
+        set(false);
+        assert!(check().is_ok());
+    }
+}
@@ -0,0 +1,689 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use calamine::{open_workbook_auto, open_workbook_auto_from_rs, DataType, Range, Reader, Sheets};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::config::MatrixConfig;
+use crate::premium::PremiumError;
+
+/// A worksheet as raw cell text, header row included.
+pub struct Sheet {
+    pub name: String,
+    pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatrixRow {
+    /// `code:band`, the lookup key of the row's premium band.
+    pub key: String,
+    pub code: String,
+    pub sum_insured: String,
+    /// Deductible of a top-up product's row.
+    pub deductible: Option<String>,
+    /// Gender of a gendered rate; unisex rows have none.
+    pub gender: Option<Gender>,
+    pub age_band: String,
+    pub premium: i32,
+    pub score: i32,
+}
+
+impl MatrixRow {
+    /// The key less the product code.
+    pub fn band(&self) -> &str {
+        &self.key[self.code.len() + 1..]
+    }
+}
+
+/// The premium band of a sum insured: the sum insured itself, or for top-up
+/// products `sumInsured:deductible`.
+pub fn band(sum_insured: &str, deductible: Option<&str>) -> String {
+    match deductible {
+        Some(deductible) => format!("{}:{}", sum_insured, deductible),
+        None => sum_insured.to_string(),
+    }
+}
+
+/// The gender a gendered rate is filed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Male,
+    Female,
+}
+
+impl Gender {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Gender::Male => "male",
+            Gender::Female => "female",
+        }
+    }
+
+    /// Reads a `gender` cell: `M`, `F`, `male` or `female` in any case.
+    pub fn from_cell(cell: &str) -> Option<Gender> {
+        match cell.to_ascii_lowercase().as_str() {
+            "m" | "male" => Some(Gender::Male),
+            "f" | "female" => Some(Gender::Female),
+            _ => None,
+        }
+    }
+}
+
+/// The band of the rates filed for `gender` within `band`.
+pub fn gendered(band: &str, gender: Gender) -> String {
+    format!("{}:{}", band, gender.as_str())
+}
+
+/// The sum insured, deductible and gender of a band, the reverse of [`band`]
+/// and [`gendered`].
+pub fn band_parts(band: &str) -> (&str, Option<&str>, Option<Gender>) {
+    let (band, gender) = match band.rsplit_once(':') {
+        Some((rest, "male")) => (rest, Some(Gender::Male)),
+        Some((rest, "female")) => (rest, Some(Gender::Female)),
+        _ => (band, None),
+    };
+    match band.split_once(':') {
+        Some((sum_insured, deductible)) => (sum_insured, Some(deductible), gender),
+        None => (band, None, gender),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RowError {
+    pub sheet: String,
+    pub row: usize,
+    pub message: String,
+}
+
+impl RowError {
+    fn new(sheet: &str, row: usize, message: &str) -> Self {
+        RowError {
+            sheet: sheet.to_string(),
+            row,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Outcome of a load or dry run. Row numbers in the error lists are 1-based
+/// positions in their sheet.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadReport {
+    pub version: Option<u64>,
+    pub dry_run: bool,
+    pub valid: bool,
+    pub rows_read: usize,
+    pub rows_loaded: usize,
+    pub rows_skipped: usize,
+    pub duplicate_keys: Vec<RowError>,
+    pub parse_errors: Vec<RowError>,
+    pub elapsed_ms: u128,
+    /// Rows written per second, 0 when nothing was written.
+    pub rows_per_sec: u64,
+}
+
+/// Rows written per second over `elapsed`, counted as at least a millisecond.
+pub fn rows_per_sec(rows: usize, elapsed: Duration) -> u64 {
+    (rows as f64 / elapsed.as_secs_f64().max(0.001)) as u64
+}
+
+pub struct ParsedMatrix {
+    pub rows: Vec<MatrixRow>,
+    pub rows_read: usize,
+    pub skipped: usize,
+    pub duplicates: Vec<RowError>,
+    pub errors: Vec<RowError>,
+}
+
+impl ParsedMatrix {
+    pub fn is_valid(&self) -> bool {
+        self.duplicates.is_empty() && self.errors.is_empty()
+    }
+
+    pub fn report(self, dry_run: bool, started: Instant) -> LoadReport {
+        LoadReport {
+            version: None,
+            dry_run,
+            valid: self.is_valid(),
+            rows_read: self.rows_read,
+            rows_loaded: 0,
+            rows_skipped: self.skipped,
+            duplicate_keys: self.duplicates,
+            parse_errors: self.errors,
+            elapsed_ms: started.elapsed().as_millis(),
+            rows_per_sec: 0,
+        }
+    }
+}
+
+/// A workbook opened for reading one sheet at a time.
+pub enum Workbook {
+    File(Sheets<BufReader<File>>),
+    Remote(Sheets<Cursor<Vec<u8>>>),
+}
+
+/// Opens the fetched workbook in `contents`, or without it the workbook at
+/// the configured path.
+pub fn open_workbook(
+    config: &MatrixConfig,
+    contents: Option<Vec<u8>>,
+) -> anyhow::Result<Workbook, PremiumError> {
+    match contents {
+        Some(contents) => match open_workbook_auto_from_rs(Cursor::new(contents)) {
+            Ok(work_book) => Ok(Workbook::Remote(work_book)),
+            Err(err) => {
+                error!("Error while opening remote workbook {}", err);
+                Err(PremiumError::InvalidInput)
+            }
+        },
+        None => match open_workbook_auto(Path::new(&config.path)) {
+            Ok(work_book) => Ok(Workbook::File(work_book)),
+            Err(err) => {
+                error!("Error while opening workbook {} {}", config.path, err);
+                Err(PremiumError::InternalServer)
+            }
+        },
+    }
+}
+
+impl Workbook {
+    /// The configured sheets, or every sheet when none are configured.
+    pub fn sheet_names(&self, config: &MatrixConfig) -> Vec<String> {
+        if !config.sheets.is_empty() {
+            return config.sheets.clone();
+        }
+        match self {
+            Workbook::File(work_book) => work_book.sheet_names().to_vec(),
+            Workbook::Remote(work_book) => work_book.sheet_names().to_vec(),
+        }
+    }
+
+    /// The cells of one sheet. Only this sheet is held in memory, and its
+    /// rows become text one at a time through [`row_text`].
+    pub fn sheet(&mut self, name: &str) -> anyhow::Result<Range<DataType>, PremiumError> {
+        let (range, source) = match self {
+            Workbook::File(work_book) => (work_book.worksheet_range(name), "workbook"),
+            Workbook::Remote(work_book) => (work_book.worksheet_range(name), "remote workbook"),
+        };
+        match range {
+            Some(Ok(range)) => Ok(range),
+            _ => {
+                error!("Worksheet {} not found in {}", name, source);
+                Err(PremiumError::InternalServer)
+            }
+        }
+    }
+}
+
+pub fn row_text(row: &[DataType]) -> Vec<String> {
+    row.iter().map(|value| value.to_string()).collect()
+}
+
+/// Reads the configured sheets of the workbook, or every sheet when none are
+/// configured, as text. `contents` holds a fetched workbook; without it the
+/// workbook at the configured path is read.
+pub fn read_workbook(
+    config: &MatrixConfig,
+    contents: Option<Vec<u8>>,
+) -> anyhow::Result<Vec<Sheet>, PremiumError> {
+    let mut work_book = open_workbook(config, contents)?;
+    let names = work_book.sheet_names(config);
+    let mut sheets = Vec::with_capacity(names.len());
+    for name in names {
+        let range = work_book.sheet(&name)?;
+        sheets.push(Sheet {
+            rows: range.rows().map(row_text).collect(),
+            name,
+        });
+    }
+    Ok(sheets)
+}
+
+/// Finds a column by header name ignoring case, spaces and underscores, so
+/// "Sum Insured" and "sum_insured" both find `sumInsured`.
+pub(crate) fn find_column(header: &[String], name: &str) -> Option<usize> {
+    header.iter().position(|cell| {
+        cell.chars()
+            .filter(|c| !c.is_whitespace() && *c != '_')
+            .collect::<String>()
+            .eq_ignore_ascii_case(name)
+    })
+}
+
+/// Positions of the matrix columns, located by header name.
+struct MatrixColumns {
+    code: Option<usize>,
+    sum_insured: usize,
+    premium: usize,
+    age_band: usize,
+    score: Option<usize>,
+    deductible: Option<usize>,
+    gender: Option<usize>,
+}
+
+impl MatrixColumns {
+    /// Locates the columns with `find_column`, returning the missing
+    /// required columns on failure.
+    fn from_header(
+        header: &[String],
+        code_required: bool,
+    ) -> Result<MatrixColumns, Vec<&'static str>> {
+        let find = |name: &str| find_column(header, name);
+        let code = find("code");
+        let sum_insured = find("sumInsured");
+        let premium = find("premium");
+        let age_band = find("ageBand");
+        match (
+            code.is_some() || !code_required,
+            sum_insured,
+            premium,
+            age_band,
+        ) {
+            (true, Some(sum_insured), Some(premium), Some(age_band)) => Ok(MatrixColumns {
+                code,
+                sum_insured,
+                premium,
+                age_band,
+                score: find("score"),
+                deductible: find("deductible"),
+                gender: find("gender"),
+            }),
+            _ => Err([
+                ("code", code.is_some() || !code_required),
+                ("sumInsured", sum_insured.is_some()),
+                ("premium", premium.is_some()),
+                ("ageBand", age_band.is_some()),
+            ]
+            .iter()
+            .filter(|(_, found)| !found)
+            .map(|(name, _)| *name)
+            .collect()),
+        }
+    }
+}
+
+/// Parses the sheet rows below each header, skipping blank ones and rejecting
+/// non numeric premiums, a repeated score for the same code and sum insured,
+/// and scores that do not increase down the sheet for a code and sum insured.
+/// Without a score column a row's score is its position among the rows of
+/// its code and sum insured. With `product_sheets` the sheet name is the
+/// product code and a code column is not needed.
+/// An optional `deductible` column prices top-up rows by sum insured and
+/// deductible together; rows left empty there are ordinary bands.
+/// An optional `gender` column files rates for one gender, keyed
+/// `code:band:male` or `code:band:female`; rows left empty there are the
+/// unisex rates quotes fall back to.
+pub fn parse_matrix(sheets: &[Sheet], product_sheets: bool) -> ParsedMatrix {
+    let mut parser = MatrixParser::new(product_sheets);
+    let mut rows = Vec::new();
+    for sheet in sheets {
+        let Some((header, body)) = sheet.rows.split_first() else {
+            parser.missing_header(&sheet.name);
+            continue;
+        };
+        if !parser.begin_sheet(&sheet.name, header) {
+            continue;
+        }
+        for row in body {
+            rows.extend(parser.row(row));
+        }
+    }
+    let mut parsed = parser.finish();
+    parsed.rows = rows;
+    parsed
+}
+
+/// The checks of [`parse_matrix`] applied one row at a time, so a workbook
+/// can be parsed while it is read and its rows handed on in chunks. Only the
+/// last score of each code and sum insured is kept between rows.
+pub struct MatrixParser {
+    product_sheets: bool,
+    sheet: String,
+    columns: Option<MatrixColumns>,
+    row_number: usize,
+    rows_read: usize,
+    skipped: usize,
+    duplicates: Vec<RowError>,
+    errors: Vec<RowError>,
+    last_scores: HashMap<String, i32>,
+    band_counts: HashMap<String, i32>,
+}
+
+impl MatrixParser {
+    pub fn new(product_sheets: bool) -> Self {
+        MatrixParser {
+            product_sheets,
+            sheet: String::new(),
+            columns: None,
+            row_number: 1,
+            rows_read: 0,
+            skipped: 0,
+            duplicates: Vec::new(),
+            errors: Vec::new(),
+            last_scores: HashMap::new(),
+            band_counts: HashMap::new(),
+        }
+    }
+
+    /// False once any row has been rejected, so callers can stop writing.
+    pub fn is_valid(&self) -> bool {
+        self.duplicates.is_empty() && self.errors.is_empty()
+    }
+
+    /// Every `code:band` key seen so far.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.last_scores.keys()
+    }
+
+    pub fn missing_header(&mut self, sheet: &str) {
+        self.columns = None;
+        self.errors
+            .push(RowError::new(sheet, 1, "header row is missing"));
+    }
+
+    /// Starts a sheet, returning false when its header lacks a required
+    /// column; rows passed before the next sheet starts are then ignored.
+    pub fn begin_sheet(&mut self, name: &str, header: &[String]) -> bool {
+        self.sheet = name.to_string();
+        self.row_number = 1;
+        self.columns = match MatrixColumns::from_header(header, !self.product_sheets) {
+            Ok(columns) => Some(columns),
+            Err(missing) => {
+                self.errors.push(RowError::new(
+                    name,
+                    1,
+                    &format!("missing required columns: {}", missing.join(", ")),
+                ));
+                None
+            }
+        };
+        self.columns.is_some()
+    }
+
+    /// Parses the next row of the current sheet.
+    pub fn row(&mut self, row: &[String]) -> Option<MatrixRow> {
+        let columns = self.columns.as_ref()?;
+        self.row_number += 1;
+        self.rows_read += 1;
+        let error = |message: &str| RowError::new(&self.sheet, self.row_number, message);
+        let cell = |column: usize| row.get(column).map_or("", |value| value.trim());
+        if row.iter().all(|value| value.trim().is_empty()) {
+            self.skipped += 1;
+            return None;
+        }
+        let code = match (self.product_sheets, columns.code) {
+            (false, Some(column)) => cell(column),
+            _ => self.sheet.as_str(),
+        };
+        let deductible = columns
+            .deductible
+            .map(cell)
+            .filter(|deductible| !deductible.is_empty());
+        let gender = match columns.gender.map(cell).filter(|gender| !gender.is_empty()) {
+            Some(text) => match Gender::from_cell(text) {
+                Some(gender) => Some(gender),
+                None => {
+                    let error = error("gender is not M or F");
+                    self.errors.push(error);
+                    return None;
+                }
+            },
+            None => None,
+        };
+        let band = band(cell(columns.sum_insured), deductible);
+        let key = match gender {
+            Some(gender) => format!("{}:{}", code, gendered(&band, gender)),
+            None => format!("{}:{}", code, band),
+        };
+        if cell(columns.age_band).is_empty() {
+            let error = error("ageBand is empty");
+            self.errors.push(error);
+            return None;
+        }
+        let premium = match cell(columns.premium).parse::<i32>() {
+            Ok(premium) => premium,
+            Err(_) => {
+                let error = error("premium is not a whole number");
+                self.errors.push(error);
+                return None;
+            }
+        };
+        let band = self.band_counts.entry(key.clone()).or_insert(0);
+        *band += 1;
+        let score = match columns.score {
+            Some(column) => match cell(column).parse::<i32>() {
+                Ok(score) => score,
+                Err(_) => {
+                    let error = error("score is not a whole number");
+                    self.errors.push(error);
+                    return None;
+                }
+            },
+            None => *band,
+        };
+        match self.last_scores.get(&key) {
+            Some(last) if *last == score => {
+                let error = error(&format!("duplicate score {} for {}", score, key));
+                self.duplicates.push(error);
+                return None;
+            }
+            Some(last) if *last > score => {
+                let error = error(&format!(
+                    "score {} for {} follows higher score {}",
+                    score, key, last
+                ));
+                self.errors.push(error);
+                return None;
+            }
+            _ => {}
+        }
+        let row = MatrixRow {
+            key: key.clone(),
+            code: code.to_string(),
+            sum_insured: cell(columns.sum_insured).to_string(),
+            deductible: deductible.map(str::to_string),
+            gender,
+            age_band: cell(columns.age_band).to_string(),
+            premium,
+            score,
+        };
+        self.last_scores.insert(key, score);
+        Some(row)
+    }
+
+    /// The counts and rejected rows; `rows` is left empty.
+    pub fn finish(self) -> ParsedMatrix {
+        ParsedMatrix {
+            rows: Vec::new(),
+            rows_read: self.rows_read,
+            skipped: self.skipped,
+            duplicates: self.duplicates,
+            errors: self.errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(name: &str, rows: &[&[&str]]) -> Sheet {
+        Sheet {
+            name: name.to_string(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|value| value.to_string()).collect())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_matrix() {
+        let sheets = [sheet(
+            "matrix",
+            &[
+                &["code", "sumInsured", "ageBand", "premium", "score"],
+                &["1A", "100000", "18-30", "250", "1"],
+                &["1A", "100000", "31-44", "500", "2"],
+                &["1A", "100000", "45-56", "abc", "3"],
+                &["1A", "100000", "56-61", "750", "2"],
+                &["", "", "", "", ""],
+                &["1A", "100000", "62-66", "950", "1"],
+                &["2A", "100000", "18-30", "300", "1"],
+            ],
+        )];
+
+        let parsed = parse_matrix(&sheets, false);
+        assert_eq!(parsed.rows.len(), 3);
+        assert_eq!(parsed.rows_read, 7);
+        assert_eq!(parsed.skipped, 1);
+        let error_rows: Vec<usize> = parsed.errors.iter().map(|error| error.row).collect();
+        assert_eq!(error_rows, vec![4, 7]);
+        let duplicate_rows: Vec<usize> = parsed.duplicates.iter().map(|error| error.row).collect();
+        assert_eq!(duplicate_rows, vec![5]);
+    }
+
+    #[test]
+    fn test_load_report() {
+        let sheets = [sheet(
+            "matrix",
+            &[
+                &["code", "sumInsured", "ageBand", "premium", "score"],
+                &["1A", "100000", "18-30", "250", "1"],
+                &["", "", "", "", ""],
+                &["1A", "100000", "18-30", "260", "1"],
+                &["1A", "100000", "31-44", "n/a", "2"],
+            ],
+        )];
+
+        let report = parse_matrix(&sheets, false).report(true, Instant::now());
+        let report = serde_json::to_value(&report).unwrap();
+        assert_eq!(report["dryRun"], true);
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["version"], serde_json::Value::Null);
+        assert_eq!(report["rowsRead"], 4);
+        assert_eq!(report["rowsLoaded"], 0);
+        assert_eq!(report["rowsSkipped"], 1);
+        assert_eq!(
+            report["duplicateKeys"],
+            serde_json::json!([{
+                "sheet": "matrix",
+                "row": 4,
+                "message": "duplicate score 1 for 1A:100000"
+            }])
+        );
+        assert_eq!(report["parseErrors"][0]["row"], 5);
+        assert_eq!(
+            report["parseErrors"][0]["message"],
+            "premium is not a whole number"
+        );
+    }
+
+    #[test]
+    fn test_parse_matrix_by_column_name() {
+        let sheets = [sheet(
+            "matrix",
+            &[
+                &["Premium", "Plan", "Age Band", "Code", "sum_insured"],
+                &["250", "gold", "18-30", "1A", "100000"],
+                &["500", "gold", "31-44", "1A", "100000"],
+            ],
+        )];
+
+        let parsed = parse_matrix(&sheets, false);
+        assert!(parsed.is_valid());
+        assert_eq!(parsed.rows[1].key, "1A:100000");
+        assert_eq!(parsed.rows[1].premium, 500);
+        assert_eq!(parsed.rows[1].score, 2);
+    }
+
+    #[test]
+    fn test_parse_matrix_missing_columns() {
+        let sheets = [sheet("matrix", &[&["code", "premium"], &["1A", "250"]])];
+
+        let parsed = parse_matrix(&sheets, false);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(
+            parsed.errors[0].message,
+            "missing required columns: sumInsured, ageBand"
+        );
+    }
+
+    #[test]
+    fn test_parse_product_sheets() {
+        let header: &[&str] = &["sumInsured", "ageBand", "premium"];
+        let sheets = [
+            sheet("1A", &[header, &["100000", "18-30", "250"]]),
+            sheet("2A", &[header, &["100000", "18-30", "300"]]),
+        ];
+
+        let parsed = parse_matrix(&sheets, true);
+        assert!(parsed.is_valid());
+        let keys: Vec<&str> = parsed.rows.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(keys, vec!["1A:100000", "2A:100000"]);
+    }
+
+    #[test]
+    fn test_parse_top_up_deductibles() {
+        let sheets = [sheet(
+            "matrix",
+            &[
+                &["code", "sumInsured", "deductible", "ageBand", "premium"],
+                &["TU", "500000", "300000", "18-30", "120"],
+                &["TU", "500000", "500000", "18-30", "90"],
+                &["1A", "100000", "", "18-30", "250"],
+            ],
+        )];
+
+        let parsed = parse_matrix(&sheets, false);
+        assert!(parsed.is_valid());
+        let keys: Vec<&str> = parsed.rows.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["TU:500000:300000", "TU:500000:500000", "1A:100000"]
+        );
+        assert_eq!(parsed.rows[1].band(), "500000:500000");
+        assert_eq!(parsed.rows[1].score, 1);
+    }
+
+    #[test]
+    fn test_parse_gendered_rates() {
+        let sheets = [sheet(
+            "matrix",
+            &[
+                &["code", "sumInsured", "gender", "ageBand", "premium"],
+                &["1A", "100000", "M", "18-30", "260"],
+                &["1A", "100000", "female", "18-30", "240"],
+                &["1A", "100000", "", "18-30", "250"],
+                &["1A", "100000", "X", "18-30", "250"],
+            ],
+        )];
+
+        let parsed = parse_matrix(&sheets, false);
+        let keys: Vec<&str> = parsed.rows.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["1A:100000:male", "1A:100000:female", "1A:100000"]
+        );
+        assert_eq!(parsed.rows[1].gender, Some(Gender::Female));
+        assert_eq!(parsed.errors[0].message, "gender is not M or F");
+        assert_eq!(
+            band_parts("500000:300000:male"),
+            ("500000", Some("300000"), Some(Gender::Male))
+        );
+        assert_eq!(band_parts("100000"), ("100000", None, None));
+    }
+
+    #[test]
+    fn test_rows_per_sec() {
+        assert_eq!(rows_per_sec(5000, Duration::from_millis(2500)), 2000);
+        assert_eq!(rows_per_sec(0, Duration::from_secs(3)), 0);
+        assert_eq!(rows_per_sec(10, Duration::ZERO), 10_000);
+    }
+}
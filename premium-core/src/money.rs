@@ -0,0 +1,253 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::sync::OnceLock;
+
+use log::warn;
+use serde::{Serialize, Serializer};
+
+use crate::config::CurrencyConfig;
+use crate::premium::PremiumError;
+use crate::products;
+
+static CONFIG: OnceLock<CurrencyConfig> = OnceLock::new();
+
+/// Sets the default and per product currencies; everything is priced in INR
+/// when this is never called.
+pub fn configure(config: &CurrencyConfig) {
+    if CONFIG.set(config.clone()).is_err() {
+        warn!("currencies already configured");
+    }
+}
+
+fn config() -> &'static CurrencyConfig {
+    CONFIG.get_or_init(CurrencyConfig::default)
+}
+
+/// An ISO 4217 currency and the number of decimals its amounts carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Currency {
+    pub code: String,
+    pub minor_units: u32,
+}
+
+impl Currency {
+    pub fn new(code: &str) -> Self {
+        let minor_units = config()
+            .minor_units
+            .get(code)
+            .copied()
+            .unwrap_or_else(|| iso_minor_units(code));
+        Currency {
+            code: code.to_string(),
+            minor_units,
+        }
+    }
+
+    /// The currency the product's matrix premiums are priced in, from the
+    /// product registry when it names one.
+    pub fn for_product(product_code: &str) -> Self {
+        if let Some(code) = products::settings(product_code).currency {
+            return Currency::new(&code);
+        }
+        let config = config();
+        let code = config.products.get(product_code).unwrap_or(&config.default);
+        Currency::new(code)
+    }
+
+    fn scale(&self) -> f64 {
+        10_f64.powi(self.minor_units as i32)
+    }
+}
+
+fn iso_minor_units(code: &str) -> u32 {
+    match code {
+        "CLP" | "ISK" | "JPY" | "KRW" | "UGX" | "VND" | "XAF" | "XOF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// An amount held in whole minor units of its currency, so arithmetic
+/// rounds to what can actually be charged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn from_minor(minor: i64, currency: Currency) -> Self {
+        Money { minor, currency }
+    }
+
+    /// Reads a decimal amount such as a matrix premium, rounding it to the
+    /// currency's minor units.
+    pub fn parse(amount: &str, currency: Currency) -> anyhow::Result<Self, PremiumError> {
+        let value: f64 = amount
+            .trim()
+            .parse()
+            .map_err(|_| PremiumError::RiskCalculation)?;
+        if !value.is_finite() {
+            return Err(PremiumError::RiskCalculation);
+        }
+        Ok(Money::from_minor(
+            (value * currency.scale()).round() as i64,
+            currency,
+        ))
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> &Currency {
+        &self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    /// `percent` of the amount, rounded half away from zero.
+    pub fn percent(&self, percent: u32) -> Money {
+        self.scale(percent as f64 / 100.0)
+    }
+
+    pub fn scale(&self, ratio: f64) -> Money {
+        let minor = (self.minor as f64 * ratio).round() as i64;
+        Money::from_minor(minor, self.currency.clone())
+    }
+
+    /// The nearest multiple of `units` whole currency units, halves rounded
+    /// up.
+    pub fn round_to(&self, units: u32) -> Money {
+        let step = units as i64 * 10_i64.pow(self.currency.minor_units);
+        if step == 0 {
+            return self.clone();
+        }
+        let minor = (self.minor + step / 2).div_euclid(step) * step;
+        Money::from_minor(minor, self.currency.clone())
+    }
+
+    /// The smallest multiple of `units` whole currency units not below the
+    /// amount.
+    pub fn round_up_to(&self, units: u32) -> Money {
+        let down = self.round_down_to(units);
+        match down.minor == self.minor {
+            true => down,
+            false => {
+                let step = units as i64 * 10_i64.pow(self.currency.minor_units);
+                Money::from_minor(down.minor + step, self.currency.clone())
+            }
+        }
+    }
+
+    /// The largest multiple of `units` whole currency units not above the
+    /// amount.
+    pub fn round_down_to(&self, units: u32) -> Money {
+        let step = units as i64 * 10_i64.pow(self.currency.minor_units);
+        if step == 0 {
+            return self.clone();
+        }
+        Money::from_minor(self.minor.div_euclid(step) * step, self.currency.clone())
+    }
+
+    /// One of `parts` equal installments, rounded.
+    pub fn split(&self, parts: u32) -> Money {
+        self.scale(1.0 / parts as f64)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        debug_assert_eq!(self.currency, other.currency);
+        Money::from_minor(self.minor + other.minor, self.currency)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        debug_assert_eq!(self.currency, other.currency);
+        Money::from_minor(self.minor - other.minor, self.currency)
+    }
+}
+
+/// Whole amounts print without decimals, as matrix premiums always have;
+/// anything else carries every minor digit, e.g. `787.50`.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10_i64.pow(self.currency.minor_units);
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let (major, fraction) = (self.minor.abs() / scale, self.minor.abs() % scale);
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, major);
+        }
+        let width = self.currency.minor_units as usize;
+        write!(f, "{}{}.{:0width$}", sign, major, fraction, width = width)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minor_units() {
+        let inr = Currency::new("INR");
+        let jpy = Currency::new("JPY");
+        let kwd = Currency::new("KWD");
+        assert_eq!(
+            (inr.minor_units, jpy.minor_units, kwd.minor_units),
+            (2, 0, 3)
+        );
+
+        assert_eq!(Money::parse("750", inr.clone()).unwrap().to_string(), "750");
+        assert_eq!(
+            Money::parse("787.5", inr.clone()).unwrap().to_string(),
+            "787.50"
+        );
+        assert_eq!(
+            Money::parse("787.5", jpy.clone()).unwrap().to_string(),
+            "788"
+        );
+        assert_eq!(Money::parse("1.2345", kwd).unwrap().to_string(), "1.235");
+        assert_eq!(Money::from_minor(-5, inr.clone()).to_string(), "-0.05");
+        assert!(Money::parse("n/a", inr.clone()).is_err());
+
+        let premium = Money::parse("750", inr).unwrap();
+        assert_eq!(premium.percent(105).split(12).to_string(), "65.63");
+        assert_eq!(
+            Money::parse("9999", jpy.clone())
+                .unwrap()
+                .percent(15)
+                .to_string(),
+            "1500"
+        );
+        assert_eq!(premium.round_to(100).to_string(), "800");
+        assert_eq!(
+            Money::parse("1249.99", jpy)
+                .unwrap()
+                .round_to(50)
+                .to_string(),
+            "1250"
+        );
+        let amount = Money::parse("12301.40", Currency::new("INR")).unwrap();
+        assert_eq!(amount.round_up_to(100).to_string(), "12400");
+        assert_eq!(amount.round_down_to(10).to_string(), "12300");
+        assert_eq!(premium.round_up_to(50).to_string(), "750");
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::config::{MatrixConfig, PedConfig};
+use crate::matrix::{find_column, read_workbook, RowError, Sheet};
+use crate::premium::PremiumError;
+
+/// What declaring a pre-existing condition does to a quote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PedAction {
+    /// Percent added to the premium.
+    Load(u32),
+    /// The quote needs an underwriter's decision.
+    Refer,
+    Decline,
+}
+
+/// A condition code from the PED rules worksheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PedRule {
+    pub condition: String,
+    pub action: PedAction,
+    /// Waiting period before claims for the condition are covered.
+    pub waiting_months: Option<u32>,
+}
+
+/// A declared condition priced into a quote, itemized in the response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConditionLoading {
+    pub condition: String,
+    pub loading_percent: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waiting_months: Option<u32>,
+}
+
+/// The declared conditions sorted by what their rules do with them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PedAssessment {
+    pub loadings: Vec<ConditionLoading>,
+    /// Conditions to refer, including those the worksheet does not list.
+    pub referred: Vec<String>,
+    pub declined: Vec<String>,
+}
+
+static RULES: RwLock<Option<HashMap<String, PedRule>>> = RwLock::new(None);
+
+/// Reads the PED rules from the configured worksheet, replacing any loaded
+/// before. Nothing is loaded when `ped.path` is not set, and every declared
+/// condition is then referred.
+pub fn load(config: &PedConfig, matrix: &MatrixConfig) -> anyhow::Result<usize, PremiumError> {
+    let Some(path) = &config.path else {
+        return Ok(0);
+    };
+    let workbook = MatrixConfig {
+        path: path.clone(),
+        sheets: vec![config.sheet.clone()],
+        ..matrix.clone()
+    };
+    let sheets = read_workbook(&workbook, None)?;
+    let rules = match sheets.first().map(parse_rules) {
+        Some(Ok(rules)) => rules,
+        Some(Err(errors)) => {
+            for err in &errors {
+                error!("ped sheet {} row {} {}", err.sheet, err.row, err.message);
+            }
+            return Err(PremiumError::InvalidInput);
+        }
+        None => HashMap::new(),
+    };
+    info!("loaded {} ped rules from {}", rules.len(), path);
+    let count = rules.len();
+    *RULES.write().unwrap_or_else(|err| err.into_inner()) = Some(rules);
+    Ok(count)
+}
+
+/// Parses `condition`, `action` (`load`, `refer` or `decline`), the
+/// `loadingPercent` of `load` rows and an optional `waitingMonths` column.
+/// Condition codes are matched case insensitively.
+pub fn parse_rules(sheet: &Sheet) -> Result<HashMap<String, PedRule>, Vec<RowError>> {
+    let error = |row: usize, message: &str| RowError {
+        sheet: sheet.name.clone(),
+        row,
+        message: message.to_string(),
+    };
+    let Some((header, body)) = sheet.rows.split_first() else {
+        return Err(vec![error(1, "header row is missing")]);
+    };
+    let find = |name| find_column(header, name);
+    let (Some(condition), Some(action)) = (find("condition"), find("action")) else {
+        return Err(vec![error(
+            1,
+            "missing required columns: condition, action",
+        )]);
+    };
+    let (loading, waiting) = (find("loadingPercent"), find("waitingMonths"));
+
+    let mut rules = HashMap::new();
+    let mut errors = Vec::new();
+    for (index, row) in body.iter().enumerate() {
+        let number = index + 2;
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .map_or("", |value| value.trim())
+        };
+        if row.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let action = match (
+            cell(Some(action)).to_ascii_lowercase().as_str(),
+            cell(loading).parse::<u32>(),
+        ) {
+            ("load", Ok(percent)) => PedAction::Load(percent),
+            ("refer", _) => PedAction::Refer,
+            ("decline", _) => PedAction::Decline,
+            _ => {
+                errors.push(error(
+                    number,
+                    "action must be refer, decline or load with a whole loadingPercent",
+                ));
+                continue;
+            }
+        };
+        let waiting_months = match cell(waiting) {
+            "" => None,
+            months => match months.parse() {
+                Ok(months) => Some(months),
+                Err(_) => {
+                    errors.push(error(number, "waitingMonths is not a whole number"));
+                    continue;
+                }
+            },
+        };
+        let rule = PedRule {
+            condition: cell(Some(condition)).to_ascii_uppercase(),
+            action,
+            waiting_months,
+        };
+        if rule.condition.is_empty() {
+            errors.push(error(number, "condition is empty"));
+        } else if rules.contains_key(&rule.condition) {
+            errors.push(error(
+                number,
+                &format!("duplicate condition {}", rule.condition),
+            ));
+        } else {
+            rules.insert(rule.condition.clone(), rule);
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(rules),
+        false => Err(errors),
+    }
+}
+
+/// Looks up the rule of each declared condition. Conditions the worksheet
+/// does not list are referred.
+pub fn assess(conditions: &[String]) -> PedAssessment {
+    if conditions.is_empty() {
+        return PedAssessment::default();
+    }
+    let rules = RULES.read().unwrap_or_else(|err| err.into_inner());
+    let empty = HashMap::new();
+    assess_with(rules.as_ref().unwrap_or(&empty), conditions)
+}
+
+fn assess_with(rules: &HashMap<String, PedRule>, conditions: &[String]) -> PedAssessment {
+    let mut assessment = PedAssessment::default();
+    for condition in conditions {
+        let code = condition.to_ascii_uppercase();
+        match rules.get(&code) {
+            Some(rule) => match rule.action {
+                PedAction::Load(percent) => assessment.loadings.push(ConditionLoading {
+                    condition: code,
+                    loading_percent: percent,
+                    waiting_months: rule.waiting_months,
+                }),
+                PedAction::Refer => assessment.referred.push(code),
+                PedAction::Decline => assessment.declined.push(code),
+            },
+            None => {
+                warn!("declared condition {} has no ped rule", code);
+                assessment.referred.push(code);
+            }
+        }
+    }
+    assessment
+}
+
+/// The loadings together, as one percent added to the premium.
+pub fn loading_percent(loadings: &[ConditionLoading]) -> u32 {
+    loadings.iter().map(|loading| loading.loading_percent).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_assess() {
+        let rows = [
+            vec!["condition", "action", "loadingPercent", "waitingMonths"],
+            vec!["DIAB", "load", "25", "24"],
+            vec!["htn", "Load", "10", ""],
+            vec!["CANC", "decline", "", ""],
+            vec!["CKD", "refer", "", ""],
+        ];
+        let sheet = Sheet {
+            name: "ped_rules".to_string(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .collect(),
+        };
+        let rules = parse_rules(&sheet).unwrap();
+        assert_eq!(rules["HTN"].action, PedAction::Load(10));
+
+        let codes = |codes: &[&str]| {
+            codes
+                .iter()
+                .map(|code| code.to_string())
+                .collect::<Vec<_>>()
+        };
+        let loadings = assess_with(&rules, &codes(&["diab", "HTN"])).loadings;
+        assert_eq!(loading_percent(&loadings), 35);
+        assert_eq!(loadings[0].waiting_months, Some(24));
+        let assessment = assess_with(&rules, &codes(&["DIAB", "CKD", "ASTHMA", "CANC"]));
+        assert_eq!(assessment.referred, ["CKD", "ASTHMA"]);
+        assert_eq!(assessment.declined, ["CANC"]);
+
+        let bad = Sheet {
+            rows: vec![sheet.rows[0].clone(), codes(&["X", "load", "", ""])],
+            ..sheet
+        };
+        assert_eq!(parse_rules(&bad).unwrap_err()[0].row, 2);
+    }
+}
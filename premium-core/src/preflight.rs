@@ -0,0 +1,158 @@
+use std::sync::RwLock;
+use std::time::Instant;
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::bands;
+use crate::config::PreflightConfig;
+use crate::premium::{matrix_premium, MatrixVersions};
+use crate::store;
+
+/// Latest preflight outcome; `None` until the first run finishes.
+static REPORT: RwLock<Option<PreflightReport>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreflightReport {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+    pub elapsed_ms: u128,
+}
+
+/// The outcome of the latest run, for the readiness probe.
+pub fn report() -> Option<PreflightReport> {
+    REPORT.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// Checks storage is reachable and the expected matrix version is loaded,
+/// then warms the premium cache for the hot products. The report is logged
+/// and kept for [`report`].
+pub async fn run(config: &PreflightConfig) -> PreflightReport {
+    let started = Instant::now();
+    let mut checks = Vec::new();
+    match store::versions().await {
+        Ok(versions) => {
+            checks.push(Check {
+                name: "storage",
+                passed: true,
+                detail: "reachable".to_string(),
+            });
+            checks.push(version_check(&versions, config.expected_version));
+        }
+        Err(err) => checks.push(Check {
+            name: "storage",
+            passed: false,
+            detail: err.to_string(),
+        }),
+    }
+    if checks.iter().all(|check| check.passed) && !config.hot_products.is_empty() {
+        checks.push(warm(config).await);
+    }
+
+    let report = PreflightReport {
+        ready: checks.iter().all(|check| check.passed),
+        checks,
+        elapsed_ms: started.elapsed().as_millis(),
+    };
+    let summary: Vec<String> = report
+        .checks
+        .iter()
+        .map(|check| {
+            let outcome = if check.passed { "ok" } else { "failed" };
+            format!("{} {} ({})", check.name, outcome, check.detail)
+        })
+        .collect();
+    if report.ready {
+        info!("preflight passed: {}", summary.join(", "));
+    } else {
+        warn!("preflight failed: {}", summary.join(", "));
+    }
+    *REPORT.write().unwrap_or_else(|err| err.into_inner()) = Some(report.clone());
+    report
+}
+
+fn version_check(versions: &MatrixVersions, expected: Option<u64>) -> Check {
+    let (passed, detail) = match (versions.active, expected) {
+        (None, _) => (false, "no matrix version is active".to_string()),
+        (Some(active), None) => (true, format!("version {} active", active)),
+        (Some(active), Some(expected)) => {
+            if versions
+                .versions
+                .iter()
+                .any(|version| version.version == expected)
+            {
+                (
+                    true,
+                    format!("version {} loaded, {} active", expected, active),
+                )
+            } else {
+                (false, format!("version {} not loaded", expected))
+            }
+        }
+    };
+    Check {
+        name: "version",
+        passed,
+        detail,
+    }
+}
+
+async fn warm(config: &PreflightConfig) -> Check {
+    let mut warmed = 0;
+    let mut missing = Vec::new();
+    for product in &config.hot_products {
+        for sum_insured in &product.sums_insured {
+            for score in bands::scores(&product.code) {
+                match matrix_premium(None, &product.code, sum_insured, score).await {
+                    Ok(_) => warmed += 1,
+                    Err(_) => missing.push(format!("{}:{}:{}", product.code, sum_insured, score)),
+                }
+            }
+        }
+    }
+    let detail = if missing.is_empty() {
+        format!("{} premiums cached", warmed)
+    } else {
+        format!("{} premiums cached, missing {}", warmed, missing.join(" "))
+    };
+    Check {
+        name: "warm",
+        passed: missing.is_empty(),
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::premium::MatrixVersion;
+
+    #[test]
+    fn test_version_check() {
+        let versions = |active| MatrixVersions {
+            active,
+            versions: vec![MatrixVersion {
+                version: 3,
+                loaded_at: String::new(),
+                rows: 7,
+                active: active == Some(3),
+                activated_at: Vec::new(),
+            }],
+        };
+        assert!(!version_check(&versions(None), None).passed);
+        assert!(version_check(&versions(Some(3)), None).passed);
+        assert!(version_check(&versions(Some(3)), Some(3)).passed);
+        let missing = version_check(&versions(Some(3)), Some(4));
+        assert!(!missing.passed);
+        assert_eq!(missing.detail, "version 4 not loaded");
+    }
+}
@@ -0,0 +1,1273 @@
+use std::ops::Not;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate};
+use log::error;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::add_ons::{self, AddOnPremium, OptionalCover};
+use crate::audit::{self, AuditRecord, Caller};
+use crate::bands;
+use crate::channels;
+use crate::clock;
+use crate::commission::{self, Commission};
+use crate::config::MatrixConfig;
+use crate::discounts::{self, AppliedDiscount};
+use crate::dob;
+use crate::eligibility;
+use crate::expiry;
+use crate::flags::{self, Flag};
+use crate::frequency::{self, Breakdown, PaymentFrequency};
+use crate::integrity::Digester;
+use crate::maintenance;
+use crate::matrix::{
+    self, open_workbook, row_text, rows_per_sec, Gender, LoadReport, MatrixParser, MatrixRow,
+    ParsedMatrix,
+};
+use crate::money::{Currency, Money};
+use crate::ped::{self, ConditionLoading};
+use crate::pricing_rules::{self, Facts, RuleAdjustment};
+use crate::products;
+use crate::quote_cache;
+use crate::rate_test;
+use crate::referral;
+use crate::short_period;
+use crate::single_flight::SingleFlight;
+use crate::source;
+use crate::store::{self, MatrixPremium, VersionWriter};
+use crate::underwriting;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HealthRequest {
+    pub code: String,
+    #[serde(rename = "sumInsured")]
+    pub sum_insured: String,
+    /// In one of the configured formats; may be left empty when `age` is
+    /// given.
+    #[serde(rename = "dateOfBirth", default)]
+    pub date_of_birth: String,
+    /// Age stated instead of a date of birth, for feeds that only carry the
+    /// age. Must agree with `dateOfBirth` when both are given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+    /// Policy period for short-period quotes; the annual premium is quoted
+    /// when both are absent.
+    #[serde(rename = "policyStartDate", default)]
+    pub policy_start_date: Option<String>,
+    #[serde(rename = "policyEndDate", default)]
+    pub policy_end_date: Option<String>,
+    /// Codes from the discounts worksheet to take off the premium.
+    #[serde(rename = "discountCodes", default)]
+    pub discount_codes: Vec<String>,
+    /// Installment schedule; quoted as a single annual payment when absent.
+    #[serde(rename = "paymentFrequency", default)]
+    pub payment_frequency: Option<PaymentFrequency>,
+    /// Rating zone, for products with zone factors.
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// Deductible of a top-up product, priced from the matrix band of the
+    /// sum insured and this deductible together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deductible: Option<String>,
+    /// Priced from the product's rates for this gender when the matrix files
+    /// them, from its unisex rates otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gender: Option<Gender>,
+    /// Optional covers, priced from the options worksheet on top of the
+    /// matrix premium.
+    #[serde(rename = "maternityCover", default, skip_serializing_if = "Not::not")]
+    pub maternity_cover: bool,
+    #[serde(rename = "opdCover", default, skip_serializing_if = "Not::not")]
+    pub opd_cover: bool,
+    #[serde(rename = "restoreBenefit", default, skip_serializing_if = "Not::not")]
+    pub restore_benefit: bool,
+    /// Codes of pre-existing conditions the proposer declared, loaded or
+    /// referred by the PED rules.
+    #[serde(
+        rename = "declaredConditions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub declared_conditions: Vec<String>,
+    /// Height and weight of the insured, for the BMI underwriting rules.
+    #[serde(rename = "heightCm", default, skip_serializing_if = "Option::is_none")]
+    pub height_cm: Option<f64>,
+    #[serde(rename = "weightKg", default, skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+    /// Prices the request as it would have been on this YYYY-MM-DD date: the
+    /// age on the date, from the matrix version active at its end.
+    #[serde(rename = "asOf", default, skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<String>,
+    /// Code of the channel or distributor the quote is sold through, which
+    /// is paid the commission configured for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Sales channel the quote is priced for, such as `online`, taken from
+    /// the `X-Channel` header rather than the body.
+    #[serde(
+        rename = "salesChannel",
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sales_channel: Option<String>,
+}
+
+impl HealthRequest {
+    /// The matrix band the request is priced from: the sum insured, and for
+    /// top-up products `sumInsured:deductible`.
+    pub fn band(&self) -> String {
+        matrix::band(&self.sum_insured, self.deductible.as_deref())
+    }
+
+    /// What rollouts bucket the request by, so that requotes and
+    /// revalidations of it land on the same side.
+    pub fn rollout_key(&self) -> String {
+        format!("{}|{}|{}", self.code, self.band(), self.date_of_birth)
+    }
+
+    /// The optional covers asked for, in the order they are itemized.
+    pub fn optional_covers(&self) -> Vec<OptionalCover> {
+        OptionalCover::ALL
+            .into_iter()
+            .filter(|cover| match cover {
+                OptionalCover::MaternityCover => self.maternity_cover,
+                OptionalCover::OpdCover => self.opd_cover,
+                OptionalCover::RestoreBenefit => self.restore_benefit,
+            })
+            .collect()
+    }
+
+    /// The `asOf` date of a backdated quote. Fails with `InvalidInput` for a
+    /// malformed or future date.
+    pub fn as_of_date(&self) -> anyhow::Result<Option<NaiveDate>, PremiumError> {
+        let Some(as_of) = &self.as_of else {
+            return Ok(None);
+        };
+        match NaiveDate::parse_from_str(as_of, "%Y-%m-%d") {
+            Ok(date) if date <= clock::today() => Ok(Some(date)),
+            _ => {
+                error!("asOf {} is not a past date", as_of);
+                Err(PremiumError::InvalidInput)
+            }
+        }
+    }
+
+    /// The insured's age today, or on the `asOf` date, from the date of
+    /// birth, or the stated `age` when there is none. A stated age that
+    /// disagrees with the date of birth fails with `Validation`.
+    pub(crate) fn age(&self) -> anyhow::Result<i32, PremiumError> {
+        if self.date_of_birth.trim().is_empty() {
+            return match self.age {
+                Some(age) => Ok(age as i32),
+                None => Err(PremiumError::Validation {
+                    field: "dateOfBirth".to_string(),
+                    message: "required unless age is given".to_string(),
+                }),
+            };
+        }
+        let age = match self.as_of_date()? {
+            Some(date) => calculate_age_on(&self.date_of_birth, date)?,
+            None => calculate_age(&self.date_of_birth)?,
+        };
+        match self.age {
+            Some(stated) if stated as i32 != age => {
+                error!("stated age {} disagrees with age {}", stated, age);
+                Err(PremiumError::Validation {
+                    field: "age".to_string(),
+                    message: format!("{} does not match dateOfBirth", stated),
+                })
+            }
+            _ => Ok(age),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthResponse {
+    pub premium: String,
+    /// ISO 4217 code of every amount in the response.
+    pub currency: String,
+    /// Matrix version the quote was priced from.
+    #[serde(rename = "matrixVersion")]
+    pub matrix_version: u64,
+    /// RFC 3339 time after which the quote is no longer honoured.
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub discounts: Vec<AppliedDiscount>,
+    /// Set when the discounted premium fell below the product's minimum
+    /// premium and was raised to it.
+    #[serde(rename = "minimumPremiumApplied", skip_serializing_if = "Not::not")]
+    pub minimum_premium_applied: bool,
+    /// What raising the premium to the minimum added, included in `premium`.
+    #[serde(
+        rename = "minimumPremiumUplift",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub minimum_premium_uplift: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<Breakdown>,
+    /// Tax on `premium` for products with a tax rate, and the two together.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tax: Option<String>,
+    #[serde(rename = "totalPremium", skip_serializing_if = "Option::is_none")]
+    pub total_premium: Option<String>,
+    /// What rounding to the product's round figure added to the amount
+    /// charged, `totalPremium` or else `premium`; negative when it took off.
+    #[serde(rename = "roundingAdjustment", skip_serializing_if = "Option::is_none")]
+    pub rounding_adjustment: Option<String>,
+    /// Loadings of the declared conditions, included in `premium`.
+    #[serde(rename = "conditionLoadings", skip_serializing_if = "Vec::is_empty")]
+    pub condition_loadings: Vec<ConditionLoading>,
+    /// Loading of a referral the underwriting service accepted, included in
+    /// `premium`.
+    #[serde(
+        rename = "referralLoadingPercent",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub referral_loading_percent: Option<u32>,
+    /// Pricing rules that matched, included in `premium`.
+    #[serde(rename = "ruleAdjustments", skip_serializing_if = "Vec::is_empty")]
+    pub rule_adjustments: Vec<RuleAdjustment>,
+    /// Optional covers asked for, included in `premium`.
+    #[serde(rename = "addOns", skip_serializing_if = "Vec::is_empty")]
+    pub add_ons: Vec<AddOnPremium>,
+    /// Commission due to the request's channel, for settlement. Callers
+    /// other than operators and the channel's own distributor must not be
+    /// shown it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commission: Option<Commission>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    /// The sums insured the product is priced for, with `013` errors.
+    #[serde(rename = "validSumsInsured", skip_serializing_if = "Vec::is_empty")]
+    pub valid_sums_insured: Vec<String>,
+    /// Why the quote was referred with `017` or declined with `018`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+}
+
+impl From<&PremiumError> for ErrorResponse {
+    fn from(err: &PremiumError) -> Self {
+        ErrorResponse {
+            code: err.code().to_string(),
+            message: err.to_string(),
+            valid_sums_insured: match err {
+                PremiumError::SumInsuredNotOffered { valid, .. } => valid.clone(),
+                _ => Vec::new(),
+            },
+            reasons: match err {
+                PremiumError::ReferToUnderwriter { reasons }
+                | PremiumError::CoverDeclined { reasons } => reasons.clone(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[non_exhaustive]
+pub enum PremiumError {
+    #[error("Internal server error")]
+    InternalServer,
+    #[error("Invalid request")]
+    InvalidInput,
+    #[error("Header {0} not provided or invalid")]
+    InvalidHeader(String),
+    #[error("Cannot calculate risk for input")]
+    RiskCalculation,
+    #[error("Matrix version {0} not found")]
+    VersionNotFound(u64),
+    #[error("Request body exceeds {0} bytes")]
+    PayloadTooLarge(u64),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("A request with this Idempotency-Key is still in progress")]
+    RequestInProgress,
+    #[error("Age {0} is outside the entry ages of the product")]
+    AgeNotEligible(i32),
+    #[error("Discount code {0} cannot be applied")]
+    DiscountNotApplicable(String),
+    #[error("Field {field} violates {constraint}: {detail}")]
+    SchemaViolation {
+        field: String,
+        constraint: String,
+        detail: String,
+    },
+    #[error("Storage unavailable, retry after {0} seconds")]
+    Unavailable(u64),
+    #[error("Sum insured {sum_insured} is not offered for the product")]
+    SumInsuredNotOffered {
+        sum_insured: String,
+        valid: Vec<String>,
+    },
+    #[error("Pricing temporarily unavailable")]
+    PricingUnavailable,
+    #[error("Unknown tenant {0}")]
+    UnknownTenant(String),
+    #[error("Premium {premium} for product {code} is outside its configured bounds")]
+    PremiumOutOfBounds { code: String, premium: String },
+    #[error("Quote needs an underwriter's decision: {}", .reasons.join(", "))]
+    ReferToUnderwriter { reasons: Vec<String> },
+    #[error("Cover is declined: {}", .reasons.join(", "))]
+    CoverDeclined { reasons: Vec<String> },
+    #[error("No matrix version was active on {0}")]
+    NoVersionAsOf(String),
+    #[error("Bulk job {0} not found")]
+    JobNotFound(String),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("Credentials missing or rejected")]
+    Unauthorized,
+    #[error("Too many requests, retry after {0} seconds")]
+    RateLimited(u64),
+    #[error("{0} is unavailable")]
+    UpstreamUnavailable(String),
+    #[error("Field {field} is invalid: {message}")]
+    Validation { field: String, message: String },
+    #[error("Date of birth {0}")]
+    InvalidDateOfBirth(String),
+    #[error("Optional cover {0} is not offered for the product")]
+    CoverNotOffered(String),
+}
+
+impl PremiumError {
+    /// Stable error code reported to callers alongside the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PremiumError::InternalServer => "001",
+            PremiumError::InvalidInput => "002",
+            PremiumError::InvalidHeader(_) => "003",
+            PremiumError::RiskCalculation => "004",
+            PremiumError::VersionNotFound(_) => "005",
+            PremiumError::PayloadTooLarge(_) => "006",
+            PremiumError::Timeout => "007",
+            PremiumError::RequestInProgress => "008",
+            PremiumError::AgeNotEligible(_) => "009",
+            PremiumError::DiscountNotApplicable(_) => "010",
+            PremiumError::SchemaViolation { .. } => "011",
+            PremiumError::Unavailable(_) => "012",
+            PremiumError::SumInsuredNotOffered { .. } => "013",
+            PremiumError::PricingUnavailable => "014",
+            PremiumError::UnknownTenant(_) => "015",
+            PremiumError::PremiumOutOfBounds { .. } => "016",
+            PremiumError::ReferToUnderwriter { .. } => "017",
+            PremiumError::CoverDeclined { .. } => "018",
+            PremiumError::NoVersionAsOf(_) => "019",
+            PremiumError::JobNotFound(_) => "020",
+            PremiumError::NotFound(_) => "021",
+            PremiumError::Unauthorized => "022",
+            PremiumError::RateLimited(_) => "023",
+            PremiumError::UpstreamUnavailable(_) => "024",
+            PremiumError::Validation { .. } => "025",
+            PremiumError::InvalidDateOfBirth(_) => "026",
+            PremiumError::CoverNotOffered(_) => "027",
+        }
+    }
+
+    /// The HTTP status the error is answered with. Other transports map
+    /// their own codes from it.
+    pub fn status(&self) -> u16 {
+        match self {
+            PremiumError::InvalidInput
+            | PremiumError::InvalidHeader(_)
+            | PremiumError::RiskCalculation
+            | PremiumError::AgeNotEligible(_)
+            | PremiumError::DiscountNotApplicable(_)
+            | PremiumError::SchemaViolation { .. }
+            | PremiumError::SumInsuredNotOffered { .. }
+            | PremiumError::UnknownTenant(_)
+            | PremiumError::Validation { .. }
+            | PremiumError::InvalidDateOfBirth(_)
+            | PremiumError::CoverNotOffered(_) => 400,
+            PremiumError::Unauthorized => 401,
+            PremiumError::VersionNotFound(_)
+            | PremiumError::NoVersionAsOf(_)
+            | PremiumError::JobNotFound(_)
+            | PremiumError::NotFound(_) => 404,
+            PremiumError::RequestInProgress => 409,
+            PremiumError::PayloadTooLarge(_) => 413,
+            PremiumError::ReferToUnderwriter { .. } | PremiumError::CoverDeclined { .. } => 422,
+            PremiumError::RateLimited(_) => 429,
+            PremiumError::InternalServer | PremiumError::PremiumOutOfBounds { .. } => 500,
+            PremiumError::UpstreamUnavailable(_) => 502,
+            PremiumError::Unavailable(_) | PremiumError::PricingUnavailable => 503,
+            PremiumError::Timeout => 504,
+        }
+    }
+
+    /// Seconds the caller should wait before retrying, sent as `Retry-After`.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            PremiumError::Unavailable(secs) | PremiumError::RateLimited(secs) => Some(*secs),
+            _ => None,
+        }
+    }
+}
+
+/// Quotes the premium less any discount codes, but not below the product's
+/// minimum premium, with the installment breakdown for the requested payment
+/// frequency. `premium` is the annualized amount in that case.
+pub async fn quote(input: HealthRequest) -> anyhow::Result<HealthResponse, PremiumError> {
+    quote_for(input, &Caller::default()).await
+}
+
+/// `quote` on behalf of `caller`, who is named in the audit record.
+pub async fn quote_for(
+    input: HealthRequest,
+    caller: &Caller,
+) -> anyhow::Result<HealthResponse, PremiumError> {
+    let priced = price(&input).await?;
+    let applied = discounts::apply(&input.discount_codes, &priced.premium)?;
+    let settings = products::settings(&input.code);
+    let discounted = discounts::net(&priced.premium, &applied);
+    let (discounted, minimum_uplift) = products::apply_minimum(&settings, &discounted);
+    let breakdown = input
+        .payment_frequency
+        .map(|frequency| frequency::breakdown(frequency, &discounted));
+    let premium = match &breakdown {
+        Some(breakdown) => breakdown.annualized_premium.clone(),
+        None => discounted,
+    };
+    let charge = products::charge(&settings, &premium);
+    let commission = commission::compute(&input, &charge.premium)?;
+    let response = HealthResponse {
+        total_premium: charge.total_premium.map(|total| total.to_string()),
+        tax: charge.tax.map(|tax| tax.to_string()),
+        rounding_adjustment: charge
+            .rounding_adjustment
+            .map(|adjustment| adjustment.to_string()),
+        premium: charge.premium.to_string(),
+        minimum_premium_applied: minimum_uplift.is_some(),
+        minimum_premium_uplift: minimum_uplift.map(|uplift| uplift.to_string()),
+        currency: priced.premium.currency().code.clone(),
+        matrix_version: priced.matrix_version,
+        expires_at: expiry::expires_at(),
+        discounts: applied,
+        breakdown,
+        condition_loadings: priced.condition_loadings.clone(),
+        referral_loading_percent: priced.referral_loading_percent,
+        rule_adjustments: priced.rule_adjustments.clone(),
+        add_ons: priced.add_ons.clone(),
+        commission,
+    };
+    audit::record(&AuditRecord {
+        timestamp: audit::timestamp(),
+        caller: caller.id.as_deref(),
+        request_id: caller.request_id.as_deref(),
+        request: &input,
+        matrix_version: priced.matrix_version,
+        age: priced.age,
+        score: priced.score,
+        matrix_premium: &priced.matrix_premium,
+        short_period_percent: priced.short_period_percent,
+        discounts: &response.discounts,
+        loading_percent: response
+            .breakdown
+            .as_ref()
+            .map(|breakdown| breakdown.loading_percent),
+        condition_loadings: &response.condition_loadings,
+        referral_loading_percent: response.referral_loading_percent,
+        rule_adjustments: &response.rule_adjustments,
+        premium: &response.premium,
+        currency: &response.currency,
+        commission: response.commission.as_ref(),
+    })
+    .await?;
+    Ok(response)
+}
+
+pub async fn calculate_premium(input: HealthRequest) -> anyhow::Result<String, PremiumError> {
+    Ok(price(&input).await?.premium.to_string())
+}
+
+/// A premium, the matrix version it was priced from and the factors that
+/// led to it.
+#[derive(Debug, Clone)]
+pub struct Priced {
+    pub premium: Money,
+    pub matrix_version: u64,
+    /// The matrix band priced from, gendered when the matrix has rates for
+    /// the requested gender.
+    pub band: String,
+    /// The matrix premium before short-period scaling.
+    pub matrix_premium: Money,
+    pub age: i32,
+    pub score: i32,
+    pub short_period_percent: Option<u32>,
+    /// The product's factor for the requested zone.
+    pub zone_percent: Option<u32>,
+    /// The factor of the sales channel for the product.
+    pub channel_percent: Option<u32>,
+    pub condition_loadings: Vec<ConditionLoading>,
+    /// Loading the underwriting service added when it accepted a referral.
+    pub referral_loading_percent: Option<u32>,
+    pub rule_adjustments: Vec<RuleAdjustment>,
+    /// Optional covers, scaled for a short policy period like the premium.
+    pub add_ons: Vec<AddOnPremium>,
+}
+
+/// The premium in the product's currency, scaled down for a short policy
+/// period and by the zone and sales channel factors, loaded for declared
+/// conditions and adjusted by the pricing rules that match, plus any optional
+/// covers. Quotes the underwriting rules decline fail with `CoverDeclined`;
+/// those they refer go to the underwriting service, whose loading is added
+/// when it accepts them, and fail with `ReferToUnderwriter` otherwise.
+pub async fn price(input: &HealthRequest) -> anyhow::Result<Priced, PremiumError> {
+    maintenance::check()?;
+    let settings = products::settings(&input.code);
+    products::check_sum_insured(&settings, &input.code, &input.sum_insured)?;
+    let zone_percent = products::zone_percent(&settings, input.zone.as_deref())?;
+    let channel_percent = channels::percent(input.sales_channel.as_deref(), &input.code);
+    let short_period_percent = short_period::percent(
+        input.policy_start_date.as_deref(),
+        input.policy_end_date.as_deref(),
+    )?;
+    let age = input.age()?;
+    eligibility::check(&input.code, age)?;
+    let (condition_loadings, referral_loading_percent) = match underwriting::assess(input, age) {
+        Err(PremiumError::ReferToUnderwriter { reasons }) => {
+            let percent = referral::refer(input, age, reasons).await?;
+            (
+                ped::assess(&input.declared_conditions).loadings,
+                Some(percent),
+            )
+        }
+        assessed => (assessed?, None),
+    };
+    let score = bands::score(&input.code, age);
+    //info!("age {} score {}", score, age);
+
+    let version = pricing_version(input).await?;
+    let (band, matrix) = match band_premium(version, input, score).await {
+        Err(PremiumError::RiskCalculation) => return Err(missing_premium(input).await),
+        matrix => matrix?,
+    };
+    rate_test::shadow(&input.code, &band, score, &matrix);
+    let matrix_premium = Money::parse(&matrix.premium, Currency::for_product(&input.code))?;
+    let loadings = Loadings {
+        zone_percent,
+        channel_percent,
+        loading_percent: ped::loading_percent(&condition_loadings)
+            + referral_loading_percent.unwrap_or(0),
+        rule_adjustments: pricing_rules::evaluate(&Facts::of(input, age)),
+    };
+    let annual = loadings.apply(matrix_premium.clone());
+    products::check_bounds(&settings, &input.code, &annual)?;
+    let premium = match short_period_percent {
+        Some(percent) => loadings.apply(matrix_premium.percent(percent)),
+        None => annual,
+    };
+    let rule_adjustments = loadings.rule_adjustments;
+    let mut add_ons = add_ons::price(input)?;
+    if let Some(percent) = short_period_percent {
+        for add_on in &mut add_ons {
+            add_on.premium = add_on.premium.percent(percent);
+        }
+    }
+    let premium = add_ons
+        .iter()
+        .fold(premium, |premium, add_on| premium + add_on.premium.clone());
+    Ok(Priced {
+        premium,
+        matrix_version: matrix.version,
+        band,
+        matrix_premium,
+        age,
+        score,
+        short_period_percent,
+        zone_percent,
+        channel_percent,
+        condition_loadings,
+        referral_loading_percent,
+        rule_adjustments,
+        add_ons,
+    })
+}
+
+/// What turns a matrix premium into the quoted one, bar the short period and
+/// add-ons.
+struct Loadings {
+    zone_percent: Option<u32>,
+    channel_percent: Option<u32>,
+    /// Condition and referral loadings together.
+    loading_percent: u32,
+    rule_adjustments: Vec<RuleAdjustment>,
+}
+
+impl Loadings {
+    fn apply(&self, premium: Money) -> Money {
+        let premium = match self.zone_percent {
+            Some(percent) => premium.percent(percent),
+            None => premium,
+        };
+        let premium = match self.channel_percent {
+            Some(percent) => premium.percent(percent),
+            None => premium,
+        };
+        let premium = match self.loading_percent {
+            0 => premium,
+            loading => premium.percent(100 + loading),
+        };
+        pricing_rules::apply(&premium, &self.rule_adjustments)
+    }
+}
+
+/// The band `input` is priced from and its premium: the rates for the
+/// requested gender, or the unisex rates when the matrix has none for it.
+async fn band_premium(
+    version: Option<u64>,
+    input: &HealthRequest,
+    score: i32,
+) -> anyhow::Result<(String, MatrixPremium), PremiumError> {
+    let band = input.band();
+    let gendered_rates = flags::enabled(Flag::GenderedRates, &input.rollout_key());
+    if let Some(gender) = input.gender.filter(|_| gendered_rates) {
+        let gendered = matrix::gendered(&band, gender);
+        match matrix_premium(version, &input.code, &gendered, score).await {
+            Err(PremiumError::RiskCalculation) => {}
+            premium => return premium.map(|premium| (gendered, premium)),
+        }
+    }
+    let premium = matrix_premium(version, &input.code, &band, score).await?;
+    Ok((band, premium))
+}
+
+/// Why the matrix has no premium for `input`: `SumInsuredNotOffered` with
+/// the product's sums insured when the active version prices the product for
+/// others, `RiskCalculation` otherwise.
+async fn missing_premium(input: &HealthRequest) -> PremiumError {
+    let catalog = products::catalog().await.unwrap_or_default();
+    match catalog
+        .into_iter()
+        .find(|product| product.code == input.code)
+    {
+        Some(product) if !product.sums_insured.contains(&input.sum_insured) => {
+            error!(
+                "sum insured {} is not in the matrix for product {}",
+                input.sum_insured, input.code
+            );
+            PremiumError::SumInsuredNotOffered {
+                sum_insured: input.sum_insured.clone(),
+                valid: product.sums_insured,
+            }
+        }
+        _ => PremiumError::RiskCalculation,
+    }
+}
+
+/// The version `input` is priced from: the one active on its `asOf` date,
+/// else the candidate of a split rate test, else `None` for the active one.
+pub(crate) async fn pricing_version(
+    input: &HealthRequest,
+) -> anyhow::Result<Option<u64>, PremiumError> {
+    let Some(date) = input.as_of_date()? else {
+        return Ok(rate_test::split_version(input));
+    };
+    let next_day = date.succ_opt().ok_or(PremiumError::InvalidInput)?;
+    let until = clock::start_of_day(next_day).ok_or(PremiumError::InvalidInput)?;
+    match active_before(&store::versions().await?.versions, until) {
+        Some(version) => Ok(Some(version)),
+        None => Err(PremiumError::NoVersionAsOf(date.to_string())),
+    }
+}
+
+/// The version activated last before `until`. Versions activated before
+/// activations were recorded count as activated when they were loaded.
+fn active_before(versions: &[MatrixVersion], until: DateTime<FixedOffset>) -> Option<u64> {
+    versions
+        .iter()
+        .flat_map(|version| {
+            let activations = match version.activated_at.is_empty() {
+                true => std::slice::from_ref(&version.loaded_at),
+                false => version.activated_at.as_slice(),
+            };
+            activations
+                .iter()
+                .filter_map(|at| DateTime::parse_from_rfc3339(at).ok())
+                .map(move |at| (at, version.version))
+        })
+        .filter(|(at, _)| *at < until)
+        .max()
+        .map(|(_, version)| version)
+}
+
+/// The matrix premium from `version`, or the active version when `None`.
+pub(crate) async fn matrix_premium(
+    version: Option<u64>,
+    code: &str,
+    sum_insured: &str,
+    score: i32,
+) -> anyhow::Result<MatrixPremium, PremiumError> {
+    let cache_key = quote_cache::key(version, code, sum_insured, score);
+    if let Some(premium) = quote_cache::get(&cache_key) {
+        return Ok(premium);
+    }
+
+    let premium = lookups()
+        .run(&cache_key, || {
+            store::premium(version, code, sum_insured, score)
+        })
+        .await?;
+    quote_cache::insert(cache_key, premium.clone());
+    Ok(premium)
+}
+
+/// Store lookups in flight, so a burst of identical quotes reads the store
+/// once.
+static LOOKUPS: OnceLock<SingleFlight<MatrixPremium>> = OnceLock::new();
+
+fn lookups() -> &'static SingleFlight<MatrixPremium> {
+    LOOKUPS.get_or_init(SingleFlight::new)
+}
+
+/// Lookups answered with the result of an identical one already in flight.
+pub fn shared_lookups() -> u64 {
+    lookups().shared()
+}
+
+pub(crate) fn calculate_age(dob_str: &str) -> anyhow::Result<i32, PremiumError> {
+    calculate_age_on(dob_str, clock::today())
+}
+
+/// Earliest year of birth accepted; older ones are taken for typos.
+const EARLIEST_BIRTH_YEAR: i32 = 1900;
+
+/// Whole years from `dob_str`, in any configured format, to `today`,
+/// counting a 29 February birthday as the clock's leap day policy says.
+/// Fails with `InvalidDateOfBirth` for a date that does not exist, is before
+/// 1900 or is after `today`.
+pub fn calculate_age_on(dob_str: &str, today: NaiveDate) -> anyhow::Result<i32, PremiumError> {
+    let invalid = |reason: &str| {
+        error!("date of birth {} {}", dob_str, reason);
+        PremiumError::InvalidDateOfBirth(format!("{} {}", dob_str, reason))
+    };
+    let dob = dob::parse(dob_str).ok_or_else(|| invalid("is not a calendar date"))?;
+    if dob.year() < EARLIEST_BIRTH_YEAR {
+        return Err(invalid("is before 1900"));
+    }
+    if dob > today {
+        return Err(invalid("is in the future"));
+    }
+    let years = today.year() - dob.year();
+    match clock::birthday(dob, today.year()) {
+        Some(birthday) if today < birthday => Ok(years - 1),
+        _ => Ok(years),
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixVersion {
+    pub version: u64,
+    pub loaded_at: String,
+    pub rows: usize,
+    pub active: bool,
+    /// RFC 3339 times the version was made active, oldest first.
+    pub activated_at: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MatrixVersions {
+    pub active: Option<u64>,
+    pub versions: Vec<MatrixVersion>,
+}
+
+/// Lists every loaded matrix version, oldest first.
+pub async fn versions() -> anyhow::Result<MatrixVersions, PremiumError> {
+    store::versions().await
+}
+
+/// Points quotes at a previously loaded matrix version.
+pub async fn activate(version: u64) -> anyhow::Result<bool, PremiumError> {
+    store::activate(version).await?;
+    quote_cache::invalidate();
+    Ok(true)
+}
+
+async fn fetch_source(
+    config: &MatrixConfig,
+    source_url: Option<&str>,
+) -> anyhow::Result<Option<Vec<u8>>, PremiumError> {
+    match source_url {
+        Some(url) => Ok(Some(source::fetch(url, &config.source).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Parses and validates the workbook without writing anything to redis.
+pub async fn validate(
+    config: &MatrixConfig,
+    source_url: Option<&str>,
+) -> anyhow::Result<LoadReport, PremiumError> {
+    let started = Instant::now();
+    let mut sink = RowSink::new(false);
+    let parsed = stream_matrix(config, fetch_source(config, source_url).await?, &mut sink).await?;
+    Ok(parsed.report(true, started))
+}
+
+/// Writes the workbook into a new matrix version and activates it once every
+/// row is stored. Rows are parsed while the workbook is read and written in
+/// chunks of `chunkRows`, so memory does not grow with the workbook. An
+/// invalid workbook is reported and whatever was written is discarded.
+pub async fn load(
+    config: &MatrixConfig,
+    source_url: Option<&str>,
+) -> anyhow::Result<LoadReport, PremiumError> {
+    let started = Instant::now();
+    let contents = fetch_source(config, source_url).await?;
+    let mut sink = RowSink::new(true);
+    let parsed = match stream_matrix(config, contents, &mut sink).await {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            sink.abort().await;
+            return Err(err);
+        }
+    };
+    if !parsed.is_valid() {
+        error!(
+            "matrix has {} invalid and {} duplicate rows",
+            parsed.errors.len(),
+            parsed.duplicates.len()
+        );
+        sink.abort().await;
+        return Ok(parsed.report(false, started));
+    }
+
+    let rows_loaded = sink.written;
+    let version = match sink.writer.take() {
+        Some(writer) => writer.commit().await?,
+        None => store::begin_version().await?.commit().await?,
+    };
+    let digests = std::mem::take(&mut sink.digester).finish();
+    if let Err(err) = store::record_digests(version, &digests).await {
+        error!("matrix version {} loaded without digests {}", version, err);
+    }
+    quote_cache::invalidate();
+    let mut report = parsed.report(false, started);
+    report.version = Some(version);
+    report.rows_loaded = rows_loaded;
+    report.rows_per_sec = rows_per_sec(rows_loaded, started.elapsed());
+    Ok(report)
+}
+
+/// Where parsed rows go: a version writer opened on the first chunk, or
+/// nowhere for a dry run. Written rows are digested for integrity checks.
+struct RowSink {
+    write: bool,
+    writer: Option<VersionWriter>,
+    written: usize,
+    digester: Digester,
+}
+
+impl RowSink {
+    fn new(write: bool) -> Self {
+        RowSink {
+            write,
+            writer: None,
+            written: 0,
+            digester: Digester::default(),
+        }
+    }
+
+    async fn flush(&mut self, rows: &[MatrixRow]) -> anyhow::Result<(), PremiumError> {
+        if !self.write || rows.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            self.writer = Some(store::begin_version().await?);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write(rows).await?;
+        }
+        self.digester.add_rows(rows);
+        self.written += rows.len();
+        Ok(())
+    }
+
+    async fn abort(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.abort().await;
+        }
+    }
+}
+
+/// Parses the workbook a sheet at a time, handing valid rows to `sink` in
+/// chunks. Writing stops at the first rejected row; parsing goes on so the
+/// report lists every problem.
+async fn stream_matrix(
+    config: &MatrixConfig,
+    contents: Option<Vec<u8>>,
+    sink: &mut RowSink,
+) -> anyhow::Result<ParsedMatrix, PremiumError> {
+    let chunk_rows = config.chunk_rows.max(1);
+    let mut work_book = open_workbook(config, contents)?;
+    let mut parser = MatrixParser::new(config.product_sheets);
+    let mut chunk = Vec::with_capacity(chunk_rows);
+    for name in work_book.sheet_names(config) {
+        let range = work_book.sheet(&name)?;
+        let mut rows = range.rows();
+        let Some(header) = rows.next() else {
+            parser.missing_header(&name);
+            continue;
+        };
+        if !parser.begin_sheet(&name, &row_text(header)) {
+            continue;
+        }
+        for row in rows {
+            chunk.extend(parser.row(&row_text(row)));
+            if chunk.len() >= chunk_rows {
+                if parser.is_valid() {
+                    sink.flush(&chunk).await?;
+                }
+                chunk.clear();
+            }
+        }
+    }
+    if parser.is_valid() {
+        sink.flush(&chunk).await?;
+    }
+    Ok(parser.finish())
+}
+
+pub async fn keys_exists() -> anyhow::Result<bool, PremiumError> {
+    store::keys_exists().await
+}
+
+/// Keys of the active matrix version per product code.
+pub async fn key_counts() -> anyhow::Result<store::KeyCounts, PremiumError> {
+    store::key_counts().await
+}
+
+pub async fn unload() -> anyhow::Result<bool, PremiumError> {
+    store::unload().await?;
+    quote_cache::invalidate();
+    Ok(true)
+}
+
+impl From<HealthResponse> for String {
+    fn from(value: HealthResponse) -> Self {
+        value.premium
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use async_std::task;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_calculate_age() {
+        let dob_str = String::from("1977-09-14");
+        let today = Arc::new(FixedClock(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()));
+        let age = task::block_on(clock::scope(today, async { calculate_age(&dob_str) }));
+        assert_eq!(age.unwrap(), 46);
+
+        let on = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        // The default policy ages 29 February birthdays on 1 March.
+        assert_eq!(calculate_age_on("2000-02-29", on(2023, 2, 28)).unwrap(), 22);
+        assert_eq!(calculate_age_on("2000-02-29", on(2023, 3, 1)).unwrap(), 23);
+        assert_eq!(calculate_age_on("2000-02-29", on(2024, 2, 29)).unwrap(), 24);
+        assert_eq!(calculate_age_on("1900-01-01", on(2000, 1, 1)).unwrap(), 100);
+        assert_eq!(calculate_age_on("1980-06-15", on(2020, 6, 20)).unwrap(), 40);
+        for dob in ["2023-02-29", "1899-12-31", "2024-01-02", "14/09/1977"] {
+            assert!(
+                matches!(
+                    calculate_age_on(dob, on(2024, 1, 1)),
+                    Err(PremiumError::InvalidDateOfBirth(_))
+                ),
+                "{}",
+                dob
+            );
+        }
+    }
+
+    #[test]
+    fn test_age_compares_birth_month_before_day() {
+        let on = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        // Comparing days alone aged these a year early and a year late.
+        assert_eq!(calculate_age_on("1980-06-15", on(2020, 5, 20)).unwrap(), 39);
+        assert_eq!(calculate_age_on("1980-06-15", on(2020, 7, 10)).unwrap(), 40);
+        assert_eq!(calculate_age_on("1980-06-15", on(2020, 6, 14)).unwrap(), 39);
+        assert_eq!(calculate_age_on("1980-06-15", on(2020, 6, 15)).unwrap(), 40);
+    }
+
+    #[test]
+    fn test_as_of_version() {
+        let version = |version, loaded_at: &str, activated_at: &[&str]| MatrixVersion {
+            version,
+            loaded_at: loaded_at.to_string(),
+            rows: 7,
+            active: false,
+            activated_at: activated_at.iter().map(|at| at.to_string()).collect(),
+        };
+        let versions = [
+            version(1, "2026-01-01T10:00:00+00:00", &[]),
+            version(
+                2,
+                "2026-03-01T10:00:00+00:00",
+                &["2026-03-01T10:00:00+00:00"],
+            ),
+            version(
+                3,
+                "2026-05-01T10:00:00+00:00",
+                &["2026-05-01T10:00:00+00:00"],
+            ),
+        ];
+        let until = |at: &str| DateTime::parse_from_rfc3339(at).unwrap();
+        assert_eq!(
+            active_before(&versions, until("2025-12-31T00:00:00+00:00")),
+            None
+        );
+        assert_eq!(
+            active_before(&versions, until("2026-02-01T00:00:00+00:00")),
+            Some(1)
+        );
+        assert_eq!(
+            active_before(&versions, until("2026-06-01T00:00:00+00:00")),
+            Some(3)
+        );
+
+        let mut rolled_back = versions;
+        rolled_back[1]
+            .activated_at
+            .push("2026-05-10T09:00:00+00:00".to_string());
+        assert_eq!(
+            active_before(&rolled_back, until("2026-06-01T00:00:00+00:00")),
+            Some(2)
+        );
+
+        let request = HealthRequest {
+            as_of: Some("2999-01-01".to_string()),
+            ..HealthRequest::default()
+        };
+        assert!(matches!(
+            request.as_of_date(),
+            Err(PremiumError::InvalidInput)
+        ));
+    }
+
+    #[test]
+    fn test_validate_bundled_workbook() {
+        task::block_on(async {
+            let report = validate(&MatrixConfig::bundled(), None).await.unwrap();
+            assert!(report.valid, "{:?}", report.parse_errors);
+            assert_eq!(report.rows_read, 7);
+
+            let chunked = MatrixConfig {
+                chunk_rows: 2,
+                ..MatrixConfig::bundled()
+            };
+            let report = validate(&chunked, None).await.unwrap();
+            assert!(report.valid, "{:?}", report.parse_errors);
+            assert_eq!(report.rows_read, 7);
+        });
+    }
+
+    #[test]
+    fn test_calculate_premium() {
+        let request: HealthRequest = HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: "100000".to_string(),
+            date_of_birth: "1977-09-14".to_string(),
+            ..HealthRequest::default()
+        };
+
+        let today = Arc::new(FixedClock(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()));
+        task::block_on(clock::scope(today, async {
+            let premium = calculate_premium(request).await;
+            assert!(premium.is_ok());
+            assert_eq!(premium.unwrap(), "750".to_string());
+        }));
+    }
+
+    #[test]
+    fn test_key_exists() {
+        task::block_on(async {
+            let result = keys_exists().await;
+            assert!(result.is_ok());
+            assert!(result.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_load() {
+        task::block_on(async {
+            let result = load(&MatrixConfig::bundled(), None).await;
+            assert!(result.is_ok());
+            let report = result.unwrap();
+            assert!(report.valid);
+            assert_eq!(report.rows_loaded, report.rows_read);
+        });
+    }
+
+    #[test]
+    fn test_bounds_apply_to_loaded_premium() {
+        let settings = crate::config::ProductSettings {
+            premium_ceiling: Some(900),
+            ..Default::default()
+        };
+        let matrix_premium = Money::parse("800", Currency::new("INR")).unwrap();
+        let loadings = Loadings {
+            zone_percent: None,
+            channel_percent: None,
+            loading_percent: 25,
+            rule_adjustments: Vec::new(),
+        };
+        assert!(products::check_bounds(&settings, "1A", &matrix_premium).is_ok());
+        let annual = loadings.apply(matrix_premium);
+        assert_eq!(annual.to_string(), "1000");
+        assert!(matches!(
+            products::check_bounds(&settings, "1A", &annual),
+            Err(PremiumError::PremiumOutOfBounds { premium, .. }) if premium == "1000"
+        ));
+    }
+
+    #[test]
+    fn test_unload() {
+        task::block_on(async {
+            let result = unload().await;
+            assert!(result.is_ok());
+            assert!(result.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_reload_after_unload_gets_new_version() {
+        task::block_on(crate::tenant::scope(Some("reload".to_string()), async {
+            let first = load(&MatrixConfig::bundled(), None).await.unwrap();
+            assert!(unload().await.unwrap());
+            let second = load(&MatrixConfig::bundled(), None).await.unwrap();
+            assert!(second.version.unwrap() > first.version.unwrap());
+        }));
+    }
+
+    fn workbook(sheets: &[(&str, &[&[&str]])]) -> Vec<u8> {
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        for (name, rows) in sheets {
+            let sheet = workbook.add_worksheet();
+            sheet.set_name(*name).unwrap();
+            for (line, row) in rows.iter().enumerate() {
+                for (column, value) in row.iter().enumerate() {
+                    sheet
+                        .write_string(line as u32, column as u16, *value)
+                        .unwrap();
+                }
+            }
+        }
+        workbook.save_to_buffer().unwrap()
+    }
+
+    #[test]
+    fn test_stream_matrix_in_chunks() {
+        let header: &[&str] = &["code", "sumInsured", "ageBand", "premium", "score"];
+        let contents = workbook(&[
+            (
+                "first",
+                &[
+                    header,
+                    &["1A", "100000", "18-30", "250", "1"],
+                    &["1A", "100000", "31-44", "500", "2"],
+                    &["", "", "", "", ""],
+                    &["1A", "100000", "45-56", "abc", "3"],
+                ],
+            ),
+            (
+                "second",
+                &[
+                    header,
+                    &["2A", "100000", "18-30", "300", "1"],
+                    &["2A", "100000", "31-44", "300", "1"],
+                ],
+            ),
+        ]);
+        // Chunking changes when rows are handed on, never what is reported.
+        for chunk_rows in [0, 1, 2, 10_000] {
+            let config = MatrixConfig {
+                sheets: Vec::new(),
+                chunk_rows,
+                ..MatrixConfig::default()
+            };
+            let mut sink = RowSink::new(false);
+            let parsed =
+                task::block_on(stream_matrix(&config, Some(contents.clone()), &mut sink)).unwrap();
+            assert!(parsed.rows.is_empty());
+            assert_eq!(parsed.rows_read, 6, "chunk_rows {}", chunk_rows);
+            assert_eq!(parsed.skipped, 1);
+            let errors: Vec<(&str, usize)> = parsed
+                .errors
+                .iter()
+                .map(|error| (error.sheet.as_str(), error.row))
+                .collect();
+            assert_eq!(errors, vec![("first", 5)]);
+            let duplicates: Vec<(&str, usize)> = parsed
+                .duplicates
+                .iter()
+                .map(|error| (error.sheet.as_str(), error.row))
+                .collect();
+            assert_eq!(duplicates, vec![("second", 3)]);
+            // a dry run writes nothing
+            assert_eq!(sink.written, 0);
+            assert!(sink.writer.is_none());
+        }
+        let mut sink = RowSink::new(false);
+        let config = MatrixConfig::default();
+        let broken = task::block_on(stream_matrix(
+            &config,
+            Some(b"not a workbook".to_vec()),
+            &mut sink,
+        ));
+        assert!(matches!(broken, Err(PremiumError::InvalidInput)));
+    }
+
+    /// Days from 1900-01-01 to 2100-12-31.
+    fn date(days: i64) -> NaiveDate {
+        NaiveDate::from_ymd_opt(1900, 1, 1).unwrap() + chrono::Duration::days(days)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_age_of_any_text(text in ".*") {
+            let today = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+            let _ = calculate_age_on(&text, today);
+        }
+
+        #[test]
+        fn prop_age_counts_birthdays(born in 0..73_049i64, lived in 0..45_000i64) {
+            let dob = date(born);
+            let on = dob + chrono::Duration::days(lived);
+            let age = calculate_age_on(&dob.format("%Y-%m-%d").to_string(), on).unwrap();
+            let years = on.year() - dob.year();
+            prop_assert!(age >= 0);
+            prop_assert!(age == years || age == years - 1);
+            let next = calculate_age_on(
+                &dob.format("%Y-%m-%d").to_string(),
+                on + chrono::Duration::days(1),
+            )
+            .unwrap();
+            prop_assert!(next == age || next == age + 1);
+        }
+
+        #[test]
+        fn prop_age_turns_on_the_birthday(born in 0..73_049i64, age in 1..100i32) {
+            let dob = date(born);
+            prop_assume!((dob.month(), dob.day()) != (2, 29));
+            let birthday = dob.with_year(dob.year() + age).unwrap();
+            let dob = dob.format("%Y-%m-%d").to_string();
+            prop_assert_eq!(calculate_age_on(&dob, birthday).unwrap(), age);
+            prop_assert_eq!(
+                calculate_age_on(&dob, birthday - chrono::Duration::days(1)).unwrap(),
+                age - 1
+            );
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::sync::RwLock;
+
+use log::{error, info};
+use serde::Serialize;
+
+use crate::config::{MatrixConfig, PricingRulesConfig};
+use crate::matrix::{find_column, read_workbook, RowError, Sheet};
+use crate::money::Money;
+use crate::premium::{HealthRequest, PremiumError};
+use crate::underwriting;
+
+/// A quote attribute a rule can test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Age,
+    SumInsured,
+    Deductible,
+    Bmi,
+    Code,
+    Zone,
+    PaymentFrequency,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        Some(match name {
+            "age" => Field::Age,
+            "sumInsured" => Field::SumInsured,
+            "deductible" => Field::Deductible,
+            "bmi" => Field::Bmi,
+            "code" => Field::Code,
+            "zone" => Field::Zone,
+            "paymentFrequency" => Field::PaymentFrequency,
+            _ => return None,
+        })
+    }
+
+    fn is_number(self) -> bool {
+        matches!(
+            self,
+            Field::Age | Field::SumInsured | Field::Deductible | Field::Bmi
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(Field, Op, Literal),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// The attributes of one quote request, as rules see them. A condition on
+/// an attribute the request leaves out is false.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Facts {
+    pub age: i32,
+    pub sum_insured: Option<f64>,
+    pub deductible: Option<f64>,
+    pub bmi: Option<f64>,
+    pub code: String,
+    pub zone: Option<String>,
+    pub payment_frequency: String,
+}
+
+impl Facts {
+    pub fn of(input: &HealthRequest, age: i32) -> Facts {
+        Facts {
+            age,
+            sum_insured: input.sum_insured.parse().ok(),
+            deductible: input
+                .deductible
+                .as_deref()
+                .and_then(|value| value.parse().ok()),
+            bmi: input
+                .height_cm
+                .zip(input.weight_kg)
+                .map(|(height, weight)| underwriting::bmi(height, weight)),
+            code: input.code.clone(),
+            zone: input.zone.clone(),
+            payment_frequency: input
+                .payment_frequency
+                .and_then(|frequency| serde_json::to_value(frequency).ok())
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_else(|| "annual".to_string()),
+        }
+    }
+
+    fn get(&self, field: Field) -> Option<Literal> {
+        match field {
+            Field::Age => Some(Literal::Number(f64::from(self.age))),
+            Field::SumInsured => self.sum_insured.map(Literal::Number),
+            Field::Deductible => self.deductible.map(Literal::Number),
+            Field::Bmi => self.bmi.map(Literal::Number),
+            Field::Code => Some(Literal::Text(self.code.clone())),
+            Field::Zone => self.zone.clone().map(Literal::Text),
+            Field::PaymentFrequency => Some(Literal::Text(self.payment_frequency.clone())),
+        }
+    }
+}
+
+impl Expr {
+    fn matches(&self, facts: &Facts) -> bool {
+        match self {
+            Expr::Compare(field, op, literal) => match (facts.get(*field), literal) {
+                (Some(Literal::Number(value)), Literal::Number(limit)) => match op {
+                    Op::Eq => value == *limit,
+                    Op::Ne => value != *limit,
+                    Op::Lt => value < *limit,
+                    Op::Le => value <= *limit,
+                    Op::Gt => value > *limit,
+                    Op::Ge => value >= *limit,
+                },
+                (Some(Literal::Text(value)), Literal::Text(text)) => match op {
+                    Op::Eq => value.eq_ignore_ascii_case(text),
+                    _ => !value.eq_ignore_ascii_case(text),
+                },
+                _ => false,
+            },
+            Expr::Not(expr) => !expr.matches(facts),
+            Expr::And(left, right) => left.matches(facts) && right.matches(facts),
+            Expr::Or(left, right) => left.matches(facts) || right.matches(facts),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or("unterminated string")?;
+                let text = chars[i + 1..i + 1 + end].iter().collect();
+                (Token::Text(text), end + 2)
+            }
+            (c, _) if c.is_ascii_digit() => {
+                let width = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == '.')
+                    .count();
+                let number: String = chars[i..i + width].iter().collect();
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("{} is not a number", number))?;
+                (Token::Number(number), width)
+            }
+            (c, _) if c.is_ascii_alphabetic() => {
+                let width = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                (Token::Ident(chars[i..i + width].iter().collect()), width)
+            }
+            (c, _) => return Err(format!("unexpected {}", c)),
+        };
+        tokens.push(token);
+        i += width;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or := and ("||" and)*`, `and := unary ("&&"
+/// unary)*` and `unary := "!" unary | "(" or ")" | field op literal`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.position) == Some(token);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.eat(&Token::Close) {
+                    true => Ok(expr),
+                    false => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let field = Field::parse(&name).ok_or(format!("unknown field {}", name))?;
+                let Some(Token::Op(op)) = self.next() else {
+                    return Err(format!("{} needs a comparison", name));
+                };
+                let literal = match self.next() {
+                    Some(Token::Number(number)) if field.is_number() => Literal::Number(number),
+                    Some(Token::Text(text)) if !field.is_number() => match op {
+                        Op::Eq | Op::Ne => Literal::Text(text),
+                        _ => return Err(format!("{} can only be compared with == or !=", name)),
+                    },
+                    _ => {
+                        let kind = match field.is_number() {
+                            true => "a number",
+                            false => "a quoted string",
+                        };
+                        return Err(format!("{} must be compared with {}", name, kind));
+                    }
+                };
+                Ok(Expr::Compare(field, op, literal))
+            }
+            _ => Err("expected a condition".to_string()),
+        }
+    }
+}
+
+/// A rule from the pricing rules worksheet, `condition => +15%`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PricingRule {
+    pub name: String,
+    pub rule: String,
+    /// Percent added to the premium, negative for a discount.
+    pub percent: i32,
+    #[serde(skip)]
+    condition: Expr,
+}
+
+impl PricingRule {
+    /// Parses `condition => +N%` or `condition => -N%`.
+    pub fn parse(name: &str, rule: &str) -> Result<PricingRule, String> {
+        let (condition, adjustment) = rule.rsplit_once("=>").ok_or("missing =>")?;
+        let adjustment = adjustment.trim();
+        let percent = adjustment
+            .strip_suffix('%')
+            .and_then(|percent| percent.trim().parse::<i32>().ok())
+            .ok_or(format!("{} is not a percent such as +15%", adjustment))?;
+        let mut parser = Parser {
+            tokens: tokenize(condition)?,
+            position: 0,
+        };
+        let condition = parser.or()?;
+        if parser.position != parser.tokens.len() {
+            return Err("unexpected text after the condition".to_string());
+        }
+        Ok(PricingRule {
+            name: name.to_string(),
+            rule: rule.trim().to_string(),
+            percent,
+            condition,
+        })
+    }
+
+    pub fn matches(&self, facts: &Facts) -> bool {
+        self.condition.matches(facts)
+    }
+}
+
+/// A rule that matched a quote, itemized in the response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleAdjustment {
+    pub name: String,
+    pub percent: i32,
+}
+
+static RULES: RwLock<Vec<PricingRule>> = RwLock::new(Vec::new());
+
+/// Reads the pricing rules from the configured worksheet, replacing any
+/// loaded before. No rules apply when `pricingRules.path` is not set.
+pub fn load(
+    config: &PricingRulesConfig,
+    matrix: &MatrixConfig,
+) -> anyhow::Result<usize, PremiumError> {
+    let Some(path) = &config.path else {
+        return Ok(0);
+    };
+    let workbook = MatrixConfig {
+        path: path.clone(),
+        sheets: vec![config.sheet.clone()],
+        ..matrix.clone()
+    };
+    let sheets = read_workbook(&workbook, None)?;
+    let rules = match sheets.first().map(parse_rules) {
+        Some(Ok(rules)) => rules,
+        Some(Err(errors)) => {
+            for err in &errors {
+                error!(
+                    "pricing rules sheet {} row {} {}",
+                    err.sheet, err.row, err.message
+                );
+            }
+            return Err(PremiumError::InvalidInput);
+        }
+        None => Vec::new(),
+    };
+    info!("loaded {} pricing rules from {}", rules.len(), path);
+    let count = rules.len();
+    *RULES.write().unwrap_or_else(|err| err.into_inner()) = rules;
+    Ok(count)
+}
+
+/// Parses the `name` and `rule` columns, in sheet order.
+pub fn parse_rules(sheet: &Sheet) -> Result<Vec<PricingRule>, Vec<RowError>> {
+    let error = |row: usize, message: String| RowError {
+        sheet: sheet.name.clone(),
+        row,
+        message,
+    };
+    let Some((header, body)) = sheet.rows.split_first() else {
+        return Err(vec![error(1, "header row is missing".to_string())]);
+    };
+    let (Some(name), Some(rule)) = (find_column(header, "name"), find_column(header, "rule"))
+    else {
+        return Err(vec![error(
+            1,
+            "missing required columns: name, rule".to_string(),
+        )]);
+    };
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (index, row) in body.iter().enumerate() {
+        let cell = |column: usize| row.get(column).map_or("", |value| value.trim());
+        if row.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        match PricingRule::parse(cell(name), cell(rule)) {
+            Ok(rule) => rules.push(rule),
+            Err(message) => errors.push(error(index + 2, message)),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(rules),
+        false => Err(errors),
+    }
+}
+
+/// The active rules, in sheet order.
+pub fn rules() -> Vec<PricingRule> {
+    RULES.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// The adjustments of every active rule matching `facts`.
+pub fn evaluate(facts: &Facts) -> Vec<RuleAdjustment> {
+    let rules = RULES.read().unwrap_or_else(|err| err.into_inner());
+    rules
+        .iter()
+        .filter(|rule| rule.matches(facts))
+        .map(|rule| RuleAdjustment {
+            name: rule.name.clone(),
+            percent: rule.percent,
+        })
+        .collect()
+}
+
+/// `premium` with the adjustments added together, never below zero.
+pub fn apply(premium: &Money, adjustments: &[RuleAdjustment]) -> Money {
+    match adjustments
+        .iter()
+        .map(|adjustment| adjustment.percent)
+        .sum()
+    {
+        0 => premium.clone(),
+        total => premium.percent((100 + total).max(0) as u32),
+    }
+}
+
+/// An active rule and whether it matched the tested request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleResult {
+    #[serde(flatten)]
+    pub rule: PricingRule,
+    pub matched: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleTest {
+    pub facts: Facts,
+    pub rules: Vec<RuleResult>,
+    /// The percent the matching rules add to the premium together.
+    pub percent: i32,
+}
+
+/// Evaluates the active rules against `input` without pricing it. Fails
+/// like a quote would on a date of birth no age can be taken from.
+pub fn test(input: &HealthRequest) -> anyhow::Result<RuleTest, PremiumError> {
+    let facts = Facts::of(input, input.age()?);
+    let rules: Vec<RuleResult> = rules()
+        .into_iter()
+        .map(|rule| RuleResult {
+            matched: rule.matches(&facts),
+            rule,
+        })
+        .collect();
+    Ok(RuleTest {
+        percent: rules
+            .iter()
+            .filter(|result| result.matched)
+            .map(|result| result.rule.percent)
+            .sum(),
+        facts,
+        rules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match_rules() {
+        let facts = Facts {
+            age: 62,
+            sum_insured: Some(1000000.0),
+            deductible: None,
+            bmi: None,
+            code: "1A".to_string(),
+            zone: Some("A".to_string()),
+            payment_frequency: "annual".to_string(),
+        };
+        let matches = |rule: &str| PricingRule::parse("r", rule).unwrap().matches(&facts);
+        assert!(matches("age > 60 && sumInsured >= 1000000 => +15%"));
+        assert!(!matches(
+            "age > 60 && !(code == \"1a\" || zone == \"B\") => +15%"
+        ));
+        assert!(matches("bmi > 30 || paymentFrequency == \"annual\" => -5%"));
+        assert!(!matches("bmi <= 30 => +5%"));
+        assert_eq!(
+            PricingRule::parse("r", "age<18=>-10%").unwrap().percent,
+            -10
+        );
+
+        for bad in [
+            "age > 60",
+            "age > 60 => 15",
+            "height > 1 => +5%",
+            "code > \"1A\" => +5%",
+            "age == \"60\" => +5%",
+            "(age > 60 => +5%",
+            "age > 60 age => +5%",
+        ] {
+            assert!(PricingRule::parse("r", bad).is_err(), "{}", bad);
+        }
+
+        let money = Money::parse("1000", crate::money::Currency::new("INR")).unwrap();
+        let adjustment = |percent| RuleAdjustment {
+            name: "r".to_string(),
+            percent,
+        };
+        assert_eq!(
+            apply(&money, &[adjustment(15), adjustment(-5)]).to_string(),
+            "1100"
+        );
+        assert!(apply(&money, &[adjustment(-150)]).is_zero());
+    }
+}
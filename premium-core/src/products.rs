@@ -0,0 +1,568 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use log::{error, info};
+
+use crate::config::{MatrixConfig, ProductRegistryConfig, ProductSettings, RoundingMode};
+use crate::matrix::{band_parts, find_column, read_workbook, RowError, Sheet};
+use crate::money::Money;
+use crate::premium::PremiumError;
+use crate::store::{self, VersionPremiums};
+
+/// Values of the percent, rounding and premium columns.
+const UNSIGNED: RangeInclusive<i64> = 0..=u32::MAX as i64;
+/// Values of the age columns.
+const SIGNED: RangeInclusive<i64> = i32::MIN as i64..=i32::MAX as i64;
+
+static PRODUCTS: RwLock<Option<HashMap<String, ProductSettings>>> = RwLock::new(None);
+static OUT_OF_BOUNDS: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the product settings from the config and `products.path`, replacing
+/// any loaded before, and returns how many products have settings.
+pub fn load(
+    config: &ProductRegistryConfig,
+    matrix: &MatrixConfig,
+) -> anyhow::Result<usize, PremiumError> {
+    let mut products = config.products.clone();
+    if let Some(path) = &config.path {
+        let read = if path.ends_with(".json") {
+            read_json(path)?
+        } else {
+            read_sheet(config, matrix, path)?
+        };
+        info!("read settings of {} products from {}", read.len(), path);
+        products.extend(read);
+    }
+    let count = products.len();
+    *PRODUCTS.write().unwrap_or_else(|err| err.into_inner()) = Some(products);
+    Ok(count)
+}
+
+fn read_json(path: &str) -> anyhow::Result<HashMap<String, ProductSettings>, PremiumError> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        error!("error reading product settings {} {}", path, err);
+        PremiumError::InternalServer
+    })?;
+    serde_json::from_str(&text).map_err(|err| {
+        error!("product settings {} are invalid {}", path, err);
+        PremiumError::InvalidInput
+    })
+}
+
+fn read_sheet(
+    config: &ProductRegistryConfig,
+    matrix: &MatrixConfig,
+    path: &str,
+) -> anyhow::Result<HashMap<String, ProductSettings>, PremiumError> {
+    let workbook = MatrixConfig {
+        path: path.to_string(),
+        sheets: vec![config.sheet.clone()],
+        ..matrix.clone()
+    };
+    let sheets = read_workbook(&workbook, None)?;
+    match sheets.first().map(parse_products) {
+        Some(Ok(products)) => Ok(products),
+        Some(Err(errors)) => {
+            for err in &errors {
+                error!(
+                    "product sheet {} row {} {}",
+                    err.sheet, err.row, err.message
+                );
+            }
+            Err(PremiumError::InvalidInput)
+        }
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// Parses a `code` column and any of `taxPercent`, `minAge`, `maxAge`,
+/// `rounding`, `roundingMode` (`nearest`, `up` or `down`), `currency`,
+/// `sumsInsured` (comma separated), `zoneFactors`
+/// (`zone=percent` pairs, comma separated), `cacheControl`, `premiumFloor`,
+/// `premiumCeiling` and `minimumPremium`. Empty cells are left unset.
+pub fn parse_products(sheet: &Sheet) -> Result<HashMap<String, ProductSettings>, Vec<RowError>> {
+    let error = |row: usize, message: &str| RowError {
+        sheet: sheet.name.clone(),
+        row,
+        message: message.to_string(),
+    };
+    let Some((header, body)) = sheet.rows.split_first() else {
+        return Err(vec![error(1, "header row is missing")]);
+    };
+    let find = |name| find_column(header, name);
+    let Some(code) = find("code") else {
+        return Err(vec![error(1, "missing required column: code")]);
+    };
+    let (tax_percent, min_age, max_age) = (find("taxPercent"), find("minAge"), find("maxAge"));
+    let (rounding, rounding_mode) = (find("rounding"), find("roundingMode"));
+    let currency = find("currency");
+    let (sums_insured, zone_factors) = (find("sumsInsured"), find("zoneFactors"));
+    let cache_control = find("cacheControl");
+    let (premium_floor, premium_ceiling) = (find("premiumFloor"), find("premiumCeiling"));
+    let minimum_premium = find("minimumPremium");
+
+    let mut products = HashMap::new();
+    let mut errors = Vec::new();
+    for (index, row) in body.iter().enumerate() {
+        let number = index + 2;
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .map_or("", |value| value.trim())
+        };
+        if row.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let list = |column| {
+            cell(column)
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let mut invalid = Vec::new();
+        // Values outside `range` are invalid rather than wrapped by the cast.
+        let mut number_in =
+            |column: Option<usize>, name: &str, range: RangeInclusive<i64>| match cell(column) {
+                "" => None,
+                value => match value.parse::<i64>() {
+                    Ok(value) if range.contains(&value) => Some(value),
+                    _ => {
+                        invalid.push(name.to_string());
+                        None
+                    }
+                },
+            };
+        let mut settings = ProductSettings {
+            tax_percent: number_in(tax_percent, "taxPercent", UNSIGNED).map(|value| value as u32),
+            min_age: number_in(min_age, "minAge", SIGNED).map(|value| value as i32),
+            max_age: number_in(max_age, "maxAge", SIGNED).map(|value| value as i32),
+            rounding: number_in(rounding, "rounding", UNSIGNED).map(|value| value as u32),
+            rounding_mode: RoundingMode::default(),
+            currency: Some(cell(currency).to_ascii_uppercase()).filter(|code| !code.is_empty()),
+            sums_insured: list(sums_insured).map(str::to_string).collect(),
+            zone_factors: HashMap::new(),
+            cache_control: Some(cell(cache_control).to_string()).filter(|value| !value.is_empty()),
+            premium_floor: number_in(premium_floor, "premiumFloor", UNSIGNED)
+                .map(|value| value as u32),
+            premium_ceiling: number_in(premium_ceiling, "premiumCeiling", UNSIGNED)
+                .map(|value| value as u32),
+            minimum_premium: number_in(minimum_premium, "minimumPremium", UNSIGNED)
+                .map(|value| value as u32),
+        };
+        match cell(rounding_mode).to_ascii_lowercase().as_str() {
+            "" | "nearest" => {}
+            "up" => settings.rounding_mode = RoundingMode::Up,
+            "down" => settings.rounding_mode = RoundingMode::Down,
+            _ => invalid.push("roundingMode".to_string()),
+        }
+        for pair in list(zone_factors) {
+            match pair
+                .split_once('=')
+                .map(|(zone, percent)| (zone.trim(), percent.trim().parse()))
+            {
+                Some((zone, Ok(percent))) if !zone.is_empty() => {
+                    settings.zone_factors.insert(zone.to_string(), percent);
+                }
+                _ => invalid.push("zoneFactors".to_string()),
+            }
+        }
+        let code = cell(Some(code)).to_string();
+        if code.is_empty() {
+            errors.push(error(number, "code is empty"));
+        } else if !invalid.is_empty() {
+            errors.push(error(number, &format!("invalid {}", invalid.join(", "))));
+        } else {
+            match products.entry(code) {
+                Entry::Occupied(entry) => {
+                    errors.push(error(number, &format!("duplicate code {}", entry.key())))
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(settings);
+                }
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(products),
+        false => Err(errors),
+    }
+}
+
+/// The product's settings; all unset when it has none or nothing is loaded.
+pub fn settings(product_code: &str) -> ProductSettings {
+    let products = PRODUCTS.read().unwrap_or_else(|err| err.into_inner());
+    products
+        .as_ref()
+        .and_then(|products| products.get(product_code))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// A product the active matrix has premiums for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogProduct {
+    pub code: String,
+    /// The sums insured it is priced for, smallest first.
+    pub sums_insured: Vec<String>,
+    /// The deductibles of a top-up product, smallest first; empty otherwise.
+    pub deductibles: Vec<String>,
+    pub settings: ProductSettings,
+}
+
+/// The products of the active matrix version with their settings; empty
+/// while no version is active.
+pub async fn catalog() -> anyhow::Result<Vec<CatalogProduct>, PremiumError> {
+    match store::versions().await?.active {
+        Some(version) => Ok(catalog_of(&store::version_premiums(version).await?)),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn catalog_of(premiums: &VersionPremiums) -> Vec<CatalogProduct> {
+    let mut catalog: Vec<CatalogProduct> = Vec::new();
+    for (key, _) in premiums.keys() {
+        let Some((code, band)) = key.split_once(':') else {
+            continue;
+        };
+        let (sum_insured, deductible, _) = band_parts(band);
+        let product = match catalog.iter_mut().find(|product| product.code == code) {
+            Some(product) => product,
+            None => {
+                catalog.push(CatalogProduct {
+                    code: code.to_string(),
+                    sums_insured: Vec::new(),
+                    deductibles: Vec::new(),
+                    settings: settings(code),
+                });
+                catalog.last_mut().expect("just pushed")
+            }
+        };
+        if !product.sums_insured.iter().any(|sum| sum == sum_insured) {
+            product.sums_insured.push(sum_insured.to_string());
+        }
+        if let Some(deductible) = deductible {
+            if !product.deductibles.iter().any(|known| known == deductible) {
+                product.deductibles.push(deductible.to_string());
+            }
+        }
+    }
+    let numerically = |amount: &String| (amount.parse::<u64>().unwrap_or(u64::MAX), amount.clone());
+    for product in &mut catalog {
+        product.sums_insured.sort_by_key(numerically);
+        product.deductibles.sort_by_key(numerically);
+    }
+    catalog
+}
+
+/// Fails with `SumInsuredNotOffered` when the product lists the sums insured
+/// it is sold with and `sum_insured` is not one of them.
+pub fn check_sum_insured(
+    settings: &ProductSettings,
+    product_code: &str,
+    sum_insured: &str,
+) -> anyhow::Result<(), PremiumError> {
+    if settings.sums_insured.is_empty()
+        || settings.sums_insured.iter().any(|sum| sum == sum_insured)
+    {
+        return Ok(());
+    }
+    error!(
+        "sum insured {} is not offered for product {}",
+        sum_insured, product_code
+    );
+    Err(PremiumError::SumInsuredNotOffered {
+        sum_insured: sum_insured.to_string(),
+        valid: settings.sums_insured.clone(),
+    })
+}
+
+/// Premiums refused for falling outside their product's bounds since the
+/// process started.
+pub fn out_of_bounds() -> u64 {
+    OUT_OF_BOUNDS.load(Ordering::Relaxed)
+}
+
+/// Fails with `PremiumOutOfBounds` when `premium` is under the product's
+/// floor or over its ceiling.
+pub fn check_bounds(
+    settings: &ProductSettings,
+    product_code: &str,
+    premium: &Money,
+) -> anyhow::Result<(), PremiumError> {
+    let bound = |units: u32| units as i64 * 10_i64.pow(premium.currency().minor_units);
+    let below = settings
+        .premium_floor
+        .is_some_and(|floor| premium.minor() < bound(floor));
+    let above = settings
+        .premium_ceiling
+        .is_some_and(|ceiling| premium.minor() > bound(ceiling));
+    if !below && !above {
+        return Ok(());
+    }
+    OUT_OF_BOUNDS.fetch_add(1, Ordering::Relaxed);
+    error!(
+        "premium {} of product {} is outside its bounds {:?}..{:?}, check the matrix",
+        premium, product_code, settings.premium_floor, settings.premium_ceiling
+    );
+    Err(PremiumError::PremiumOutOfBounds {
+        code: product_code.to_string(),
+        premium: premium.to_string(),
+    })
+}
+
+/// The premium raised to the product's minimum premium, and the uplift that
+/// took when it was below it.
+pub fn apply_minimum(settings: &ProductSettings, premium: &Money) -> (Money, Option<Money>) {
+    let Some(units) = settings.minimum_premium else {
+        return (premium.clone(), None);
+    };
+    let scale = 10_i64.pow(premium.currency().minor_units);
+    let minimum = Money::from_minor(units as i64 * scale, premium.currency().clone());
+    match premium.minor() < minimum.minor() {
+        true => (minimum.clone(), Some(minimum - premium.clone())),
+        false => (premium.clone(), None),
+    }
+}
+
+/// The premium rounded as the product asks, unchanged otherwise.
+pub fn round(settings: &ProductSettings, premium: &Money) -> Money {
+    match (settings.rounding, settings.rounding_mode) {
+        (Some(units), RoundingMode::Nearest) => premium.round_to(units),
+        (Some(units), RoundingMode::Up) => premium.round_up_to(units),
+        (Some(units), RoundingMode::Down) => premium.round_down_to(units),
+        (None, _) => premium.clone(),
+    }
+}
+
+/// What is charged for a premium: the tax on it and the rounded total for
+/// taxed products, the rounded premium itself otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Charge {
+    pub premium: Money,
+    pub tax: Option<Money>,
+    pub total_premium: Option<Money>,
+    /// What rounding added to the amount charged, negative when it took
+    /// off; set for products that round.
+    pub rounding_adjustment: Option<Money>,
+}
+
+/// Taxes `premium` and rounds the amount charged, last, so that it ends in
+/// the round figure the product asks for.
+pub fn charge(settings: &ProductSettings, premium: &Money) -> Charge {
+    let tax = settings.tax_percent.map(|percent| premium.percent(percent));
+    let charged = match &tax {
+        Some(tax) => premium.clone() + tax.clone(),
+        None => premium.clone(),
+    };
+    let rounded = round(settings, &charged);
+    let rounding_adjustment = settings.rounding.map(|_| rounded.clone() - charged.clone());
+    match tax {
+        Some(tax) => Charge {
+            premium: premium.clone(),
+            tax: Some(tax),
+            total_premium: Some(rounded),
+            rounding_adjustment,
+        },
+        None => Charge {
+            premium: rounded,
+            tax: None,
+            total_premium: None,
+            rounding_adjustment,
+        },
+    }
+}
+
+/// Percent of the premium charged for `zone`. Products without zone
+/// factors ignore the zone; products with them need a listed one.
+pub fn zone_percent(
+    settings: &ProductSettings,
+    zone: Option<&str>,
+) -> anyhow::Result<Option<u32>, PremiumError> {
+    if settings.zone_factors.is_empty() {
+        return Ok(None);
+    }
+    match zone.and_then(|zone| settings.zone_factors.get(zone)) {
+        Some(percent) => Ok(Some(*percent)),
+        None => {
+            error!("zone {:?} has no factor", zone);
+            Err(PremiumError::InvalidInput)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    #[test]
+    fn test_parse_products() {
+        let rows = [
+            vec![
+                "code",
+                "taxPercent",
+                "maxAge",
+                "currency",
+                "sumsInsured",
+                "zoneFactors",
+                "roundingMode",
+            ],
+            vec![
+                "1A",
+                "18",
+                "65",
+                "inr",
+                "100000, 200000",
+                "A=100, B=90",
+                "Up",
+            ],
+            vec!["2B", "", "", "", "", "", ""],
+            vec!["3C", "x", "", "", "", "B", "sideways"],
+            vec!["4D", "-1", "", "", "", "", ""],
+        ];
+        let sheet = Sheet {
+            name: "products".to_string(),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .collect(),
+        };
+        let errors = parse_products(&sheet).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].row, 4);
+        assert_eq!(
+            errors[0].message,
+            "invalid taxPercent, roundingMode, zoneFactors"
+        );
+        // A negative percent is refused, not wrapped to 4294967295.
+        assert_eq!(errors[1].row, 5);
+        assert_eq!(errors[1].message, "invalid taxPercent");
+
+        let sheet = Sheet {
+            rows: sheet.rows[..3].to_vec(),
+            ..sheet
+        };
+        let products = parse_products(&sheet).unwrap();
+        let settings = &products["1A"];
+        assert_eq!(settings.tax_percent, Some(18));
+        assert_eq!(settings.max_age, Some(65));
+        assert_eq!(settings.rounding_mode, RoundingMode::Up);
+        assert_eq!(settings.currency.as_deref(), Some("INR"));
+        assert_eq!(settings.sums_insured, vec!["100000", "200000"]);
+        assert_eq!(zone_percent(settings, Some("B")).unwrap(), Some(90));
+        assert!(zone_percent(settings, None).is_err());
+        assert!(matches!(
+            check_sum_insured(settings, "1A", "300000"),
+            Err(PremiumError::SumInsuredNotOffered { valid, .. }) if valid == ["100000", "200000"]
+        ));
+        assert_eq!(products["2B"], ProductSettings::default());
+        assert_eq!(zone_percent(&products["2B"], Some("B")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_charge() {
+        let inr = |amount: &str| Money::parse(amount, Currency::new("INR")).unwrap();
+        let charged = |settings: &ProductSettings| {
+            let charge = charge(settings, &inr("10424.10"));
+            [
+                Some(charge.premium),
+                charge.tax,
+                charge.total_premium,
+                charge.rounding_adjustment,
+            ]
+            .map(|amount| amount.map(|amount| amount.to_string()))
+        };
+        let taxed = ProductSettings {
+            tax_percent: Some(18),
+            rounding: Some(100),
+            rounding_mode: RoundingMode::Up,
+            ..ProductSettings::default()
+        };
+        assert_eq!(
+            charged(&taxed),
+            [
+                Some("10424.10".to_string()),
+                Some("1876.34".to_string()),
+                Some("12400".to_string()),
+                Some("99.56".to_string()),
+            ]
+        );
+        let nearest = ProductSettings {
+            rounding: Some(50),
+            ..ProductSettings::default()
+        };
+        assert_eq!(
+            charged(&nearest),
+            [
+                Some("10400".to_string()),
+                None,
+                None,
+                Some("-24.10".to_string())
+            ]
+        );
+        assert_eq!(
+            charged(&ProductSettings::default()),
+            [Some("10424.10".to_string()), None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_apply_minimum() {
+        let settings = ProductSettings {
+            minimum_premium: Some(5000),
+            ..ProductSettings::default()
+        };
+        let inr = |amount| Money::parse(amount, Currency::new("INR")).unwrap();
+        let (premium, uplift) = apply_minimum(&settings, &inr("4250.50"));
+        assert_eq!(premium.to_string(), "5000");
+        assert_eq!(uplift.unwrap().to_string(), "749.50");
+        assert_eq!(apply_minimum(&settings, &inr("5000")), (inr("5000"), None));
+        assert_eq!(
+            apply_minimum(&ProductSettings::default(), &inr("10")),
+            (inr("10"), None)
+        );
+    }
+
+    #[test]
+    fn test_check_bounds() {
+        let settings = ProductSettings {
+            premium_floor: Some(500),
+            premium_ceiling: Some(900),
+            ..ProductSettings::default()
+        };
+        let inr = |amount| Money::parse(amount, crate::money::Currency::new("INR")).unwrap();
+        assert!(check_bounds(&settings, "1A", &inr("500")).is_ok());
+        assert!(check_bounds(&settings, "1A", &inr("900")).is_ok());
+        assert!(matches!(
+            check_bounds(&settings, "1A", &inr("499.99")),
+            Err(PremiumError::PremiumOutOfBounds { .. })
+        ));
+        assert!(check_bounds(&settings, "1A", &inr("90000")).is_err());
+        assert!(check_bounds(&ProductSettings::default(), "1A", &inr("1")).is_ok());
+    }
+
+    #[test]
+    fn test_catalog_of() {
+        let premiums: VersionPremiums = [
+            (("1A:500000".to_string(), 1), 900),
+            (("1A:100000".to_string(), 1), 450),
+            (("1A:100000".to_string(), 2), 600),
+            (("2B:200000".to_string(), 1), 700),
+            (("TU:500000:500000".to_string(), 1), 90),
+            (("TU:500000:300000".to_string(), 1), 120),
+        ]
+        .into_iter()
+        .collect();
+        let catalog = catalog_of(&premiums);
+        assert_eq!(catalog.len(), 3);
+        assert_eq!(catalog[0].code, "1A");
+        assert_eq!(catalog[0].sums_insured, vec!["100000", "500000"]);
+        assert_eq!(catalog[1].sums_insured, vec!["200000"]);
+        assert_eq!(catalog[2].sums_insured, vec!["500000"]);
+        assert_eq!(catalog[2].deductibles, vec!["300000", "500000"]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use log::info;
+use moka::sync::Cache;
+use serde::Serialize;
+
+use crate::config::PremiumCacheConfig;
+use crate::store::MatrixPremium;
+use crate::tenant;
+
+type Configured = (PremiumCacheConfig, Cache<String, MatrixPremium>);
+
+static CACHE: RwLock<Option<Configured>> = RwLock::new(None);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Lookups of the premium cache since the process started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups answered from the cache, `None` before the first.
+    pub hit_ratio: Option<f64>,
+}
+
+pub fn stats() -> CacheStats {
+    let cache = cache();
+    let (hits, misses) = (HITS.load(Ordering::Relaxed), MISSES.load(Ordering::Relaxed));
+    CacheStats {
+        enabled: cache.is_some(),
+        entries: cache.map_or(0, |cache| cache.entry_count()),
+        hits,
+        misses,
+        hit_ratio: ratio(hits, misses),
+    }
+}
+
+fn ratio(hits: u64, misses: u64) -> Option<f64> {
+    (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+}
+
+/// Builds the premium cache; lookups go straight to redis when this is never
+/// called or the cache is disabled. Calling it again with changed settings
+/// replaces the cache and its entries.
+pub fn configure(config: &PremiumCacheConfig) {
+    let mut cache = CACHE.write().unwrap_or_else(|err| err.into_inner());
+    if cache.as_ref().map(|(current, _)| current) == Some(config) {
+        return;
+    }
+    if cache.is_some() {
+        info!("premium cache reconfigured to {:?}", config);
+    }
+    *cache = build(config).map(|built| (config.clone(), built));
+}
+
+fn build(config: &PremiumCacheConfig) -> Option<Cache<String, MatrixPremium>> {
+    if !config.enabled {
+        return None;
+    }
+    Some(
+        Cache::builder()
+            .max_capacity(config.max_entries)
+            .time_to_live(Duration::from_secs(config.ttl_secs))
+            .build(),
+    )
+}
+
+fn cache() -> Option<Cache<String, MatrixPremium>> {
+    let cache = CACHE.read().unwrap_or_else(|err| err.into_inner());
+    cache.as_ref().map(|(_, cache)| cache.clone())
+}
+
+/// Entries of different tenants never share a key, and entries of a pinned
+/// `version` never share one with the active version's.
+pub fn key(version: Option<u64>, code: &str, sum_insured: &str, score: i32) -> String {
+    let key = format!("{}:{}:{}", code, sum_insured, score);
+    tenant::scoped_key(&match version {
+        Some(version) => format!("v{}:{}", version, key),
+        None => key,
+    })
+}
+
+pub fn get(key: &str) -> Option<MatrixPremium> {
+    let premium = cache()?.get(key);
+    match premium {
+        Some(_) => HITS.fetch_add(1, Ordering::Relaxed),
+        None => MISSES.fetch_add(1, Ordering::Relaxed),
+    };
+    premium
+}
+
+pub fn insert(key: String, premium: MatrixPremium) {
+    if let Some(cache) = cache() {
+        cache.insert(key, premium);
+    }
+}
+
+/// Drops every cached premium after the matrix changes. Other instances
+/// keep serving their entries until the TTL expires.
+pub fn invalidate() {
+    if let Some(cache) = cache() {
+        cache.invalidate_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_cache() {
+        let config = PremiumCacheConfig {
+            enabled: true,
+            ..PremiumCacheConfig::default()
+        };
+        let cache = build(&config).unwrap();
+        let premium = MatrixPremium {
+            version: 3,
+            premium: "250".to_string(),
+        };
+        cache.insert(key(None, "1A", "100000", 1), premium.clone());
+        assert_eq!(key(Some(3), "1A", "100000", 1), "v3:1A:100000:1");
+        assert_eq!(cache.get("1A:100000:1"), Some(premium));
+        cache.invalidate_all();
+        assert_eq!(cache.get("1A:100000:1"), None);
+
+        assert!(build(&PremiumCacheConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(ratio(0, 0), None);
+        assert_eq!(ratio(3, 1), Some(0.75));
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use log::{info, warn};
+
+use crate::config::{RateTestConfig, RateTestMode};
+use crate::flags::{self, Flag};
+use crate::premium::{matrix_premium, HealthRequest};
+use crate::store::MatrixPremium;
+use crate::tenant;
+
+static CONFIG: RwLock<Option<RateTestConfig>> = RwLock::new(None);
+
+static SHADOW_QUOTES: AtomicU64 = AtomicU64::new(0);
+static SHADOW_HIGHER: AtomicU64 = AtomicU64::new(0);
+static SHADOW_LOWER: AtomicU64 = AtomicU64::new(0);
+static SHADOW_FAILED: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the candidate version and how it is tested, replacing the settings
+/// of an earlier call so a reload can start, change or stop a test.
+pub fn configure(config: &RateTestConfig) {
+    let mut current = CONFIG.write().unwrap_or_else(|err| err.into_inner());
+    if current.as_ref() != Some(config) {
+        match config.candidate_version {
+            Some(version) => info!(
+                "rate test of version {} in {:?} mode, split {}%",
+                version, config.mode, config.split_percent
+            ),
+            None if current.is_some() => info!("rate test stopped"),
+            None => {}
+        }
+    }
+    *current = Some(config.clone());
+}
+
+/// The candidate version and mode, for quotes of the default tenant; the
+/// version numbers of other tenants are their own.
+fn candidate() -> Option<(u64, RateTestConfig)> {
+    if tenant::current().is_some() {
+        return None;
+    }
+    let config = CONFIG.read().unwrap_or_else(|err| err.into_inner());
+    let config = config.as_ref()?;
+    config
+        .candidate_version
+        .map(|version| (version, config.clone()))
+}
+
+/// The version a split test prices `input` from: the candidate for
+/// `splitPercent` of requests, `None` for the active version otherwise. The
+/// same request always lands on the same side, so requotes and
+/// revalidations agree.
+pub fn split_version(input: &HealthRequest) -> Option<u64> {
+    let (version, config) = candidate()?;
+    if config.mode != RateTestMode::Split {
+        return None;
+    }
+    (bucket(&input.rollout_key()) < config.split_percent).then_some(version)
+}
+
+/// FNV-1a of `key` mod 100, stable across builds and restarts.
+pub(crate) fn bucket(key: &str) -> u32 {
+    let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    (hash % 100) as u32
+}
+
+/// In shadow mode, prices the same matrix cell from the candidate version in
+/// the background and logs and counts how it differs from `served`.
+pub fn shadow(code: &str, band: &str, score: i32, served: &MatrixPremium) {
+    let Some((version, config)) = candidate() else {
+        return;
+    };
+    if config.mode != RateTestMode::Shadow || version == served.version {
+        return;
+    }
+    if !flags::enabled(Flag::ShadowPricing, &format!("{}|{}|{}", code, band, score)) {
+        return;
+    }
+    let (code, band, served) = (code.to_string(), band.to_string(), served.clone());
+    async_std::task::spawn(async move {
+        let candidate = matrix_premium(Some(version), &code, &band, score).await;
+        record(&code, &band, score, &served, version, candidate.ok());
+    });
+}
+
+fn record(
+    code: &str,
+    band: &str,
+    score: i32,
+    served: &MatrixPremium,
+    version: u64,
+    candidate: Option<MatrixPremium>,
+) {
+    SHADOW_QUOTES.fetch_add(1, Ordering::Relaxed);
+    let premiums = candidate.as_ref().and_then(|candidate| {
+        let parse = |premium: &str| premium.parse::<f64>().ok();
+        parse(&served.premium).zip(parse(&candidate.premium))
+    });
+    let Some((active, shadow)) = premiums else {
+        SHADOW_FAILED.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "shadow version {} has no premium for {}:{} score {}",
+            version, code, band, score
+        );
+        return;
+    };
+    if shadow > active {
+        SHADOW_HIGHER.fetch_add(1, Ordering::Relaxed);
+    } else if shadow < active {
+        SHADOW_LOWER.fetch_add(1, Ordering::Relaxed);
+    }
+    let delta = if active == 0.0 {
+        0.0
+    } else {
+        (shadow - active) / active * 100.0
+    };
+    info!(
+        "shadow version {} prices {}:{} score {} at {} against {} from version {} ({:+.1}%)",
+        version, code, band, score, shadow, active, served.version, delta
+    );
+}
+
+/// Counts of shadow lookups since startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShadowStats {
+    pub quotes: u64,
+    /// Quotes the candidate priced higher or lower than the active version.
+    pub higher: u64,
+    pub lower: u64,
+    /// Quotes the candidate had no premium for.
+    pub failed: u64,
+}
+
+pub fn stats() -> ShadowStats {
+    ShadowStats {
+        quotes: SHADOW_QUOTES.load(Ordering::Relaxed),
+        higher: SHADOW_HIGHER.load(Ordering::Relaxed),
+        lower: SHADOW_LOWER.load(Ordering::Relaxed),
+        failed: SHADOW_FAILED.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_shadow() {
+        let request = |dob: &str| HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: "100000".to_string(),
+            date_of_birth: dob.to_string(),
+            ..HealthRequest::default()
+        };
+        configure(&RateTestConfig {
+            candidate_version: Some(7),
+            mode: RateTestMode::Split,
+            split_percent: 30,
+        });
+        let split: Vec<Option<u64>> = (1..=200)
+            .map(|day| split_version(&request(&format!("1977-{}", day))))
+            .collect();
+        let candidates = split.iter().filter(|version| version.is_some()).count();
+        assert!((30..=90).contains(&candidates), "{}", candidates);
+        assert_eq!(
+            split_version(&request("1977-1")),
+            split_version(&request("1977-1"))
+        );
+        let scoped = async_std::task::block_on(tenant::scope(Some("acme".to_string()), async {
+            (1..=200)
+                .filter_map(|day| split_version(&request(&format!("1977-{}", day))))
+                .count()
+        }));
+        assert_eq!(scoped, 0);
+
+        let before = stats();
+        let served = |premium: &str| MatrixPremium {
+            version: 6,
+            premium: premium.to_string(),
+        };
+        record("1A", "100000", 3, &served("750"), 7, Some(served("800")));
+        record("1A", "100000", 3, &served("750"), 7, None);
+        let after = stats();
+        assert_eq!(after.quotes - before.quotes, 2);
+        assert_eq!(after.higher - before.higher, 1);
+        assert_eq!(after.failed - before.failed, 1);
+        configure(&RateTestConfig::default());
+        assert_eq!(split_version(&request("1977-1")), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use async_std::task;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::breaker::CircuitBreaker;
+use crate::config::{ReferralConfig, ReferralDecision, ReferralOutcome};
+use crate::premium::{HealthRequest, PremiumError};
+
+struct Referrals {
+    config: ReferralConfig,
+    breaker: CircuitBreaker,
+}
+
+static REFERRALS: OnceLock<Referrals> = OnceLock::new();
+
+static SENT: AtomicU64 = AtomicU64::new(0);
+static ACCEPTED: AtomicU64 = AtomicU64::new(0);
+static DECLINED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the underwriting service; referred quotes stay referred when this is
+/// never called.
+pub fn configure(config: &ReferralConfig) {
+    let referrals = Referrals {
+        config: config.clone(),
+        breaker: CircuitBreaker::new("underwriting service", config.circuit_breaker.clone()),
+    };
+    if REFERRALS.set(referrals).is_err() {
+        warn!("underwriting referral already configured");
+    }
+}
+
+/// Counters of the referrals made since startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferralStats {
+    /// Requests sent to the service.
+    pub sent: u64,
+    pub accepted: u64,
+    pub declined: u64,
+    /// Referrals left referred because the service failed, timed out or its
+    /// circuit was open.
+    pub failed: u64,
+}
+
+pub fn stats() -> ReferralStats {
+    ReferralStats {
+        sent: SENT.load(Ordering::Relaxed),
+        accepted: ACCEPTED.load(Ordering::Relaxed),
+        declined: DECLINED.load(Ordering::Relaxed),
+        failed: FAILED.load(Ordering::Relaxed),
+    }
+}
+
+/// The body POSTed to the service.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReferralRequest<'a> {
+    request: &'a HealthRequest,
+    age: i32,
+    reasons: &'a [String],
+}
+
+/// Asks the underwriting service about a quote the rules referred for
+/// `reasons`, returning the loading percent to add when it accepts. Fails
+/// with `CoverDeclined` when it declines, and with `ReferToUnderwriter` when
+/// it refers or cannot be asked.
+pub async fn refer(
+    input: &HealthRequest,
+    age: i32,
+    reasons: Vec<String>,
+) -> anyhow::Result<u32, PremiumError> {
+    match REFERRALS.get() {
+        Some(referrals) => refer_with(referrals, input, age, reasons).await,
+        None => Err(PremiumError::ReferToUnderwriter { reasons }),
+    }
+}
+
+async fn refer_with(
+    referrals: &Referrals,
+    input: &HealthRequest,
+    age: i32,
+    reasons: Vec<String>,
+) -> anyhow::Result<u32, PremiumError> {
+    let decision = match (&referrals.config.stub, &referrals.config.url) {
+        (Some(stub), _) => stub.clone(),
+        (None, Some(url)) => match call(referrals, url, input, age, &reasons).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                FAILED.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "underwriting referral of product {} failed, quote stays referred: {}",
+                    input.code, err
+                );
+                return Err(PremiumError::ReferToUnderwriter { reasons });
+            }
+        },
+        (None, None) => return Err(PremiumError::ReferToUnderwriter { reasons }),
+    };
+    info!(
+        "underwriting service decided {:?} for product {}",
+        decision.outcome, input.code
+    );
+    // The service's own reasons, when it gives any, replace the rules'.
+    let reasons = match decision.reasons.is_empty() {
+        true => reasons,
+        false => decision.reasons,
+    };
+    match decision.outcome {
+        ReferralOutcome::Accept => {
+            ACCEPTED.fetch_add(1, Ordering::Relaxed);
+            Ok(decision.loading_percent)
+        }
+        ReferralOutcome::Decline => {
+            DECLINED.fetch_add(1, Ordering::Relaxed);
+            Err(PremiumError::CoverDeclined { reasons })
+        }
+        ReferralOutcome::Refer => Err(PremiumError::ReferToUnderwriter { reasons }),
+    }
+}
+
+/// POSTs the referral through the breaker and reads the decision, failing
+/// on any transport error, non-2xx status or unreadable body.
+async fn call(
+    referrals: &Referrals,
+    url: &str,
+    input: &HealthRequest,
+    age: i32,
+    reasons: &[String],
+) -> Result<ReferralDecision, String> {
+    referrals
+        .breaker
+        .allow()
+        .map_err(|_| "circuit open".to_string())?;
+    SENT.fetch_add(1, Ordering::Relaxed);
+    let body = serde_json::to_string(&ReferralRequest {
+        request: input,
+        age,
+        reasons,
+    })
+    .map_err(|err| err.to_string())?;
+    let url = url.to_string();
+    let headers = referrals.config.headers.clone();
+    let timeout = Duration::from_millis(referrals.config.timeout_ms);
+    let result = task::spawn_blocking(move || {
+        let mut request = ureq::post(&url)
+            .timeout(timeout)
+            .set("Content-Type", "application/json");
+        for (name, value) in &headers {
+            request = request.set(name, value);
+        }
+        request
+            .send_string(&body)
+            .map_err(|err| err.to_string())?
+            .into_json::<ReferralDecision>()
+            .map_err(|err| err.to_string())
+    })
+    .await;
+    match result {
+        Ok(_) => referrals.breaker.success(),
+        Err(_) => referrals.breaker.failure(),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CircuitBreakerConfig;
+
+    fn referrals(config: ReferralConfig) -> Referrals {
+        Referrals {
+            breaker: CircuitBreaker::new("test", config.circuit_breaker.clone()),
+            config,
+        }
+    }
+
+    #[test]
+    fn test_stub_decisions() {
+        let input = HealthRequest::default();
+        let reasons = || vec!["BMI 36.2 is above 35".to_string()];
+        let stubbed = |outcome, reasons: Vec<&str>| {
+            referrals(ReferralConfig {
+                stub: Some(ReferralDecision {
+                    outcome,
+                    loading_percent: 25,
+                    reasons: reasons.into_iter().map(str::to_string).collect(),
+                }),
+                ..ReferralConfig::default()
+            })
+        };
+        task::block_on(async {
+            let accepted = stubbed(ReferralOutcome::Accept, Vec::new());
+            assert_eq!(
+                refer_with(&accepted, &input, 40, reasons()).await.unwrap(),
+                25
+            );
+            let declined = stubbed(ReferralOutcome::Decline, vec!["smoker"]);
+            assert!(matches!(
+                refer_with(&declined, &input, 40, reasons()).await,
+                Err(PremiumError::CoverDeclined { reasons }) if reasons == ["smoker"]
+            ));
+            let referred = stubbed(ReferralOutcome::Refer, Vec::new());
+            assert!(matches!(
+                refer_with(&referred, &input, 40, reasons()).await,
+                Err(PremiumError::ReferToUnderwriter { reasons: kept }) if kept == reasons()
+            ));
+        });
+    }
+
+    #[test]
+    fn test_unreachable_service_stays_referred() {
+        let unreachable = referrals(ReferralConfig {
+            url: Some("http://127.0.0.1:9/decisions".to_string()),
+            timeout_ms: 500,
+            circuit_breaker: CircuitBreakerConfig {
+                failure_threshold: 1,
+                open_ms: 60_000,
+                half_open_probes: 1,
+            },
+            ..ReferralConfig::default()
+        });
+        let input = HealthRequest::default();
+        task::block_on(async {
+            for _ in 0..2 {
+                assert!(matches!(
+                    refer_with(
+                        &unreachable,
+                        &input,
+                        40,
+                        vec!["age 70 is above 65".to_string()]
+                    )
+                    .await,
+                    Err(PremiumError::ReferToUnderwriter { .. })
+                ));
+            }
+        });
+        assert_eq!(unreachable.breaker.state(), "open");
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use log::{error, warn};
+use redis::{ErrorKind, RedisError, RedisResult};
+use serde::Serialize;
+
+use crate::chaos;
+use crate::config::RetryConfig;
+use crate::connection::{conn_read, conn_write, redis_error, RedisConnection};
+use crate::premium::PremiumError;
+
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static RECOVERED: AtomicU64 = AtomicU64::new(0);
+static EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts of retried redis commands since the process started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryStats {
+    /// Attempts made after a first one failed.
+    pub retries: u64,
+    /// Commands that succeeded after at least one retry.
+    pub recovered: u64,
+    /// Commands that still failed transiently on their last attempt.
+    pub exhausted: u64,
+}
+
+pub fn stats() -> RetryStats {
+    RetryStats {
+        retries: RETRIES.load(Ordering::Relaxed),
+        recovered: RECOVERED.load(Ordering::Relaxed),
+        exhausted: EXHAUSTED.load(Ordering::Relaxed),
+    }
+}
+
+/// Errors worth another attempt: dropped or refused connections, cluster
+/// redirects and a node still loading or down. Timeouts are not retried, as
+/// every attempt would wait out the full timeout again.
+pub fn is_transient(err: &RedisError) -> bool {
+    if err.is_timeout() {
+        return false;
+    }
+    err.is_io_error()
+        || err.is_connection_dropped()
+        || err.is_connection_refusal()
+        || err.is_cluster_error()
+        || matches!(
+            err.kind(),
+            ErrorKind::BusyLoadingError | ErrorKind::MasterDown
+        )
+}
+
+/// Exponential delay before retry `attempt` (1 for the first retry), capped
+/// at the maximum, with the upper half jittered so callers spread out.
+pub fn backoff(config: &RetryConfig, attempt: u32) -> Duration {
+    let exponential = config
+        .base_delay_ms
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let delay = exponential.min(config.max_delay_ms);
+    let half = delay / 2;
+    Duration::from_millis(half + fastrand::u64(0..=delay - half))
+}
+
+/// Which node a command has to reach.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+/// Runs `command` on a fresh connection, retrying transient failures on a
+/// new connection with backoff. Only commands that are safe to repeat may go
+/// through here. Faults injected by chaos mode are met here like real ones.
+pub(crate) async fn with_retry<T, F>(
+    config: &RetryConfig,
+    what: &str,
+    access: Access,
+    mut command: F,
+) -> anyhow::Result<T, PremiumError>
+where
+    F: FnMut(&mut RedisConnection) -> RedisResult<T>,
+{
+    let attempts = config.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let mut conn = match access {
+            Access::Read => conn_read().await?,
+            Access::Write => conn_write().await?,
+        };
+        if let Some(delay) = chaos::latency() {
+            async_std::task::sleep(delay).await;
+        }
+        let err = match chaos::fault().and_then(|()| command(&mut conn)) {
+            Ok(value) => {
+                if attempt > 1 {
+                    RECOVERED.fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(value);
+            }
+            Err(err) => err,
+        };
+        drop(conn);
+        if !is_transient(&err) {
+            error!("Redis error while {} {}", what, err);
+            return Err(redis_error(&err));
+        }
+        if attempt >= attempts {
+            EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+            error!(
+                "Redis error while {} after {} attempts {}",
+                what, attempt, err
+            );
+            return Err(redis_error(&err));
+        }
+        let delay = backoff(config, attempt);
+        warn!(
+            "transient redis error while {}, retrying in {}ms {}",
+            what,
+            delay.as_millis(),
+            err
+        );
+        RETRIES.fetch_add(1, Ordering::Relaxed);
+        async_std::task::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_transient_and_backoff() {
+        let reset = RedisError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_transient(&reset));
+        let moved = RedisError::from((ErrorKind::Moved, "moved", "3999 10.0.0.2:6379".to_string()));
+        assert!(is_transient(&moved));
+        let timeout = RedisError::from(io::Error::from(io::ErrorKind::TimedOut));
+        assert!(!is_transient(&timeout));
+        let wrong_type = RedisError::from((ErrorKind::TypeError, "not a sorted set"));
+        assert!(!is_transient(&wrong_type));
+
+        let config = RetryConfig {
+            max_attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+        };
+        for _ in 0..20 {
+            let first = backoff(&config, 1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = backoff(&config, 4);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::OnceLock;
+
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::JSONSchema;
+use log::error;
+use serde_json::{json, Value};
+
+use crate::premium::PremiumError;
+
+const DATE_PATTERN: &str = "^[0-9]{4}-[0-9]{2}-[0-9]{2}$";
+
+static SCHEMA: OnceLock<Value> = OnceLock::new();
+static COMPILED: OnceLock<JSONSchema> = OnceLock::new();
+
+/// JSON Schema of a quote request body. Unknown fields are allowed, as they
+/// are when the body is deserialized.
+pub fn health_request() -> &'static Value {
+    SCHEMA.get_or_init(|| {
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "HealthRequest",
+            "type": "object",
+            "required": ["code", "sumInsured"],
+            "if": {"not": {"required": ["age"]}},
+            "then": {"required": ["dateOfBirth"]},
+            "properties": {
+                "code": {"type": "string", "minLength": 1},
+                "sumInsured": {"type": "string", "pattern": "^0*[1-9][0-9]*(\\.[0-9]+)?$"},
+                "dateOfBirth": {"type": "string", "minLength": 1},
+                "age": {"type": ["integer", "null"], "minimum": 0, "maximum": 150},
+                "policyStartDate": {"type": ["string", "null"], "pattern": DATE_PATTERN},
+                "policyEndDate": {"type": ["string", "null"], "pattern": DATE_PATTERN},
+                "discountCodes": {
+                    "type": "array",
+                    "items": {"type": "string", "minLength": 1}
+                },
+                "paymentFrequency": {
+                    "enum": ["annual", "semi-annual", "quarterly", "monthly", null]
+                },
+                "zone": {"type": ["string", "null"], "minLength": 1},
+                "deductible": {"type": ["string", "null"], "pattern": "^[0-9]+$"},
+                "gender": {"enum": ["male", "female", null]},
+                "maternityCover": {"type": ["boolean", "null"]},
+                "opdCover": {"type": ["boolean", "null"]},
+                "restoreBenefit": {"type": ["boolean", "null"]},
+                "declaredConditions": {
+                    "type": "array",
+                    "items": {"type": "string", "minLength": 1}
+                },
+                "heightCm": {"type": ["number", "null"], "exclusiveMinimum": 0},
+                "weightKg": {"type": ["number", "null"], "exclusiveMinimum": 0},
+                "asOf": {"type": ["string", "null"], "pattern": DATE_PATTERN},
+                "channel": {"type": ["string", "null"], "minLength": 1}
+            }
+        })
+    })
+}
+
+fn compiled() -> &'static JSONSchema {
+    COMPILED.get_or_init(|| {
+        JSONSchema::compile(health_request()).expect("health request schema compiles")
+    })
+}
+
+/// Checks a quote request body against the schema, reporting the first
+/// offending field and the constraint it breaks. The `dateOfBirth` unless
+/// `age` condition is reported after the plain constraints.
+pub fn validate(body: &Value) -> anyhow::Result<(), PremiumError> {
+    let errors = match compiled().validate(body) {
+        Ok(()) => return Ok(()),
+        Err(errors) => errors,
+    };
+    let Some(err) = errors.min_by_key(|err| err.schema_path.to_string().starts_with("/then"))
+    else {
+        return Ok(());
+    };
+    let mut field = err.instance_path.to_string();
+    if let ValidationErrorKind::Required { property } = &err.kind {
+        let property = property
+            .as_str()
+            .map_or(property.to_string(), str::to_string);
+        field = format!("{}/{}", field, property);
+    }
+    let constraint = err
+        .schema_path
+        .to_string()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    error!("health request fails schema at {} {}", field, err);
+    Err(PremiumError::SchemaViolation {
+        field,
+        constraint,
+        detail: err.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violation(body: Value) -> (String, String) {
+        match validate(&body) {
+            Err(PremiumError::SchemaViolation {
+                field, constraint, ..
+            }) => (field, constraint),
+            other => panic!("expected a schema violation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": "1990-01-01"});
+        assert!(validate(&valid).is_ok());
+
+        let missing = json!({"code": "1A", "sumInsured": "100000"});
+        assert_eq!(
+            violation(missing),
+            ("/dateOfBirth".to_string(), "required".to_string())
+        );
+        let missing = json!({"code": "1A"});
+        assert_eq!(
+            violation(missing),
+            ("/sumInsured".to_string(), "required".to_string())
+        );
+        let age = json!({"code": "1A", "sumInsured": "100000", "age": 40});
+        assert!(validate(&age).is_ok());
+        let age = json!({"code": "1A", "sumInsured": "100000", "age": -1});
+        assert_eq!(violation(age), ("/age".to_string(), "minimum".to_string()));
+
+        let pattern = json!({"code": "1A", "sumInsured": "1 lakh", "dateOfBirth": "1990-01-01"});
+        assert_eq!(
+            violation(pattern),
+            ("/sumInsured".to_string(), "pattern".to_string())
+        );
+        for sum_insured in ["0", "000", "-100000"] {
+            let request =
+                json!({"code": "1A", "sumInsured": sum_insured, "dateOfBirth": "1990-01-01"});
+            assert_eq!(violation(request).1, "pattern");
+        }
+
+        let frequency = json!({
+            "code": "1A",
+            "sumInsured": "100000",
+            "dateOfBirth": "1990-01-01",
+            "paymentFrequency": "weekly"
+        });
+        assert_eq!(
+            violation(frequency),
+            ("/paymentFrequency".to_string(), "enum".to_string())
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::OnceLock;
+
+use chrono::{Months, NaiveDate};
+use log::warn;
+
+use crate::config::{ShortPeriodConfig, ShortRate};
+use crate::premium::PremiumError;
+
+static RATES: OnceLock<Vec<ShortRate>> = OnceLock::new();
+
+/// Sets the short-rate table; the default scale applies when this is never
+/// called.
+pub fn configure(config: &ShortPeriodConfig) {
+    let mut rates = config.rates.clone();
+    rates.sort_by_key(|rate| rate.months);
+    if RATES.set(rates).is_err() {
+        warn!("short period rates already configured");
+    }
+}
+
+fn rates() -> &'static [ShortRate] {
+    RATES.get_or_init(|| ShortPeriodConfig::default().rates)
+}
+
+/// Whole months from `start` to `end`, with any part month counted as one.
+fn months(start: NaiveDate, end: NaiveDate) -> u32 {
+    let mut months = 0;
+    while let Some(next) = start.checked_add_months(Months::new(months)) {
+        if next >= end {
+            break;
+        }
+        months += 1;
+    }
+    months
+}
+
+pub(crate) fn parse_date(value: &str) -> anyhow::Result<NaiveDate, PremiumError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| PremiumError::InvalidInput)
+}
+
+/// Percentage of the annual premium due for the policy period, `None` when
+/// no period was given. Both dates are required together, the end must fall
+/// after the start and the period must be covered by the table.
+pub fn percent(
+    start: Option<&str>,
+    end: Option<&str>,
+) -> anyhow::Result<Option<u32>, PremiumError> {
+    let (start, end) = match (start, end) {
+        (None, None) => return Ok(None),
+        (Some(start), Some(end)) => (parse_date(start)?, parse_date(end)?),
+        _ => return Err(PremiumError::InvalidInput),
+    };
+    if end <= start {
+        return Err(PremiumError::InvalidInput);
+    }
+    let months = months(start, end);
+    rate(rates(), months)
+        .map(Some)
+        .ok_or(PremiumError::InvalidInput)
+}
+
+fn rate(rates: &[ShortRate], months: u32) -> Option<u32> {
+    rates
+        .iter()
+        .find(|rate| rate.months >= months)
+        .map(|rate| rate.percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_period_percent() {
+        let date = |value| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        assert_eq!(months(date("2024-01-01"), date("2024-04-01")), 3);
+        assert_eq!(months(date("2024-01-01"), date("2024-04-02")), 4);
+        assert_eq!(months(date("2024-01-31"), date("2024-02-29")), 1);
+        assert_eq!(months(date("2024-01-01"), date("2025-01-01")), 12);
+
+        assert_eq!(percent(None, None).unwrap(), None);
+        assert_eq!(
+            percent(Some("2024-01-01"), Some("2024-04-01")).unwrap(),
+            Some(30)
+        );
+        assert_eq!(
+            percent(Some("2024-01-01"), Some("2024-01-10")).unwrap(),
+            Some(15)
+        );
+        assert!(percent(Some("2024-01-01"), None).is_err());
+        assert!(percent(Some("2024-04-01"), Some("2024-01-01")).is_err());
+        assert!(percent(Some("2024-01-01"), Some("2025-02-01")).is_err());
+    }
+}
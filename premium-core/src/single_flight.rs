@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::premium::PremiumError;
+
+type Outcome<T> = Option<Result<T, PremiumError>>;
+
+/// Runs one call per key at a time; callers arriving while it is in flight
+/// wait for it and share its result instead of making their own.
+pub struct SingleFlight<T> {
+    calls: Mutex<HashMap<String, Arc<async_std::sync::Mutex<Outcome<T>>>>>,
+    shared: AtomicU64,
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+            shared: AtomicU64::new(0),
+        }
+    }
+
+    /// Calls that were answered with another call's result.
+    pub fn shared(&self) -> u64 {
+        self.shared.load(Ordering::Relaxed)
+    }
+
+    pub async fn run<F, Fut>(&self, key: &str, call: F) -> Result<T, PremiumError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, PremiumError>>,
+    {
+        let (flight, leader) = {
+            let mut calls = self.calls.lock().unwrap_or_else(|err| err.into_inner());
+            match calls.get(key) {
+                Some(flight) => (flight.clone(), None),
+                None => {
+                    let flight = Arc::new(async_std::sync::Mutex::new(None));
+                    // Taken before the call is visible so followers queue
+                    // behind the leader.
+                    let guard = flight.try_lock_arc().expect("new lock is free");
+                    calls.insert(key.to_string(), flight.clone());
+                    (flight, Some(guard))
+                }
+            }
+        };
+        if let Some(mut guard) = leader {
+            let result = call().await;
+            *guard = Some(result.clone());
+            self.calls
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .remove(key);
+            return result;
+        }
+        let outcome = flight.lock().await.clone();
+        match outcome {
+            Some(result) => {
+                self.shared.fetch_add(1, Ordering::Relaxed);
+                result
+            }
+            // The leader was cancelled before finishing: drop its entry and
+            // call directly.
+            None => {
+                self.forget(key, &flight);
+                call().await
+            }
+        }
+    }
+}
+
+impl<T> SingleFlight<T> {
+    fn forget(&self, key: &str, flight: &Arc<async_std::sync::Mutex<Outcome<T>>>) {
+        let mut calls = self.calls.lock().unwrap_or_else(|err| err.into_inner());
+        if calls
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, flight))
+        {
+            calls.remove(key);
+        }
+    }
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+    fn default() -> Self {
+        SingleFlight::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    #[test]
+    fn test_concurrent_calls_share_one_result() {
+        static CALLS: AtomicU32 = AtomicU32::new(0);
+        let flight = Arc::new(SingleFlight::new());
+        let lookup = || async {
+            task::sleep(Duration::from_millis(50)).await;
+            Ok(CALLS.fetch_add(1, Ordering::SeqCst) + 1)
+        };
+        task::block_on(async {
+            let waiting: Vec<_> = (0..5)
+                .map(|_| {
+                    let flight = flight.clone();
+                    task::spawn(async move { flight.run("1A:100000:3", lookup).await })
+                })
+                .collect();
+            for handle in waiting {
+                assert_eq!(handle.await.unwrap(), 1);
+            }
+            assert_eq!(flight.shared(), 4);
+            assert_eq!(flight.run("1A:100000:3", lookup).await.unwrap(), 2);
+        });
+    }
+}
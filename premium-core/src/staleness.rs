@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use chrono::Local;
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::config::StalenessConfig;
+use crate::store::{self, KeyHealth};
+
+/// Latest check outcome; `None` until the first check finishes.
+static REPORT: RwLock<Option<StalenessReport>> = RwLock::new(None);
+static INCOMPLETE: AtomicU64 = AtomicU64::new(0);
+static EXPIRING: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StalenessReport {
+    /// Every key loaded with the active version is still there.
+    pub complete: bool,
+    /// The first key to expire has less than `expiryWarningSecs` left.
+    pub expiring: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_keys: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in_secs: Option<u64>,
+    pub detail: String,
+    pub checked_at: String,
+}
+
+/// Checks that found keys of the active version missing, and that found it
+/// about to expire.
+pub struct StalenessStats {
+    pub incomplete: u64,
+    pub expiring: u64,
+}
+
+pub fn stats() -> StalenessStats {
+    StalenessStats {
+        incomplete: INCOMPLETE.load(Ordering::Relaxed),
+        expiring: EXPIRING.load(Ordering::Relaxed),
+    }
+}
+
+/// The outcome of the latest check, for the readiness probe.
+pub fn report() -> Option<StalenessReport> {
+    REPORT.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// Counts the active version's keys against those it was loaded with and
+/// reads how long until the first expires. A matrix missing keys is logged
+/// as an error and one close to expiry as a warning. The report is kept for
+/// [`report`].
+pub async fn check(config: &StalenessConfig) -> StalenessReport {
+    let report = match store::key_health().await {
+        Ok(health) => evaluate(health.as_ref(), config.expiry_warning_secs),
+        Err(err) => StalenessReport {
+            complete: false,
+            expiring: false,
+            version: None,
+            expected_keys: None,
+            keys: None,
+            expires_in_secs: None,
+            detail: err.to_string(),
+            checked_at: Local::now().to_rfc3339(),
+        },
+    };
+    if !report.complete {
+        INCOMPLETE.fetch_add(1, Ordering::Relaxed);
+        error!("matrix keys check failed: {}", report.detail);
+    } else if report.expiring {
+        EXPIRING.fetch_add(1, Ordering::Relaxed);
+        warn!("matrix keys check: {}", report.detail);
+    } else {
+        info!("matrix keys check passed: {}", report.detail);
+    }
+    *REPORT.write().unwrap_or_else(|err| err.into_inner()) = Some(report.clone());
+    report
+}
+
+/// Checks every `intervalSecs` until the process exits; never when 0.
+pub async fn watch(config: StalenessConfig) {
+    if config.interval_secs == 0 {
+        return;
+    }
+    let interval = std::time::Duration::from_secs(config.interval_secs);
+    loop {
+        check(&config).await;
+        async_std::task::sleep(interval).await;
+    }
+}
+
+fn evaluate(health: Option<&KeyHealth>, expiry_warning_secs: u64) -> StalenessReport {
+    let checked_at = Local::now().to_rfc3339();
+    let Some(health) = health else {
+        return StalenessReport {
+            complete: true,
+            expiring: false,
+            version: None,
+            expected_keys: None,
+            keys: None,
+            expires_in_secs: None,
+            detail: "no expiring matrix keys to check".to_string(),
+            checked_at,
+        };
+    };
+    let complete = health.keys > 0
+        && health
+            .expected_keys
+            .is_none_or(|expected| health.keys >= expected);
+    let expiring = health
+        .expires_in_secs
+        .is_some_and(|secs| secs < expiry_warning_secs);
+    let mut detail = match health.expected_keys {
+        Some(expected) => format!(
+            "version {} has {} of {} keys",
+            health.version, health.keys, expected
+        ),
+        None => format!("version {} has {} keys", health.version, health.keys),
+    };
+    if let Some(secs) = health.expires_in_secs {
+        detail.push_str(&format!(", first expiring in {}s", secs));
+    }
+    StalenessReport {
+        complete,
+        expiring,
+        version: Some(health.version),
+        expected_keys: health.expected_keys,
+        keys: Some(health.keys),
+        expires_in_secs: health.expires_in_secs,
+        detail,
+        checked_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let health = |keys, expires_in_secs| KeyHealth {
+            version: 4,
+            expected_keys: Some(10),
+            keys,
+            expires_in_secs,
+        };
+        let report = evaluate(Some(&health(10, None)), 3600);
+        assert!(report.complete && !report.expiring);
+        assert_eq!(report.detail, "version 4 has 10 of 10 keys");
+
+        let report = evaluate(Some(&health(7, Some(600))), 3600);
+        assert!(!report.complete && report.expiring);
+        assert_eq!(
+            report.detail,
+            "version 4 has 7 of 10 keys, first expiring in 600s"
+        );
+
+        assert!(!evaluate(Some(&health(10, Some(7200))), 3600).expiring);
+        let unrecorded = KeyHealth {
+            expected_keys: None,
+            ..health(0, None)
+        };
+        assert!(!evaluate(Some(&unrecorded), 3600).complete);
+        assert!(evaluate(None, 3600).complete);
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use log::error;
+
+use crate::integrity::Digests;
+use crate::matrix::MatrixRow;
+use crate::premium::{MatrixVersion, MatrixVersions, PremiumError};
+use crate::store::{count_keys, Idempotency, KeyCounts, MatrixPremium, VersionPremiums};
+use crate::tenant;
+
+struct Version {
+    info: MatrixVersion,
+    premiums: HashMap<(String, i32), i32>,
+    digests: Option<Digests>,
+}
+
+#[derive(Default)]
+struct Matrix {
+    versions: Vec<Version>,
+    active: Option<u64>,
+    next_version: u64,
+}
+
+/// Every loaded version by tenant, kept until the process exits or the
+/// tenant's matrix is unloaded.
+static MATRICES: RwLock<BTreeMap<Option<String>, Matrix>> = RwLock::new(BTreeMap::new());
+
+fn read<T>(read: impl FnOnce(&Matrix) -> T) -> T {
+    let matrices = MATRICES.read().unwrap_or_else(|err| err.into_inner());
+    match matrices.get(&tenant::current()) {
+        Some(matrix) => read(matrix),
+        None => read(&Matrix::default()),
+    }
+}
+
+fn write<T>(write: impl FnOnce(&mut Matrix) -> T) -> T {
+    let mut matrices = MATRICES.write().unwrap_or_else(|err| err.into_inner());
+    write(matrices.entry(tenant::current()).or_default())
+}
+
+pub async fn premium(
+    version: Option<u64>,
+    code: &str,
+    sum_insured: &str,
+    score: i32,
+) -> anyhow::Result<MatrixPremium, PremiumError> {
+    read(|matrix| {
+        let Some(number) = version.or(matrix.active) else {
+            error!("no premium matrix version is active");
+            return Err(PremiumError::RiskCalculation);
+        };
+        let Some(loaded) = matrix
+            .versions
+            .iter()
+            .find(|loaded| loaded.info.version == number)
+        else {
+            return Err(PremiumError::VersionNotFound(number));
+        };
+        let key = (format!("{}:{}", code, sum_insured), score);
+        match loaded.premiums.get(&key) {
+            Some(premium) => Ok(MatrixPremium {
+                version: number,
+                premium: premium.to_string(),
+            }),
+            None => {
+                error!("matrix has no value for sum assumed and score");
+                Err(PremiumError::RiskCalculation)
+            }
+        }
+    })
+}
+
+pub async fn versions() -> anyhow::Result<MatrixVersions, PremiumError> {
+    Ok(read(|matrix| MatrixVersions {
+        active: matrix.active,
+        versions: matrix
+            .versions
+            .iter()
+            .map(|version| MatrixVersion {
+                version: version.info.version,
+                loaded_at: version.info.loaded_at.clone(),
+                rows: version.info.rows,
+                active: matrix.active == Some(version.info.version),
+                activated_at: version.info.activated_at.clone(),
+            })
+            .collect(),
+    }))
+}
+
+pub async fn activate(version: u64) -> anyhow::Result<(), PremiumError> {
+    write(|matrix| {
+        let Some(loaded) = matrix
+            .versions
+            .iter_mut()
+            .find(|v| v.info.version == version)
+        else {
+            return Err(PremiumError::VersionNotFound(version));
+        };
+        loaded.info.activated_at.push(Local::now().to_rfc3339());
+        matrix.active = Some(version);
+        Ok(())
+    })
+}
+
+pub async fn version_premiums(version: u64) -> anyhow::Result<VersionPremiums, PremiumError> {
+    read(
+        |matrix| match matrix.versions.iter().find(|v| v.info.version == version) {
+            Some(version) => Ok(version
+                .premiums
+                .iter()
+                .map(|(key, premium)| (key.clone(), *premium))
+                .collect()),
+            None => Err(PremiumError::VersionNotFound(version)),
+        },
+    )
+}
+
+/// Rows of a version being loaded, added to the matrix on commit.
+pub struct VersionWriter {
+    premiums: HashMap<(String, i32), i32>,
+    rows: usize,
+}
+
+pub async fn begin_version() -> anyhow::Result<VersionWriter, PremiumError> {
+    Ok(VersionWriter {
+        premiums: HashMap::new(),
+        rows: 0,
+    })
+}
+
+impl VersionWriter {
+    pub async fn write(&mut self, rows: &[MatrixRow]) -> anyhow::Result<(), PremiumError> {
+        self.premiums.extend(
+            rows.iter()
+                .map(|row| ((row.key.clone(), row.score), row.premium)),
+        );
+        self.rows += rows.len();
+        Ok(())
+    }
+
+    pub async fn commit(self) -> anyhow::Result<u64, PremiumError> {
+        Ok(write(|matrix| {
+            matrix.next_version += 1;
+            let version = matrix.next_version;
+            let now = Local::now().to_rfc3339();
+            matrix.versions.push(Version {
+                info: MatrixVersion {
+                    version,
+                    loaded_at: now.clone(),
+                    rows: self.rows,
+                    active: false,
+                    activated_at: vec![now],
+                },
+                premiums: self.premiums,
+                digests: None,
+            });
+            matrix.active = Some(version);
+            version
+        }))
+    }
+
+    pub async fn abort(self) {}
+}
+
+pub async fn record_digests(version: u64, digests: &Digests) -> anyhow::Result<(), PremiumError> {
+    write(|matrix| {
+        match matrix
+            .versions
+            .iter_mut()
+            .find(|v| v.info.version == version)
+        {
+            Some(loaded) => {
+                loaded.digests = Some(digests.clone());
+                Ok(())
+            }
+            None => Err(PremiumError::VersionNotFound(version)),
+        }
+    })
+}
+
+pub async fn digests(version: u64) -> anyhow::Result<Option<Digests>, PremiumError> {
+    read(
+        |matrix| match matrix.versions.iter().find(|v| v.info.version == version) {
+            Some(loaded) => Ok(loaded.digests.clone()),
+            None => Err(PremiumError::VersionNotFound(version)),
+        },
+    )
+}
+
+pub async fn key_counts() -> anyhow::Result<KeyCounts, PremiumError> {
+    Ok(read(|matrix| {
+        let active = matrix
+            .versions
+            .iter()
+            .find(|version| Some(version.info.version) == matrix.active);
+        count_keys(
+            active
+                .into_iter()
+                .flat_map(|version| version.premiums.keys().map(|(key, _)| key.as_str())),
+        )
+    }))
+}
+
+/// Drops the tenant's versions but keeps counting, so a later load is never
+/// given a number quotes were cached or tagged under.
+pub async fn unload() -> anyhow::Result<(), PremiumError> {
+    write(|matrix| {
+        matrix.versions.clear();
+        matrix.active = None;
+    });
+    Ok(())
+}
+
+/// Results by tenant scoped idempotency key, `None` while the claiming request
+/// runs, with their expiry.
+type IdempotencyKeys = HashMap<String, (Option<String>, Instant)>;
+
+static IDEMPOTENCY: Mutex<Option<IdempotencyKeys>> = Mutex::new(None);
+
+pub async fn claim_idempotency_key(
+    key: &str,
+    ttl: Duration,
+) -> anyhow::Result<Idempotency, PremiumError> {
+    let key = tenant::scoped_key(key);
+    let mut keys = IDEMPOTENCY.lock().unwrap_or_else(|err| err.into_inner());
+    let keys = keys.get_or_insert_with(HashMap::new);
+    let now = Instant::now();
+    keys.retain(|_, (_, expires)| *expires > now);
+    match keys.get(&key) {
+        Some((Some(result), _)) => Ok(Idempotency::Completed(result.clone())),
+        Some((None, _)) => Ok(Idempotency::InProgress),
+        None => {
+            keys.insert(key, (None, now + ttl));
+            Ok(Idempotency::Claimed)
+        }
+    }
+}
+
+pub async fn complete_idempotency_key(
+    key: &str,
+    result: &str,
+    ttl: Duration,
+) -> anyhow::Result<(), PremiumError> {
+    let mut keys = IDEMPOTENCY.lock().unwrap_or_else(|err| err.into_inner());
+    keys.get_or_insert_with(HashMap::new).insert(
+        tenant::scoped_key(key),
+        (Some(result.to_string()), Instant::now() + ttl),
+    );
+    Ok(())
+}
+
+pub async fn release_idempotency_key(key: &str) -> anyhow::Result<(), PremiumError> {
+    let mut keys = IDEMPOTENCY.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(keys) = keys.as_mut() {
+        keys.remove(&tenant::scoped_key(key));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MatrixConfig;
+    use crate::matrix::{parse_matrix, read_workbook};
+    use async_std::task;
+
+    async fn write_version(rows: &[MatrixRow]) -> u64 {
+        let mut writer = begin_version().await.unwrap();
+        writer.write(rows).await.unwrap();
+        writer.commit().await.unwrap()
+    }
+
+    #[test]
+    fn test_bundled_matrix_in_memory() {
+        let sheets = read_workbook(&MatrixConfig::bundled(), None).unwrap();
+        let parsed = parse_matrix(&sheets, false);
+        task::block_on(async {
+            let first = write_version(&parsed.rows).await;
+            let quoted = premium(None, "1A", "100000", 3).await.unwrap();
+            assert_eq!((quoted.version, quoted.premium.as_str()), (first, "750"));
+            assert!(matches!(
+                premium(None, "1A", "200000", 3).await,
+                Err(PremiumError::RiskCalculation)
+            ));
+
+            let second = write_version(&parsed.rows[..1]).await;
+            assert!(premium(None, "1A", "100000", 3).await.is_err());
+            let pinned = premium(Some(first), "1A", "100000", 3).await.unwrap();
+            assert_eq!(pinned.version, first);
+            assert!(matches!(
+                premium(Some(second + 1), "1A", "100000", 3).await,
+                Err(PremiumError::VersionNotFound(_))
+            ));
+            activate(first).await.unwrap();
+            assert_eq!(versions().await.unwrap().active, Some(first));
+            assert!(matches!(
+                activate(second + 1).await,
+                Err(PremiumError::VersionNotFound(_))
+            ));
+
+            unload().await.unwrap();
+            assert!(key_counts().await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let sheets = read_workbook(&MatrixConfig::bundled(), None).unwrap();
+        let parsed = parse_matrix(&sheets, false);
+        let acme = || Some("acme".to_string());
+        task::block_on(async {
+            let version = tenant::scope(acme(), write_version(&parsed.rows)).await;
+            assert_eq!(version, 1);
+            tenant::scope(Some("beta".to_string()), async {
+                assert!(premium(None, "1A", "100000", 3).await.is_err());
+                assert!(versions().await.unwrap().versions.is_empty());
+                assert!(key_counts().await.unwrap().is_empty());
+            })
+            .await;
+            tenant::scope(acme(), async {
+                assert_eq!(
+                    premium(None, "1A", "100000", 3).await.unwrap().premium,
+                    "750"
+                );
+                unload().await.unwrap();
+                assert!(key_counts().await.unwrap().is_empty());
+            })
+            .await;
+        });
+    }
+
+    #[test]
+    fn test_unload_keeps_version_numbers() {
+        let sheets = read_workbook(&MatrixConfig::bundled(), None).unwrap();
+        let parsed = parse_matrix(&sheets, false);
+        task::block_on(tenant::scope(Some("gamma".to_string()), async {
+            let first = write_version(&parsed.rows).await;
+            unload().await.unwrap();
+            assert!(versions().await.unwrap().versions.is_empty());
+            assert_eq!(write_version(&parsed.rows).await, first + 1);
+        }));
+    }
+
+    #[test]
+    fn test_key_counts_of_active_version() {
+        let sheets = read_workbook(&MatrixConfig::bundled(), None).unwrap();
+        let parsed = parse_matrix(&sheets, false);
+        let expected = count_keys(parsed.rows.iter().map(|row| row.key.as_str()));
+        // each key holds several scores but is counted once
+        assert!(expected.values().sum::<usize>() < parsed.rows.len());
+        task::block_on(tenant::scope(Some("delta".to_string()), async {
+            assert!(key_counts().await.unwrap().is_empty());
+            let first = write_version(&parsed.rows).await;
+            assert_eq!(key_counts().await.unwrap(), expected);
+
+            write_version(&parsed.rows[..1]).await;
+            let code = parsed.rows[0].code.clone();
+            assert_eq!(key_counts().await.unwrap(), KeyCounts::from([(code, 1)]));
+            activate(first).await.unwrap();
+            assert_eq!(key_counts().await.unwrap(), expected);
+        }));
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let ttl = Duration::from_secs(60);
+        task::block_on(async {
+            let key = "/loads:deploy-7";
+            assert_eq!(
+                claim_idempotency_key(key, ttl).await.unwrap(),
+                Idempotency::Claimed
+            );
+            assert_eq!(
+                claim_idempotency_key(key, ttl).await.unwrap(),
+                Idempotency::InProgress
+            );
+            complete_idempotency_key(key, "done", ttl).await.unwrap();
+            assert_eq!(
+                claim_idempotency_key(key, ttl).await.unwrap(),
+                Idempotency::Completed("done".to_string())
+            );
+            release_idempotency_key(key).await.unwrap();
+            assert_eq!(
+                claim_idempotency_key(key, ttl).await.unwrap(),
+                Idempotency::Claimed
+            );
+        });
+    }
+
+    #[test]
+    fn test_idempotency_keys_per_tenant() {
+        let ttl = Duration::from_secs(60);
+        let key = "/api/v1/healths/premiums/loads:deploy-8";
+        let tenant = |name: &str| Some(name.to_string());
+        task::block_on(async {
+            tenant::scope(tenant("acme"), async {
+                claim_idempotency_key(key, ttl).await.unwrap();
+                complete_idempotency_key(key, "acme's report", ttl)
+                    .await
+                    .unwrap();
+            })
+            .await;
+            let beta = tenant::scope(tenant("beta"), claim_idempotency_key(key, ttl)).await;
+            assert_eq!(beta.unwrap(), Idempotency::Claimed);
+            let acme = tenant::scope(tenant("acme"), claim_idempotency_key(key, ttl)).await;
+            assert_eq!(
+                acme.unwrap(),
+                Idempotency::Completed("acme's report".to_string())
+            );
+        });
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::config::{StorageBackend, StorageConfig};
+use crate::connection;
+use crate::integrity::Digests;
+use crate::matrix::MatrixRow;
+use crate::premium::{MatrixVersions, PremiumError};
+
+mod memory;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod redis;
+
+/// The backends compiled into this build.
+#[derive(Clone, Copy)]
+enum Backend {
+    Redis,
+    Memory,
+    #[cfg(feature = "postgres")]
+    Postgres,
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Selects where the matrix is kept; redis is used when this is never called.
+/// Fails when the configured backend is not compiled in.
+pub fn configure(config: &StorageConfig) -> anyhow::Result<()> {
+    let backend = match config.backend {
+        StorageBackend::Redis => Backend::Redis,
+        StorageBackend::Memory => Backend::Memory,
+        #[cfg(feature = "postgres")]
+        StorageBackend::Postgres => {
+            postgres::configure(&config.postgres)?;
+            Backend::Postgres
+        }
+        #[cfg(not(feature = "postgres"))]
+        StorageBackend::Postgres => {
+            anyhow::bail!("postgres storage needs a build with the postgres feature")
+        }
+    };
+    if BACKEND.set(backend).is_err() {
+        warn!("storage backend already configured");
+    }
+    Ok(())
+}
+
+fn backend() -> Backend {
+    *BACKEND.get_or_init(|| Backend::Redis)
+}
+
+/// A premium and the matrix version it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixPremium {
+    pub version: u64,
+    pub premium: String,
+}
+
+/// The premium for `score` in `version`, or in the active matrix version
+/// when `None`. `band` is the sum insured, with the deductible appended for
+/// top-up products. Fails with `VersionNotFound` for a version never loaded.
+pub async fn premium(
+    version: Option<u64>,
+    code: &str,
+    band: &str,
+    score: i32,
+) -> anyhow::Result<MatrixPremium, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::premium(version, code, band, score).await,
+        Backend::Memory => memory::premium(version, code, band, score).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::premium(version, code, band, score).await,
+    }
+}
+
+pub async fn versions() -> anyhow::Result<MatrixVersions, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::versions().await,
+        Backend::Memory => memory::versions().await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::versions().await,
+    }
+}
+
+pub async fn activate(version: u64) -> anyhow::Result<(), PremiumError> {
+    match backend() {
+        Backend::Redis => redis::activate(version).await,
+        Backend::Memory => memory::activate(version).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::activate(version).await,
+    }
+}
+
+/// Where the backend keeps the premium band of `code` in `version`, for
+/// explaining a lookup.
+pub fn location(version: u64, code: &str, band: &str) -> String {
+    let key = format!("{}:{}", code, band);
+    match backend() {
+        Backend::Redis => redis::matrix_key(version, &key),
+        Backend::Memory => format!("memory v{} {}", version, key),
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => format!(
+            "premium_matrix version={} code={} sum_insured={}",
+            version, code, band
+        ),
+    }
+}
+
+/// Every premium of a version, keyed by `code:band` and score.
+pub type VersionPremiums = BTreeMap<(String, i32), i32>;
+
+/// Reads a whole loaded version, failing with `VersionNotFound` otherwise.
+pub async fn version_premiums(version: u64) -> anyhow::Result<VersionPremiums, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::version_premiums(version).await,
+        Backend::Memory => memory::version_premiums(version).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::version_premiums(version).await,
+    }
+}
+
+/// A new matrix version written in chunks. Quotes keep using the active
+/// version until the writer is committed, which makes the new one active.
+pub enum VersionWriter {
+    Redis(redis::VersionWriter),
+    Memory(memory::VersionWriter),
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::VersionWriter),
+}
+
+pub async fn begin_version() -> anyhow::Result<VersionWriter, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::begin_version().await.map(VersionWriter::Redis),
+        Backend::Memory => memory::begin_version().await.map(VersionWriter::Memory),
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::begin_version().await.map(VersionWriter::Postgres),
+    }
+}
+
+impl VersionWriter {
+    pub async fn write(&mut self, rows: &[MatrixRow]) -> anyhow::Result<(), PremiumError> {
+        match self {
+            VersionWriter::Redis(writer) => writer.write(rows).await,
+            VersionWriter::Memory(writer) => writer.write(rows).await,
+            #[cfg(feature = "postgres")]
+            VersionWriter::Postgres(writer) => writer.write(rows).await,
+        }
+    }
+
+    pub async fn commit(self) -> anyhow::Result<u64, PremiumError> {
+        match self {
+            VersionWriter::Redis(writer) => writer.commit().await,
+            VersionWriter::Memory(writer) => writer.commit().await,
+            #[cfg(feature = "postgres")]
+            VersionWriter::Postgres(writer) => writer.commit().await,
+        }
+    }
+
+    /// Discards what was written; the active version is left as it was.
+    pub async fn abort(self) {
+        match self {
+            VersionWriter::Redis(writer) => writer.abort().await,
+            VersionWriter::Memory(writer) => writer.abort().await,
+            #[cfg(feature = "postgres")]
+            VersionWriter::Postgres(writer) => writer.abort().await,
+        }
+    }
+}
+
+/// Stores `rows` as a new matrix version and makes it the active one.
+pub async fn write_version(rows: &[MatrixRow]) -> anyhow::Result<u64, PremiumError> {
+    let mut writer = begin_version().await?;
+    if let Err(err) = writer.write(rows).await {
+        writer.abort().await;
+        return Err(err);
+    }
+    writer.commit().await
+}
+
+/// Records the per product digests of a committed version.
+pub async fn record_digests(version: u64, digests: &Digests) -> anyhow::Result<(), PremiumError> {
+    match backend() {
+        Backend::Redis => redis::record_digests(version, digests).await,
+        Backend::Memory => memory::record_digests(version, digests).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::record_digests(version, digests).await,
+    }
+}
+
+/// The digests recorded when `version` was loaded, `None` for a version
+/// loaded before they were.
+pub async fn digests(version: u64) -> anyhow::Result<Option<Digests>, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::digests(version).await,
+        Backend::Memory => memory::digests(version).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::digests(version).await,
+    }
+}
+
+/// Keys of the active version by product code, one per sum insured, or per
+/// sum insured and deductible for top-up products.
+pub type KeyCounts = BTreeMap<String, usize>;
+
+/// Counts `code:band` keys per product code. A key listed twice, as SCAN may
+/// list it, or once per score is counted once.
+pub(crate) fn count_keys<'a>(keys: impl IntoIterator<Item = &'a str>) -> KeyCounts {
+    let keys: BTreeSet<&str> = keys.into_iter().collect();
+    let mut counts = KeyCounts::new();
+    for key in keys {
+        let code = key.split(':').next().unwrap_or_default();
+        *counts.entry(code.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// Counts the active version's keys per product; empty when no version is
+/// active.
+pub async fn key_counts() -> anyhow::Result<KeyCounts, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::key_counts().await,
+        Backend::Memory => memory::key_counts().await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::key_counts().await,
+    }
+}
+
+/// Fails with `InternalServer` when the active version has no keys.
+pub async fn keys_exists() -> anyhow::Result<bool, PremiumError> {
+    if key_counts().await?.is_empty() {
+        Err(PremiumError::InternalServer)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Connections of the storage backend, for diagnostics.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+    pub backend: &'static str,
+    /// Connections in use.
+    pub active: u64,
+    pub idle: u64,
+    /// `None` when connections are not pooled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// Time callers waited for a connection; `None` when not measured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_ms_avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_ms_max: Option<f64>,
+    /// Redis connects, failures and circuit breaker state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redis: Option<connection::ConnectionStats>,
+}
+
+/// Connection counts of the configured backend, `None` for the memory store.
+pub fn pool_stats() -> Option<PoolStats> {
+    match backend() {
+        Backend::Redis => {
+            let stats = connection::stats();
+            Some(PoolStats {
+                backend: "redis",
+                active: stats.open,
+                idle: 0,
+                max_connections: None,
+                wait_ms_avg: Some(stats.wait_ms_avg),
+                wait_ms_max: Some(stats.wait_ms_max),
+                redis: Some(stats),
+            })
+        }
+        Backend::Memory => None,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::pool_stats(),
+    }
+}
+
+/// The keys of the active matrix version against the count written when it
+/// was loaded, and the time left on the first to expire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyHealth {
+    pub version: u64,
+    /// `None` for a version loaded before key counts were recorded.
+    pub expected_keys: Option<usize>,
+    pub keys: usize,
+    /// `None` when no key has an expiry.
+    pub expires_in_secs: Option<u64>,
+}
+
+/// The active version's keys, `None` when no version is active or the
+/// backend keeps rows that never expire.
+pub async fn key_health() -> anyhow::Result<Option<KeyHealth>, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::key_health().await,
+        // Rows in memory and postgres are never expired or evicted.
+        Backend::Memory => Ok(None),
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => Ok(None),
+    }
+}
+
+/// Removes every matrix version.
+pub async fn unload() -> anyhow::Result<(), PremiumError> {
+    match backend() {
+        Backend::Redis => redis::unload().await,
+        Backend::Memory => memory::unload().await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::unload().await,
+    }
+}
+
+/// State of an idempotency key when a request claims it. Keys are scoped to
+/// the current tenant.
+#[derive(Debug, PartialEq)]
+pub enum Idempotency {
+    /// First use; the caller runs the operation and then completes or
+    /// releases the key.
+    Claimed,
+    /// Another request holding the key has not finished.
+    InProgress,
+    /// The recorded result of the earlier request.
+    Completed(String),
+}
+
+/// Claims `key` for `ttl` unless an earlier request already holds it.
+pub async fn claim_idempotency_key(
+    key: &str,
+    ttl: Duration,
+) -> anyhow::Result<Idempotency, PremiumError> {
+    match backend() {
+        Backend::Redis => redis::claim_idempotency_key(key, ttl).await,
+        Backend::Memory => memory::claim_idempotency_key(key, ttl).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::claim_idempotency_key(key, ttl).await,
+    }
+}
+
+/// Records the result later requests with `key` are answered with.
+pub async fn complete_idempotency_key(
+    key: &str,
+    result: &str,
+    ttl: Duration,
+) -> anyhow::Result<(), PremiumError> {
+    match backend() {
+        Backend::Redis => redis::complete_idempotency_key(key, result, ttl).await,
+        Backend::Memory => memory::complete_idempotency_key(key, result, ttl).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::complete_idempotency_key(key, result, ttl).await,
+    }
+}
+
+/// Frees a claimed key so the operation can be retried.
+pub async fn release_idempotency_key(key: &str) -> anyhow::Result<(), PremiumError> {
+    match backend() {
+        Backend::Redis => redis::release_idempotency_key(key).await,
+        Backend::Memory => memory::release_idempotency_key(key).await,
+        #[cfg(feature = "postgres")]
+        Backend::Postgres => postgres::release_idempotency_key(key).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_keys() {
+        let counts = count_keys([
+            "1A:100000",
+            "1A:200000",
+            "1A:100000",
+            "2B:500000:25000",
+            "2B:500000:25000:female",
+        ]);
+        assert_eq!(counts.len(), 2);
+        assert_eq!((counts["1A"], counts["2B"]), (2, 2));
+        assert!(count_keys([]).is_empty());
+    }
+}
@@ -0,0 +1,462 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::error;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+
+use crate::config::PostgresConfig;
+use crate::integrity::Digests;
+use crate::matrix::MatrixRow;
+use crate::premium::{MatrixVersion, MatrixVersions, PremiumError};
+use crate::store::{Idempotency, KeyCounts, MatrixPremium, PoolStats, VersionPremiums};
+use crate::tenant;
+
+/// Created on first use. At most one version row per tenant is active, the
+/// default tenant having the empty name, and matrix rows go with the version
+/// they were loaded under. Top-up rows keep their band, sum insured and
+/// deductible, in `sum_insured`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS premium_matrix_version (
+    version BIGSERIAL PRIMARY KEY,
+    loaded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    row_count INTEGER NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT FALSE
+);
+ALTER TABLE premium_matrix_version ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT '';
+ALTER TABLE premium_matrix_version
+    ADD COLUMN IF NOT EXISTS activated_at TIMESTAMPTZ[] NOT NULL DEFAULT '{}';
+ALTER TABLE premium_matrix_version ADD COLUMN IF NOT EXISTS digests TEXT;
+DROP INDEX IF EXISTS premium_matrix_version_active;
+CREATE UNIQUE INDEX IF NOT EXISTS premium_matrix_version_tenant_active
+    ON premium_matrix_version (tenant) WHERE active;
+CREATE TABLE IF NOT EXISTS premium_matrix (
+    version BIGINT NOT NULL REFERENCES premium_matrix_version (version) ON DELETE CASCADE,
+    code TEXT NOT NULL,
+    sum_insured TEXT NOT NULL,
+    age_band TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    premium INTEGER NOT NULL,
+    PRIMARY KEY (version, code, sum_insured, score)
+);
+CREATE TABLE IF NOT EXISTS premium_idempotency (
+    key TEXT PRIMARY KEY,
+    result TEXT,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+";
+
+static POOL: OnceLock<PgPool> = OnceLock::new();
+static SCHEMA_READY: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the pool; connections are opened on the first query.
+pub fn configure(config: &PostgresConfig) -> anyhow::Result<()> {
+    let url = match &config.url {
+        Some(url) => url.clone(),
+        None => env::var("DATABASE_URL")
+            .map_err(|_| anyhow::anyhow!("storage.postgres.url or DATABASE_URL is required"))?,
+    };
+    let pool = PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .connect_lazy(&url)?;
+    let _ = POOL.set(pool);
+    Ok(())
+}
+
+async fn pool() -> anyhow::Result<&'static PgPool, PremiumError> {
+    let Some(pool) = POOL.get() else {
+        error!("postgres storage is not configured");
+        return Err(PremiumError::InternalServer);
+    };
+    if !SCHEMA_READY.load(Ordering::Acquire) {
+        if let Err(err) = sqlx::raw_sql(SCHEMA).execute(pool).await {
+            error!("Postgres error while creating the matrix schema {}", err);
+            return Err(PremiumError::InternalServer);
+        }
+        SCHEMA_READY.store(true, Ordering::Release);
+    }
+    Ok(pool)
+}
+
+/// The pool's open and idle connections. sqlx does not report how long
+/// queries waited for one.
+pub fn pool_stats() -> Option<PoolStats> {
+    let pool = POOL.get()?;
+    let idle = pool.num_idle() as u64;
+    Some(PoolStats {
+        backend: "postgres",
+        active: (pool.size() as u64).saturating_sub(idle),
+        idle,
+        max_connections: Some(pool.options().get_max_connections()),
+        wait_ms_avg: None,
+        wait_ms_max: None,
+        redis: None,
+    })
+}
+
+/// The `tenant` column value of the current tenant.
+fn tenant() -> String {
+    tenant::current().unwrap_or_default()
+}
+
+fn internal(action: &str, err: sqlx::Error) -> PremiumError {
+    error!("Postgres error while {} {}", action, err);
+    PremiumError::InternalServer
+}
+
+pub async fn premium(
+    version: Option<u64>,
+    code: &str,
+    sum_insured: &str,
+    score: i32,
+) -> anyhow::Result<MatrixPremium, PremiumError> {
+    let pool = pool().await?;
+    // A NULL version reads the active one.
+    let result = sqlx::query(
+        "SELECT m.version, m.premium FROM premium_matrix m
+         JOIN premium_matrix_version v ON v.version = m.version
+         WHERE v.tenant = $4 AND m.code = $1 AND m.sum_insured = $2 AND m.score = $3
+           AND (v.version = $5 OR ($5 IS NULL AND v.active))",
+    )
+    .bind(code)
+    .bind(sum_insured)
+    .bind(score)
+    .bind(tenant())
+    .bind(version.map(|version| version as i64))
+    .fetch_optional(pool)
+    .await
+    .map_err(|err| internal("getting score", err))?;
+    match (result, version) {
+        (Some(row), _) => Ok(MatrixPremium {
+            version: row.get::<i64, _>("version") as u64,
+            premium: row.get::<i32, _>("premium").to_string(),
+        }),
+        (None, Some(version)) => {
+            let loaded = sqlx::query(
+                "SELECT 1 FROM premium_matrix_version WHERE version = $1 AND tenant = $2",
+            )
+            .bind(version as i64)
+            .bind(tenant())
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| internal("checking matrix version", err))?;
+            if loaded.is_none() {
+                return Err(PremiumError::VersionNotFound(version));
+            }
+            error!(
+                "postgres has no premium in version {} for sum assumed and score",
+                version
+            );
+            Err(PremiumError::RiskCalculation)
+        }
+        (None, None) => {
+            error!("postgres has no active premium for sum assumed and score");
+            Err(PremiumError::RiskCalculation)
+        }
+    }
+}
+
+pub async fn versions() -> anyhow::Result<MatrixVersions, PremiumError> {
+    let rows = sqlx::query(
+        "SELECT version, loaded_at, row_count, active, activated_at FROM premium_matrix_version
+         WHERE tenant = $1 ORDER BY version",
+    )
+    .bind(tenant())
+    .fetch_all(pool().await?)
+    .await
+    .map_err(|err| internal("listing matrix versions", err))?;
+
+    let versions: Vec<MatrixVersion> = rows
+        .iter()
+        .map(|row| MatrixVersion {
+            version: row.get::<i64, _>("version") as u64,
+            loaded_at: row.get::<DateTime<Utc>, _>("loaded_at").to_rfc3339(),
+            rows: row.get::<i32, _>("row_count") as usize,
+            active: row.get("active"),
+            activated_at: row
+                .get::<Vec<DateTime<Utc>>, _>("activated_at")
+                .iter()
+                .map(DateTime::to_rfc3339)
+                .collect(),
+        })
+        .collect();
+    let active = versions
+        .iter()
+        .find(|version| version.active)
+        .map(|version| version.version);
+    Ok(MatrixVersions { active, versions })
+}
+
+pub async fn activate(version: u64) -> anyhow::Result<(), PremiumError> {
+    let mut tx = pool()
+        .await?
+        .begin()
+        .await
+        .map_err(|err| internal("starting activation", err))?;
+    let loaded =
+        sqlx::query("SELECT 1 FROM premium_matrix_version WHERE version = $1 AND tenant = $2")
+            .bind(version as i64)
+            .bind(tenant())
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|err| internal("checking matrix version", err))?;
+    if loaded.is_none() {
+        return Err(PremiumError::VersionNotFound(version));
+    }
+    set_active(&mut tx, version).await?;
+    tx.commit()
+        .await
+        .map_err(|err| internal("activating matrix version", err))
+}
+
+/// Two statements, as the partial unique index is checked row by row.
+async fn set_active(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    version: u64,
+) -> anyhow::Result<(), PremiumError> {
+    sqlx::query("UPDATE premium_matrix_version SET active = FALSE WHERE active AND tenant = $1")
+        .bind(tenant())
+        .execute(&mut **tx)
+        .await
+        .map_err(|err| internal("deactivating matrix version", err))?;
+    sqlx::query(
+        "UPDATE premium_matrix_version
+         SET active = TRUE, activated_at = array_append(activated_at, now())
+         WHERE version = $1",
+    )
+    .bind(version as i64)
+    .execute(&mut **tx)
+    .await
+    .map_err(|err| internal("activating matrix version", err))?;
+    Ok(())
+}
+
+/// Inserts the version and its rows in one transaction, so quotes never see
+/// a partially loaded matrix.
+pub async fn version_premiums(version: u64) -> anyhow::Result<VersionPremiums, PremiumError> {
+    let pool = pool().await?;
+    let loaded =
+        sqlx::query("SELECT 1 FROM premium_matrix_version WHERE version = $1 AND tenant = $2")
+            .bind(version as i64)
+            .bind(tenant())
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| internal("checking matrix version", err))?;
+    if loaded.is_none() {
+        return Err(PremiumError::VersionNotFound(version));
+    }
+    let rows = sqlx::query(
+        "SELECT code || ':' || sum_insured AS key, score, premium FROM premium_matrix
+         WHERE version = $1",
+    )
+    .bind(version as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|err| internal("reading matrix version", err))?;
+    Ok(rows
+        .iter()
+        .map(|row| ((row.get("key"), row.get("score")), row.get("premium")))
+        .collect())
+}
+
+/// A version loaded inside one transaction; nothing is visible until commit
+/// and an abort rolls every chunk back.
+pub struct VersionWriter {
+    tx: sqlx::Transaction<'static, sqlx::Postgres>,
+    version: i64,
+    rows: usize,
+}
+
+pub async fn begin_version() -> anyhow::Result<VersionWriter, PremiumError> {
+    let mut tx = pool()
+        .await?
+        .begin()
+        .await
+        .map_err(|err| internal("starting load", err))?;
+    let version: i64 = sqlx::query_scalar(
+        "INSERT INTO premium_matrix_version (row_count, tenant) VALUES (0, $1) RETURNING version",
+    )
+    .bind(tenant())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|err| internal("allocating matrix version", err))?;
+    Ok(VersionWriter {
+        tx,
+        version,
+        rows: 0,
+    })
+}
+
+impl VersionWriter {
+    pub async fn write(&mut self, rows: &[MatrixRow]) -> anyhow::Result<(), PremiumError> {
+        sqlx::query(
+            "INSERT INTO premium_matrix (version, code, sum_insured, age_band, score, premium)
+             SELECT $1, * FROM UNNEST($2::TEXT[], $3::TEXT[], $4::TEXT[], $5::INTEGER[], $6::INTEGER[])",
+        )
+        .bind(self.version)
+        .bind(rows.iter().map(|row| row.code.clone()).collect::<Vec<_>>())
+        .bind(rows.iter().map(|row| row.band().to_string()).collect::<Vec<_>>())
+        .bind(rows.iter().map(|row| row.age_band.clone()).collect::<Vec<_>>())
+        .bind(rows.iter().map(|row| row.score).collect::<Vec<_>>())
+        .bind(rows.iter().map(|row| row.premium).collect::<Vec<_>>())
+        .execute(&mut *self.tx)
+        .await
+        .map_err(|err| internal("loading matrix version", err))?;
+        self.rows += rows.len();
+        Ok(())
+    }
+
+    pub async fn commit(mut self) -> anyhow::Result<u64, PremiumError> {
+        sqlx::query("UPDATE premium_matrix_version SET row_count = $2 WHERE version = $1")
+            .bind(self.version)
+            .bind(self.rows as i32)
+            .execute(&mut *self.tx)
+            .await
+            .map_err(|err| internal("counting matrix version rows", err))?;
+        set_active(&mut self.tx, self.version as u64).await?;
+        self.tx
+            .commit()
+            .await
+            .map_err(|err| internal("committing matrix version", err))?;
+        Ok(self.version as u64)
+    }
+
+    pub async fn abort(self) {
+        if let Err(err) = self.tx.rollback().await {
+            internal("rolling back matrix version", err);
+        }
+    }
+}
+
+/// Digests are kept as JSON text next to the version.
+pub async fn record_digests(version: u64, digests: &Digests) -> anyhow::Result<(), PremiumError> {
+    let digests = serde_json::to_string(digests).map_err(|_| PremiumError::InternalServer)?;
+    sqlx::query(
+        "UPDATE premium_matrix_version SET digests = $1 WHERE version = $2 AND tenant = $3",
+    )
+    .bind(digests)
+    .bind(version as i64)
+    .bind(tenant())
+    .execute(pool().await?)
+    .await
+    .map_err(|err| internal("recording matrix digests", err))?;
+    Ok(())
+}
+
+pub async fn digests(version: u64) -> anyhow::Result<Option<Digests>, PremiumError> {
+    let row = sqlx::query(
+        "SELECT digests FROM premium_matrix_version WHERE version = $1 AND tenant = $2",
+    )
+    .bind(version as i64)
+    .bind(tenant())
+    .fetch_optional(pool().await?)
+    .await
+    .map_err(|err| internal("reading matrix digests", err))?;
+    let Some(row) = row else {
+        return Err(PremiumError::VersionNotFound(version));
+    };
+    match row.get::<Option<String>, _>("digests") {
+        Some(digests) => serde_json::from_str(&digests).map(Some).map_err(|err| {
+            error!("matrix version {} has unreadable digests {}", version, err);
+            PremiumError::InternalServer
+        }),
+        None => Ok(None),
+    }
+}
+
+/// A key is a product's sum insured band, as in redis.
+pub async fn key_counts() -> anyhow::Result<KeyCounts, PremiumError> {
+    let rows = sqlx::query(
+        "SELECT m.code, COUNT(DISTINCT m.sum_insured) AS keys FROM premium_matrix m
+         JOIN premium_matrix_version v ON v.version = m.version
+         WHERE v.tenant = $1 AND v.active GROUP BY m.code",
+    )
+    .bind(tenant())
+    .fetch_all(pool().await?)
+    .await
+    .map_err(|err| internal("counting matrix keys", err))?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("code"), row.get::<i64, _>("keys") as usize))
+        .collect())
+}
+
+/// Deletes the current tenant's versions, their rows going with them.
+pub async fn unload() -> anyhow::Result<(), PremiumError> {
+    sqlx::query("DELETE FROM premium_matrix_version WHERE tenant = $1")
+        .bind(tenant())
+        .execute(pool().await?)
+        .await
+        .map_err(|err| internal("deleting matrix", err))?;
+    Ok(())
+}
+
+/// A null result marks a claimed key whose request has not finished. Keys are
+/// stored prefixed with the tenant.
+pub async fn claim_idempotency_key(
+    key: &str,
+    ttl: Duration,
+) -> anyhow::Result<Idempotency, PremiumError> {
+    let key = &tenant::scoped_key(key);
+    let pool = pool().await?;
+    sqlx::query("DELETE FROM premium_idempotency WHERE key = $1 AND expires_at <= now()")
+        .bind(key)
+        .execute(pool)
+        .await
+        .map_err(|err| internal("expiring idempotency key", err))?;
+    let claimed = sqlx::query(
+        "INSERT INTO premium_idempotency (key, expires_at)
+         VALUES ($1, now() + $2 * INTERVAL '1 millisecond')
+         ON CONFLICT (key) DO NOTHING",
+    )
+    .bind(key)
+    .bind(ttl.as_millis() as f64)
+    .execute(pool)
+    .await
+    .map_err(|err| internal("claiming idempotency key", err))?;
+    if claimed.rows_affected() == 1 {
+        return Ok(Idempotency::Claimed);
+    }
+    let result: Option<Option<String>> =
+        sqlx::query_scalar("SELECT result FROM premium_idempotency WHERE key = $1")
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| internal("reading idempotency key", err))?;
+    match result.flatten() {
+        Some(result) => Ok(Idempotency::Completed(result)),
+        None => Ok(Idempotency::InProgress),
+    }
+}
+
+pub async fn complete_idempotency_key(
+    key: &str,
+    result: &str,
+    ttl: Duration,
+) -> anyhow::Result<(), PremiumError> {
+    let key = &tenant::scoped_key(key);
+    sqlx::query(
+        "INSERT INTO premium_idempotency (key, result, expires_at)
+         VALUES ($1, $2, now() + $3 * INTERVAL '1 millisecond')
+         ON CONFLICT (key) DO UPDATE SET result = $2, expires_at = EXCLUDED.expires_at",
+    )
+    .bind(key)
+    .bind(result)
+    .bind(ttl.as_millis() as f64)
+    .execute(pool().await?)
+    .await
+    .map_err(|err| internal("recording idempotency key", err))?;
+    Ok(())
+}
+
+pub async fn release_idempotency_key(key: &str) -> anyhow::Result<(), PremiumError> {
+    sqlx::query("DELETE FROM premium_idempotency WHERE key = $1")
+        .bind(tenant::scoped_key(key))
+        .execute(pool().await?)
+        .await
+        .map_err(|err| internal("releasing idempotency key", err))?;
+    Ok(())
+}
@@ -0,0 +1,717 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use async_std::task;
+use chrono::Local;
+use log::{error, info};
+use redis::cluster_routing::get_slot;
+use redis::{Commands, Connection, RedisError, RedisResult};
+
+use crate::connection::{
+    conn_write, key_ttl, load_settings, redis_error, retry_config, RedisConnection,
+};
+use crate::integrity::Digests;
+use crate::matrix::{rows_per_sec, MatrixRow};
+use crate::premium::{MatrixVersion, MatrixVersions, PremiumError};
+use crate::retry::{with_retry, Access};
+use crate::store::{count_keys, Idempotency, KeyCounts, KeyHealth, MatrixPremium, VersionPremiums};
+use crate::tenant::scoped_key;
+
+// Version bookkeeping keys share the {premium} hash tag so the activation
+// transaction stays on one cluster slot.
+
+/// Holds the number of the matrix version quotes are priced from.
+const ACTIVE_VERSION_KEY: &str = "{premium}:active";
+/// Counter handing out matrix version numbers.
+const VERSION_COUNTER_KEY: &str = "{premium}:version";
+/// Sorted set of loaded versions, scored by version number.
+const VERSIONS_KEY: &str = "{premium}:versions";
+/// Field of a version's info hash listing the times it was activated,
+/// comma separated.
+const ACTIVATED_AT_FIELD: &str = "activatedAt";
+/// Field of a version's info hash counting the matrix keys it was loaded with.
+const KEYS_FIELD: &str = "keys";
+/// Field of a version's info hash holding its per product digests as JSON.
+const DIGESTS_FIELD: &str = "digests";
+
+fn idempotency_key(key: &str) -> String {
+    scoped_key(&format!("{{premium}}:idempotency:{}", key))
+}
+
+pub(super) fn matrix_key(version: u64, key: &str) -> String {
+    scoped_key(&format!("premium:v{}:{}", version, key))
+}
+
+fn version_info_key(version: u64) -> String {
+    scoped_key(&format!("{{premium}}:versions:{}", version))
+}
+
+/// Runs a command that is safe to repeat under the configured retry policy.
+async fn retrying<T, F>(what: &str, access: Access, command: F) -> anyhow::Result<T, PremiumError>
+where
+    F: FnMut(&mut RedisConnection) -> RedisResult<T>,
+{
+    with_retry(retry_config(), what, access, command).await
+}
+
+/// Pages through the keys matching `pattern` with SCAN, handing each page to
+/// `page`, so redis is never blocked the way KEYS blocks it. A cluster's keys
+/// are spread over its masters and SCAN only walks the node it is sent to, so
+/// there each master is scanned in turn while `page` runs its commands
+/// through the cluster connection.
+fn scan<F>(conn: &mut RedisConnection, pattern: &str, mut page: F) -> RedisResult<()>
+where
+    F: FnMut(&mut RedisConnection, Vec<String>) -> RedisResult<()>,
+{
+    let mut masters = conn.masters()?;
+    if masters.is_empty() {
+        return scan_node(conn, None, pattern, &mut page);
+    }
+    for master in &mut masters {
+        scan_node(conn, Some(master), pattern, &mut page)?;
+    }
+    Ok(())
+}
+
+/// Walks one node's keys, `node` or else the node `conn` is connected to.
+fn scan_node<F>(
+    conn: &mut RedisConnection,
+    mut node: Option<&mut Connection>,
+    pattern: &str,
+    page: &mut F,
+) -> RedisResult<()>
+where
+    F: FnMut(&mut RedisConnection, Vec<String>) -> RedisResult<()>,
+{
+    let mut cursor: u64 = 0;
+    loop {
+        let mut scan = redis::cmd("SCAN");
+        scan.arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(1000);
+        let (next, keys): (u64, Vec<String>) = match node.as_deref_mut() {
+            Some(node) => scan.query(node)?,
+            None => scan.query(conn)?,
+        };
+        if !keys.is_empty() {
+            page(conn, keys)?;
+        }
+        if next == 0 {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Deletes `keys` with one DEL per cluster slot, as a cluster refuses a DEL
+/// whose keys hash to different slots.
+fn del(conn: &mut RedisConnection, keys: &[String]) -> RedisResult<()> {
+    for keys in sendable(is_cluster(conn), keys, |key| key.as_str()) {
+        conn.del::<_, ()>(keys)?;
+    }
+    Ok(())
+}
+
+fn is_cluster(conn: &RedisConnection) -> bool {
+    matches!(conn, RedisConnection::Cluster(_))
+}
+
+/// `items` in the groups that can go to redis as one command or pipeline:
+/// all of them for a single node, one group per slot for a cluster, which
+/// sends a whole pipeline to the node of its first key. Matrix keys carry no
+/// hash tag, so a page or batch of them spans many slots.
+fn sendable<T>(cluster: bool, items: &[T], key: fn(&T) -> &str) -> Vec<Vec<&T>> {
+    if !cluster {
+        return match items.is_empty() {
+            true => Vec::new(),
+            false => vec![items.iter().collect()],
+        };
+    }
+    let mut slots: BTreeMap<u16, Vec<&T>> = BTreeMap::new();
+    for item in items {
+        slots
+            .entry(get_slot(key(item).as_bytes()))
+            .or_default()
+            .push(item);
+    }
+    slots.into_values().collect()
+}
+
+pub async fn premium(
+    version: Option<u64>,
+    code: &str,
+    sum_insured: &str,
+    score: i32,
+) -> anyhow::Result<MatrixPremium, PremiumError> {
+    let matrix_key_suffix = format!("{}:{}", code, sum_insured);
+    let (active_key, versions_key) = (scoped_key(ACTIVE_VERSION_KEY), scoped_key(VERSIONS_KEY));
+    let lookup = retrying("getting score", Access::Read, move |conn| {
+        let version: Option<u64> = match version {
+            Some(version) => Some(version),
+            None => conn.get(&active_key)?,
+        };
+        let Some(version) = version else {
+            return Ok(None);
+        };
+        let values: Vec<String> =
+            conn.zrangebyscore(matrix_key(version, &matrix_key_suffix), score, score)?;
+        let loaded: Option<u64> = match values.is_empty() {
+            true => conn.zscore(&versions_key, version)?,
+            false => Some(version),
+        };
+        Ok(Some((version, values, loaded.is_some())))
+    })
+    .await?;
+    let Some((version, values, loaded)) = lookup else {
+        error!("no premium matrix version is active");
+        return Err(PremiumError::RiskCalculation);
+    };
+    if !loaded {
+        return Err(PremiumError::VersionNotFound(version));
+    }
+    if values.is_empty() {
+        error!("redis has more than two values or no values for sum assumed and score");
+        return Err(PremiumError::RiskCalculation);
+    }
+    Ok(MatrixPremium {
+        version,
+        premium: values[0].to_string(),
+    })
+}
+
+pub async fn versions() -> anyhow::Result<MatrixVersions, PremiumError> {
+    let (active_key, versions_key) = (scoped_key(ACTIVE_VERSION_KEY), scoped_key(VERSIONS_KEY));
+    let (active, infos) = retrying("listing matrix versions", Access::Read, |conn| {
+        let active: Option<u64> = conn.get(&active_key)?;
+        let numbers: Vec<u64> = conn.zrange(&versions_key, 0, -1)?;
+        let mut infos = Vec::with_capacity(numbers.len());
+        for version in numbers {
+            let info: HashMap<String, String> = conn.hgetall(version_info_key(version))?;
+            infos.push((version, info));
+        }
+        Ok((active, infos))
+    })
+    .await?;
+
+    let versions = infos
+        .into_iter()
+        .map(|(version, info)| MatrixVersion {
+            version,
+            loaded_at: info.get("loadedAt").cloned().unwrap_or_default(),
+            rows: info
+                .get("rows")
+                .and_then(|rows| rows.parse().ok())
+                .unwrap_or_default(),
+            active: active == Some(version),
+            activated_at: info
+                .get(ACTIVATED_AT_FIELD)
+                .map(|times| times.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+    Ok(MatrixVersions { active, versions })
+}
+
+/// Walks the version's keys with SCAN rather than KEYS so redis is not
+/// blocked while a large matrix is read.
+pub async fn version_premiums(version: u64) -> anyhow::Result<VersionPremiums, PremiumError> {
+    let (versions_key, prefix) = (scoped_key(VERSIONS_KEY), matrix_key(version, ""));
+    let premiums = retrying("reading matrix version", Access::Read, move |conn| {
+        let loaded: Option<u64> = conn.zscore(&versions_key, version)?;
+        if loaded.is_none() {
+            return Ok(None);
+        }
+        let mut premiums = VersionPremiums::new();
+        scan(conn, &format!("{}*", prefix), |conn, keys| {
+            for key in keys {
+                let entries: Vec<(i32, f64)> = conn.zrange_withscores(&key, 0, -1)?;
+                for (premium, score) in entries {
+                    premiums.insert((key[prefix.len()..].to_string(), score as i32), premium);
+                }
+            }
+            Ok(())
+        })?;
+        Ok(Some(premiums))
+    })
+    .await?;
+    premiums.ok_or(PremiumError::VersionNotFound(version))
+}
+
+pub async fn activate(version: u64) -> anyhow::Result<(), PremiumError> {
+    let (versions_key, active_key) = (scoped_key(VERSIONS_KEY), scoped_key(ACTIVE_VERSION_KEY));
+    let activated = retrying("activating matrix version", Access::Write, move |conn| {
+        let loaded: Option<u64> = conn.zscore(&versions_key, version)?;
+        if loaded.is_none() {
+            return Ok(false);
+        }
+        let info_key = version_info_key(version);
+        let activated: Option<String> = conn.hget(&info_key, ACTIVATED_AT_FIELD)?;
+        let now = Local::now().to_rfc3339();
+        let activated = match activated {
+            Some(times) => format!("{},{}", times, now),
+            None => now,
+        };
+        redis::pipe()
+            .atomic()
+            .hset(&info_key, ACTIVATED_AT_FIELD, activated)
+            .ignore()
+            .set(&active_key, version)
+            .ignore()
+            .query::<()>(conn)?;
+        Ok(true)
+    })
+    .await?;
+    if activated {
+        Ok(())
+    } else {
+        Err(PremiumError::VersionNotFound(version))
+    }
+}
+
+/// A version being written under its own namespace. The active version is
+/// only pointed at it on commit, so quotes never see a partially loaded
+/// matrix. Each chunk goes out in pipelines of `loadBatchSize` rows spread
+/// over `loadConcurrency` connections writing at once.
+pub struct VersionWriter {
+    version: u64,
+    /// Matrix keys of the version less the row key, taken when the load
+    /// starts as the writing threads run outside the tenant's scope.
+    prefix: String,
+    rows: usize,
+    keys: HashSet<String>,
+    started: Instant,
+}
+
+pub async fn begin_version() -> anyhow::Result<VersionWriter, PremiumError> {
+    let mut conn = conn_write().await?;
+    let version: u64 = match conn.incr(scoped_key(VERSION_COUNTER_KEY), 1) {
+        Ok(version) => version,
+        Err(err) => {
+            error!("Redis error while allocating matrix version {}", err);
+            return Err(redis_error(&err));
+        }
+    };
+    Ok(VersionWriter {
+        version,
+        prefix: matrix_key(version, ""),
+        rows: 0,
+        keys: HashSet::new(),
+        started: Instant::now(),
+    })
+}
+
+impl VersionWriter {
+    /// Hands each writer its share of the chunk on a blocking thread with a
+    /// connection of its own, and waits for all of them.
+    pub async fn write(&mut self, rows: &[MatrixRow]) -> anyhow::Result<(), PremiumError> {
+        let entries: Vec<Entry> = rows
+            .iter()
+            .map(|row| Entry {
+                key: format!("{}{}", self.prefix, row.key),
+                premium: row.premium,
+                score: row.score,
+            })
+            .collect();
+        self.keys
+            .extend(entries.iter().map(|entry| entry.key.clone()));
+        let (batch_size, concurrency) = load_settings();
+        let mut writers = Vec::new();
+        let mut result = Ok(());
+        for share in shares(entries, batch_size, concurrency) {
+            let conn = match conn_write().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+            let version = self.version;
+            writers.push(task::spawn_blocking(move || {
+                write_batches(version, conn, &share)
+            }));
+        }
+        for writer in writers {
+            let written = writer.await;
+            result = result.and(written);
+        }
+        result?;
+        self.rows += rows.len();
+        Ok(())
+    }
+
+    pub async fn commit(self) -> anyhow::Result<u64, PremiumError> {
+        let (version, rows) = (self.version, self.rows);
+        let elapsed = self.started.elapsed();
+        info!(
+            "wrote {} rows of matrix version {} in {}ms ({} rows/s)",
+            rows,
+            version,
+            elapsed.as_millis(),
+            rows_per_sec(rows, elapsed)
+        );
+        let now = Local::now().to_rfc3339();
+        let info = [
+            ("loadedAt", now.clone()),
+            ("rows", rows.to_string()),
+            (KEYS_FIELD, self.keys.len().to_string()),
+            (ACTIVATED_AT_FIELD, now),
+        ];
+        let mut conn = conn_write().await?;
+        let result: Result<(), RedisError> = redis::pipe()
+            .atomic()
+            .hset_multiple(version_info_key(version), &info)
+            .ignore()
+            .zadd(scoped_key(VERSIONS_KEY), version, version)
+            .ignore()
+            .set(scoped_key(ACTIVE_VERSION_KEY), version)
+            .ignore()
+            .query(&mut conn);
+        match result {
+            Ok(_) => Ok(version),
+            Err(err) => {
+                error!(
+                    "Redis error while activating matrix version {} {}",
+                    version, err
+                );
+                Err(redis_error(&err))
+            }
+        }
+    }
+
+    /// Deletes the rows written so far; the version number is not reused.
+    pub async fn abort(self) {
+        let keys: Vec<String> = self.keys.into_iter().collect();
+        let (batch_size, _) = load_settings();
+        let mut conn = match conn_write().await {
+            Ok(conn) => conn,
+            Err(_) => return,
+        };
+        for batch in keys.chunks(batch_size) {
+            let result: RedisResult<()> = del(&mut conn, batch);
+            if let Err(err) = result {
+                error!(
+                    "Redis error while removing aborted matrix version {} {}",
+                    self.version, err
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// A matrix key and the premium to add to it at the row's score.
+struct Entry {
+    key: String,
+    premium: i32,
+    score: i32,
+}
+
+/// `entries` cut into batches of `batch_size` and dealt round robin to at
+/// most `workers` writers.
+fn shares<T>(entries: Vec<T>, batch_size: usize, workers: usize) -> Vec<Vec<Vec<T>>> {
+    let mut entries = entries.into_iter();
+    let mut batches = Vec::new();
+    loop {
+        let batch: Vec<T> = entries.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        batches.push(batch);
+    }
+    let mut shares: Vec<Vec<Vec<T>>> = (0..workers.min(batches.len()))
+        .map(|_| Vec::new())
+        .collect();
+    let writers = shares.len();
+    for (index, batch) in batches.into_iter().enumerate() {
+        shares[index % writers].push(batch);
+    }
+    shares
+}
+
+/// The pipelines writing `batch`, one per slot on a cluster.
+fn pipelines(cluster: bool, batch: &[Entry], ttl: Option<u64>) -> Vec<redis::Pipeline> {
+    sendable(cluster, batch, |entry| entry.key.as_str())
+        .into_iter()
+        .map(|entries| {
+            let mut pipe = redis::pipe();
+            for entry in entries {
+                pipe.zadd(&entry.key, entry.premium, entry.score).ignore();
+                if let Some(ttl) = ttl {
+                    pipe.expire(&entry.key, ttl as usize).ignore();
+                }
+            }
+            pipe
+        })
+        .collect()
+}
+
+/// One writer's share of a load, each batch sent as a pipeline per slot.
+fn write_batches(
+    version: u64,
+    mut conn: RedisConnection,
+    batches: &[Vec<Entry>],
+) -> anyhow::Result<(), PremiumError> {
+    let (cluster, ttl) = (is_cluster(&conn), key_ttl());
+    for batch in batches {
+        for pipe in pipelines(cluster, batch, ttl) {
+            let result: RedisResult<()> = pipe.query(&mut conn);
+            if let Err(err) = result {
+                error!(
+                    "Redis error while loading matrix version {} {}",
+                    version, err
+                );
+                return Err(redis_error(&err));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_digests(version: u64, digests: &Digests) -> anyhow::Result<(), PremiumError> {
+    let (info_key, digests) = (
+        version_info_key(version),
+        serde_json::to_string(digests).map_err(|_| PremiumError::InternalServer)?,
+    );
+    retrying("recording matrix digests", Access::Write, move |conn| {
+        conn.hset(&info_key, DIGESTS_FIELD, &digests)
+    })
+    .await
+}
+
+pub async fn digests(version: u64) -> anyhow::Result<Option<Digests>, PremiumError> {
+    let info_key = version_info_key(version);
+    let digests: Option<String> = retrying("reading matrix digests", Access::Read, move |conn| {
+        conn.hget(&info_key, DIGESTS_FIELD)
+    })
+    .await?;
+    match digests.map(|digests| serde_json::from_str(&digests)) {
+        Some(Ok(digests)) => Ok(Some(digests)),
+        Some(Err(err)) => {
+            error!("matrix version {} has unreadable digests {}", version, err);
+            Err(PremiumError::InternalServer)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Counts the active version's keys per product code.
+pub async fn key_counts() -> anyhow::Result<KeyCounts, PremiumError> {
+    let active_key = scoped_key(ACTIVE_VERSION_KEY);
+    retrying("counting matrix keys", Access::Read, move |conn| {
+        let Some(version): Option<u64> = conn.get(&active_key)? else {
+            return Ok(KeyCounts::new());
+        };
+        let prefix = matrix_key(version, "");
+        let mut keys = Vec::new();
+        scan(conn, &format!("{}*", prefix), |_, page| {
+            keys.extend(page);
+            Ok(())
+        })?;
+        Ok(count_keys(keys.iter().map(|key| &key[prefix.len()..])))
+    })
+    .await
+}
+
+/// Counts the active version's keys with SCAN and reads their time to live
+/// in one pipeline per page, or per slot of a page on a cluster.
+pub async fn key_health() -> anyhow::Result<Option<KeyHealth>, PremiumError> {
+    let active_key = scoped_key(ACTIVE_VERSION_KEY);
+    retrying("checking matrix keys", Access::Read, move |conn| {
+        let Some(version): Option<u64> = conn.get(&active_key)? else {
+            return Ok(None);
+        };
+        let expected_keys: Option<usize> = conn.hget(version_info_key(version), KEYS_FIELD)?;
+        let prefix = matrix_key(version, "");
+        let (mut keys, mut expires_in_secs) = (0, None::<u64>);
+        scan(conn, &format!("{}*", prefix), |conn, page| {
+            let mut ttls = Vec::with_capacity(page.len());
+            for keys in sendable(is_cluster(conn), &page, |key| key.as_str()) {
+                let mut pipe = redis::pipe();
+                for key in keys {
+                    pipe.ttl(key);
+                }
+                ttls.extend(pipe.query::<Vec<i64>>(conn)?);
+            }
+            // -1 for a key without expiry, -2 for one gone since the scan.
+            for ttl in ttls {
+                if ttl == -2 {
+                    continue;
+                }
+                keys += 1;
+                if let Ok(ttl) = u64::try_from(ttl) {
+                    expires_in_secs = Some(expires_in_secs.map_or(ttl, |min| min.min(ttl)));
+                }
+            }
+            Ok(())
+        })?;
+        Ok(Some(KeyHealth {
+            version,
+            expected_keys,
+            keys,
+            expires_in_secs,
+        }))
+    })
+    .await
+}
+
+/// Removes the current tenant's versions: their matrix keys and info hashes,
+/// the versions set and the active version. Other tenants' matrices stay in
+/// place, and so does the version counter, so the next load gets a number
+/// quotes and ETags have not seen, as do idempotency records, flags and
+/// audits.
+pub async fn unload() -> anyhow::Result<(), PremiumError> {
+    let patterns = [
+        scoped_key("premium:v*:*"),
+        scoped_key("{premium}:versions:*"),
+    ];
+    let bookkeeping = [scoped_key(VERSIONS_KEY), scoped_key(ACTIVE_VERSION_KEY)];
+    retrying("removing matrix keys", Access::Write, move |conn| {
+        for pattern in &patterns {
+            scan(conn, pattern, |conn, keys| del(conn, &keys))?;
+        }
+        del(conn, &bookkeeping)
+    })
+    .await
+}
+
+/// An empty value marks a claimed key whose request has not finished.
+pub async fn claim_idempotency_key(
+    key: &str,
+    ttl: Duration,
+) -> anyhow::Result<Idempotency, PremiumError> {
+    let mut conn = conn_write().await?;
+
+    let claimed: RedisResult<Option<String>> = redis::cmd("SET")
+        .arg(idempotency_key(key))
+        .arg("")
+        .arg("NX")
+        .arg("PX")
+        .arg(ttl.as_millis() as u64)
+        .query(&mut conn);
+    match claimed {
+        Ok(Some(_)) => return Ok(Idempotency::Claimed),
+        Ok(None) => {}
+        Err(err) => {
+            error!("Redis error while claiming idempotency key {}", err);
+            return Err(redis_error(&err));
+        }
+    }
+    let stored: RedisResult<Option<String>> = conn.get(idempotency_key(key));
+    match stored {
+        Ok(Some(result)) if !result.is_empty() => Ok(Idempotency::Completed(result)),
+        Ok(_) => Ok(Idempotency::InProgress),
+        Err(err) => {
+            error!("Redis error while reading idempotency key {}", err);
+            Err(redis_error(&err))
+        }
+    }
+}
+
+pub async fn complete_idempotency_key(
+    key: &str,
+    result: &str,
+    ttl: Duration,
+) -> anyhow::Result<(), PremiumError> {
+    let (key, result) = (idempotency_key(key), result.to_string());
+    retrying("recording idempotency key", Access::Write, move |conn| {
+        conn.pset_ex(&key, &result, ttl.as_millis() as usize)
+    })
+    .await
+}
+
+pub async fn release_idempotency_key(key: &str) -> anyhow::Result<(), PremiumError> {
+    let key = idempotency_key(key);
+    retrying("releasing idempotency key", Access::Write, move |conn| {
+        conn.del(&key)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant;
+    use async_std::task;
+
+    #[test]
+    fn test_deletes_grouped_by_slot() {
+        let keys: Vec<String> = ["premium:v3:1A:100000", "premium:v3:1A:200000"]
+            .into_iter()
+            .chain(["{premium}:versions", "{premium}:active"])
+            .map(str::to_string)
+            .collect();
+        let slots = sendable(true, &keys, |key| key.as_str());
+        // The bookkeeping keys share the {premium} hash tag, matrix keys do not.
+        assert_eq!(slots.len(), 3);
+        assert!(slots.iter().any(|keys| keys.len() == 2));
+        assert!(slots.iter().all(|keys| keys
+            .iter()
+            .all(|key| get_slot(key.as_bytes()) == get_slot(keys[0].as_bytes()))));
+        assert_eq!(sendable(false, &keys, |key| key.as_str()).len(), 1);
+    }
+
+    #[test]
+    fn test_load_shares_and_pipelines() {
+        let shares = shares((0..10).collect(), 3, 2);
+        assert_eq!(
+            shares,
+            vec![
+                vec![vec![0, 1, 2], vec![6, 7, 8]],
+                vec![vec![3, 4, 5], vec![9]],
+            ]
+        );
+        assert_eq!(
+            super::shares((0..2).collect(), 3, 4),
+            vec![vec![vec![0, 1]]]
+        );
+        assert!(super::shares(Vec::<i32>::new(), 3, 4).is_empty());
+
+        let batch: Vec<Entry> = ["1A:100000:18-35", "1A:200000:18-35", "2B:500000:36-45"]
+            .into_iter()
+            .map(|key| Entry {
+                key: format!("premium:v3:{}", key),
+                premium: 5000,
+                score: 30,
+            })
+            .collect();
+        let keys = |pipe: &redis::Pipeline| -> Vec<Vec<u8>> {
+            pipe.cmd_iter()
+                .filter_map(|cmd| match cmd.args_iter().nth(1) {
+                    Some(redis::Arg::Simple(key)) => Some(key.to_vec()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let single = pipelines(false, &batch, Some(60));
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].cmd_iter().count(), 6);
+        let cluster = pipelines(true, &batch, Some(60));
+        assert_eq!(cluster.len(), 3);
+        for pipe in &cluster {
+            let keys = keys(pipe);
+            assert_eq!(keys.len(), 2);
+            assert!(keys.iter().all(|key| get_slot(key) == get_slot(&keys[0])));
+        }
+    }
+
+    #[test]
+    fn test_idempotency_keys_per_tenant() {
+        let key = "/api/v1/healths/premiums/loads:deploy-8";
+        let tenant = |name: &str| Some(name.to_string());
+        task::block_on(async {
+            let acme = tenant::scope(tenant("acme"), async { idempotency_key(key) }).await;
+            let beta = tenant::scope(tenant("beta"), async { idempotency_key(key) }).await;
+            assert_eq!(
+                acme,
+                "tenant:acme:{premium}:idempotency:/api/v1/healths/premiums/loads:deploy-8"
+            );
+            assert_ne!(acme, beta);
+            assert_eq!(
+                idempotency_key(key),
+                "{premium}:idempotency:/api/v1/healths/premiums/loads:deploy-8"
+            );
+        });
+    }
+}
@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::pin;
+use std::task::Poll;
+
+use crate::premium::PremiumError;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Restores the tenant of the enclosing scope, also when a poll panics.
+struct Restore(Option<String>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.set(self.0.take());
+    }
+}
+
+/// Runs `future` with `tenant` as the current tenant, so the matrix it reads
+/// and loads is the tenant's own. `None` is the default tenant, stored under
+/// the keys of a single tenant deployment. Work handed to other tasks or
+/// threads has to capture `current()` and scope itself again.
+pub async fn scope<F: Future>(tenant: Option<String>, future: F) -> F::Output {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| -> Poll<F::Output> {
+        let _restore = Restore(CURRENT.replace(tenant.clone()));
+        future.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// The tenant of the running scope, `None` outside one.
+pub fn current() -> Option<String> {
+    CURRENT.with_borrow(|tenant| tenant.clone())
+}
+
+/// `key` in the current tenant's namespace, `tenant:{id}:{key}`, unchanged
+/// for the default tenant so a single tenant deployment reads its existing
+/// keys. No default key starts with `tenant:`, so a tenant whatever its name
+/// cannot reach them.
+pub fn scoped_key(key: &str) -> String {
+    match current() {
+        Some(tenant) => format!("tenant:{}:{}", tenant, key),
+        None => key.to_string(),
+    }
+}
+
+/// Tenant ids end up in store keys, so they are kept to lowercase letters,
+/// digits, `-` and `_`, at most 64 characters.
+pub fn parse(tenant: &str) -> anyhow::Result<String, PremiumError> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= 64
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+    if valid {
+        Ok(tenant.to_string())
+    } else {
+        Err(PremiumError::UnknownTenant(tenant.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    #[test]
+    fn test_scope() {
+        task::block_on(async {
+            assert_eq!(current(), None);
+            let inner = scope(Some("acme".to_string()), async {
+                task::yield_now().await;
+                let nested = scope(None, async { scoped_key("k") }).await;
+                (current(), scoped_key("k"), nested)
+            })
+            .await;
+            assert_eq!(
+                inner,
+                (
+                    Some("acme".to_string()),
+                    "tenant:acme:k".to_string(),
+                    "k".to_string()
+                )
+            );
+            assert_eq!(current(), None);
+        });
+        assert!(parse("acme-01").is_ok());
+        assert!(parse("Acme").is_err());
+        assert!(parse("a:b").is_err());
+        // A tenant named after a key prefix stays out of the default keys.
+        let premium = task::block_on(scope(Some("premium".to_string()), async {
+            scoped_key("v3:1A")
+        }));
+        assert_eq!(premium, "tenant:premium:v3:1A");
+    }
+}
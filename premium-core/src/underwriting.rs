@@ -0,0 +1,208 @@
+use std::sync::OnceLock;
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::audit::Caller;
+use crate::config::{RuleOutcome, UnderwritingConfig, UnderwritingFactor, UnderwritingRule};
+use crate::ped::{self, ConditionLoading, PedAssessment};
+use crate::premium::{self, HealthRequest, HealthResponse, PremiumError};
+use crate::referral;
+
+static RULES: OnceLock<UnderwritingConfig> = OnceLock::new();
+
+/// Sets the underwriting rules and the service referred quotes go to; only
+/// the PED rules refer or decline quotes when this is never called.
+pub fn configure(config: &UnderwritingConfig) {
+    if RULES.set(config.clone()).is_err() {
+        warn!("underwriting rules already configured");
+    }
+    referral::configure(&config.referral);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Outcome {
+    Accept,
+    Refer,
+    Decline,
+}
+
+/// The underwriting outcome of a quote request: the quote when it is
+/// accepted, why it is not otherwise.
+#[derive(Debug, Serialize)]
+pub struct Decision {
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<HealthResponse>,
+}
+
+/// Quotes `input` for `caller` as a decision, so referred and declined
+/// requests are outcomes rather than errors.
+pub async fn decide(
+    input: HealthRequest,
+    caller: &Caller,
+) -> anyhow::Result<Decision, PremiumError> {
+    let (outcome, reasons, quote) = match premium::quote_for(input, caller).await {
+        Ok(quote) => (Outcome::Accept, Vec::new(), Some(quote)),
+        Err(PremiumError::ReferToUnderwriter { reasons }) => (Outcome::Refer, reasons, None),
+        Err(PremiumError::CoverDeclined { reasons }) => (Outcome::Decline, reasons, None),
+        Err(err) => return Err(err),
+    };
+    Ok(Decision {
+        outcome,
+        reasons,
+        quote,
+    })
+}
+
+/// Body mass index of a height in centimetres and a weight in kilograms.
+pub fn bmi(height_cm: f64, weight_kg: f64) -> f64 {
+    let metres = height_cm / 100.0;
+    weight_kg / (metres * metres)
+}
+
+/// The loadings of the declared conditions when the request is accepted.
+/// Fails with `CoverDeclined` naming every reason to decline, or else with
+/// `ReferToUnderwriter` naming every reason to refer.
+pub fn assess(
+    input: &HealthRequest,
+    age: i32,
+) -> anyhow::Result<Vec<ConditionLoading>, PremiumError> {
+    let config = RULES.get_or_init(UnderwritingConfig::default);
+    let ped = ped::assess(&input.declared_conditions);
+    assess_with(&config.rules, input, age, ped)
+}
+
+fn assess_with(
+    rules: &[UnderwritingRule],
+    input: &HealthRequest,
+    age: i32,
+    ped: PedAssessment,
+) -> anyhow::Result<Vec<ConditionLoading>, PremiumError> {
+    let mut referred: Vec<String> = ped
+        .referred
+        .iter()
+        .map(|code| format!("declared condition {}", code))
+        .collect();
+    let mut declined: Vec<String> = ped
+        .declined
+        .iter()
+        .map(|code| format!("declared condition {}", code))
+        .collect();
+    let bmi = match (input.height_cm, input.weight_kg) {
+        (Some(height), Some(weight)) => Some(bmi(height, weight)),
+        _ => None,
+    };
+    for rule in rules {
+        if !rule.products.is_empty() && !rule.products.contains(&input.code) {
+            continue;
+        }
+        let (name, value) = match rule.factor {
+            UnderwritingFactor::Age => ("age", Some(f64::from(age))),
+            UnderwritingFactor::Bmi => ("BMI", bmi),
+            UnderwritingFactor::SumInsured => ("sum insured", input.sum_insured.parse().ok()),
+        };
+        // BMI rules pass quotes that leave out the height or weight.
+        let Some(value) = value else {
+            continue;
+        };
+        let shown = match rule.factor {
+            UnderwritingFactor::Bmi => format!("{:.1}", value),
+            _ => value.to_string(),
+        };
+        let reason = match (rule.min, rule.max) {
+            (Some(min), _) if value < min => format!("{} {} is below {}", name, shown, min),
+            (_, Some(max)) if value > max => format!("{} {} is above {}", name, shown, max),
+            _ => continue,
+        };
+        match rule.outcome {
+            RuleOutcome::Refer => referred.push(reason),
+            RuleOutcome::Decline => declined.push(reason),
+        }
+    }
+    if !declined.is_empty() {
+        info!(
+            "quote for product {} declined: {}",
+            input.code,
+            declined.join(", ")
+        );
+        return Err(PremiumError::CoverDeclined { reasons: declined });
+    }
+    if !referred.is_empty() {
+        info!(
+            "quote for product {} referred: {}",
+            input.code,
+            referred.join(", ")
+        );
+        return Err(PremiumError::ReferToUnderwriter { reasons: referred });
+    }
+    Ok(ped.loadings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assess_rules() {
+        let rules: Vec<UnderwritingRule> = serde_json::from_value(serde_json::json!([
+            {"factor": "bmi", "max": 35, "outcome": "refer"},
+            {"factor": "bmi", "max": 40, "outcome": "decline"},
+            {"factor": "age", "max": 55, "outcome": "refer", "products": ["1A"]},
+            {"factor": "sumInsured", "max": 1000000, "outcome": "refer"}
+        ]))
+        .unwrap();
+        let request = HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: "500000".to_string(),
+            height_cm: Some(170.0),
+            weight_kg: Some(70.0),
+            ..HealthRequest::default()
+        };
+        let assess = |request: &HealthRequest, age, ped| assess_with(&rules, request, age, ped);
+        assert!(assess(&request, 40, PedAssessment::default()).is_ok());
+
+        let heavy = HealthRequest {
+            weight_kg: Some(110.0),
+            ..request.clone()
+        };
+        assert!(matches!(
+            assess(&heavy, 40, PedAssessment::default()),
+            Err(PremiumError::ReferToUnderwriter { reasons }) if reasons == ["BMI 38.1 is above 35"]
+        ));
+
+        let ped = PedAssessment {
+            referred: vec!["CKD".to_string()],
+            ..PedAssessment::default()
+        };
+        assert!(matches!(
+            assess(&request, 60, ped),
+            Err(PremiumError::ReferToUnderwriter { reasons })
+                if reasons == ["declared condition CKD", "age 60 is above 55"]
+        ));
+
+        let heavier = HealthRequest {
+            code: "2B".to_string(),
+            weight_kg: Some(130.0),
+            ..request.clone()
+        };
+        assert!(matches!(
+            assess(&heavier, 60, PedAssessment::default()),
+            Err(PremiumError::CoverDeclined { reasons }) if reasons == ["BMI 45.0 is above 40"]
+        ));
+
+        let unmeasured = HealthRequest {
+            height_cm: None,
+            sum_insured: "2000000".to_string(),
+            ..heavier
+        };
+        assert!(matches!(
+            assess(&unmeasured, 40, PedAssessment::default()),
+            Err(PremiumError::ReferToUnderwriter { reasons })
+                if reasons == ["sum insured 2000000 is above 1000000"]
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+//! Load, quote and unload against a real Redis started with testcontainers.
+//! Needs Docker, so it only runs when asked for:
+//! `cargo test -p premium-core --test redis -- --ignored`.
+
+use async_std::task;
+use chrono::{Local, Months};
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::SyncRunner;
+use testcontainers::GenericImage;
+
+use premium_core::config::{MatrixConfig, RedisConfig};
+use premium_core::premium::{self, HealthRequest, PremiumError};
+use premium_core::{connection, integrity, store};
+
+/// The workbook the service ships with: product 1A at 100000, one row per
+/// age band.
+fn fixture() -> MatrixConfig {
+    MatrixConfig {
+        path: concat!(env!("CARGO_MANIFEST_DIR"), "/../premium_tables.xlsx").to_string(),
+        ..MatrixConfig::default()
+    }
+}
+
+fn quote(age: u32) -> HealthRequest {
+    let born = Local::now().date_naive() - Months::new(age * 12 + 3);
+    HealthRequest {
+        code: "1A".to_string(),
+        sum_insured: "100000".to_string(),
+        date_of_birth: born.format("%Y-%m-%d").to_string(),
+        ..HealthRequest::default()
+    }
+}
+
+#[test]
+#[ignore = "starts a redis container with docker"]
+fn test_load_quote_unload() {
+    let redis = GenericImage::new("redis", "7-alpine")
+        .with_exposed_port(6379.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+        .start()
+        .expect("redis container");
+    let url = format!(
+        "redis://{}:{}",
+        redis.get_host().unwrap(),
+        redis.get_host_port_ipv4(6379).unwrap()
+    );
+    connection::configure(RedisConfig {
+        url: Some(url),
+        key_ttl_secs: Some(3600),
+        ..RedisConfig::default()
+    });
+
+    task::block_on(async {
+        assert!(matches!(
+            premium::quote(quote(40)).await,
+            Err(PremiumError::RiskCalculation)
+        ));
+
+        let report = premium::load(&fixture(), None).await.unwrap();
+        assert!(report.valid);
+        assert_eq!(report.version, Some(1));
+        assert_eq!(report.rows_loaded, 7);
+        for (age, expected) in [(25, "250"), (40, "500"), (50, "750"), (80, "12000")] {
+            let quoted = premium::quote(quote(age)).await.unwrap();
+            assert_eq!(quoted.premium, expected, "age {}", age);
+            assert_eq!(quoted.matrix_version, 1);
+        }
+
+        let keys = premium::key_counts().await.unwrap();
+        assert_eq!(keys.get("1A"), Some(&1));
+        let health = store::key_health().await.unwrap().unwrap();
+        assert_eq!(
+            (health.version, health.expected_keys),
+            (1, Some(health.keys))
+        );
+        assert!(health.expires_in_secs.is_some_and(|secs| secs <= 3600));
+        let integrity = integrity::check().await.unwrap();
+        assert!(integrity.recorded && integrity.consistent);
+
+        let report = premium::load(&fixture(), None).await.unwrap();
+        assert_eq!(report.version, Some(2));
+        assert!(premium::activate(1).await.unwrap());
+        let versions = premium::versions().await.unwrap();
+        assert_eq!(versions.active, Some(1));
+        assert_eq!(versions.versions.len(), 2);
+        assert_eq!(premium::quote(quote(40)).await.unwrap().matrix_version, 1);
+
+        assert!(premium::unload().await.unwrap());
+        assert!(premium::key_counts().await.unwrap().is_empty());
+        assert!(premium::quote(quote(40)).await.is_err());
+    });
+}
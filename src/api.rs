@@ -0,0 +1,178 @@
+use premium_core::add_ons::AddOnPremium;
+use premium_core::commission::Commission;
+use premium_core::discounts::AppliedDiscount;
+use premium_core::frequency::PaymentFrequency;
+use premium_core::ped::ConditionLoading;
+use premium_core::premium::HealthResponse;
+use premium_core::pricing_rules::RuleAdjustment;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Version of the public API a route is served under. Handlers and the core
+/// work with the v1 shapes; other versions are converted on the way out so
+/// the pricing logic stays shared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/api/v1",
+            ApiVersion::V2 => "/api/v2",
+        }
+    }
+
+    /// The quote body this version returns.
+    pub fn quote_body(self, response: HealthResponse) -> serde_json::Result<Value> {
+        match self {
+            ApiVersion::V1 => serde_json::to_value(response),
+            ApiVersion::V2 => serde_json::to_value(QuoteV2::from(response)),
+        }
+    }
+}
+
+/// The v2 quote: the amounts grouped under `premium`, and the discount and
+/// payment sections always present instead of left out when empty.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteV2 {
+    pub premium: PremiumV2,
+    pub currency: String,
+    pub matrix_version: u64,
+    pub expires_at: String,
+    pub discounts: Vec<AppliedDiscount>,
+    pub condition_loadings: Vec<ConditionLoading>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referral_loading_percent: Option<u32>,
+    pub rule_adjustments: Vec<RuleAdjustment>,
+    pub add_ons: Vec<AddOnPremium>,
+    pub payment: PaymentV2,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commission: Option<Commission>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumV2 {
+    /// Premium before tax.
+    pub net: String,
+    pub tax: String,
+    /// Premium with tax; `net` for products without a tax rate.
+    pub total: String,
+    /// What raising the premium to the product's minimum added to `net`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minimum_uplift: Option<String>,
+    /// What rounding to the product's round figure added to `total`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounding_adjustment: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentV2 {
+    pub frequency: PaymentFrequency,
+    pub loading_percent: u32,
+    pub installments: u32,
+    pub installment_amount: String,
+}
+
+impl From<HealthResponse> for QuoteV2 {
+    fn from(response: HealthResponse) -> Self {
+        let payment = match response.breakdown {
+            Some(breakdown) => PaymentV2 {
+                frequency: breakdown.payment_frequency,
+                loading_percent: breakdown.loading_percent,
+                installments: breakdown.installments,
+                installment_amount: breakdown.installment_premium.to_string(),
+            },
+            None => PaymentV2 {
+                frequency: PaymentFrequency::Annual,
+                loading_percent: 0,
+                installments: 1,
+                installment_amount: response.premium.clone(),
+            },
+        };
+        QuoteV2 {
+            premium: PremiumV2 {
+                total: response
+                    .total_premium
+                    .unwrap_or_else(|| response.premium.clone()),
+                tax: response.tax.unwrap_or_else(|| "0".to_string()),
+                net: response.premium,
+                minimum_uplift: response.minimum_premium_uplift,
+                rounding_adjustment: response.rounding_adjustment,
+            },
+            currency: response.currency,
+            matrix_version: response.matrix_version,
+            expires_at: response.expires_at,
+            discounts: response.discounts,
+            condition_loadings: response.condition_loadings,
+            referral_loading_percent: response.referral_loading_percent,
+            rule_adjustments: response.rule_adjustments,
+            add_ons: response.add_ons,
+            payment,
+            commission: response.commission,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_quote_body() {
+        let response = || HealthResponse {
+            premium: "750".to_string(),
+            currency: "INR".to_string(),
+            matrix_version: 2,
+            expires_at: "2026-10-15T00:00:00+05:30".to_string(),
+            discounts: Vec::new(),
+            minimum_premium_applied: false,
+            minimum_premium_uplift: None,
+            breakdown: None,
+            tax: None,
+            total_premium: None,
+            rounding_adjustment: None,
+            condition_loadings: Vec::new(),
+            referral_loading_percent: None,
+            rule_adjustments: Vec::new(),
+            add_ons: Vec::new(),
+            commission: None,
+        };
+        assert_eq!(
+            ApiVersion::V1.quote_body(response()).unwrap(),
+            json!({
+                "premium": "750",
+                "currency": "INR",
+                "matrixVersion": 2,
+                "expiresAt": "2026-10-15T00:00:00+05:30"
+            })
+        );
+        assert_eq!(
+            ApiVersion::V2.quote_body(response()).unwrap(),
+            json!({
+                "premium": {"net": "750", "tax": "0", "total": "750"},
+                "currency": "INR",
+                "matrixVersion": 2,
+                "expiresAt": "2026-10-15T00:00:00+05:30",
+                "discounts": [],
+                "conditionLoadings": [],
+                "ruleAdjustments": [],
+                "addOns": [],
+                "payment": {
+                    "frequency": "annual",
+                    "loadingPercent": 0,
+                    "installments": 1,
+                    "installmentAmount": "750"
+                }
+            })
+        );
+    }
+}
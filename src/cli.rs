@@ -0,0 +1,144 @@
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::Config;
+use crate::logging::LogFormat;
+use premium_core::premium::{self, ErrorResponse, HealthRequest};
+
+/// Health insurance premium service. Runs the HTTP service when no command
+/// is given.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub serve: ServeArgs,
+    #[arg(long, global = true, env = "LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs the HTTP service
+    Serve(ServeArgs),
+    /// Loads a matrix workbook from a path or an https:// or s3:// URL
+    Load {
+        file: String,
+        /// Validates the workbook without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Removes every loaded matrix
+    Unload,
+    /// Exits non-zero unless a matrix is loaded
+    Check,
+    /// Prices a quote from the active matrix
+    Quote {
+        #[arg(long)]
+        code: String,
+        #[arg(long)]
+        sum: String,
+        /// Date of birth as YYYY-MM-DD
+        #[arg(long)]
+        dob: String,
+    },
+    /// Regenerates the published quote API pact
+    WriteContract { path: Option<String> },
+    /// Verifies a pact against a running service
+    VerifyContract {
+        pact: Option<String>,
+        base_url: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    #[arg(long, env = "LISTEN_ADDRESS", default_value = "0.0.0.0")]
+    pub address: String,
+    #[arg(long, env = "LISTEN_PORT")]
+    pub port: Option<u16>,
+}
+
+/// Runs a matrix or quote command against the configured store, printing
+/// its JSON result. Returns whether the command succeeded.
+pub async fn run(command: Command, config: &Config) -> anyhow::Result<bool> {
+    let result = match command {
+        Command::Load { file, dry_run } => {
+            let mut matrix = config.matrix.clone();
+            let source_url = match file.contains("://") {
+                true => Some(file.as_str()),
+                false => {
+                    matrix.path = file.clone();
+                    None
+                }
+            };
+            let report = match dry_run {
+                true => premium::validate(&matrix, source_url).await,
+                false => premium::load(&matrix, source_url).await,
+            };
+            report.map(|report| (report.valid, serde_json::to_string_pretty(&report)))
+        }
+        Command::Unload => premium::unload()
+            .await
+            .map(|ok| (ok, serde_json::to_string(&serde_json::json!({ "ok": ok })))),
+        Command::Check => premium::key_counts().await.map(|keys| {
+            let ok = !keys.is_empty();
+            (
+                ok,
+                serde_json::to_string(&serde_json::json!({ "ok": ok, "keys": keys })),
+            )
+        }),
+        Command::Quote { code, sum, dob } => premium::quote(HealthRequest {
+            code,
+            sum_insured: sum,
+            date_of_birth: dob,
+            ..HealthRequest::default()
+        })
+        .await
+        .map(|response| (true, serde_json::to_string(&response))),
+        Command::Serve(_) | Command::WriteContract { .. } | Command::VerifyContract { .. } => {
+            anyhow::bail!("not a matrix command")
+        }
+    };
+    match result {
+        Ok((ok, output)) => {
+            println!("{}", output?);
+            Ok(ok)
+        }
+        Err(err) => {
+            eprintln!("{}", serde_json::to_string(&ErrorResponse::from(&err))?);
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let cli = Cli::try_parse_from([
+            "premium-rs",
+            "quote",
+            "--code",
+            "1A",
+            "--sum",
+            "100000",
+            "--dob",
+            "1977-09-14",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::Quote { ref code, .. }) if code == "1A"));
+
+        let cli = Cli::try_parse_from(["premium-rs", "load", "tables.xlsx", "--dry-run"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Load { dry_run: true, .. })
+        ));
+
+        let cli = Cli::try_parse_from(["premium-rs", "--port", "8080"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.serve.port, Some(8080));
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::fs;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use premium_core::config::{
+    AddOnsConfig, AgeBandConfig, AuditConfig, BulkConfig, ChannelPricingConfig, ChaosConfig,
+    ClockConfig, CommissionConfig, CurrencyConfig, DateOfBirthConfig, DiscountConfig,
+    EligibilityConfig, FlagsConfig, GroupConfig, MaintenanceConfig, MatrixConfig,
+    PaymentFrequencyConfig, PedConfig, PreflightConfig, PremiumCacheConfig, PricingRulesConfig,
+    ProductRegistryConfig, QuoteExpiryConfig, RateTestConfig, RedisConfig, ShortPeriodConfig,
+    StalenessConfig, StorageConfig, UnderwritingConfig,
+};
+
+use crate::mapping::FieldMapping;
+
+/// Service configuration read from the JSON file named by the `PREMIUM_CONFIG`
+/// env var. Every section is optional so an absent file means defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// Prices of the optional covers quotes can add.
+    pub add_ons: AddOnsConfig,
+    /// Caching directives keyed by request path, e.g. `/api/v1/healths/premiums`.
+    pub cache: HashMap<String, CachePolicy>,
+    /// Operators allowed into the admin page and matrix admin endpoints.
+    pub admin: AdminConfig,
+    /// Sink for the records of every quote.
+    pub audit: AuditConfig,
+    /// Staging only: artificial latency and failures for a share of redis
+    /// commands.
+    pub chaos: ChaosConfig,
+    /// Age bands that map a policyholder's age to the matrix score.
+    pub age_bands: AgeBandConfig,
+    /// Row limit, concurrency and retention of bulk CSV quote jobs.
+    pub bulk: BulkConfig,
+    /// Time zone quote dates such as ages are reckoned in.
+    pub clock: ClockConfig,
+    pub limits: LimitsConfig,
+    /// Gzip and brotli encoding of large responses.
+    pub compression: CompressionConfig,
+    /// Log filter in `RUST_LOG` syntax, replacing `RUST_LOG` when set.
+    pub log_level: Option<String>,
+    pub idempotency: IdempotencyConfig,
+    /// Lets browsers on the listed origins call the API; disabled when absent.
+    pub cors: Option<CorsConfig>,
+    /// The server HTTP connections are handled by.
+    pub http: HttpConfig,
+    /// Currency of each product's premiums.
+    pub currency: CurrencyConfig,
+    /// Formats `dateOfBirth` may be written in.
+    pub date_of_birth: DateOfBirthConfig,
+    /// Worksheet of discount codes quotes may name.
+    pub discounts: DiscountConfig,
+    /// Minimum and maximum entry ages per product.
+    pub eligibility: EligibilityConfig,
+    /// Starts the service refusing quotes, for rate migrations.
+    pub maintenance: MaintenanceConfig,
+    /// Per-product settings that take precedence over the service wide ones.
+    pub products: ProductRegistryConfig,
+    /// Census limit and discount slabs for group quotes.
+    pub group: GroupConfig,
+    /// Consumes quote requests from Kafka when present.
+    pub kafka: Option<KafkaConfig>,
+    /// Request/response field mappings keyed by the partner's `X-Api-Key`.
+    pub partners: HashMap<String, FieldMapping>,
+    /// Price factors of the sales channels named by `X-Channel`.
+    pub channel_pricing: ChannelPricingConfig,
+    /// Commission paid to each distribution channel.
+    pub commission: CommissionConfig,
+    /// Channel codes of distributors keyed by their `X-Api-Key`. A
+    /// distributor is shown the commission of quotes for its own channel.
+    pub distributors: HashMap<String, String>,
+    /// Loadings and decline rules for declared pre-existing conditions.
+    pub ped: PedConfig,
+    /// Percent loadings for premiums paid in installments.
+    pub payment_frequency: PaymentFrequencyConfig,
+    /// Startup checks that gate `/readyz`.
+    pub preflight: PreflightConfig,
+    /// Worksheet of expression rules loading or discounting quotes.
+    pub pricing_rules: PricingRulesConfig,
+    /// In-process cache of premiums looked up from redis.
+    pub premium_cache: PremiumCacheConfig,
+    /// A candidate matrix version priced for a share of quotes, or in the
+    /// shadow of every quote, before it is activated.
+    pub rate_test: RateTestConfig,
+    /// Behaviours switched per request, from this file or redis.
+    pub flags: FlagsConfig,
+    /// How long issued quotes are honoured.
+    pub quote_expiry: QuoteExpiryConfig,
+    /// Short-rate table for quotes with a policy period under a year.
+    pub short_period: ShortPeriodConfig,
+    /// Background check for missing or expiring matrix keys, gating
+    /// `/readyz`.
+    pub staleness: StalenessConfig,
+    /// Tenants with a matrix of their own, named by `X-Tenant-Id` or a
+    /// `/tenants/{id}` path prefix. Requests naming no tenant use the default
+    /// one; naming any tenant is refused when none are listed.
+    pub tenants: Vec<String>,
+    /// Age, BMI and sum insured limits referring or declining quotes.
+    pub underwriting: UnderwritingConfig,
+    /// Serves HTTPS instead of HTTP when present; needs the `tls` feature.
+    pub tls: Option<TlsConfig>,
+    /// Also, or only, serves HTTP on a unix domain socket for a sidecar proxy.
+    pub unix_socket: Option<UnixSocketConfig>,
+    pub webhook: WebhookConfig,
+    pub matrix: MatrixConfig,
+    pub redis: RedisConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CachePolicy {
+    pub cache_control: Option<String>,
+    pub vary: Option<String>,
+}
+
+/// PEM files of the server certificate chain and its private key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(not(any(feature = "tls", feature = "hyper")), allow(dead_code))]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    /// CA bundle client certificates are verified against. When set, the
+    /// admin endpoints only answer connections that presented one.
+    pub client_ca_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CorsConfig {
+    /// Exact origins such as `https://quotes.example.com`, or just `*`.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable by the page.
+    pub exposed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response.
+    pub max_age_secs: u64,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "OPTIONS".to_string()],
+            allowed_headers: vec![
+                "Content-Type".to_string(),
+                "X-Api-Key".to_string(),
+                "X-Channel".to_string(),
+                "X-Request-Id".to_string(),
+                "X-Tenant-Id".to_string(),
+            ],
+            exposed_headers: vec!["X-Request-Id".to_string()],
+            max_age_secs: 600,
+            allow_credentials: false,
+        }
+    }
+}
+
+/// Which server accepts HTTP connections. The routes, middleware and
+/// handlers are the same tide app under either. The connection settings are
+/// hyper's; tide has none to tune.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+#[cfg_attr(not(feature = "hyper"), allow(dead_code))]
+pub struct HttpConfig {
+    pub server: HttpServer,
+    /// Whether HTTP/1.1 connections stay open between requests.
+    pub keep_alive: bool,
+    /// HTTP/2 PING interval that keeps multiplexed connections alive through
+    /// proxies; no pings when absent.
+    pub keep_alive_interval_secs: Option<u64>,
+    /// Requests in flight at once on one HTTP/2 connection.
+    pub max_concurrent_streams: u32,
+    /// Closes a connection with no request in flight for this long; 0 keeps
+    /// idle connections open.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            server: HttpServer::default(),
+            keep_alive: true,
+            keep_alive_interval_secs: None,
+            max_concurrent_streams: 200,
+            idle_timeout_secs: 120,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpServer {
+    /// tide's own HTTP/1.1 server, with TLS and unix sockets.
+    #[default]
+    Tide,
+    /// hyper, serving HTTP/1.1 and HTTP/2 on TCP, cleartext or negotiated
+    /// over TLS; needs the `hyper` feature.
+    Hyper,
+}
+
+/// HTTP basic credentials of operators, password keyed by username. The admin
+/// page and matrix admin endpoints refuse every request while this is empty,
+/// unless `insecure` opens them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AdminConfig {
+    pub users: HashMap<String, String>,
+    /// Leaves the admin endpoints and matrix RPCs open to anyone while no
+    /// `users` are configured; for local development only.
+    pub insecure: bool,
+    /// Serves the admin, matrix and metrics endpoints on this port only,
+    /// leaving the quote endpoints on the listen port.
+    pub port: Option<u16>,
+    /// Address of the admin port; the listen address when absent.
+    pub address: Option<String>,
+}
+
+/// How long results of load and unload requests sent with an
+/// `Idempotency-Key` are kept for replay.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IdempotencyConfig {
+    pub ttl_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig { ttl_secs: 86_400 }
+    }
+}
+
+/// Compresses response bodies of at least `min_bytes` for clients that send
+/// a matching `Accept-Encoding`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_bytes: 1024,
+        }
+    }
+}
+
+/// Bounds on each HTTP request; a request timeout of 0 disables it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LimitsConfig {
+    pub max_body_bytes: u64,
+    pub request_timeout_ms: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_body_bytes: 1024 * 1024,
+            request_timeout_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct KafkaConfig {
+    pub brokers: Vec<String>,
+    pub request_topic: String,
+    pub response_topic: String,
+    #[serde(default = "default_kafka_group")]
+    pub group: String,
+}
+
+fn default_kafka_group() -> String {
+    "premium-rs".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnixSocketConfig {
+    pub path: String,
+    /// Whether the TCP listener is kept alongside the socket.
+    #[serde(default = "default_unix_socket_tcp")]
+    pub tcp: bool,
+}
+
+fn default_unix_socket_tcp() -> bool {
+    true
+}
+
+/// Delivery settings for matrix load callbacks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WebhookConfig {
+    /// HMAC-SHA256 key for the `X-Premium-Signature` header; unsigned when absent.
+    pub secret: Option<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            secret: None,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl AdminConfig {
+    /// Whether admin calls need no credentials at all.
+    pub fn open(&self) -> bool {
+        self.insecure && self.users.is_empty()
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        match std::env::var("PREMIUM_CONFIG") {
+            Ok(path) => {
+                info!("loading configuration from {}", path);
+                Config::from_json(&fs::read_to_string(path)?)
+            }
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Config> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use premium_core::config::{RedisMode, StorageBackend};
+
+    #[test]
+    fn test_from_json() {
+        let config = Config::from_json(
+            r#"{"cache": {"/api/v1/healths/premiums": {"cacheControl": "no-store"}}}"#,
+        )
+        .unwrap();
+        let policy = &config.cache["/api/v1/healths/premiums"];
+        assert_eq!(policy.cache_control.as_deref(), Some("no-store"));
+        assert!(policy.vary.is_none());
+    }
+
+    #[test]
+    fn test_from_empty_json() {
+        let config = Config::from_json("{}").unwrap();
+        assert!(config.cache.is_empty());
+        assert_eq!(config.redis.mode, RedisMode::Sentinel);
+        assert_eq!(config.redis.master_name, "redis-premium-master");
+    }
+
+    #[test]
+    fn test_redis_cluster_json() {
+        let config = Config::from_json(
+            r#"{"redis": {"mode": "cluster", "clusterNodes": ["redis://node-1:6379"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.redis.mode, RedisMode::Cluster);
+        assert_eq!(config.redis.cluster_nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_unix_socket_json() {
+        let config =
+            Config::from_json(r#"{"unixSocket": {"path": "/var/run/premium.sock"}}"#).unwrap();
+        let socket = config.unix_socket.unwrap();
+        assert_eq!(socket.path, "/var/run/premium.sock");
+        assert!(socket.tcp);
+    }
+
+    #[test]
+    fn test_storage_json() {
+        let config = Config::from_json(
+            r#"{"storage": {"backend": "postgres", "postgres": {"url": "postgres://db/premium"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.storage.backend, StorageBackend::Postgres);
+        assert_eq!(config.storage.postgres.max_connections, 5);
+        assert_eq!(
+            Config::from_json("{}").unwrap().storage.backend,
+            StorageBackend::Redis
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use premium_core::premium::{ErrorResponse, PremiumError};
+
+/// Consumer-driven contract in the Pact v2 JSON layout, so files exchanged
+/// with downstream teams can be verified by either side's tooling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pact {
+    pub consumer: Pacticipant,
+    pub provider: Pacticipant,
+    pub interactions: Vec<Interaction>,
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pacticipant {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Interaction {
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_state: Option<String>,
+    pub request: ContractRequest,
+    pub response: ContractResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractResponse {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+/// What the provider actually answered for an interaction.
+pub struct Outcome {
+    pub status: u16,
+    pub body: Option<Value>,
+}
+
+/// The quote API contract, built from the handler types so it changes
+/// whenever their serialized shape does.
+pub fn quote_api_pact() -> Pact {
+    let json_headers =
+        BTreeMap::from([("Content-Type".to_string(), "application/json".to_string())]);
+    let quote = json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": "1977-09-14"});
+    let premiums = "/api/v1/healths/premiums".to_string();
+
+    Pact {
+        consumer: Pacticipant {
+            name: "premium-consumer".to_string(),
+        },
+        provider: Pacticipant {
+            name: "premium-rs".to_string(),
+        },
+        interactions: vec![
+            Interaction {
+                description: "a health check".to_string(),
+                provider_state: None,
+                request: ContractRequest {
+                    method: "GET".to_string(),
+                    path: "/".to_string(),
+                    headers: BTreeMap::new(),
+                    body: None,
+                },
+                response: ContractResponse {
+                    status: 200,
+                    body: None,
+                },
+            },
+            Interaction {
+                description: "a quote without a json content type".to_string(),
+                provider_state: None,
+                request: ContractRequest {
+                    method: "POST".to_string(),
+                    path: premiums.clone(),
+                    headers: BTreeMap::new(),
+                    body: Some(quote.clone()),
+                },
+                response: ContractResponse {
+                    status: 400,
+                    body: Some(error_body(PremiumError::InvalidHeader(
+                        "content-type".to_string(),
+                    ))),
+                },
+            },
+            Interaction {
+                description: "a quote with a malformed body".to_string(),
+                provider_state: None,
+                request: ContractRequest {
+                    method: "POST".to_string(),
+                    path: premiums.clone(),
+                    headers: json_headers.clone(),
+                    body: Some(json!({"code": "1A"})),
+                },
+                response: ContractResponse {
+                    status: 400,
+                    body: Some(error_body(PremiumError::SchemaViolation {
+                        field: "/sumInsured".to_string(),
+                        constraint: "required".to_string(),
+                        detail: "\"sumInsured\" is a required property".to_string(),
+                    })),
+                },
+            },
+            Interaction {
+                description: "a quote for a loaded plan".to_string(),
+                provider_state: Some("premium matrix is loaded".to_string()),
+                request: ContractRequest {
+                    method: "POST".to_string(),
+                    path: premiums,
+                    headers: json_headers,
+                    body: Some(quote),
+                },
+                response: ContractResponse {
+                    status: 200,
+                    body: Some(json!({"premium": "750"})),
+                },
+            },
+        ],
+        metadata: json!({"pactSpecification": {"version": "2.0.0"}}),
+    }
+}
+
+fn error_body(err: PremiumError) -> Value {
+    let message = match &err {
+        PremiumError::InvalidHeader(header) => format!("Header {} not provided or invalid", header),
+        other => other.to_string(),
+    };
+    serde_json::to_value(ErrorResponse {
+        message,
+        ..ErrorResponse::from(&err)
+    })
+    .unwrap_or(Value::Null)
+}
+
+/// Replays every interaction through `send` and returns one message per
+/// mismatch; an empty result means the provider honours the contract.
+pub async fn verify<F, Fut>(pact: &Pact, mut send: F) -> Vec<String>
+where
+    F: FnMut(ContractRequest) -> Fut,
+    Fut: Future<Output = Result<Outcome, String>>,
+{
+    let mut failures = Vec::new();
+    for interaction in &pact.interactions {
+        match send(interaction.request.clone()).await {
+            Ok(outcome) => {
+                if outcome.status != interaction.response.status {
+                    failures.push(format!(
+                        "{}: expected status {} got {}",
+                        interaction.description, interaction.response.status, outcome.status
+                    ));
+                }
+                if let Some(expected) = &interaction.response.body {
+                    let actual = outcome.body.unwrap_or(Value::Null);
+                    if !matches(expected, &actual) {
+                        failures.push(format!(
+                            "{}: expected body {} got {}",
+                            interaction.description, expected, actual
+                        ));
+                    }
+                }
+            }
+            Err(err) => failures.push(format!("{}: {}", interaction.description, err)),
+        }
+    }
+    failures
+}
+
+/// Sends contract requests to a running provider at `base_url`.
+pub async fn send_http(base_url: String, request: ContractRequest) -> Result<Outcome, String> {
+    async_std::task::spawn_blocking(move || {
+        let url = format!("{}{}", base_url.trim_end_matches('/'), request.path);
+        let mut http = ureq::request(&request.method, &url).timeout(Duration::from_secs(10));
+        for (name, value) in &request.headers {
+            http = http.set(name, value);
+        }
+        let result = match &request.body {
+            Some(body) => http.send_string(&body.to_string()),
+            None => http.call(),
+        };
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(err.to_string()),
+        };
+        let status = response.status();
+        let body = response.into_string().map_err(|err| err.to_string())?;
+        Ok(Outcome {
+            status,
+            body: serde_json::from_str(&body).ok(),
+        })
+    })
+    .await
+}
+
+/// Pact body matching: objects may carry extra fields, everything else must
+/// be equal.
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| matches(value, actual))),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| matches(expected, actual))
+        }
+        (expected, actual) => expected == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_published_pact_is_current() {
+        let published: Pact = serde_json::from_str(include_str!(
+            "../contracts/premium-consumer-premium-rs.json"
+        ))
+        .unwrap();
+        assert_eq!(
+            published,
+            quote_api_pact(),
+            "regenerate with `premium-rs write-contract`"
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(
+            &json!({"code": "002"}),
+            &json!({"code": "002", "message": "Invalid request"})
+        ));
+        assert!(!matches(&json!({"code": "002"}), &json!({"code": "003"})));
+        assert!(!matches(&json!([1, 2]), &json!([1])));
+    }
+}
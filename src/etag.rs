@@ -0,0 +1,59 @@
+use premium_core::clock;
+use premium_core::premium::HealthRequest;
+use sha2::{Digest, Sha256};
+
+use crate::api::ApiVersion;
+
+/// Entity tag of a quote: the same request priced from the same matrix
+/// version on the same day gets the same premium, as ages and discount
+/// validity only change from one day to the next. `api` and `partner` keep
+/// differently shaped responses apart.
+pub fn quote(
+    request: &HealthRequest,
+    matrix_version: u64,
+    api: ApiVersion,
+    partner: Option<&str>,
+) -> String {
+    let mut digest = Sha256::new();
+    digest.update(serde_json::to_vec(request).unwrap_or_default());
+    digest.update(matrix_version.to_be_bytes());
+    digest.update(clock::today().to_string());
+    digest.update(api.prefix());
+    digest.update(partner.unwrap_or_default());
+    format!("\"{}\"", &hex::encode(digest.finalize())[..32])
+}
+
+/// Whether an `If-None-Match` header value names `etag`, comparing weakly
+/// as RFC 9110 asks for GET and HEAD.
+pub fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_etag() {
+        let request = HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: "100000".to_string(),
+            date_of_birth: "1977-09-14".to_string(),
+            ..HealthRequest::default()
+        };
+        let etag = quote(&request, 3, ApiVersion::V1, None);
+        assert_eq!(etag, quote(&request.clone(), 3, ApiVersion::V1, None));
+        assert_ne!(etag, quote(&request, 4, ApiVersion::V1, None));
+        assert_ne!(etag, quote(&request, 3, ApiVersion::V2, None));
+        assert_ne!(etag, quote(&request, 3, ApiVersion::V1, Some("partner")));
+
+        assert!(matches(&etag, &etag));
+        assert!(matches(&format!("\"other\", W/{}", etag), &etag));
+        assert!(matches("*", &etag));
+        assert!(!matches("\"other\"", &etag));
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::OnceLock;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Error, ErrorExtensions, Object, Schema, SimpleObject,
+};
+use premium_core::audit::Caller;
+use premium_core::premium::{self, PremiumError};
+use premium_core::products::{self, CatalogProduct};
+use serde_json::{json, Value};
+
+pub type QuoteSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+static SCHEMA: OnceLock<QuoteSchema> = OnceLock::new();
+
+/// The schema served at `/api/v1/graphql`. Requests carry the audited
+/// `Caller` as data.
+pub fn schema() -> &'static QuoteSchema {
+    SCHEMA.get_or_init(|| Schema::build(Query, EmptyMutation, EmptySubscription).finish())
+}
+
+/// A quote as `premiums` returns it, without the discount and installment
+/// details.
+#[derive(Debug, SimpleObject)]
+pub struct Quote {
+    premium: String,
+    currency: String,
+    matrix_version: u64,
+    expires_at: String,
+    tax: Option<String>,
+    total_premium: Option<String>,
+    rounding_adjustment: Option<String>,
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct Product {
+    code: String,
+    sums_insured: Vec<String>,
+    deductibles: Vec<String>,
+    currency: Option<String>,
+    tax_percent: Option<u32>,
+    min_age: Option<i32>,
+    max_age: Option<i32>,
+    zones: Vec<String>,
+}
+
+impl From<CatalogProduct> for Product {
+    fn from(product: CatalogProduct) -> Self {
+        let mut zones: Vec<String> = product.settings.zone_factors.into_keys().collect();
+        zones.sort();
+        Product {
+            code: product.code,
+            sums_insured: product.sums_insured,
+            deductibles: product.deductibles,
+            currency: product.settings.currency,
+            tax_percent: product.settings.tax_percent,
+            min_age: product.settings.min_age,
+            max_age: product.settings.max_age,
+            zones,
+        }
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Quotes a premium; arguments are checked like a `premiums` request body.
+    #[allow(clippy::too_many_arguments)]
+    async fn premium(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+        sum_insured: String,
+        date_of_birth: String,
+        discount_codes: Option<Vec<String>>,
+        payment_frequency: Option<String>,
+        zone: Option<String>,
+        deductible: Option<String>,
+        gender: Option<String>,
+        maternity_cover: Option<bool>,
+        opd_cover: Option<bool>,
+        restore_benefit: Option<bool>,
+        declared_conditions: Option<Vec<String>>,
+        height_cm: Option<f64>,
+        weight_kg: Option<f64>,
+        as_of: Option<String>,
+    ) -> async_graphql::Result<Quote> {
+        let mut value = json!({
+            "code": code,
+            "sumInsured": sum_insured,
+            "dateOfBirth": date_of_birth,
+            "discountCodes": discount_codes.unwrap_or_default(),
+            "paymentFrequency": payment_frequency,
+            "zone": zone,
+            "deductible": deductible,
+            "gender": gender,
+            "maternityCover": maternity_cover,
+            "opdCover": opd_cover,
+            "restoreBenefit": restore_benefit,
+            "declaredConditions": declared_conditions.unwrap_or_default(),
+            "heightCm": height_cm,
+            "weightKg": weight_kg,
+            "asOf": as_of,
+        });
+        if let Some(fields) = value.as_object_mut() {
+            fields.retain(|_, field: &mut Value| !field.is_null());
+        }
+        let request = crate::validate_value(value, None).map_err(error)?;
+        let caller = ctx.data_opt::<Caller>().cloned().unwrap_or_default();
+        let response = premium::quote_for(request, &caller).await.map_err(error)?;
+        Ok(Quote {
+            premium: response.premium,
+            currency: response.currency,
+            matrix_version: response.matrix_version,
+            expires_at: response.expires_at,
+            tax: response.tax,
+            total_premium: response.total_premium,
+            rounding_adjustment: response.rounding_adjustment,
+        })
+    }
+
+    /// The products of the active matrix version.
+    async fn products(&self) -> async_graphql::Result<Vec<Product>> {
+        let catalog = products::catalog().await.map_err(error)?;
+        Ok(catalog.into_iter().map(Product::from).collect())
+    }
+}
+
+/// A GraphQL error carrying the REST error code as its `code` extension.
+fn error(err: PremiumError) -> Error {
+    Error::new(err.to_string()).extend_with(|_, extensions| extensions.set("code", err.code()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    #[test]
+    fn test_invalid_premium_arguments() {
+        let query =
+            r#"{ premium(code: "1A", sumInsured: "abc", dateOfBirth: "1977-09-14") { premium } }"#;
+        let response = task::block_on(schema().execute(query));
+        assert_eq!(response.errors.len(), 1);
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(
+            extensions.get("code"),
+            Some(&async_graphql::Value::from("011"))
+        );
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use std::time::Duration;
+
+use log::{info, warn};
+use tide::http::auth::BasicAuth;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{self, header::CONTENT_TYPE, HeaderName, Method};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::{transport::Server, Request, Response, Status};
+use tonic_web::GrpcWebLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::validate_request::{ValidateRequest, ValidateRequestHeaderLayer};
+
+use crate::config::{Config, CorsConfig};
+use crate::middleware::password_matches;
+use premium_core::audit::Caller;
+use premium_core::premium::{self, PremiumError};
+use premium_core::tenant;
+
+pub mod proto {
+    tonic::include_proto!("premium.v1");
+}
+
+use proto::premium_service_server::{PremiumService, PremiumServiceServer};
+use proto::{HealthRequest, HealthResponse, MatrixRequest, MatrixResponse};
+
+#[derive(Debug)]
+pub struct GrpcPremiumService {
+    config: Arc<Config>,
+}
+
+impl GrpcPremiumService {
+    /// Why a matrix RPC is refused: it is left out of gRPC while the admin
+    /// endpoints have their own port, and needs an operator otherwise.
+    fn refusal<T>(&self, request: &Request<T>, rpc: &str) -> Option<Status> {
+        if self.config.admin.port.is_some() {
+            warn!("grpc {} refused, matrix admin is on the admin port", rpc);
+            return Some(Status::unimplemented(format!(
+                "{} is served on the admin port only",
+                rpc
+            )));
+        }
+        match operator(request) {
+            true => None,
+            false => Some(unauthenticated(rpc)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PremiumService for GrpcPremiumService {
+    async fn calculate_premium(
+        &self,
+        request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        let (caller, tenant) = (caller(&request), tenant_of(&request));
+        let quote = premium::quote_for(request.into_inner().try_into()?, &caller);
+        let response = tenant::scope(tenant, quote).await.map_err(status)?;
+        let (installments, installment_premium) = response
+            .breakdown
+            .map(|breakdown| {
+                (
+                    breakdown.installments,
+                    breakdown.installment_premium.to_string(),
+                )
+            })
+            .unwrap_or((1, response.premium.clone()));
+        Ok(Response::new(HealthResponse {
+            premium: response.premium,
+            installments,
+            installment_premium,
+            currency: response.currency,
+            matrix_version: response.matrix_version,
+            expires_at: response.expires_at,
+            tax: response.tax.unwrap_or_default(),
+            total_premium: response.total_premium.unwrap_or_default(),
+        }))
+    }
+
+    async fn load_matrix(
+        &self,
+        request: Request<MatrixRequest>,
+    ) -> Result<Response<MatrixResponse>, Status> {
+        if let Some(refused) = self.refusal(&request, "LoadMatrix") {
+            return Err(refused);
+        }
+        let tenant = tenant_of(&request);
+        let source_url = request.into_inner().source_url;
+        let source_url = Some(source_url.as_str()).filter(|url| !url.is_empty());
+        let load = premium::load(&self.config.matrix, source_url);
+        let ok = tenant::scope(tenant, load).await.map_err(status)?.valid;
+        Ok(Response::new(MatrixResponse { ok }))
+    }
+
+    async fn unload_matrix(
+        &self,
+        request: Request<MatrixRequest>,
+    ) -> Result<Response<MatrixResponse>, Status> {
+        if let Some(refused) = self.refusal(&request, "UnloadMatrix") {
+            return Err(refused);
+        }
+        let unload = premium::unload();
+        let ok = tenant::scope(tenant_of(&request), unload)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(MatrixResponse { ok }))
+    }
+
+    async fn check_matrix(
+        &self,
+        request: Request<MatrixRequest>,
+    ) -> Result<Response<MatrixResponse>, Status> {
+        if let Some(refused) = self.refusal(&request, "CheckMatrix") {
+            return Err(refused);
+        }
+        let check = premium::keys_exists();
+        let ok = tenant::scope(tenant_of(&request), check)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(MatrixResponse { ok }))
+    }
+}
+
+impl TryFrom<HealthRequest> for premium::HealthRequest {
+    type Error = Status;
+
+    fn try_from(value: HealthRequest) -> Result<Self, Self::Error> {
+        let payment_frequency = match value.payment_frequency.as_str() {
+            "" => None,
+            frequency => Some(
+                serde_json::from_value(serde_json::Value::from(frequency))
+                    .map_err(|_| status(PremiumError::InvalidInput))?,
+            ),
+        };
+        let gender = match value.gender.as_str() {
+            "" => None,
+            gender => Some(
+                serde_json::from_value(serde_json::Value::from(gender))
+                    .map_err(|_| status(PremiumError::InvalidInput))?,
+            ),
+        };
+        Ok(premium::HealthRequest {
+            code: value.code,
+            sum_insured: value.sum_insured,
+            date_of_birth: value.date_of_birth,
+            age: value.age,
+            policy_start_date: Some(value.policy_start_date).filter(|date| !date.is_empty()),
+            policy_end_date: Some(value.policy_end_date).filter(|date| !date.is_empty()),
+            discount_codes: value.discount_codes,
+            payment_frequency,
+            zone: Some(value.zone).filter(|zone| !zone.is_empty()),
+            deductible: Some(value.deductible).filter(|deductible| !deductible.is_empty()),
+            gender,
+            maternity_cover: value.maternity_cover,
+            opd_cover: value.opd_cover,
+            restore_benefit: value.restore_benefit,
+            declared_conditions: value.declared_conditions,
+            height_cm: Some(value.height_cm).filter(|height| *height > 0.0),
+            weight_kg: Some(value.weight_kg).filter(|weight| *weight > 0.0),
+            as_of: Some(value.as_of).filter(|date| !date.is_empty()),
+            channel: None,
+            sales_channel: None,
+        })
+    }
+}
+
+/// The audited caller, from the `x-caller-id` and `x-request-id` metadata.
+fn caller<T>(request: &Request<T>) -> Caller {
+    let metadata = |key| {
+        request
+            .metadata()
+            .get(key)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    Caller {
+        id: metadata("x-caller-id"),
+        request_id: metadata("x-request-id"),
+    }
+}
+
+/// Marks a call made with the basic credentials of one of `admin.users`.
+#[derive(Debug, Clone, Copy)]
+struct Operator;
+
+/// The tenant a call's `x-tenant-id` metadata names, as the HTTP API takes
+/// it from the `X-Tenant-Id` header.
+#[derive(Debug, Clone)]
+struct Tenant(String);
+
+/// Interceptor marking calls whose `authorization` metadata holds the basic
+/// credentials of an operator. Calls without them go through unmarked, as
+/// quotes need none; the matrix RPCs refuse them. Calls naming a tenant that
+/// is not configured are refused with `INVALID_ARGUMENT`.
+#[derive(Debug, Clone)]
+struct Authenticate {
+    users: HashMap<String, String>,
+    /// Marks every call, under `admin.insecure` with no users.
+    open: bool,
+    tenants: Arc<HashSet<String>>,
+}
+
+impl Authenticate {
+    fn new(config: &Config) -> Self {
+        Authenticate {
+            users: config.admin.users.clone(),
+            open: config.admin.open(),
+            tenants: Arc::new(config.tenants.iter().cloned().collect()),
+        }
+    }
+}
+
+impl Interceptor for Authenticate {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let named = request
+            .metadata()
+            .get("x-tenant-id")
+            .map(|value| value.to_str().unwrap_or_default().to_string());
+        if let Some(named) = named {
+            match tenant::parse(&named) {
+                Ok(named) if self.tenants.contains(&named) => {
+                    request.extensions_mut().insert(Tenant(named));
+                }
+                _ => {
+                    warn!("grpc call for unknown tenant {} refused", named);
+                    return Err(status(PremiumError::UnknownTenant(named)));
+                }
+            }
+        }
+        let auth = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|credentials| BasicAuth::from_credentials(credentials.trim()).ok());
+        if self.open {
+            request.extensions_mut().insert(Operator);
+        } else if let Some(auth) = auth {
+            if password_matches(&self.users, auth.username(), auth.password()) {
+                request.extensions_mut().insert(Operator);
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Whether the interceptor marked the call as an operator's; the matrix
+/// cannot be loaded, unloaded or checked anonymously.
+fn operator<T>(request: &Request<T>) -> bool {
+    request.extensions().get::<Operator>().is_some()
+}
+
+/// The tenant the interceptor found on the call, `None` for the default one.
+fn tenant_of<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<Tenant>()
+        .map(|Tenant(tenant)| tenant.clone())
+}
+
+/// The status refusing an anonymous call to a matrix RPC.
+fn unauthenticated(rpc: &str) -> Status {
+    warn!("grpc admin credentials rejected for {}", rpc);
+    status(PremiumError::Unauthorized)
+}
+
+/// CORS for gRPC-web calls from the origins allowed to call the HTTP API,
+/// with the headers gRPC-web needs on top. No origin is allowed without a
+/// `cors` config.
+fn grpc_web_cors(config: Option<&CorsConfig>) -> CorsLayer {
+    let Some(config) = config else {
+        return CorsLayer::new();
+    };
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .filter_map(|origin| origin.parse().ok()),
+        )
+    };
+    let names = |names: &[&str], configured: &[String]| -> Vec<HeaderName> {
+        let mut headers: Vec<HeaderName> = Vec::new();
+        let configured = configured.iter().map(String::as_str);
+        for name in names.iter().copied().chain(configured) {
+            match name.parse() {
+                Ok(header) if !headers.contains(&header) => headers.push(header),
+                _ => {}
+            }
+        }
+        headers
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::POST, Method::OPTIONS])
+        .allow_headers(names(
+            &["content-type", "x-grpc-web", "x-user-agent", "grpc-timeout"],
+            &config.allowed_headers,
+        ))
+        .expose_headers(names(
+            &["grpc-status", "grpc-message", "grpc-status-details-bin"],
+            &config.exposed_headers,
+        ))
+        .max_age(Duration::from_secs(config.max_age_secs))
+}
+
+/// The one RPC served over gRPC-web.
+const GRPC_WEB_RPC: &str = "/premium.v1.PremiumService/CalculatePremium";
+
+/// Keeps gRPC-web to quotes: any other RPC sent by a browser is refused with
+/// `PERMISSION_DENIED` before it reaches the service, so no web page can
+/// load or unload the matrix, whichever origins CORS allows.
+#[derive(Debug, Clone, Copy)]
+struct GrpcWebQuotesOnly;
+
+impl<B> ValidateRequest<B> for GrpcWebQuotesOnly {
+    type ResponseBody = BoxBody;
+
+    fn validate(&mut self, request: &mut http::Request<B>) -> Result<(), http::Response<BoxBody>> {
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("application/grpc-web"));
+        match content_type {
+            Some(content_type) if request.uri().path() != GRPC_WEB_RPC => {
+                warn!("grpc-web call to {} refused", request.uri().path());
+                let mut response =
+                    Status::permission_denied("only CalculatePremium is served over gRPC-web")
+                        .into_http();
+                if let Ok(content_type) = content_type.parse() {
+                    response.headers_mut().insert(CONTENT_TYPE, content_type);
+                }
+                Err(response)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The gRPC code matching the error's HTTP status.
+fn status(err: PremiumError) -> Status {
+    let message = err.to_string();
+    match err.status() {
+        400 => Status::invalid_argument(message),
+        401 => Status::unauthenticated(message),
+        404 => Status::not_found(message),
+        409 => Status::aborted(message),
+        413 | 429 => Status::resource_exhausted(message),
+        422 => Status::failed_precondition(message),
+        502 | 503 => Status::unavailable(message),
+        504 => Status::deadline_exceeded(message),
+        _ => Status::internal(message),
+    }
+}
+
+/// Serves gRPC, and gRPC-web over HTTP/1.1 for browsers on the same port,
+/// under the same body size and request time limits as the HTTP API.
+pub async fn serve(addr: SocketAddr, config: Arc<Config>) -> Result<(), tonic::transport::Error> {
+    info!("premium grpc service started on {}", addr);
+    let limits = &config.limits;
+    let service = PremiumServiceServer::new(GrpcPremiumService {
+        config: config.clone(),
+    })
+    .max_decoding_message_size(usize::try_from(limits.max_body_bytes).unwrap_or(usize::MAX));
+    let mut server = Server::builder();
+    if limits.request_timeout_ms > 0 {
+        server = server.timeout(Duration::from_millis(limits.request_timeout_ms));
+    }
+    server
+        .accept_http1(true)
+        .layer(grpc_web_cors(config.cors.as_ref()))
+        .layer(ValidateRequestHeaderLayer::custom(GrpcWebQuotesOnly))
+        .layer(GrpcWebLayer::new())
+        .add_service(InterceptedService::new(service, Authenticate::new(&config)))
+        .serve(addr)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use premium_core::config::{StorageBackend, StorageConfig};
+    use premium_core::matrix::MatrixRow;
+    use premium_core::store;
+    use tonic::metadata::MetadataValue;
+    use tonic::Code;
+
+    fn service(users: &[(&str, &str)]) -> GrpcPremiumService {
+        service_with(users, None)
+    }
+
+    fn service_with(users: &[(&str, &str)], admin_port: Option<u16>) -> GrpcPremiumService {
+        let mut config = Config::default();
+        config.admin.port = admin_port;
+        for (user, password) in users {
+            config
+                .admin
+                .users
+                .insert(user.to_string(), password.to_string());
+        }
+        GrpcPremiumService {
+            config: Arc::new(config),
+        }
+    }
+
+    /// A matrix request after the interceptor has seen it.
+    fn intercepted(
+        service: &GrpcPremiumService,
+        authorization: Option<&str>,
+    ) -> Request<MatrixRequest> {
+        let metadata: Vec<_> = authorization
+            .map(|authorization| ("authorization", authorization))
+            .into_iter()
+            .collect();
+        intercepted_with(service, &metadata).unwrap()
+    }
+
+    fn intercepted_with(
+        service: &GrpcPremiumService,
+        metadata: &[(&'static str, &str)],
+    ) -> Result<Request<MatrixRequest>, Code> {
+        let mut request = Request::new(());
+        for (key, value) in metadata {
+            let value: MetadataValue<_> = value.parse().unwrap();
+            request.metadata_mut().insert(*key, value);
+        }
+        let request = Authenticate::new(&service.config)
+            .call(request)
+            .map_err(|refused| refused.code())?;
+        let (metadata, extensions, ()) = request.into_parts();
+        Ok(Request::from_parts(
+            metadata,
+            extensions,
+            MatrixRequest::default(),
+        ))
+    }
+
+    #[async_std::test]
+    async fn test_unauthenticated_unload_is_refused() {
+        let service = service(&[("ops", "secret")]);
+        // "ops:wrong" and "ops:secret"
+        for authorization in [None, Some("Basic b3BzOndyb25n")] {
+            let refused = service
+                .unload_matrix(intercepted(&service, authorization))
+                .await
+                .unwrap_err();
+            assert_eq!(refused.code(), Code::Unauthenticated);
+        }
+        let operator = intercepted(&service, Some("Basic b3BzOnNlY3JldA=="));
+        assert!(super::operator(&operator));
+    }
+
+    #[async_std::test]
+    async fn test_no_operators_refuse_matrix_calls() {
+        let service = service(&[]);
+        let refused = service
+            .check_matrix(intercepted(&service, Some("Basic b3BzOnNlY3JldA==")))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code(), Code::Unauthenticated);
+    }
+
+    #[async_std::test]
+    async fn test_matrix_calls_left_to_admin_port() {
+        let service = service_with(&[("ops", "secret")], Some(9443));
+        let refused = service
+            .unload_matrix(intercepted(&service, Some("Basic b3BzOnNlY3JldA==")))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code(), Code::Unimplemented);
+    }
+
+    #[async_std::test]
+    async fn test_matrix_calls_scoped_to_tenant() {
+        store::configure(&StorageConfig {
+            backend: StorageBackend::Memory,
+            ..StorageConfig::default()
+        })
+        .unwrap();
+        let mut service = service(&[("ops", "secret")]);
+        Arc::get_mut(&mut service.config).unwrap().tenants =
+            vec!["grpc-acme".to_string(), "grpc-beta".to_string()];
+        tenant::scope(Some("grpc-acme".to_string()), async {
+            let mut writer = store::begin_version().await.unwrap();
+            writer
+                .write(&[MatrixRow {
+                    key: "1A:100000".to_string(),
+                    code: "1A".to_string(),
+                    sum_insured: "100000".to_string(),
+                    deductible: None,
+                    gender: None,
+                    age_band: "18-35".to_string(),
+                    premium: 5000,
+                    score: 1,
+                }])
+                .await
+                .unwrap();
+            writer.commit().await.unwrap();
+        })
+        .await;
+
+        let check = |tenant| {
+            let request = intercepted_with(
+                &service,
+                &[
+                    ("authorization", "Basic b3BzOnNlY3JldA=="),
+                    ("x-tenant-id", tenant),
+                ],
+            );
+            async {
+                match service.check_matrix(request?).await {
+                    Ok(response) => Ok(response.into_inner().ok),
+                    Err(refused) => Err(refused.code()),
+                }
+            }
+        };
+        assert_eq!(check("grpc-acme").await, Ok(true));
+        assert_eq!(check("grpc-beta").await, Err(Code::Internal));
+        for unknown in ["grpc-other", "Grpc-Acme"] {
+            assert_eq!(check(unknown).await, Err(Code::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn test_grpc_web_serves_quotes_only() {
+        let call = |path: &str, content_type: &str| {
+            let mut request = http::Request::post(path)
+                .header(CONTENT_TYPE, content_type)
+                .body(())
+                .unwrap();
+            GrpcWebQuotesOnly.validate(&mut request).err()
+        };
+        let refused = call(
+            "/premium.v1.PremiumService/UnloadMatrix",
+            "application/grpc-web+proto",
+        )
+        .unwrap();
+        assert_eq!(refused.headers()["grpc-status"], "7");
+        assert_eq!(
+            refused.headers()[CONTENT_TYPE],
+            "application/grpc-web+proto"
+        );
+        assert!(call(GRPC_WEB_RPC, "application/grpc-web-text").is_none());
+        assert!(call(
+            "/premium.v1.PremiumService/UnloadMatrix",
+            "application/grpc"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_premium_error_to_status() {
+        assert_eq!(status(PremiumError::InternalServer).code(), Code::Internal);
+        assert_eq!(
+            status(PremiumError::RiskCalculation).code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            status(PremiumError::Unauthorized).code(),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            status(PremiumError::UpstreamUnavailable(
+                "Matrix source".to_string()
+            ))
+            .code(),
+            Code::Unavailable
+        );
+    }
+}
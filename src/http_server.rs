@@ -0,0 +1,374 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes};
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use log::{error, info, warn};
+use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url, Version};
+use tide::StatusCode;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{HttpConfig, TlsConfig};
+
+/// Serves `app` with hyper on `listen` until the listener fails. Each
+/// connection speaks HTTP/1.1 or HTTP/2: with prior knowledge in cleartext
+/// (h2c), or as negotiated by ALPN when `tls` is given. Every request is
+/// answered by `app` as tide would answer it.
+pub async fn serve<State: Clone + Send + Sync + 'static>(
+    app: tide::Server<State>,
+    listen: String,
+    config: &HttpConfig,
+    tls: Option<&TlsConfig>,
+) -> tide::Result<()> {
+    let addr: SocketAddr = listen.parse()?;
+    let acceptor = tls.map(acceptor).transpose()?;
+    let builder = Arc::new(builder(config));
+    let idle_timeout =
+        Some(Duration::from_secs(config.idle_timeout_secs)).filter(|timeout| !timeout.is_zero());
+    // hyper needs tokio's reactor; the handlers keep running on async-std.
+    async_std::task::spawn_blocking(move || {
+        tokio::runtime::Runtime::new()?.block_on(accept(app, addr, builder, acceptor, idle_timeout))
+    })
+    .await?;
+    Ok(())
+}
+
+/// A connection builder with the configured keep-alive and stream limits.
+fn builder(config: &HttpConfig) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.max_concurrent_streams)
+        .keep_alive_interval(config.keep_alive_interval_secs.map(Duration::from_secs));
+    builder
+}
+
+/// Terminates TLS with the configured certificate, offering HTTP/2 ahead of
+/// HTTP/1.1. Client certificates are only checked by the tide server.
+fn acceptor(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    if config.client_ca_path.is_some() {
+        anyhow::bail!("tls.clientCaPath needs the tide server; set http.server to tide");
+    }
+    let chain = CertificateDer::pem_file_iter(&config.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| anyhow::anyhow!("no certificates in {}: {}", config.cert_path, err))?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|err| anyhow::anyhow!("no private key in {}: {}", config.key_path, err))?;
+    let mut server = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+async fn accept<State: Clone + Send + Sync + 'static>(
+    app: tide::Server<State>,
+    addr: SocketAddr,
+    builder: Arc<auto::Builder<TokioExecutor>>,
+    acceptor: Option<TlsAcceptor>,
+    idle_timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    match acceptor {
+        Some(_) => info!("serving https/1.1 and h2 with hyper on {}", addr),
+        None => info!("serving http/1.1 and h2c with hyper on {}", addr),
+    }
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (app, builder, acceptor) = (app.clone(), builder.clone(), acceptor.clone());
+        tokio::spawn(async move {
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let io = TokioIo::new(stream);
+                        connection(&builder, io, app, peer, addr, idle_timeout).await
+                    }
+                    Err(err) => Err(err.into()),
+                },
+                None => {
+                    let io = TokioIo::new(stream);
+                    connection(&builder, io, app, peer, addr, idle_timeout).await
+                }
+            };
+            if let Err(err) = result {
+                warn!("connection from {} ended {}", peer, err);
+            }
+        });
+    }
+}
+
+/// Requests in flight on a connection and when it last had none.
+struct Activity(Mutex<(usize, Instant)>);
+
+impl Activity {
+    fn begin(self: &Arc<Self>) -> InFlight {
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).0 += 1;
+        InFlight(self.clone())
+    }
+
+    fn idle_for(&self) -> Option<Duration> {
+        let (in_flight, since) = *self.0.lock().unwrap_or_else(|err| err.into_inner());
+        (in_flight == 0).then(|| since.elapsed())
+    }
+}
+
+/// A request being answered; the connection is not idle until it drops.
+struct InFlight(Arc<Activity>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut activity = self.0 .0.lock().unwrap_or_else(|err| err.into_inner());
+        activity.0 -= 1;
+        activity.1 = Instant::now();
+    }
+}
+
+/// Serves one connection, shutting it down gracefully once it has had no
+/// request in flight for `idle_timeout`.
+async fn connection<State, I>(
+    builder: &auto::Builder<TokioExecutor>,
+    io: I,
+    app: tide::Server<State>,
+    peer: SocketAddr,
+    local: SocketAddr,
+    idle_timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    State: Clone + Send + Sync + 'static,
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let activity = Arc::new(Activity(Mutex::new((0, Instant::now()))));
+    let requests = activity.clone();
+    let service = service_fn(move |request| {
+        let in_flight = requests.begin();
+        let response = handle(app.clone(), peer, local, request);
+        async move {
+            let response = response.await;
+            drop(in_flight);
+            response
+        }
+    });
+    let connection = builder.serve_connection(io, service);
+    tokio::pin!(connection);
+    let Some(idle_timeout) = idle_timeout else {
+        return connection.await;
+    };
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            result = connection.as_mut() => return result,
+            _ = tokio::time::sleep(IDLE_CHECK.min(idle_timeout)), if !closing => {
+                if activity.idle_for().is_some_and(|idle| idle >= idle_timeout) {
+                    connection.as_mut().graceful_shutdown();
+                    closing = true;
+                }
+            }
+        }
+    }
+}
+
+/// How often connections are checked for having gone idle.
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+/// Answers one hyper request with `app`. A failure to read the request
+/// body or to build the response is answered with a bare status.
+async fn handle<State, B>(
+    app: tide::Server<State>,
+    peer: SocketAddr,
+    local: SocketAddr,
+    request: hyper::Request<B>,
+) -> Result<hyper::Response<Full<Bytes>>, Infallible>
+where
+    State: Clone + Send + Sync + 'static,
+    B: Body,
+    B::Error: std::fmt::Display,
+{
+    let (parts, body) = request.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) => {
+            warn!("cannot read request body from {} {}", peer, err);
+            return Ok(status(StatusCode::BadRequest));
+        }
+    };
+    let response = match to_tide(&parts, body, peer, local) {
+        Some(request) => app.respond(request).await,
+        None => return Ok(status(StatusCode::BadRequest)),
+    };
+    match response {
+        Ok(response) => Ok(from_tide(response).await),
+        Err(err) => {
+            error!("request failed {}", err);
+            Ok(status(StatusCode::InternalServerError))
+        }
+    }
+}
+
+/// The tide request standing for a hyper request, `None` when its method or
+/// URL cannot be represented.
+fn to_tide(
+    parts: &hyper::http::request::Parts,
+    body: Bytes,
+    peer: SocketAddr,
+    local: SocketAddr,
+) -> Option<HttpRequest> {
+    let method: Method = parts.method.as_str().parse().ok()?;
+    // HTTP/2 requests carry the authority in the URI, HTTP/1.1 ones in Host.
+    let host = match parts.uri.authority() {
+        Some(authority) => authority.to_string(),
+        None => parts
+            .headers
+            .get(hyper::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map_or_else(|| local.to_string(), str::to_string),
+    };
+    let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+    let url = Url::parse(&format!("http://{}{}", host, path)).ok()?;
+    let mut request = HttpRequest::new(method, url);
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            request.append_header(name.as_str(), value);
+        }
+    }
+    request.set_version(match parts.version {
+        hyper::Version::HTTP_10 => Some(Version::Http1_0),
+        hyper::Version::HTTP_2 => Some(Version::Http2_0),
+        _ => Some(Version::Http1_1),
+    });
+    request.set_peer_addr(Some(peer));
+    request.set_local_addr(Some(local));
+    if !body.is_empty() {
+        // Setting a body replaces the caller's Content-Type with the body's.
+        let content_type = request
+            .header("Content-Type")
+            .map(|value| value.as_str().to_string());
+        request.set_body(body.to_vec());
+        request.remove_header("Content-Type");
+        if let Some(content_type) = content_type {
+            request.insert_header("Content-Type", content_type.as_str());
+        }
+    }
+    Some(request)
+}
+
+/// The hyper response for a tide one. The body is sent whole, with the
+/// length hyper works out from it.
+async fn from_tide(mut response: HttpResponse) -> hyper::Response<Full<Bytes>> {
+    let body = match response.body_bytes().await {
+        Ok(body) => body,
+        Err(err) => {
+            error!("cannot read response body {}", err);
+            return status(StatusCode::InternalServerError);
+        }
+    };
+    let mut builder = hyper::Response::builder().status(u16::from(response.status()));
+    for (name, values) in response.iter() {
+        if name == "content-length" || name == "transfer-encoding" {
+            continue;
+        }
+        for value in values.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    builder
+        .body(Full::new(Bytes::from(body)))
+        .unwrap_or_else(|_| status(StatusCode::InternalServerError))
+}
+
+fn status(status: StatusCode) -> hyper::Response<Full<Bytes>> {
+    let mut response = hyper::Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = hyper::StatusCode::from_u16(status.into())
+        .unwrap_or(hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use tide::Request;
+
+    #[test]
+    fn test_handle() {
+        let mut app = tide::new();
+        app.at("/echo").post(|mut req: Request<()>| async move {
+            let body = req.body_string().await?;
+            let mut response = tide::Response::new(StatusCode::Created);
+            response.insert_header("X-Version", format!("{:?}", req.version()));
+            response.insert_header("X-Query", req.url().query().unwrap_or_default());
+            response.insert_header("X-Peer", req.peer_addr().unwrap_or_default());
+            response.set_body(body);
+            response.set_content_type(req.content_type().unwrap());
+            Ok(response)
+        });
+        let peer: SocketAddr = "10.0.0.7:51000".parse().unwrap();
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        task::block_on(async {
+            let request = hyper::Request::post("/echo?code=1A")
+                .version(hyper::Version::HTTP_2)
+                .header("Host", "quotes.example.com")
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from_static(b"{\"code\":\"1A\"}")))
+                .unwrap();
+            let response = handle(app.clone(), peer, local, request).await.unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::CREATED);
+            let header = |name: &str| response.headers()[name].to_str().unwrap().to_string();
+            assert_eq!(header("x-version"), "Some(Http2_0)");
+            assert_eq!(header("x-query"), "code=1A");
+            assert_eq!(header("x-peer"), "10.0.0.7:51000");
+            assert!(header("content-type").starts_with("application/json"));
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, Bytes::from_static(b"{\"code\":\"1A\"}"));
+
+            let missing = hyper::Request::get("/nowhere")
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            let response = handle(app, peer, local, missing).await.unwrap();
+            assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn test_activity() {
+        let activity = Arc::new(Activity(Mutex::new((0, Instant::now()))));
+        assert!(activity.idle_for().is_some());
+        let first = activity.begin();
+        let second = activity.begin();
+        drop(first);
+        assert_eq!(activity.idle_for(), None);
+        drop(second);
+        assert!(activity.idle_for().unwrap() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_acceptor() {
+        let config = TlsConfig {
+            cert_path: "missing-cert.pem".to_string(),
+            key_path: "missing-key.pem".to_string(),
+            client_ca_path: Some("ca.pem".to_string()),
+        };
+        let err = acceptor(&config).err().unwrap();
+        assert!(err.to_string().contains("clientCaPath"));
+        let config = TlsConfig {
+            client_ca_path: None,
+            ..config
+        };
+        let err = acceptor(&config).err().unwrap();
+        assert!(err.to_string().contains("missing-cert.pem"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use async_std::task;
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use log::{error, info};
+
+use crate::config::KafkaConfig;
+use premium_core::audit::Caller;
+use premium_core::premium::{self, ErrorResponse, HealthRequest, PremiumError};
+use premium_core::schema;
+
+/// Reads quote requests from the request topic and publishes the premium, or
+/// the error, to the response topic keyed like the request so callers can
+/// correlate replies. Blocks the calling thread.
+pub fn consume(config: KafkaConfig) -> kafka::Result<()> {
+    let mut consumer = Consumer::from_hosts(config.brokers.clone())
+        .with_topic(config.request_topic.clone())
+        .with_group(config.group.clone())
+        .with_fallback_offset(FetchOffset::Latest)
+        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+        .create()?;
+    let mut producer = Producer::from_hosts(config.brokers.clone())
+        .with_ack_timeout(Duration::from_secs(1))
+        .with_required_acks(RequiredAcks::One)
+        .create()?;
+    info!(
+        "premium kafka consumer started on topic {}",
+        config.request_topic
+    );
+
+    loop {
+        for message_set in consumer.poll()?.iter() {
+            for message in message_set.messages() {
+                let reply = task::block_on(quote(message.key, message.value));
+                let record =
+                    Record::from_key_value(config.response_topic.as_str(), message.key, reply);
+                if let Err(err) = producer.send(&record) {
+                    error!("Kafka error while publishing quote response {}", err);
+                }
+            }
+            consumer.consume_messageset(message_set)?;
+        }
+        consumer.commit_consumed()?;
+    }
+}
+
+/// Checks the message against the request schema before deserializing it.
+fn parse(payload: &[u8]) -> anyhow::Result<HealthRequest, PremiumError> {
+    let value = serde_json::from_slice::<serde_json::Value>(payload).map_err(|err| {
+        error!(
+            "Serialization error while converting message to struct {}",
+            err
+        );
+        PremiumError::InvalidInput
+    })?;
+    schema::validate(&value)?;
+    serde_json::from_value::<HealthRequest>(value).map_err(|err| {
+        error!(
+            "Serialization error while converting message to struct {}",
+            err
+        );
+        PremiumError::InvalidInput
+    })
+}
+
+/// Prices one message, auditing it under the `kafka` caller with the
+/// message key as the request id.
+async fn quote(key: &[u8], payload: &[u8]) -> Vec<u8> {
+    let caller = Caller {
+        id: Some("kafka".to_string()),
+        request_id: Some(String::from_utf8_lossy(key).into_owned()).filter(|key| !key.is_empty()),
+    };
+    let result = match parse(payload) {
+        Ok(request) => premium::quote_for(request, &caller).await,
+        Err(err) => Err(err),
+    };
+    let reply = match result {
+        Ok(response) => serde_json::to_vec(&response),
+        Err(err) => serde_json::to_vec(&ErrorResponse::from(&err)),
+    };
+    reply.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_invalid_message() {
+        let reply = task::block_on(quote(b"quote-1", b"not json"));
+        let reply: serde_json::Value = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(reply["code"], "002");
+    }
+}
@@ -1,138 +1,1517 @@
-mod premium;
-use log::{error, info};
-use premium::*;
-use serde::Serialize;
+mod api;
+mod cli;
+mod config;
+mod contract;
+mod etag;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "hyper")]
+mod http_server;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod logging;
+mod mapping;
+mod middleware;
+mod problem;
+#[cfg(feature = "grpc")]
+mod protobuf;
+mod reload;
+#[cfg(test)]
+mod suite;
+#[cfg(feature = "tls")]
+mod tls;
+mod watch;
+mod webhook;
+mod xml;
+use std::collections::BTreeMap;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::{ApiVersion, QuoteV2};
+use async_std::prelude::FutureExt;
+use clap::Parser;
+use cli::{Cli, Command, ServeArgs};
+use config::{Config, HttpServer};
+use log::{error, info, warn};
+use mapping::FieldMapping;
+use premium_core::audit::{AuditFilter, Caller};
+use premium_core::commission::Commission;
+use premium_core::compare::{compare, CompareRequest};
+use premium_core::config::{FlagSetting, StorageBackend};
+use premium_core::endorsement::{calculate_endorsement, EndorsementRequest};
+use premium_core::expiry::{revalidate, RevalidationRequest};
+use premium_core::export::ExportFormat;
+use premium_core::flags::Flag;
+use premium_core::group::{calculate_group, GroupRequest};
+use premium_core::history::{history, HistoryQuery};
+use premium_core::premium::*;
+use premium_core::{
+    add_ons, audit, bands, bulk, channels, chaos, clock, commission, connection, diff, discounts,
+    dob, eligibility, expiry, explain, export, flags, frequency, group, integrity, maintenance,
+    money, ped, preflight, pricing_rules, products, quote_cache, rate_test, referral, retry,
+    schema, short_period, staleness, store, tenant, underwriting,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tide::http::auth::BasicAuth;
+use tide::listener::ConcurrentListener;
 use tide::{Body, Request, Response, StatusCode};
 
+#[derive(Clone)]
+struct State {
+    config: Arc<Config>,
+    limits: middleware::Limits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LoadQuery {
+    #[serde(default, rename = "dryRun")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LoadRequest {
+    #[serde(rename = "callbackUrl")]
+    callback_url: Option<String>,
+    #[serde(rename = "sourceUrl")]
+    source_url: Option<String>,
+}
+
 #[async_std::main]
 async fn main() -> tide::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp(None)
-        .format_module_path(false)
-        .init();
+    let cli = Cli::parse();
+    logging::init(cli.log_format);
+
+    let serve_args = match cli.command {
+        None => cli.serve,
+        Some(Command::Serve(args)) => args,
+        Some(Command::WriteContract { path }) => return write_contract(path.as_ref()),
+        Some(Command::VerifyContract { pact, base_url }) => {
+            return verify_contract(pact.as_ref(), base_url.as_ref()).await
+        }
+        Some(command) => {
+            let config = Config::load()?;
+            configure(&config).await?;
+            if !cli::run(command, &config).await? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    };
+    serve(serve_args).await
+}
+
+/// Sets up the process wide redis, cache, pricing and store settings and
+/// reads the discount codes and product settings, loading the workbook up
+/// front when the matrix lives in memory.
+async fn configure(config: &Config) -> tide::Result<()> {
+    if let Some(level) = &config.log_level {
+        logging::set_level(level)?;
+    }
+    for name in &config.tenants {
+        tenant::parse(name)?;
+    }
+    connection::configure(config.redis.clone());
+    quote_cache::configure(&config.premium_cache);
+    bands::configure(&config.age_bands);
+    clock::configure(&config.clock)?;
+    dob::configure(&config.date_of_birth);
+    eligibility::configure(&config.eligibility);
+    underwriting::configure(&config.underwriting);
+    rate_test::configure(&config.rate_test);
+    flags::configure(&config.flags);
+    commission::configure(&config.commission);
+    channels::configure(&config.channel_pricing);
+    short_period::configure(&config.short_period);
+    group::configure(&config.group);
+    bulk::configure(&config.bulk);
+    frequency::configure(&config.payment_frequency);
+    money::configure(&config.currency);
+    expiry::configure(&config.quote_expiry);
+    maintenance::configure(&config.maintenance);
+    chaos::configure(&config.chaos);
+    discounts::load(&config.discounts, &config.matrix)?;
+    ped::load(&config.ped, &config.matrix)?;
+    add_ons::load(&config.add_ons, &config.matrix)?;
+    pricing_rules::load(&config.pricing_rules, &config.matrix)?;
+    products::load(&config.products, &config.matrix)?;
+    audit::configure(&config.audit);
+    store::configure(&config.storage)?;
+    if config.storage.backend == StorageBackend::Memory {
+        let report = load(&config.matrix, None).await?;
+        if !report.valid {
+            return Err(tide::Error::from_str(
+                StatusCode::InternalServerError,
+                format!("matrix {} is invalid", config.matrix.path),
+            ));
+        }
+        info!(
+            "loaded {} matrix rows into memory from {}",
+            report.rows_loaded, config.matrix.path
+        );
+    }
+    Ok(())
+}
+
+async fn serve(args: ServeArgs) -> tide::Result<()> {
+    let address = args.address;
+    let config = Config::load()?;
+    configure(&config).await?;
+
+    #[cfg(feature = "kafka")]
+    if let Some(kafka_config) = config.kafka.clone() {
+        std::thread::spawn(move || {
+            if let Err(err) = kafka::consume(kafka_config) {
+                error!("kafka consumer stopped {}", err);
+            }
+        });
+    }
+
+    if config.matrix.watch {
+        let matrix_config = config.matrix.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = watch::watch(matrix_config) {
+                error!("matrix watcher stopped {}", err);
+            }
+        });
+    }
+
+    if config.preflight.enabled {
+        let preflight_config = config.preflight.clone();
+        async_std::task::spawn(async move {
+            let interval = Duration::from_millis(preflight_config.retry_interval_ms);
+            while !preflight::run(&preflight_config).await.ready {
+                async_std::task::sleep(interval).await;
+            }
+        });
+    }
+
+    async_std::task::spawn(staleness::watch(config.staleness.clone()));
+    async_std::task::spawn(flags::watch(config.flags.clone()));
+
+    if config.admin.open() {
+        warn!("admin.insecure is set: admin endpoints and matrix RPCs need no credentials");
+    } else if config.admin.users.is_empty() {
+        warn!("no admin.users configured: admin endpoints and matrix RPCs refuse every call");
+    }
+    for user in middleware::plaintext_passwords(&config.admin.users) {
+        warn!(
+            "admin user {} has a plaintext password; configure its sha256: digest instead",
+            user
+        );
+    }
+
+    info!("premium service started");
+
+    #[cfg(feature = "grpc")]
+    {
+        let grpc_port = std::env::var("GRPC_PORT").unwrap_or_else(|_| "50051".to_string());
+        let grpc_addr = format!("{}:{}", address, grpc_port).parse()?;
+        let grpc_config = Arc::new(config.clone());
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().expect("tokio runtime for grpc");
+            if let Err(err) = runtime.block_on(grpc::serve(grpc_addr, grpc_config)) {
+                error!("grpc service stopped {}", err);
+            }
+        });
+    }
+
+    let state = state(&config);
+    let limits = state.limits.clone();
+    std::thread::spawn(move || {
+        if let Err(err) = reload::on_sighup(limits) {
+            error!("SIGHUP handler stopped {}", err);
+        }
+    });
+
+    let admin_address = config.admin.address.as_deref().unwrap_or(&address);
+    let (public, admin) = match config.admin.port {
+        Some(admin_port) => {
+            let mut public = server(state.clone(), &config);
+            public_routes(&mut public);
+            tenant_routes(&mut public, &config, public_routes);
+            let mut admin = server(state, &config);
+            admin_routes(&mut admin, &config);
+            tenant_routes(&mut admin, &config, |tenant| admin_routes(tenant, &config));
+            let admin_listen = format!("{}:{}", admin_address, admin_port);
+            (public, Some((admin, admin_listen)))
+        }
+        None => (app(state, &config), None),
+    };
+    match config.http.server {
+        HttpServer::Tide => {
+            let listen = listeners(&config, &address, args.port)?;
+            match admin {
+                Some((admin, admin_tcp)) => {
+                    let mut admin_listen = ConcurrentListener::new();
+                    add_tcp(&mut admin_listen, &config, admin_tcp)?;
+                    public
+                        .listen(listen)
+                        .try_join(admin.listen(admin_listen))
+                        .await?;
+                }
+                None => public.listen(listen).await?,
+            }
+        }
+        HttpServer::Hyper => serve_hyper(&config, &address, args.port, public, admin).await?,
+    }
+    Ok(())
+}
 
-    let address = std::env::var("LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port = std::env::var("LISTEN_PORT").expect("LISTEN_PORT env var is required");
-    let listen = format!("{}:{}", address, port);
+/// Serves the apps with hyper on TCP, over TLS when `tls` is configured.
+/// Unix sockets are tide's, so configuring one fails.
+#[cfg(feature = "hyper")]
+async fn serve_hyper(
+    config: &Config,
+    address: &str,
+    port: Option<u16>,
+    public: tide::Server<State>,
+    admin: Option<(tide::Server<State>, String)>,
+) -> tide::Result<()> {
+    if config.unix_socket.is_some() {
+        return Err(
+            anyhow::anyhow!("unixSocket needs the tide server; set http.server to tide").into(),
+        );
+    }
+    let port = port.ok_or_else(|| anyhow::anyhow!("LISTEN_PORT env var or --port is required"))?;
+    let (http, tls) = (&config.http, config.tls.as_ref());
+    let public = http_server::serve(public, format!("{}:{}", address, port), http, tls);
+    match admin {
+        Some((admin, admin_listen)) => {
+            public
+                .try_join(http_server::serve(admin, admin_listen, http, tls))
+                .await?;
+        }
+        None => public.await?,
+    }
+    Ok(())
+}
 
-    let mut app = tide::new();
+#[cfg(not(feature = "hyper"))]
+async fn serve_hyper(
+    _config: &Config,
+    _address: &str,
+    _port: Option<u16>,
+    _public: tide::Server<State>,
+    _admin: Option<(tide::Server<State>, String)>,
+) -> tide::Result<()> {
+    Err(
+        anyhow::anyhow!("http.server is hyper but premium-rs was built without the hyper feature")
+            .into(),
+    )
+}
+
+/// HTTP, or HTTPS when `tls` is configured, on `listen`.
+fn add_tcp(
+    listeners: &mut ConcurrentListener<State>,
+    config: &Config,
+    listen: String,
+) -> tide::Result<()> {
+    match &config.tls {
+        #[cfg(feature = "tls")]
+        Some(tls_config) => {
+            info!("serving https on {}", listen);
+            listeners.add(tls::listener(tls_config, &listen)?)?
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => {
+            return Err(anyhow::anyhow!(
+                "tls is configured but premium-rs was built without the tls feature"
+            )
+            .into())
+        }
+        None => listeners.add(listen)?,
+    }
+    Ok(())
+}
+
+/// HTTP, or HTTPS with `tls`, on `address:port` unless the unix socket
+/// replaces it, and HTTP on the unix socket when one is configured.
+fn listeners(
+    config: &Config,
+    address: &str,
+    port: Option<u16>,
+) -> tide::Result<ConcurrentListener<State>> {
+    let mut listeners = ConcurrentListener::new();
+    if config.unix_socket.as_ref().is_none_or(|socket| socket.tcp) {
+        let port =
+            port.ok_or_else(|| anyhow::anyhow!("LISTEN_PORT env var or --port is required"))?;
+        add_tcp(&mut listeners, config, format!("{}:{}", address, port))?;
+    }
+    if let Some(socket) = &config.unix_socket {
+        // A socket left behind by an earlier run would fail the bind.
+        if std::fs::metadata(&socket.path).is_ok_and(|file| file.file_type().is_socket()) {
+            std::fs::remove_file(&socket.path)?;
+        }
+        info!("serving http on unix socket {}", socket.path);
+        listeners.add(format!("http+unix://{}", socket.path))?;
+    }
+    Ok(listeners)
+}
+
+/// The public and admin endpoints on one server, as served when the admin
+/// endpoints have no port of their own.
+fn app(state: State, config: &Config) -> tide::Server<State> {
+    let mut app = server(state, config);
+    public_routes(&mut app);
+    admin_routes(&mut app, config);
+    tenant_routes(&mut app, config, |tenant| {
+        public_routes(tenant);
+        admin_routes(tenant, config);
+    });
+    app
+}
+
+/// `routes` again under `/tenants/{id}` when tenants are configured. The
+/// outer server's middleware scopes them to the tenant in the path.
+fn tenant_routes(
+    app: &mut tide::Server<State>,
+    config: &Config,
+    routes: impl FnOnce(&mut tide::Server<State>),
+) {
+    if config.tenants.is_empty() {
+        return;
+    }
+    let mut tenant = tide::with_state(app.state().clone());
+    routes(&mut tenant);
+    app.at("/tenants/:tenant").nest(tenant);
+}
+
+fn state(config: &Config) -> State {
+    State {
+        config: Arc::new(config.clone()),
+        limits: middleware::Limits::new(&config.limits),
+    }
+}
+
+/// A server with the middleware and probes every port has.
+fn server(state: State, config: &Config) -> tide::Server<State> {
+    let limits = state.limits.clone();
+    let mut app = tide::with_state(state);
+    app.with(middleware::RequestSpan);
+    if config.compression.enabled {
+        app.with(middleware::Compression::new(&config.compression));
+    }
+    app.with(problem::Problems);
+    app.with(middleware::Tenants::new(&config.tenants));
+    if let Some(cors) = &config.cors {
+        app.with(middleware::cors(cors));
+    }
+    app.with(limits);
+    app.with(middleware::CacheHeaders::new(config.cache.clone()));
 
     app.at("/").get(healthz);
-    app.at("/api/v1/healths/premiums").post(premiums);
-    app.at("/api/v1/healths/premiums/loads").post(load_matrix);
+    app.at("/readyz").get(readyz);
+    app.at("/version").get(version);
+    app
+}
+
+/// The quote endpoints.
+fn public_routes(app: &mut tide::Server<State>) {
+    for api in ApiVersion::ALL {
+        app.at(&format!("{}/healths/premiums", api.prefix()))
+            .get(move |req| premium_query(req, api))
+            .post(move |req| premiums(req, api));
+    }
+    #[cfg(feature = "graphql")]
+    app.at("/api/v1/graphql").post(graphql_query);
+    app.at("/api/v1/healths/premiums/endorsements")
+        .post(endorsements);
+    app.at("/api/v1/healths/premiums/groups").post(groups);
+    app.at("/api/v1/healths/premiums/compare")
+        .post(compare_premiums);
+    app.at("/api/v1/healths/premiums/bulk").post(submit_bulk);
+    app.at("/api/v1/healths/premiums/bulk/:id").get(bulk_job);
+    app.at("/api/v1/healths/premiums/bulk/:id/result")
+        .get(bulk_result);
+    app.at("/api/v1/healths/premiums/explain")
+        .post(explain_premium);
+    app.at("/api/v1/healths/premiums/revalidations")
+        .post(revalidations);
+    app.at("/api/v1/healths/premiums/decisions").post(decisions);
+    app.at("/api/v1/healths/premiums/schema").get(health_schema);
+}
+
+/// Matrix management, audits, metrics and the admin page.
+fn admin_routes(app: &mut tide::Server<State>, config: &Config) {
+    app.at("/metrics").get(metrics);
+    let idempotent = middleware::Idempotent::new(&config.idempotency);
+    let admin = middleware::AdminAuth::new(&config.admin);
+    #[cfg(feature = "tls")]
+    let admin = match config
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.client_ca_path.as_ref())
+    {
+        Some(_) => admin.requiring_client_certificates(),
+        None => admin,
+    };
+    app.at("/api/v1/healths/premiums/loads")
+        .with(admin.clone())
+        .with(idempotent.clone())
+        .post(load_matrix);
     app.at("/api/v1/healths/premiums/unloads")
+        .with(admin.clone())
+        .with(idempotent)
         .post(unload_matrix);
     app.at("/api/v1/healths/premiums/checks").get(check_matrix);
-    info!("premium service started");
+    app.at("/api/v1/healths/premiums/audits")
+        .with(admin.clone())
+        .get(list_audits);
+    app.at("/api/v1/healths/premiums/versions")
+        .with(admin.clone())
+        .get(list_versions);
+    app.at("/api/v1/healths/premiums/history")
+        .with(admin.clone())
+        .get(premium_history);
+    app.at("/api/v1/healths/premiums/versions/:version/activate")
+        .with(admin.clone())
+        .post(activate_version);
+    app.at("/api/v1/healths/premiums/export")
+        .with(admin.clone())
+        .get(export_matrix);
+    app.at("/api/v1/healths/premiums/versions/:version/diff/:other")
+        .with(admin.clone())
+        .get(diff_versions);
+    app.at("/api/v1/healths/premiums/rules")
+        .with(admin.clone())
+        .get(list_pricing_rules);
+    app.at("/api/v1/healths/premiums/rules/tests")
+        .with(admin.clone())
+        .post(test_pricing_rules);
+    app.at("/api/v1/healths/premiums/maintenance")
+        .with(admin.clone())
+        .get(maintenance_mode)
+        .put(set_maintenance_mode);
+    if config.chaos.enabled {
+        app.at("/api/v1/healths/premiums/chaos")
+            .with(admin.clone())
+            .get(chaos_settings)
+            .put(set_chaos_settings);
+    }
+    app.at("/admin/flags").with(admin.clone()).get(list_flags);
+    app.at("/admin/flags/:flag")
+        .with(admin.clone())
+        .put(set_flag);
+    app.at("/admin/diagnostics")
+        .with(admin.clone())
+        .get(diagnostics);
+    app.at("/admin/config/reload")
+        .with(admin.clone())
+        .post(reload_config);
+    app.at("/admin").with(admin).get(admin_page);
+}
+
+const CONTRACT_PATH: &str = "contracts/premium-consumer-premium-rs.json";
 
-    let _listener = app.listen(listen).await?;
+fn write_contract(path: Option<&String>) -> tide::Result<()> {
+    let path = path.map(String::as_str).unwrap_or(CONTRACT_PATH);
+    let pact = serde_json::to_string_pretty(&contract::quote_api_pact())?;
+    std::fs::write(path, pact + "\n")?;
+    info!("contract written to {}", path);
     Ok(())
 }
 
-async fn healthz(_req: Request<()>) -> tide::Result {
+/// Verifies a consumer pact file against a running instance, exiting non-zero
+/// when any interaction is not honoured.
+async fn verify_contract(path: Option<&String>, base_url: Option<&String>) -> tide::Result<()> {
+    let path = path.map(String::as_str).unwrap_or(CONTRACT_PATH);
+    let base_url = base_url
+        .cloned()
+        .unwrap_or_else(|| "http://localhost:8080".to_string());
+    let pact: contract::Pact = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let failures = contract::verify(&pact, |request| {
+        contract::send_http(base_url.clone(), request)
+    })
+    .await;
+    if failures.is_empty() {
+        info!(
+            "{} interactions verified against {}",
+            pact.interactions.len(),
+            base_url
+        );
+        return Ok(());
+    }
+    for failure in &failures {
+        error!("contract failure {}", failure);
+    }
+    std::process::exit(1);
+}
+
+async fn healthz(_req: Request<State>) -> tide::Result {
     let response = Response::new(StatusCode::Ok);
     Ok(response)
 }
 
-async fn premiums(mut req: Request<()>) -> tide::Result {
-    let request: HealthRequest;
-    match validate_parse_request(&mut req).await {
-        Ok(result) => request = result,
+/// Ready once the startup preflight has passed, or always when it is off,
+/// and while the latest staleness check found every matrix key.
+async fn readyz(req: Request<State>) -> tide::Result {
+    let (mut ready, mut body) = if !req.state().config.preflight.enabled {
+        (true, serde_json::json!({"ready": true, "checks": []}))
+    } else {
+        match preflight::report() {
+            Some(report) => (report.ready, serde_json::to_value(&report)?),
+            None => (false, serde_json::json!({"ready": false, "checks": []})),
+        }
+    };
+    if let Some(report) = staleness::report() {
+        ready &= report.complete;
+        body["ready"] = ready.into();
+        body["matrixKeys"] = serde_json::to_value(&report)?;
+    }
+    let mut response = make_response(&body)?;
+    if !ready {
+        response.set_status(StatusCode::ServiceUnavailable);
+    }
+    Ok(response)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionResponse {
+    #[serde(flatten)]
+    build: BuildInfo,
+    /// Left out when no version is active or the store cannot be read, so
+    /// the probe still answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matrix_version: Option<u64>,
+}
+
+async fn version(_req: Request<State>) -> tide::Result {
+    make_response(&VersionResponse {
+        build: build_info(),
+        matrix_version: versions().await.ok().and_then(|versions| versions.active),
+    })
+}
+
+/// What this binary was built from, embedded by `build.rs`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildInfo {
+    version: &'static str,
+    git_sha: &'static str,
+    /// RFC 3339 time of the build.
+    built_at: String,
+    features: Vec<&'static str>,
+}
+
+fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        built_at: env!("BUILD_EPOCH")
+            .parse()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|built_at| built_at.to_rfc3339())
+            .unwrap_or_default(),
+        features: enabled_features(),
+    }
+}
+
+/// The active matrix version and the last one loaded. `error` is set
+/// instead when the store could not be read.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct MatrixDiagnostics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_loaded_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_loaded_version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_loaded_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<anyhow::Result<MatrixVersions, PremiumError>> for MatrixDiagnostics {
+    fn from(versions: anyhow::Result<MatrixVersions, PremiumError>) -> Self {
+        let versions = match versions {
+            Ok(versions) => versions,
+            Err(err) => {
+                return MatrixDiagnostics {
+                    error: Some(err.to_string()),
+                    ..MatrixDiagnostics::default()
+                }
+            }
+        };
+        let active = versions
+            .versions
+            .iter()
+            .find(|version| Some(version.version) == versions.active);
+        let last = versions
+            .versions
+            .iter()
+            .max_by_key(|version| version.version);
+        MatrixDiagnostics {
+            active_version: versions.active,
+            active_loaded_at: active.map(|version| version.loaded_at.clone()),
+            last_loaded_version: last.map(|version| version.version),
+            last_loaded_at: last.map(|version| version.loaded_at.clone()),
+            error: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Diagnostics {
+    build: BuildInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool: Option<store::PoolStats>,
+    premium_cache: quote_cache::CacheStats,
+    /// Lookups answered by an identical one already in flight.
+    shared_lookups: u64,
+    matrix: MatrixDiagnostics,
+    flags: BTreeMap<&'static str, FlagSetting>,
+}
+
+/// Connection, cache and matrix state for on-call engineers. Answers 200
+/// even when the store is down, with the matrix section saying why.
+async fn diagnostics(_req: Request<State>) -> tide::Result {
+    make_response(&Diagnostics {
+        build: build_info(),
+        pool: store::pool_stats(),
+        premium_cache: quote_cache::stats(),
+        shared_lookups: shared_lookups(),
+        matrix: versions().await.into(),
+        flags: flags::settings(),
+    })
+}
+
+/// Optional subsystems compiled into this binary.
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "graphql") {
+        features.push("graphql");
+    }
+    if cfg!(feature = "grpc") {
+        features.push("grpc");
+    }
+    if cfg!(feature = "hyper") {
+        features.push("hyper");
+    }
+    if cfg!(feature = "kafka") {
+        features.push("kafka");
+    }
+    if cfg!(feature = "tls") {
+        features.push("tls");
+    }
+    features
+}
+
+/// Quotes under `api`; XML and protobuf are served in the v1 shapes only.
+async fn premiums(mut req: Request<State>, api: ApiVersion) -> tide::Result {
+    #[cfg(feature = "grpc")]
+    if api == ApiVersion::V1
+        && (protobuf::wants_protobuf(&req) || protobuf::has_protobuf_body(&req))
+    {
+        return protobuf_premiums(req).await;
+    }
+    if api == ApiVersion::V1 && (xml::wants_xml(&req) || xml::has_xml_body(&req)) {
+        return xml_premiums(req).await;
+    }
+    let mapping = partner_mapping(&req);
+    let request: HealthRequest = match validate_parse_request(&mut req, mapping.as_ref()).await {
+        Ok(result) => result,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    quote_response(&req, request, mapping, api).await
+}
+
+/// `premiums` for partners that send or accept XML. Partner field mappings
+/// only apply to JSON.
+async fn xml_premiums(mut req: Request<State>) -> tide::Result {
+    let request = if xml::has_xml_body(&req) {
+        body_string(&mut req)
+            .await
+            .and_then(|body| xml::parse_request(&body))
+            .and_then(|value| validate_value(value, None))
+    } else {
+        validate_parse_request(&mut req, None).await
+    };
+    let response = match request {
+        Ok(request) => quote_response(&req, request, None, ApiVersion::V1).await?,
+        Err(err) => handle_error(err),
+    };
+    if xml::wants_xml(&req) {
+        return xml::encode(response).await;
+    }
+    Ok(response)
+}
+
+/// `premiums` for mobile apps that send or accept the gRPC messages.
+#[cfg(feature = "grpc")]
+async fn protobuf_premiums(mut req: Request<State>) -> tide::Result {
+    let request = if protobuf::has_protobuf_body(&req) {
+        match req.body_bytes().await {
+            Ok(body) => {
+                protobuf::parse_request(&body).and_then(|value| validate_value(value, None))
+            }
+            Err(err) => {
+                error!("Parsing error of request body {}", err);
+                Err(PremiumError::InternalServer)
+            }
+        }
+    } else {
+        validate_parse_request(&mut req, None).await
+    };
+    let response = match request {
+        Ok(request) => quote_response(&req, request, None, ApiVersion::V1).await?,
+        Err(err) => handle_error(err),
+    };
+    if protobuf::wants_protobuf(&req) {
+        return protobuf::encode(response).await;
+    }
+    Ok(response)
+}
+
+/// The quote of `premiums` for a request given as query parameters, so it can
+/// be cached by gateways and tried without a JSON body.
+async fn premium_query(req: Request<State>, api: ApiVersion) -> tide::Result {
+    let mapping = partner_mapping(&req);
+    let request = match validate_value(query_request(&req), mapping.as_ref()) {
+        Ok(result) => result,
         Err(err) => return Ok(handle_error(err)),
     };
+    quote_response(&req, request, mapping, api).await
+}
 
-    let health_response = calculate_premium(request).await;
-    match health_response {
-        Ok(premium) => Ok(make_response::<HealthResponse>(&premium.into())?),
+async fn quote_response(
+    req: &Request<State>,
+    mut request: HealthRequest,
+    mapping: Option<FieldMapping>,
+    api: ApiVersion,
+) -> tide::Result {
+    request.sales_channel = sales_channel(req);
+    let rollout_key = req
+        .header("X-Api-Key")
+        .map_or_else(|| request.rollout_key(), |key| key.as_str().to_string());
+    let api = match api {
+        ApiVersion::V1 if flags::enabled(Flag::V2Quotes, &rollout_key) => ApiVersion::V2,
+        api => api,
+    };
+    let key = request.clone();
+    let mut health_response = match quote_for(request, &caller(req)).await {
+        Ok(response) => response,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    health_response.commission = health_response
+        .commission
+        .filter(|commission| shows_commission(req, commission));
+    let shown = health_response.commission.is_some();
+    let viewer = match shown {
+        true => Some(commission_viewer(req)),
+        false => req
+            .header("X-Api-Key")
+            .filter(|_| mapping.is_some())
+            .map(|key| key.as_str().to_string()),
+    };
+    let tag = etag::quote(&key, health_response.matrix_version, api, viewer.as_deref());
+    let not_modified = req
+        .header("If-None-Match")
+        .is_some_and(|value| etag::matches(value.as_str(), &tag));
+    let mut response = if not_modified {
+        Response::new(StatusCode::NotModified)
+    } else if let Some(mapping) = mapping {
+        let body = api.quote_body(health_response)?;
+        make_response(&mapping.map_response(body))?
+    } else if api == ApiVersion::V2 {
+        make_response(&QuoteV2::from(health_response))?
+    } else {
+        make_response(&health_response)?
+    };
+    response.insert_header("ETag", tag);
+    if shown {
+        // Commission is for this caller only; no shared cache may keep it.
+        response.insert_header("Cache-Control", "private, no-store");
+        response.append_header("Vary", "Authorization, X-Api-Key");
+    } else if let Some(cache_control) = products::settings(&key.code).cache_control {
+        response.insert_header("Cache-Control", cache_control);
+    }
+    Ok(response)
+}
+
+/// The sales channel named by `X-Channel`, which prices the quote with its
+/// factors.
+fn sales_channel(req: &Request<State>) -> Option<String> {
+    req.header("X-Channel")
+        .map(|value| value.as_str().trim().to_string())
+        .filter(|channel| !channel.is_empty())
+}
+
+/// Operators, by their credentials, and the distributor of the quote's
+/// channel, by its API key, are shown the commission; other callers are not.
+fn shows_commission(req: &Request<State>, commission: &Commission) -> bool {
+    let config = &req.state().config;
+    let distributor = req
+        .header("X-Api-Key")
+        .and_then(|key| config.distributors.get(key.as_str()));
+    distributor == Some(&commission.channel) || middleware::operator(req, &config.admin.users)
+}
+
+/// Who a quote showing commission was shown to, by API key and operator
+/// name, so its ETag never matches another caller's response.
+fn commission_viewer(req: &Request<State>) -> String {
+    let headers: &tide::http::Request = req.as_ref();
+    let operator = match BasicAuth::from_headers(headers) {
+        Ok(Some(auth)) => auth.username().to_string(),
+        _ => String::new(),
+    };
+    let api_key = req.header("X-Api-Key").map_or("", |key| key.as_str());
+    format!("{}:{}", api_key, operator)
+}
+
+async fn explain_premium(mut req: Request<State>) -> tide::Result {
+    let mapping = partner_mapping(&req);
+    let mut request: HealthRequest = match validate_parse_request(&mut req, mapping.as_ref()).await
+    {
+        Ok(result) => result,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    request.sales_channel = sales_channel(&req);
+    match explain::explain(&request).await {
+        Ok(explanation) => Ok(make_response(&explanation)?),
         Err(err) => Ok(handle_error(err)),
     }
 }
 
-async fn load_matrix(_req: Request<()>) -> tide::Result {
-    let result = load().await;
-    match result {
-        Ok(_) => Ok(Response::new(StatusCode::Ok)),
+/// The underwriting decision on a quote request: the quote when it is
+/// accepted, the reasons when it is referred or declined.
+async fn decisions(mut req: Request<State>) -> tide::Result {
+    let mapping = partner_mapping(&req);
+    let mut request: HealthRequest = match validate_parse_request(&mut req, mapping.as_ref()).await
+    {
+        Ok(result) => result,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    request.sales_channel = sales_channel(&req);
+    match underwriting::decide(request, &caller(&req)).await {
+        Ok(decision) => Ok(make_response(&decision)?),
         Err(err) => Ok(handle_error(err)),
     }
 }
 
-async fn unload_matrix(_req: Request<()>) -> tide::Result {
-    let result = unload().await;
-    match result {
-        Ok(_) => Ok(Response::new(StatusCode::Ok)),
+/// Executes a GraphQL request against the quote and product schema.
+#[cfg(feature = "graphql")]
+async fn graphql_query(mut req: Request<State>) -> tide::Result {
+    let request: async_graphql::Request = match parse_json_request(&mut req).await {
+        Ok(request) => request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    let response = graphql::schema().execute(request.data(caller(&req))).await;
+    make_response(&response)
+}
+
+/// Prometheus text exposition of the service counters.
+async fn metrics(_req: Request<State>) -> tide::Result {
+    let retries = retry::stats();
+    let shadow = rate_test::stats();
+    let stale = staleness::stats();
+    let referrals = referral::stats();
+    let counters = [
+        (
+            "premium_redis_retries_total",
+            "Redis command attempts made after a transient failure.",
+            retries.retries,
+        ),
+        (
+            "premium_redis_retry_recovered_total",
+            "Redis commands that succeeded after being retried.",
+            retries.recovered,
+        ),
+        (
+            "premium_redis_retry_exhausted_total",
+            "Redis commands that failed transiently on every attempt.",
+            retries.exhausted,
+        ),
+        (
+            "premium_lookups_shared_total",
+            "Premium lookups answered by an identical lookup already in flight.",
+            shared_lookups(),
+        ),
+        (
+            "premium_out_of_bounds_total",
+            "Quotes refused because the premium fell outside its product's bounds.",
+            products::out_of_bounds(),
+        ),
+        (
+            "premium_shadow_quotes_total",
+            "Quotes also priced from the shadow candidate matrix version.",
+            shadow.quotes,
+        ),
+        (
+            "premium_shadow_higher_total",
+            "Shadow quotes the candidate version priced higher.",
+            shadow.higher,
+        ),
+        (
+            "premium_shadow_lower_total",
+            "Shadow quotes the candidate version priced lower.",
+            shadow.lower,
+        ),
+        (
+            "premium_shadow_failed_total",
+            "Shadow quotes the candidate version had no premium for.",
+            shadow.failed,
+        ),
+        (
+            "premium_matrix_incomplete_total",
+            "Staleness checks that found keys of the active matrix version missing.",
+            stale.incomplete,
+        ),
+        (
+            "premium_matrix_expiring_total",
+            "Staleness checks that found the active matrix version about to expire.",
+            stale.expiring,
+        ),
+        (
+            "premium_referrals_sent_total",
+            "Referred quotes sent to the underwriting service.",
+            referrals.sent,
+        ),
+        (
+            "premium_referrals_accepted_total",
+            "Referred quotes the underwriting service accepted.",
+            referrals.accepted,
+        ),
+        (
+            "premium_referrals_declined_total",
+            "Referred quotes the underwriting service declined.",
+            referrals.declined,
+        ),
+        (
+            "premium_referrals_failed_total",
+            "Referrals left referred as the underwriting service failed or its circuit was open.",
+            referrals.failed,
+        ),
+    ];
+    let mut body = String::new();
+    for (name, help, value) in counters {
+        body.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+        ));
+    }
+    Ok(Response::builder(StatusCode::Ok)
+        .body(body)
+        .content_type("text/plain; version=0.0.4")
+        .build())
+}
+
+async fn health_schema(_req: Request<State>) -> tide::Result {
+    make_response(schema::health_request())
+}
+
+async fn endorsements(mut req: Request<State>) -> tide::Result {
+    let request = match parse_json_request::<EndorsementRequest>(&mut req).await {
+        Ok(request) => request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match calculate_endorsement(request).await {
+        Ok(response) => Ok(make_response(&response)?),
         Err(err) => Ok(handle_error(err)),
     }
 }
 
-async fn check_matrix(_req: Request<()>) -> tide::Result {
-    let result = keys_exists().await;
-    match result {
-        Ok(_) => Ok(Response::new(StatusCode::Ok)),
+async fn groups(mut req: Request<State>) -> tide::Result {
+    let request = match parse_json_request::<GroupRequest>(&mut req).await {
+        Ok(request) => request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match calculate_group(request).await {
+        Ok(response) => Ok(make_response(&response)?),
         Err(err) => Ok(handle_error(err)),
     }
 }
 
-fn handle_error(err: PremiumError) -> Response {
-    match err {
-        PremiumError::InternalServer => match make_json_error_response("001", err.to_string()) {
-            Ok(mut response) => {
-                response.set_status(StatusCode::InternalServerError);
-                response
+/// Quotes one applicant for every sum insured of a product, or the requested
+/// `sumsInsured`, for choosing a cover.
+async fn compare_premiums(mut req: Request<State>) -> tide::Result {
+    let request = match parse_json_request::<CompareRequest>(&mut req).await {
+        Ok(request) => request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match compare(request, &caller(&req)).await {
+        Ok(response) => Ok(make_response(&response)?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// Starts quoting an uploaded CSV of applicants; the job is polled at the
+/// `Location` it answers with. A caller accepting NDJSON instead reads each
+/// row's quote as a line while the file is quoted.
+async fn submit_bulk(mut req: Request<State>) -> tide::Result {
+    let content_type = req.content_type().map(|mime| mime.essence().to_string());
+    if content_type.as_deref() != Some("text/csv") {
+        return Ok(handle_error(PremiumError::InvalidHeader(
+            "content-type".to_string(),
+        )));
+    }
+    let body = match body_string(&mut req).await {
+        Ok(body) => body,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    if wants_ndjson(&req) {
+        return match bulk::stream(&body, &caller(&req)) {
+            Ok(lines) => {
+                let mut response = Response::new(StatusCode::Ok);
+                response.set_content_type(NDJSON);
+                response.set_body(Body::from_reader(lines, None));
+                Ok(response)
             }
-            Err(_) => Response::new(StatusCode::InternalServerError),
-        },
-        PremiumError::InvalidInput => match make_json_error_response("002", err.to_string()) {
-            Ok(mut response) => {
-                response.set_status(StatusCode::BadRequest);
-                response
+            Err(err) => Ok(handle_error(err)),
+        };
+    }
+    match bulk::submit(&body, &caller(&req)) {
+        Ok(job) => {
+            let mut response = make_response(&job)?;
+            response.set_status(StatusCode::Accepted);
+            response.insert_header(
+                "Location",
+                format!("/api/v1/healths/premiums/bulk/{}", job.id),
+            );
+            Ok(response)
+        }
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+const NDJSON: &str = "application/x-ndjson";
+
+fn wants_ndjson(req: &Request<State>) -> bool {
+    req.header("Accept").is_some_and(|accept| {
+        accept
+            .as_str()
+            .split(',')
+            .any(|mime| mime.split(';').next().unwrap_or_default().trim() == NDJSON)
+    })
+}
+
+async fn bulk_job(req: Request<State>) -> tide::Result {
+    match bulk::job(req.param("id").unwrap_or_default()) {
+        Ok(job) => Ok(make_response(&job)?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// The result CSV of a finished job; a running job answers 202 with its
+/// progress.
+async fn bulk_result(req: Request<State>) -> tide::Result {
+    let id = req.param("id").unwrap_or_default();
+    match bulk::result(id) {
+        Ok(Some(body)) => {
+            let mut response = Response::new(StatusCode::Ok);
+            response.set_content_type("text/csv");
+            response.insert_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"premium-bulk-{}.csv\"", id),
+            );
+            response.set_body(Body::from_bytes(body));
+            Ok(response)
+        }
+        Ok(None) => match bulk::job(id) {
+            Ok(job) => {
+                let mut response = make_response(&job)?;
+                response.set_status(StatusCode::Accepted);
+                Ok(response)
             }
-            Err(_) => Response::new(StatusCode::InternalServerError),
+            Err(err) => Ok(handle_error(err)),
         },
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn revalidations(mut req: Request<State>) -> tide::Result {
+    let request = match parse_json_request::<RevalidationRequest>(&mut req).await {
+        Ok(request) => request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match revalidate(request).await {
+        Ok(response) => Ok(make_response(&response)?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// The audited caller: `X-Caller-Id` and the id `RequestSpan` assigned.
+fn caller(req: &Request<State>) -> Caller {
+    Caller {
+        id: req
+            .header("X-Caller-Id")
+            .map(|header| header.as_str().to_string()),
+        request_id: req
+            .ext::<middleware::RequestId>()
+            .map(|request_id| request_id.0.clone()),
+    }
+}
+
+async fn list_audits(req: Request<State>) -> tide::Result {
+    let filter = match req.query::<AuditFilter>() {
+        Ok(filter) => filter,
+        Err(_) => return Ok(handle_error(PremiumError::InvalidInput)),
+    };
+    match audit::records(&filter).await {
+        Ok(records) => Ok(make_response(&serde_json::json!({ "records": records }))?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+fn partner_mapping(req: &Request<State>) -> Option<FieldMapping> {
+    let api_key = req.header("X-Api-Key")?;
+    req.state().config.partners.get(api_key.as_str()).cloned()
+}
+
+/// The operations page, compiled into the binary so it ships with the service.
+const ADMIN_PAGE: &str = include_str!("admin/index.html");
+
+async fn admin_page(_req: Request<State>) -> tide::Result {
+    Ok(Response::builder(StatusCode::Ok)
+        .body(ADMIN_PAGE)
+        .content_type(tide::http::mime::HTML)
+        .build())
+}
+
+async fn chaos_settings(_req: Request<State>) -> tide::Result {
+    make_response(&chaos::settings().unwrap_or_default())
+}
+
+async fn set_chaos_settings(mut req: Request<State>) -> tide::Result {
+    let settings = match parse_json_request::<chaos::ChaosSettings>(&mut req).await {
+        Ok(settings) => settings,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    chaos::set(settings);
+    make_response(&settings)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MaintenanceMode {
+    enabled: bool,
+}
+
+async fn maintenance_mode(_req: Request<State>) -> tide::Result {
+    make_response(&MaintenanceMode {
+        enabled: maintenance::enabled(),
+    })
+}
+
+/// Turns maintenance mode on or off; loads and version changes keep working
+/// either way.
+async fn set_maintenance_mode(mut req: Request<State>) -> tide::Result {
+    let mode = match parse_json_request::<MaintenanceMode>(&mut req).await {
+        Ok(mode) => mode,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    maintenance::set(mode.enabled);
+    make_response(&mode)
+}
 
-        PremiumError::RiskCalculation => match make_json_error_response("004", err.to_string()) {
-            Ok(mut response) => {
-                response.set_status(StatusCode::BadRequest);
-                response
+async fn list_flags(_req: Request<State>) -> tide::Result {
+    make_response(&flags::settings())
+}
+
+/// Changes a feature flag; with flags kept in redis every instance picks
+/// the change up on its next refresh.
+async fn set_flag(mut req: Request<State>) -> tide::Result {
+    let name = req.param("flag")?.to_string();
+    let Some(flag) = Flag::from_name(&name) else {
+        return Ok(handle_error(PremiumError::NotFound(format!(
+            "Flag {}",
+            name
+        ))));
+    };
+    let setting = match parse_json_request::<FlagSetting>(&mut req).await {
+        Ok(setting) => setting,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match flags::set(flag, setting.clone()).await {
+        Ok(()) => make_response(&setting),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn reload_config(req: Request<State>) -> tide::Result {
+    match reload::reload(&req.state().limits) {
+        Ok(reloaded) => make_response(&reloaded),
+        Err(err) => {
+            error!("configuration reload failed {}", err);
+            Ok(handle_error(
+                err.downcast::<PremiumError>()
+                    .unwrap_or(PremiumError::InvalidInput),
+            ))
+        }
+    }
+}
+
+async fn load_matrix(mut req: Request<State>) -> tide::Result {
+    let query = match req.query::<LoadQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(handle_error(PremiumError::InvalidInput)),
+    };
+    let load_request = match parse_load_request(&mut req).await {
+        Ok(load_request) => load_request,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    let source_url = load_request.source_url.as_deref();
+    if query.dry_run {
+        return match validate(&req.state().config.matrix, source_url).await {
+            Ok(report) => Ok(make_response(&report)?),
+            Err(err) => Ok(handle_error(err)),
+        };
+    }
+    if let Some(callback_url) = load_request.callback_url {
+        let config = req.state().config.clone();
+        async_std::task::spawn(tenant::scope(
+            tenant::current(),
+            webhook::load_and_notify(
+                callback_url,
+                load_request.source_url,
+                config.matrix.clone(),
+                config.webhook.clone(),
+            ),
+        ));
+        return Ok(Response::new(StatusCode::Accepted));
+    }
+
+    let result = load(&req.state().config.matrix, source_url).await;
+    match result {
+        Ok(report) => {
+            let mut response = make_response(&report)?;
+            if !report.valid {
+                response.set_status(StatusCode::UnprocessableEntity);
             }
-            Err(_) => Response::new(StatusCode::InternalServerError),
-        },
-        PremiumError::InvalidHeader(header) => {
-            match make_json_error_response(
-                "003",
-                format!("Header {} not provided or invalid", header),
-            ) {
-                Ok(mut response) => {
-                    response.set_status(StatusCode::BadRequest);
-                    response
-                }
-                Err(_) => Response::new(StatusCode::InternalServerError),
+            Ok(response)
+        }
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn unload_matrix(_req: Request<State>) -> tide::Result {
+    let result = unload().await;
+    match result {
+        Ok(_) => Ok(Response::new(StatusCode::Ok)),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// The active version's per product row counts and checksums, as loaded
+/// and as stored now; 422 when they have drifted apart.
+async fn check_matrix(_req: Request<State>) -> tide::Result {
+    match integrity::check().await {
+        Ok(report) => {
+            let mut response = make_response(&report)?;
+            if !report.consistent {
+                response.set_status(StatusCode::UnprocessableEntity);
             }
+            Ok(response)
         }
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn list_versions(_req: Request<State>) -> tide::Result {
+    match versions().await {
+        Ok(versions) => Ok(make_response(&versions)?),
+        Err(err) => Ok(handle_error(err)),
     }
 }
 
+async fn list_pricing_rules(_req: Request<State>) -> tide::Result {
+    make_response(&pricing_rules::rules())
+}
+
+/// Which active pricing rules match a quote request, without pricing it.
+async fn test_pricing_rules(mut req: Request<State>) -> tide::Result {
+    let request: HealthRequest = match validate_parse_request(&mut req, None).await {
+        Ok(result) => result,
+        Err(err) => return Ok(handle_error(err)),
+    };
+    match pricing_rules::test(&request) {
+        Ok(test) => make_response(&test),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// How the premium of one matrix cell changed across the loaded versions.
+async fn premium_history(req: Request<State>) -> tide::Result {
+    let query = match req.query::<HistoryQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(handle_error(PremiumError::InvalidInput)),
+    };
+    match history(query).await {
+        Ok(history) => Ok(make_response(&history)?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn activate_version(req: Request<State>) -> tide::Result {
+    let version = match req.param("version").map(|version| version.parse::<u64>()) {
+        Ok(Ok(version)) => version,
+        _ => return Ok(handle_error(PremiumError::InvalidInput)),
+    };
+    match activate(version).await {
+        Ok(_) => Ok(Response::new(StatusCode::Ok)),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn export_matrix(req: Request<State>) -> tide::Result {
+    let format = match req.query::<ExportQuery>() {
+        Ok(query) => query.format,
+        Err(_) => return Ok(handle_error(PremiumError::InvalidInput)),
+    };
+    match export::export(format).await {
+        Ok(export) => {
+            let mut response = Response::new(StatusCode::Ok);
+            response.set_content_type(format.content_type());
+            response.insert_header(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"premium-matrix-v{}.{}\"",
+                    export.version,
+                    format.extension()
+                ),
+            );
+            response.set_body(Body::from_bytes(export.body));
+            Ok(response)
+        }
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+async fn diff_versions(req: Request<State>) -> tide::Result {
+    let version = |name| match req.param(name).map(|version| version.parse::<u64>()) {
+        Ok(Ok(version)) => Some(version),
+        _ => None,
+    };
+    let (Some(from), Some(to)) = (version("version"), version("other")) else {
+        return Ok(handle_error(PremiumError::InvalidInput));
+    };
+    match diff::diff(from, to).await {
+        Ok(diff) => Ok(make_response(&diff)?),
+        Err(err) => Ok(handle_error(err)),
+    }
+}
+
+/// The error's `ErrorResponse` under the status and `Retry-After` its
+/// catalogue entry gives.
+fn handle_error(err: PremiumError) -> Response {
+    let mut response = match make_response(&ErrorResponse::from(&err)) {
+        Ok(response) => response,
+        Err(_) => return Response::new(StatusCode::InternalServerError),
+    };
+    response
+        .set_status(StatusCode::try_from(err.status()).unwrap_or(StatusCode::InternalServerError));
+    if let Some(retry_after) = err.retry_after() {
+        response.insert_header("Retry-After", retry_after.to_string());
+    }
+    response
+}
+
 async fn validate_parse_request(
-    req: &mut Request<()>,
+    req: &mut Request<State>,
+    mapping: Option<&FieldMapping>,
 ) -> anyhow::Result<HealthRequest, PremiumError> {
-    validate_request(&req)?;
+    validate_request(req)?;
     let body = body_string(req).await?;
-    let result = serde_json::from_str::<HealthRequest>(body.as_str());
-    match result {
+    match serde_json::from_str::<serde_json::Value>(body.as_str()) {
+        Ok(value) => validate_value(value, mapping),
+        Err(err) => {
+            error!("Invalid json in health request {}", err);
+            Err(PremiumError::InvalidInput)
+        }
+    }
+}
+
+/// The query parameters as a quote request body, with `discountCodes`
+/// comma separated.
+fn query_request(req: &Request<State>) -> serde_json::Value {
+    let mut body = serde_json::Map::new();
+    for (name, value) in req.url().query_pairs() {
+        let value = match name.as_ref() {
+            "discountCodes" => value
+                .split(',')
+                .map(str::trim)
+                .filter(|code| !code.is_empty())
+                .map(serde_json::Value::from)
+                .collect(),
+            "age" => value.parse::<u64>().map_or_else(
+                |_| serde_json::Value::from(value.as_ref()),
+                serde_json::Value::from,
+            ),
+            _ => serde_json::Value::from(value.as_ref()),
+        };
+        body.insert(name.into_owned(), value);
+    }
+    serde_json::Value::Object(body)
+}
+
+/// Maps a partner's field names, then checks the request against the schema.
+fn validate_value(
+    value: serde_json::Value,
+    mapping: Option<&FieldMapping>,
+) -> anyhow::Result<HealthRequest, PremiumError> {
+    let value = match mapping {
+        Some(mapping) => mapping.map_request(value),
+        None => value,
+    };
+    schema::validate(&value)?;
+    match serde_json::from_value::<HealthRequest>(value) {
         Ok(request) => Ok(request),
         Err(err) => {
             error!(
                 "Serialization error while converting json to struct {}",
-                err.to_string()
+                err
             );
             Err(PremiumError::InvalidInput)
         }
     }
 }
-fn validate_request(request: &Request<()>) -> anyhow::Result<Response, PremiumError> {
+
+/// Reads a JSON body that needs no partner field mapping.
+async fn parse_json_request<T: DeserializeOwned>(
+    req: &mut Request<State>,
+) -> anyhow::Result<T, PremiumError> {
+    validate_request(req)?;
+    let body = body_string(req).await?;
+    serde_json::from_str::<T>(body.as_str()).map_err(|err| {
+        error!(
+            "Serialization error while converting json to struct {}",
+            err
+        );
+        PremiumError::InvalidInput
+    })
+}
+
+async fn parse_load_request(req: &mut Request<State>) -> anyhow::Result<LoadRequest, PremiumError> {
+    let body = body_string(req).await?;
+    if body.trim().is_empty() {
+        return Ok(LoadRequest::default());
+    }
+    serde_json::from_str::<LoadRequest>(body.as_str()).map_err(|err| {
+        error!(
+            "Serialization error while converting json to struct {}",
+            err
+        );
+        PremiumError::InvalidInput
+    })
+}
+
+fn validate_request(request: &Request<State>) -> anyhow::Result<Response, PremiumError> {
     validate_headers(request)
 }
 
-fn validate_headers(request: &Request<()>) -> anyhow::Result<Response, PremiumError> {
+fn validate_headers(request: &Request<State>) -> anyhow::Result<Response, PremiumError> {
     let content_type = request.header("Content-Type").map(|header| header.as_str());
     match content_type {
         Some("application/json") => Ok(Response::new(StatusCode::Ok)),
@@ -140,25 +1519,17 @@ fn validate_headers(request: &Request<()>) -> anyhow::Result<Response, PremiumEr
     }
 }
 
-async fn body_string(req: &mut Request<()>) -> anyhow::Result<String, PremiumError> {
+async fn body_string(req: &mut Request<State>) -> anyhow::Result<String, PremiumError> {
     let body_result = req.body_string().await;
     match body_result {
         Ok(body) => Ok(body),
         Err(err) => {
-            error!("Parsing error of request body {}", err.to_string());
+            error!("Parsing error of request body {}", err);
             Err(PremiumError::InternalServer)
         }
     }
 }
 
-fn make_json_error_response(err_code: &str, message: String) -> tide::Result {
-    let err = ErrorResponse {
-        code: err_code.to_string(),
-        message: message.to_string(),
-    };
-    make_response(&err)
-}
-
 fn make_response<T: Serialize>(response: &T) -> tide::Result {
     let data = Body::from_json(&response);
     match data {
@@ -176,3 +1547,233 @@ fn make_response<T: Serialize>(response: &T) -> tide::Result {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn send(
+        app: &tide::Server<State>,
+        request: contract::ContractRequest,
+    ) -> Result<contract::Outcome, String> {
+        let method: Method = request.method.parse().map_err(|_| request.method.clone())?;
+        let url = Url::parse(&format!("http://localhost{}", request.path)).unwrap();
+        let mut http = HttpRequest::new(method, url);
+        for (name, value) in &request.headers {
+            http.insert_header(name.as_str(), value.as_str());
+        }
+        if let Some(body) = &request.body {
+            http.set_body(body.to_string());
+            if let Some(content_type) = request.headers.get("Content-Type") {
+                http.insert_header("Content-Type", content_type.as_str());
+            }
+        }
+        let mut response: HttpResponse = app.respond(http).await.map_err(|err| err.to_string())?;
+        let body = response
+            .body_string()
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(contract::Outcome {
+            status: response.status().into(),
+            body: serde_json::from_str(&body).ok(),
+        })
+    }
+
+    #[test]
+    fn test_stateless_contract_interactions() {
+        let config = Config::default();
+        let app = app(state(&config), &config);
+        let mut pact = contract::quote_api_pact();
+        pact.interactions
+            .retain(|interaction| interaction.provider_state.is_none());
+
+        task::block_on(async {
+            let failures = contract::verify(&pact, |request| send(&app, request)).await;
+            assert!(failures.is_empty(), "{:?}", failures);
+        });
+    }
+
+    #[test]
+    fn test_admin_routes_split() {
+        let config = Config::default();
+        let mut public = server(state(&config), &config);
+        public_routes(&mut public);
+        task::block_on(async {
+            for (method, path, status) in [
+                (Method::Get, "/metrics", StatusCode::NotFound),
+                (
+                    Method::Post,
+                    "/api/v1/healths/premiums/unloads",
+                    StatusCode::NotFound,
+                ),
+                (Method::Get, "/readyz", StatusCode::Ok),
+            ] {
+                let url = Url::parse(&format!("http://localhost{}", path)).unwrap();
+                let response: HttpResponse =
+                    public.respond(HttpRequest::new(method, url)).await.unwrap();
+                assert_eq!(response.status(), status, "{}", path);
+            }
+        });
+    }
+
+    #[test]
+    fn test_activate_invalid_version() {
+        let mut config = Config::default();
+        config.admin.insecure = true;
+        let app = app(state(&config), &config);
+        task::block_on(async {
+            let url =
+                Url::parse("http://localhost/api/v1/healths/premiums/versions/latest/activate")
+                    .unwrap();
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Post, url))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BadRequest);
+        });
+    }
+
+    #[test]
+    fn test_premium_query_is_validated() {
+        let config = Config::default();
+        let app = app(state(&config), &config);
+        task::block_on(async {
+            let url = Url::parse(
+                "http://localhost/api/v1/healths/premiums?code=1A&sumInsured=100000&discountCodes=A,B",
+            )
+            .unwrap();
+            let mut response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BadRequest);
+            let body: serde_json::Value = response.body_json().await.unwrap();
+            assert_eq!(body["code"], "011");
+            assert!(body["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("Field /dateOfBirth violates required"));
+        });
+    }
+
+    #[test]
+    fn test_handle_error() {
+        task::block_on(async {
+            let response = handle_error(PremiumError::RateLimited(30));
+            assert_eq!(response.status(), StatusCode::TooManyRequests);
+            assert_eq!(response["Retry-After"], "30");
+            let mut response: HttpResponse = response.into();
+            let body: serde_json::Value = response.body_json().await.unwrap();
+            assert_eq!(body["code"], "023");
+
+            let mut response: HttpResponse =
+                handle_error(PremiumError::InvalidHeader("apiKey".to_string())).into();
+            assert_eq!(response.status(), StatusCode::BadRequest);
+            let body: serde_json::Value = response.body_json().await.unwrap();
+            assert_eq!(body["message"], "Header apiKey not provided or invalid");
+        });
+    }
+
+    #[test]
+    fn test_version_reports_build_and_matrix() {
+        store::configure(&premium_core::config::StorageConfig {
+            backend: StorageBackend::Memory,
+            ..Default::default()
+        })
+        .unwrap();
+        let config = Config {
+            tenants: vec!["versioned".to_string()],
+            ..Config::default()
+        };
+        let app = server(state(&config), &config);
+        let probe = || async {
+            let url = Url::parse("http://localhost/version").unwrap();
+            let mut request = HttpRequest::new(Method::Get, url);
+            request.insert_header("X-Tenant-Id", "versioned");
+            let mut response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::Ok);
+            response.body_json::<serde_json::Value>().await.unwrap()
+        };
+        task::block_on(async {
+            let body = probe().await;
+            assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+            assert_eq!(body["gitSha"], env!("GIT_SHA"));
+            let built_at = body["builtAt"].as_str().unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(built_at).is_ok());
+            // no version is active yet, which the probe leaves out
+            assert!(body.get("matrixVersion").is_none(), "{}", body);
+
+            let version = tenant::scope(Some("versioned".to_string()), async {
+                let mut writer = store::begin_version().await.unwrap();
+                writer
+                    .write(&[premium_core::matrix::MatrixRow {
+                        key: "1A:100000".to_string(),
+                        code: "1A".to_string(),
+                        sum_insured: "100000".to_string(),
+                        deductible: None,
+                        gender: None,
+                        age_band: "18-35".to_string(),
+                        premium: 5000,
+                        score: 1,
+                    }])
+                    .await
+                    .unwrap();
+                writer.commit().await.unwrap()
+            })
+            .await;
+            assert_eq!(probe().await["matrixVersion"], version);
+        });
+    }
+
+    #[test]
+    fn test_sum_insured_not_offered() {
+        store::configure(&premium_core::config::StorageConfig {
+            backend: StorageBackend::Memory,
+            ..Default::default()
+        })
+        .unwrap();
+        task::block_on(tenant::scope(Some("offered".to_string()), async {
+            let mut writer = store::begin_version().await.unwrap();
+            let row = |sum_insured: &str| premium_core::matrix::MatrixRow {
+                key: format!("1A:{}", sum_insured),
+                code: "1A".to_string(),
+                sum_insured: sum_insured.to_string(),
+                deductible: None,
+                gender: None,
+                age_band: "18-35".to_string(),
+                premium: 5000,
+                score: 1,
+            };
+            writer.write(&[row("100000"), row("200000")]).await.unwrap();
+            writer.commit().await.unwrap();
+
+            let request = |code: &str| HealthRequest {
+                code: code.to_string(),
+                sum_insured: "300000".to_string(),
+                date_of_birth: "1990-01-01".to_string(),
+                ..HealthRequest::default()
+            };
+            let err = price(&request("1A")).await.unwrap_err();
+            assert!(matches!(
+                &err,
+                PremiumError::SumInsuredNotOffered { valid, .. } if valid == &["100000", "200000"]
+            ));
+            let mut response: HttpResponse = handle_error(err).into();
+            assert_eq!(response.status(), StatusCode::BadRequest);
+            let body: serde_json::Value = response.body_json().await.unwrap();
+            assert_eq!(body["code"], "013");
+            assert_eq!(
+                body["validSumsInsured"],
+                serde_json::json!(["100000", "200000"])
+            );
+
+            // a product the matrix does not price at all is no band problem
+            let err = price(&request("9Z")).await.unwrap_err();
+            assert!(matches!(err, PremiumError::RiskCalculation));
+            let body = serde_json::to_value(ErrorResponse::from(&err)).unwrap();
+            assert!(body.get("validSumsInsured").is_none());
+        }));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Field renames and format shims for a partner whose payload shape differs
+/// from ours, selected by the partner's API key.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FieldMapping {
+    /// Partner request field name to our field name.
+    pub request: HashMap<String, String>,
+    /// Our response field name to the partner's field name.
+    pub response: HashMap<String, String>,
+    /// chrono format of the partner's dateOfBirth, converted to `%Y-%m-%d`.
+    pub date_of_birth_format: Option<String>,
+}
+
+impl FieldMapping {
+    pub fn map_request(&self, value: Value) -> Value {
+        let mut fields = rename(value, &self.request);
+        if let Value::Object(map) = &mut fields {
+            for field in map.values_mut() {
+                if let Value::Number(number) = field {
+                    *field = Value::String(number.to_string());
+                }
+            }
+            if let Some(format) = &self.date_of_birth_format {
+                if let Some(Value::String(dob)) = map.get_mut("dateOfBirth") {
+                    if let Ok(date) = NaiveDate::parse_from_str(dob, format) {
+                        *dob = date.format("%Y-%m-%d").to_string();
+                    }
+                }
+            }
+        }
+        fields
+    }
+
+    pub fn map_response(&self, value: Value) -> Value {
+        rename(value, &self.response)
+    }
+}
+
+fn rename(value: Value, names: &HashMap<String, String>) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| match names.get(&key) {
+                    Some(name) => (name.to_string(), value),
+                    None => (key, value),
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_map_request() {
+        let mapping = FieldMapping {
+            request: HashMap::from([
+                ("planCode".to_string(), "code".to_string()),
+                ("cover".to_string(), "sumInsured".to_string()),
+                ("dob".to_string(), "dateOfBirth".to_string()),
+            ]),
+            date_of_birth_format: Some("%d/%m/%Y".to_string()),
+            ..Default::default()
+        };
+        let mapped =
+            mapping.map_request(json!({"planCode": "1A", "cover": 100000, "dob": "14/09/1977"}));
+        assert_eq!(
+            mapped,
+            json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": "1977-09-14"})
+        );
+    }
+
+    #[test]
+    fn test_map_response() {
+        let mapping = FieldMapping {
+            response: HashMap::from([("premium".to_string(), "annualPremium".to_string())]),
+            ..Default::default()
+        };
+        let mapped = mapping.map_response(json!({"premium": "750"}));
+        assert_eq!(mapped, json!({"annualPremium": "750"}));
+    }
+}
@@ -0,0 +1,904 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use async_std::io::ReadExt;
+use log::{error, warn};
+use premium_core::premium::PremiumError;
+use premium_core::store::{self, Idempotency};
+use premium_core::tenant;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tide::http::auth::BasicAuth;
+use tide::http::headers::HeaderValue;
+use tide::security::{CorsMiddleware, Origin};
+use tide::{Middleware, Next, Request, Response};
+use tracing::Instrument;
+
+use crate::config::{
+    AdminConfig, CachePolicy, CompressionConfig, CorsConfig, IdempotencyConfig, LimitsConfig,
+};
+
+/// Adds the configured Cache-Control and Vary headers to successful responses
+/// of the matching route, leaving routes without a policy untouched. A
+/// Cache-Control the handler set itself is kept, and so is its Vary.
+pub struct CacheHeaders {
+    policies: HashMap<String, CachePolicy>,
+}
+
+impl CacheHeaders {
+    pub fn new(policies: HashMap<String, CachePolicy>) -> Self {
+        CacheHeaders { policies }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for CacheHeaders {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let policy = self.policies.get(req.url().path()).cloned();
+        let mut response = next.run(req).await;
+        if let Some(policy) = policy {
+            if response.status().is_success() || response.status() == tide::StatusCode::NotModified
+            {
+                if let Some(cache_control) = policy.cache_control {
+                    if response.header("Cache-Control").is_none() {
+                        response.insert_header("Cache-Control", cache_control);
+                    }
+                }
+                if let Some(vary) = policy.vary {
+                    response.append_header("Vary", vary);
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// A content coding responses can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn encode(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(body)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The coding an `Accept-Encoding` header prefers, brotli over gzip when
+/// both are weighted alike, `None` when it accepts neither.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut weights = HashMap::new();
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';');
+        let name = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let weight = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        weights.insert(name, weight);
+    }
+    let weight = |encoding: Encoding| {
+        weights
+            .get(encoding.name())
+            .or_else(|| weights.get("*"))
+            .copied()
+            .unwrap_or(0.0)
+    };
+    [Encoding::Brotli, Encoding::Gzip]
+        .into_iter()
+        .filter(|encoding| weight(*encoding) > 0.0)
+        .fold(None, |best: Option<Encoding>, encoding| match best {
+            Some(best) if weight(best) >= weight(encoding) => Some(best),
+            _ => Some(encoding),
+        })
+}
+
+/// Whether a response of this media type gets smaller when compressed.
+fn compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || ["json", "xml", "csv", "javascript"]
+            .iter()
+            .any(|kind| content_type.contains(kind))
+}
+
+/// Compresses textual response bodies of at least the configured size with
+/// brotli or gzip, whichever the client's `Accept-Encoding` prefers. Bodies
+/// are buffered whole, and a strong ETag is weakened as the bytes sent no
+/// longer match it.
+pub struct Compression {
+    min_bytes: usize,
+}
+
+impl Compression {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Compression {
+            min_bytes: config.min_bytes,
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Compression {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let encoding = match req.method() {
+            tide::http::Method::Head => None,
+            _ => req
+                .header("Accept-Encoding")
+                .and_then(|accept| negotiate(accept.as_str())),
+        };
+        let mut response = next.run(req).await;
+        let compressible = response
+            .content_type()
+            .is_some_and(|mime| compressible(mime.essence()));
+        if !compressible
+            || response.header("Content-Encoding").is_some()
+            || response.len().is_some_and(|len| len < self.min_bytes)
+        {
+            return Ok(response);
+        }
+        response.append_header("Vary", "Accept-Encoding");
+        let Some(encoding) = encoding else {
+            return Ok(response);
+        };
+        let body = response.take_body().into_bytes().await?;
+        if body.len() < self.min_bytes {
+            response.set_body(body);
+            return Ok(response);
+        }
+        let content_type = response.content_type();
+        response.set_body(encoding.encode(&body)?);
+        if let Some(content_type) = content_type {
+            response.set_content_type(content_type);
+        }
+        response.insert_header("Content-Encoding", encoding.name());
+        if let Some(etag) = response
+            .header("ETag")
+            .map(|etag| etag.as_str().to_string())
+        {
+            if !etag.starts_with("W/") {
+                response.insert_header("ETag", format!("W/{}", etag));
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Requires HTTP basic credentials of a configured operator on the admin page
+/// and the matrix admin endpoints. Refuses everything when no operators are
+/// configured, unless `admin.insecure` opens them.
+#[derive(Clone)]
+pub struct AdminAuth {
+    users: HashMap<String, String>,
+    open: bool,
+    #[cfg(feature = "tls")]
+    client_certificates: bool,
+}
+
+impl AdminAuth {
+    pub fn new(config: &AdminConfig) -> Self {
+        AdminAuth {
+            users: config.users.clone(),
+            open: config.open(),
+            #[cfg(feature = "tls")]
+            client_certificates: false,
+        }
+    }
+
+    /// Also requires the connection to have presented a verified client
+    /// certificate, answering 403 otherwise.
+    #[cfg(feature = "tls")]
+    pub fn requiring_client_certificates(self) -> Self {
+        AdminAuth {
+            client_certificates: true,
+            ..self
+        }
+    }
+
+    fn allows<State>(&self, req: &Request<State>) -> bool {
+        self.open || operator(req, &self.users)
+    }
+}
+
+/// Whether the request carries the basic credentials of one of `users`;
+/// never when there are none.
+pub fn operator<State>(req: &Request<State>, users: &HashMap<String, String>) -> bool {
+    let headers: &tide::http::Request = req.as_ref();
+    match BasicAuth::from_headers(headers) {
+        Ok(Some(auth)) => password_matches(users, auth.username(), auth.password()),
+        _ => false,
+    }
+}
+
+/// Whether `password` is `user`'s. A configured password written
+/// `sha256:{hex}` is the SHA-256 digest of the real one, anything else is the
+/// password itself. Digests are compared in constant time, so how long a
+/// check takes does not tell how much of a guess was right.
+pub fn password_matches(users: &HashMap<String, String>, user: &str, password: &str) -> bool {
+    let given = Sha256::digest(password.as_bytes());
+    let Some(stored) = users.get(user) else {
+        return false;
+    };
+    let expected = match stored.strip_prefix("sha256:") {
+        Some(hex) => match hex::decode(hex) {
+            Ok(digest) if digest.len() == given.len() => digest,
+            _ => return false,
+        },
+        None => Sha256::digest(stored.as_bytes()).to_vec(),
+    };
+    given
+        .iter()
+        .zip(&expected)
+        .fold(0, |diff, (given, expected)| diff | (given ^ expected))
+        == 0
+}
+
+/// Users whose password is configured in plaintext rather than as a digest.
+pub fn plaintext_passwords(users: &HashMap<String, String>) -> Vec<&str> {
+    let mut plaintext: Vec<&str> = users
+        .iter()
+        .filter(|(_, password)| !password.starts_with("sha256:"))
+        .map(|(user, _)| user.as_str())
+        .collect();
+    plaintext.sort_unstable();
+    plaintext
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for AdminAuth {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        #[cfg(feature = "tls")]
+        if self.client_certificates && !crate::tls::client_verified(req.peer_addr()) {
+            warn!("admin client certificate missing for {}", req.url().path());
+            return Ok(Response::new(tide::StatusCode::Forbidden));
+        }
+        if self.allows(&req) {
+            return Ok(next.run(req).await);
+        }
+        warn!("admin credentials rejected for {}", req.url().path());
+        let mut response = crate::handle_error(PremiumError::Unauthorized);
+        response.insert_header("WWW-Authenticate", "Basic realm=\"premium-admin\"");
+        Ok(response)
+    }
+}
+
+/// Runs each request inside a span carrying its request id and route, so
+/// every log line written while handling it can be correlated, and logs its
+/// outcome. The id comes from `X-Request-Id` when the caller sends one and
+/// is echoed back.
+pub struct RequestSpan;
+
+/// The id `RequestSpan` assigned, available to handlers as a request
+/// extension.
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestSpan {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let request_id = match req.header("X-Request-Id") {
+            Some(id) => id.as_str().to_string(),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        req.set_ext(RequestId(request_id.clone()));
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            route = %req.url().path(),
+        );
+        let started = Instant::now();
+        let mut response = next.run(req).instrument(span.clone()).await;
+        let status = response.status() as u16;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        span.in_scope(|| match status {
+            500.. => tracing::error!(status, elapsed_ms, "response sent"),
+            400.. => tracing::warn!(status, elapsed_ms, "response sent"),
+            _ => tracing::info!(status, elapsed_ms, "response sent"),
+        });
+        response.insert_header("X-Request-Id", request_id);
+        Ok(response)
+    }
+}
+
+/// Runs each request as the tenant its `/tenants/{id}` path or `X-Tenant-Id`
+/// header names, so quotes and loads use that tenant's matrix. Tenants that
+/// are not configured are refused with 400.
+#[derive(Clone)]
+pub struct Tenants {
+    allowed: Arc<HashSet<String>>,
+}
+
+impl Tenants {
+    pub fn new(tenants: &[String]) -> Self {
+        Tenants {
+            allowed: Arc::new(tenants.iter().cloned().collect()),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Tenants {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let named = match req.param("tenant") {
+            Ok(tenant) => Some(tenant.to_string()),
+            Err(_) => req
+                .header("X-Tenant-Id")
+                .map(|tenant| tenant.as_str().to_string()),
+        };
+        match named {
+            Some(tenant) if !self.allowed.contains(&tenant) => {
+                Ok(crate::handle_error(PremiumError::UnknownTenant(tenant)))
+            }
+            tenant => Ok(tenant::scope(tenant, next.run(req)).await),
+        }
+    }
+}
+
+/// Answers a repeated `Idempotency-Key` on the same route with the recorded
+/// response instead of running the operation again, and with 409 while the
+/// first request is still running. Server errors are not recorded so the
+/// operation can be retried.
+#[derive(Clone)]
+pub struct Idempotent {
+    ttl: Duration,
+}
+
+impl Idempotent {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Idempotent {
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordedResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: String,
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Idempotent {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let Some(key) = req.header("Idempotency-Key") else {
+            return Ok(next.run(req).await);
+        };
+        let key = format!("{}:{}", req.url().path(), key.as_str());
+        match store::claim_idempotency_key(&key, self.ttl).await {
+            Ok(Idempotency::Claimed) => {}
+            Ok(Idempotency::InProgress) => {
+                return Ok(crate::handle_error(PremiumError::RequestInProgress))
+            }
+            Ok(Idempotency::Completed(recorded)) => {
+                let recorded: RecordedResponse = serde_json::from_str(&recorded)?;
+                let mut response = Response::new(recorded.status);
+                response.set_body(recorded.body);
+                if let Some(content_type) = recorded.content_type {
+                    response.insert_header("Content-Type", content_type);
+                }
+                response.insert_header("Idempotent-Replayed", "true");
+                return Ok(response);
+            }
+            Err(err) => return Ok(crate::handle_error(err)),
+        }
+
+        let mut response = next.run(req).await;
+        if response.status().is_server_error() {
+            if let Err(err) = store::release_idempotency_key(&key).await {
+                error!("idempotency key {} not released {}", key, err);
+            }
+            return Ok(response);
+        }
+        let content_type = response.content_type().map(|mime| mime.to_string());
+        let body = response.take_body().into_string().await?;
+        let recorded = RecordedResponse {
+            status: response.status() as u16,
+            content_type: content_type.clone(),
+            body: body.clone(),
+        };
+        if let Err(err) =
+            store::complete_idempotency_key(&key, &serde_json::to_string(&recorded)?, self.ttl)
+                .await
+        {
+            error!("idempotency key {} not recorded {}", key, err);
+        }
+        response.set_body(body);
+        if let Some(content_type) = content_type {
+            response.insert_header("Content-Type", content_type);
+        }
+        Ok(response)
+    }
+}
+
+/// Builds the CORS middleware. Requests from other origins are refused with
+/// 401 and preflight requests are answered without reaching a handler.
+pub fn cors(config: &CorsConfig) -> CorsMiddleware {
+    let mut cors = CorsMiddleware::new()
+        .allow_origin(Origin::from(config.allowed_origins.clone()))
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = header_value("maxAgeSecs", &[config.max_age_secs.to_string()]) {
+        cors = cors.max_age(max_age);
+    }
+    if let Some(methods) = header_value("allowedMethods", &config.allowed_methods) {
+        cors = cors.allow_methods(methods);
+    }
+    if let Some(headers) = header_value("allowedHeaders", &config.allowed_headers) {
+        cors = cors.allow_headers(headers);
+    }
+    if let Some(headers) = header_value("exposedHeaders", &config.exposed_headers) {
+        cors = cors.expose_headers(headers);
+    }
+    cors
+}
+
+fn header_value(setting: &str, values: &[String]) -> Option<HeaderValue> {
+    if values.is_empty() {
+        return None;
+    }
+    match values.join(", ").parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("ignoring cors {} that are not ascii", setting);
+            None
+        }
+    }
+}
+
+/// Rejects bodies over the configured size with 413 before any handler reads
+/// them, and answers 504 when a handler runs past the request timeout. Bodies
+/// sent without a length are buffered up to the limit.
+/// Settings are shared between clones and can be replaced while running.
+#[derive(Clone)]
+pub struct Limits {
+    settings: Arc<RwLock<LimitSettings>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LimitSettings {
+    max_body_bytes: u64,
+    timeout: Option<Duration>,
+}
+
+impl From<&LimitsConfig> for LimitSettings {
+    fn from(config: &LimitsConfig) -> Self {
+        LimitSettings {
+            max_body_bytes: config.max_body_bytes,
+            timeout: Some(Duration::from_millis(config.request_timeout_ms))
+                .filter(|timeout| !timeout.is_zero()),
+        }
+    }
+}
+
+impl Limits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Limits {
+            settings: Arc::new(RwLock::new(config.into())),
+        }
+    }
+
+    pub fn set(&self, config: &LimitsConfig) {
+        *self.settings.write().unwrap_or_else(|err| err.into_inner()) = config.into();
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Limits {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let settings = *self.settings.read().unwrap_or_else(|err| err.into_inner());
+        let too_large = PremiumError::PayloadTooLarge(settings.max_body_bytes);
+        match req.len() {
+            Some(len) if len as u64 > settings.max_body_bytes => {
+                warn!("request body of {} bytes refused", len);
+                return Ok(crate::handle_error(too_large));
+            }
+            Some(_) => {}
+            None => {
+                let mut body = Vec::new();
+                req.take_body()
+                    .take(settings.max_body_bytes + 1)
+                    .read_to_end(&mut body)
+                    .await?;
+                if body.len() as u64 > settings.max_body_bytes {
+                    warn!(
+                        "request body over {} bytes refused",
+                        settings.max_body_bytes
+                    );
+                    return Ok(crate::handle_error(too_large));
+                }
+                req.set_body(body);
+            }
+        }
+        match settings.timeout {
+            Some(timeout) => match async_std::future::timeout(timeout, next.run(req)).await {
+                Ok(response) => Ok(response),
+                Err(_) => {
+                    warn!("request timed out after {}ms", timeout.as_millis());
+                    Ok(crate::handle_error(PremiumError::Timeout))
+                }
+            },
+            None => Ok(next.run(req).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use std::io::Read;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip;q=0"), None);
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut app = tide::new();
+        app.with(Compression::new(&CompressionConfig {
+            min_bytes: 100,
+            ..CompressionConfig::default()
+        }));
+        app.at("/large").get(|_| async {
+            let mut response = Response::new(200);
+            response.set_body(serde_json::json!({ "premium": "1".repeat(500) }));
+            response.insert_header("ETag", "\"abc\"");
+            Ok(response)
+        });
+        app.at("/small")
+            .get(|_| async { Ok(serde_json::json!({ "premium": "1" })) });
+
+        task::block_on(async {
+            let get = |path: &str, accept: &str| {
+                let url = Url::parse(&format!("http://localhost{}", path)).unwrap();
+                let mut request = HttpRequest::new(Method::Get, url);
+                request.insert_header("Accept-Encoding", accept);
+                request
+            };
+            for (accept, encoding) in [("gzip, br", Encoding::Brotli), ("gzip", Encoding::Gzip)] {
+                let mut response: HttpResponse = app.respond(get("/large", accept)).await.unwrap();
+                assert_eq!(response["Content-Encoding"], encoding.name());
+                assert_eq!(response["Vary"], "Accept-Encoding");
+                assert_eq!(response["ETag"], "W/\"abc\"");
+                assert!(response.content_type().unwrap().essence() == "application/json");
+                let body = response.body_bytes().await.unwrap();
+                let mut json = String::new();
+                match encoding {
+                    Encoding::Brotli => {
+                        brotli::Decompressor::new(body.as_slice(), 4096)
+                            .read_to_string(&mut json)
+                            .unwrap();
+                    }
+                    Encoding::Gzip => {
+                        flate2::read::GzDecoder::new(body.as_slice())
+                            .read_to_string(&mut json)
+                            .unwrap();
+                    }
+                }
+                assert!(json.contains(&"1".repeat(500)));
+            }
+
+            let response: HttpResponse = app.respond(get("/large", "identity")).await.unwrap();
+            assert!(response.header("Content-Encoding").is_none());
+            assert_eq!(response["Vary"], "Accept-Encoding");
+            let response: HttpResponse = app.respond(get("/small", "gzip")).await.unwrap();
+            assert!(response.header("Content-Encoding").is_none());
+        });
+    }
+
+    #[test]
+    fn test_cache_headers() {
+        let mut policies = HashMap::new();
+        policies.insert(
+            "/cached".to_string(),
+            CachePolicy {
+                cache_control: Some("max-age=3600".to_string()),
+                vary: None,
+            },
+        );
+        let mut app = tide::new();
+        app.with(CacheHeaders::new(policies));
+        app.at("/cached").get(|_| async { Ok("") });
+        app.at("/plain").get(|_| async { Ok("") });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/cached").unwrap();
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(response["Cache-Control"], "max-age=3600");
+            assert!(response.header("Vary").is_none());
+
+            let url = Url::parse("http://localhost/plain").unwrap();
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert!(response.header("Cache-Control").is_none());
+        });
+    }
+
+    #[test]
+    fn test_admin_auth() {
+        let mut users = HashMap::new();
+        users.insert("ops".to_string(), "secret".to_string());
+        let mut app = tide::new();
+        app.at("/admin")
+            .with(AdminAuth::new(&AdminConfig {
+                users,
+                ..AdminConfig::default()
+            }))
+            .get(|_| async { Ok("") });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/admin").unwrap();
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url.clone()))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), tide::StatusCode::Unauthorized);
+            assert_eq!(
+                response["WWW-Authenticate"],
+                "Basic realm=\"premium-admin\""
+            );
+
+            let mut request = HttpRequest::new(Method::Get, url.clone());
+            BasicAuth::new("ops", "wrong").apply(&mut request);
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), tide::StatusCode::Unauthorized);
+
+            let mut request = HttpRequest::new(Method::Get, url);
+            BasicAuth::new("ops", "secret").apply(&mut request);
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), tide::StatusCode::Ok);
+        });
+    }
+
+    #[test]
+    fn test_hashed_passwords() {
+        let mut users = HashMap::new();
+        users.insert("ops".to_string(), "secret".to_string());
+        // sha256("secret")
+        users.insert(
+            "audit".to_string(),
+            "sha256:2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b".to_string(),
+        );
+        users.insert("broken".to_string(), "sha256:zz".to_string());
+        assert!(password_matches(&users, "ops", "secret"));
+        assert!(password_matches(&users, "audit", "secret"));
+        assert!(!password_matches(&users, "audit", "sha256:2bb80d53"));
+        assert!(!password_matches(&users, "audit", "wrong"));
+        assert!(!password_matches(&users, "broken", "zz"));
+        assert!(!password_matches(&users, "nobody", "secret"));
+        assert_eq!(plaintext_passwords(&users), vec!["ops"]);
+    }
+
+    #[test]
+    fn test_admin_auth_without_users() {
+        let admin = |insecure| {
+            let mut app = tide::new();
+            app.at("/admin")
+                .with(AdminAuth::new(&AdminConfig {
+                    insecure,
+                    ..AdminConfig::default()
+                }))
+                .get(|_| async { Ok("") });
+            app
+        };
+        task::block_on(async {
+            let url = Url::parse("http://localhost/admin").unwrap();
+            let mut request = HttpRequest::new(Method::Get, url.clone());
+            BasicAuth::new("ops", "secret").apply(&mut request);
+            let response: HttpResponse = admin(false).respond(request).await.unwrap();
+            assert_eq!(response.status(), tide::StatusCode::Unauthorized);
+
+            let response: HttpResponse = admin(true)
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), tide::StatusCode::Ok);
+        });
+    }
+
+    #[test]
+    fn test_request_id_header() {
+        let mut app = tide::new();
+        app.with(RequestSpan);
+        app.at("/").get(|_| async { Ok("") });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/").unwrap();
+            let mut request = HttpRequest::new(Method::Get, url.clone());
+            request.insert_header("X-Request-Id", "quote-42");
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response["X-Request-Id"], "quote-42");
+
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(response["X-Request-Id"].as_str().len(), 36);
+        });
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits::new(&LimitsConfig::default());
+        let mut app = tide::new();
+        app.with(limits.clone());
+        limits.set(&LimitsConfig {
+            max_body_bytes: 8,
+            request_timeout_ms: 50,
+        });
+        app.at("/echo")
+            .post(|mut req: Request<()>| async move { req.body_string().await });
+        app.at("/slow").get(|_| async {
+            async_std::task::sleep(Duration::from_millis(500)).await;
+            Ok("")
+        });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/echo").unwrap();
+            let mut request = HttpRequest::new(Method::Post, url.clone());
+            request.set_body("0123456789");
+            let mut response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), 413);
+            assert!(response.body_string().await.unwrap().contains("006"));
+
+            let mut request = HttpRequest::new(Method::Post, url);
+            request.set_body("0123");
+            let mut response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.body_string().await.unwrap(), "0123");
+
+            let url = Url::parse("http://localhost/slow").unwrap();
+            let response: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 504);
+        });
+    }
+
+    #[test]
+    fn test_cors() {
+        let mut app = tide::new();
+        app.with(cors(&CorsConfig {
+            allowed_origins: vec!["https://quotes.example.com".to_string()],
+            ..CorsConfig::default()
+        }));
+        app.at("/quote").post(|_| async { Ok("") });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/quote").unwrap();
+            let mut request = HttpRequest::new(Method::Options, url.clone());
+            request.insert_header("Origin", "https://quotes.example.com");
+            request.insert_header("Access-Control-Request-Method", "POST");
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response["Access-Control-Max-Age"], "600");
+            assert_eq!(
+                response["Access-Control-Allow-Origin"],
+                "https://quotes.example.com"
+            );
+
+            let mut request = HttpRequest::new(Method::Post, url.clone());
+            request.insert_header("Origin", "https://quotes.example.com");
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response["Access-Control-Expose-Headers"], "X-Request-Id");
+
+            let mut request = HttpRequest::new(Method::Post, url);
+            request.insert_header("Origin", "https://elsewhere.example.com");
+            let response: HttpResponse = app.respond(request).await.unwrap();
+            assert_eq!(response.status(), 401);
+        });
+    }
+
+    #[test]
+    fn test_idempotent_replay() {
+        use premium_core::config::{StorageBackend, StorageConfig};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        store::configure(&StorageConfig {
+            backend: StorageBackend::Memory,
+            ..StorageConfig::default()
+        })
+        .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut app = tide::new();
+        let counter = calls.clone();
+        app.at("/unloads")
+            .with(Idempotent::new(&IdempotencyConfig::default()))
+            .post(move |_| {
+                let counter = counter.clone();
+                async move {
+                    let calls = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    tide::Body::from_json(&serde_json::json!({ "calls": calls }))
+                }
+            });
+
+        task::block_on(async {
+            let url = Url::parse("http://localhost/unloads").unwrap();
+            let send = |key: &str| {
+                let mut request = HttpRequest::new(Method::Post, url.clone());
+                request.insert_header("Idempotency-Key", key);
+                app.respond::<HttpRequest, HttpResponse>(request)
+            };
+            let mut first = send("job-1").await.unwrap();
+            let mut replay = send("job-1").await.unwrap();
+            assert_eq!(replay["Idempotent-Replayed"], "true");
+            assert_eq!(replay["Content-Type"], "application/json");
+            assert_eq!(
+                replay.body_string().await.unwrap(),
+                first.body_string().await.unwrap()
+            );
+            send("job-2").await.unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn test_tenants() {
+        let mut app = tide::new();
+        app.with(Tenants::new(&["acme".to_string()]));
+        let current = |_| async { Ok(tenant::current().unwrap_or_default()) };
+        app.at("/quote").get(current);
+        app.at("/tenants/:tenant/quote").get(current);
+
+        task::block_on(async {
+            let send = |path: &str, header: Option<&str>| {
+                let url = Url::parse(&format!("http://localhost{}", path)).unwrap();
+                let mut request = HttpRequest::new(Method::Get, url);
+                if let Some(tenant) = header {
+                    request.insert_header("X-Tenant-Id", tenant);
+                }
+                app.respond::<HttpRequest, HttpResponse>(request)
+            };
+            let mut response = send("/quote", Some("acme")).await.unwrap();
+            assert_eq!(response.body_string().await.unwrap(), "acme");
+            let mut response = send("/tenants/acme/quote", None).await.unwrap();
+            assert_eq!(response.body_string().await.unwrap(), "acme");
+            let mut response = send("/quote", None).await.unwrap();
+            assert_eq!(response.body_string().await.unwrap(), "");
+            let mut response = send("/tenants/other/quote", None).await.unwrap();
+            assert_eq!(response.status(), 400);
+            assert!(response.body_string().await.unwrap().contains("015"));
+        });
+    }
+}
@@ -0,0 +1,124 @@
+use serde_json::{Map, Value};
+use tide::http::mime::Mime;
+use tide::{Body, Middleware, Next, Request, Response};
+
+use crate::middleware::RequestId;
+
+const PROBLEM: &str = "application/problem+json";
+
+/// Prefix of the `type` of each problem, followed by the error code.
+const TYPE_PREFIX: &str = "urn:kubesure:premium:error:";
+
+/// Whether the caller's `Accept` names `application/problem+json`.
+pub fn wants_problem<State>(req: &Request<State>) -> bool {
+    req.header("Accept").is_some_and(|accept| {
+        accept
+            .as_str()
+            .split(',')
+            .any(|mime| mime.split(';').next().unwrap_or_default().trim() == PROBLEM)
+    })
+}
+
+/// Re-encodes JSON error responses as RFC 7807 problems for callers that
+/// accept `application/problem+json`. Everyone else keeps the
+/// `ErrorResponse` body. Must run inside `RequestSpan` to see the request id.
+pub struct Problems;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Problems {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !wants_problem(&req) {
+            return Ok(next.run(req).await);
+        }
+        let instance = req.url().path().to_string();
+        let request_id = req.ext::<RequestId>().map(|id| id.0.clone());
+        let response = next.run(req).await;
+        encode(response, &instance, request_id).await
+    }
+}
+
+/// Re-encodes an `ErrorResponse` body as a problem: `code` names its `type`,
+/// `message` becomes the `detail` and any other fields are kept as
+/// extensions. Successes and responses that are not JSON pass unchanged.
+pub async fn encode(
+    mut response: Response,
+    instance: &str,
+    request_id: Option<String>,
+) -> tide::Result {
+    let is_json = response
+        .content_type()
+        .is_some_and(|mime| mime.essence() == "application/json");
+    if response.status().is_success() || !is_json || response.is_empty() == Some(true) {
+        return Ok(response);
+    }
+    let Value::Object(mut fields) = response.take_body().into_json::<Value>().await? else {
+        return Ok(response);
+    };
+    let status = response.status();
+    let code = fields.remove("code").unwrap_or(Value::Null);
+    let mut problem = Map::new();
+    if let Some(code) = code.as_str() {
+        problem.insert("type".into(), format!("{}{}", TYPE_PREFIX, code).into());
+    }
+    problem.insert("title".into(), status.canonical_reason().into());
+    problem.insert("status".into(), (status as u16).into());
+    if let Some(message) = fields.remove("message") {
+        problem.insert("detail".into(), message);
+    }
+    problem.insert("instance".into(), instance.into());
+    problem.insert("code".into(), code);
+    if let Some(request_id) = request_id {
+        problem.insert("requestId".into(), request_id.into());
+    }
+    problem.extend(fields);
+    response.set_body(Body::from_json(&problem)?);
+    response.set_content_type(PROBLEM.parse::<Mime>()?);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use serde_json::json;
+    use tide::StatusCode;
+
+    #[test]
+    fn test_encode() {
+        task::block_on(async {
+            let mut response = Response::new(StatusCode::BadRequest);
+            response.set_body(
+                Body::from_json(&json!({
+                    "code": "013",
+                    "message": "Sum insured not offered",
+                    "validSumsInsured": ["100000"]
+                }))
+                .unwrap(),
+            );
+            let path = "/api/v1/healths/premiums";
+            let mut response = encode(response, path, Some("abc".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(response.content_type().unwrap().essence(), PROBLEM);
+            let problem: Value = response.take_body().into_json().await.unwrap();
+            assert_eq!(
+                problem,
+                json!({
+                    "type": "urn:kubesure:premium:error:013",
+                    "title": "Bad Request",
+                    "status": 400,
+                    "detail": "Sum insured not offered",
+                    "instance": path,
+                    "code": "013",
+                    "requestId": "abc",
+                    "validSumsInsured": ["100000"]
+                })
+            );
+
+            let mut ok = Response::new(StatusCode::Ok);
+            ok.set_body(Body::from_json(&json!({"premium": 1})).unwrap());
+            let ok = encode(ok, path, None).await.unwrap();
+            assert_eq!(ok.content_type().unwrap().essence(), "application/json");
+        });
+    }
+}
@@ -0,0 +1,140 @@
+use log::error;
+use premium_core::premium::PremiumError;
+use prost::Message;
+use serde_json::{json, Value};
+use tide::http::mime::Mime;
+use tide::{Body, Request, Response};
+
+use crate::grpc::proto::{ErrorResponse, HealthRequest, HealthResponse};
+
+const PROTOBUF: &str = "application/x-protobuf";
+
+fn is_protobuf(mime: &str) -> bool {
+    mime.split(';').next().unwrap_or_default().trim() == PROTOBUF
+}
+
+/// Whether the request body is a protobuf `HealthRequest`.
+pub fn has_protobuf_body<State>(req: &Request<State>) -> bool {
+    req.header("Content-Type")
+        .is_some_and(|value| is_protobuf(value.as_str()))
+}
+
+/// Whether the caller accepts protobuf, or sent it with no preference given.
+pub fn wants_protobuf<State>(req: &Request<State>) -> bool {
+    match req.header("Accept").map(|value| value.as_str()) {
+        Some(accept) if accept.split(',').any(is_protobuf) => true,
+        Some(accept) if !accept.trim().starts_with("*/*") => false,
+        _ => has_protobuf_body(req),
+    }
+}
+
+/// Decodes the `premium.v1.HealthRequest` message gRPC clients send as the
+/// JSON body it stands for; empty strings are fields left out.
+pub fn parse_request(body: &[u8]) -> anyhow::Result<Value, PremiumError> {
+    let request = HealthRequest::decode(body).map_err(|err| {
+        error!("Invalid protobuf in health request {}", err);
+        PremiumError::InvalidInput
+    })?;
+    let text = |value: String| Some(value).filter(|value| !value.is_empty());
+    let mut value = json!({
+        "code": text(request.code),
+        "sumInsured": text(request.sum_insured),
+        "dateOfBirth": text(request.date_of_birth),
+        "age": request.age,
+        "policyStartDate": text(request.policy_start_date),
+        "policyEndDate": text(request.policy_end_date),
+        "discountCodes": request.discount_codes,
+        "paymentFrequency": text(request.payment_frequency),
+        "zone": text(request.zone),
+        "deductible": text(request.deductible),
+        "gender": text(request.gender),
+        "maternityCover": Some(true).filter(|_| request.maternity_cover),
+        "opdCover": Some(true).filter(|_| request.opd_cover),
+        "restoreBenefit": Some(true).filter(|_| request.restore_benefit),
+        "declaredConditions": Some(request.declared_conditions).filter(|codes| !codes.is_empty()),
+        "heightCm": Some(request.height_cm).filter(|height| *height > 0.0),
+        "weightKg": Some(request.weight_kg).filter(|weight| *weight > 0.0),
+        "asOf": text(request.as_of),
+    });
+    if let Some(fields) = value.as_object_mut() {
+        fields.retain(|_, field| !field.is_null());
+    }
+    Ok(value)
+}
+
+/// Re-encodes a JSON quote as a `HealthResponse` message and a JSON error as
+/// an `ErrorResponse`. Responses without a body pass unchanged.
+pub async fn encode(mut response: Response) -> tide::Result {
+    if response.is_empty() == Some(true) {
+        return Ok(response);
+    }
+    let value: Value = response.take_body().into_json().await?;
+    let text = |field: &Value| field.as_str().unwrap_or_default().to_string();
+    let bytes = if response.status().is_success() {
+        let breakdown = &value["breakdown"];
+        HealthResponse {
+            premium: text(&value["premium"]),
+            installments: breakdown["installments"].as_u64().unwrap_or(1) as u32,
+            installment_premium: match breakdown.is_null() {
+                true => text(&value["premium"]),
+                false => text(&breakdown["installmentPremium"]),
+            },
+            currency: text(&value["currency"]),
+            matrix_version: value["matrixVersion"].as_u64().unwrap_or_default(),
+            expires_at: text(&value["expiresAt"]),
+            tax: text(&value["tax"]),
+            total_premium: text(&value["totalPremium"]),
+        }
+        .encode_to_vec()
+    } else {
+        ErrorResponse {
+            code: text(&value["code"]),
+            message: text(&value["message"]),
+            valid_sums_insured: value["validSumsInsured"]
+                .as_array()
+                .map(|sums| sums.iter().map(text).collect())
+                .unwrap_or_default(),
+        }
+        .encode_to_vec()
+    };
+    response.set_body(Body::from_bytes(bytes));
+    response.set_content_type(PROTOBUF.parse::<Mime>()?);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use tide::StatusCode;
+
+    #[test]
+    fn test_protobuf_round_trip() {
+        let request = HealthRequest {
+            code: "1A".to_string(),
+            sum_insured: "100000".to_string(),
+            discount_codes: vec!["A".to_string()],
+            ..HealthRequest::default()
+        };
+        assert_eq!(
+            parse_request(&request.encode_to_vec()).unwrap(),
+            json!({"code": "1A", "sumInsured": "100000", "discountCodes": ["A"]})
+        );
+        assert!(parse_request(b"\xff").is_err());
+
+        task::block_on(async {
+            let mut response = Response::new(StatusCode::Ok);
+            let quote = json!({"premium": "750", "currency": "INR", "matrixVersion": 2});
+            response.set_body(Body::from_json(&quote).unwrap());
+            let mut response = encode(response).await.unwrap();
+            assert_eq!(response.content_type().unwrap().essence(), PROTOBUF);
+            let bytes = response.take_body().into_bytes().await.unwrap();
+            let message = HealthResponse::decode(bytes.as_slice()).unwrap();
+            assert_eq!(
+                (message.installments, message.installment_premium.as_str()),
+                (1, "750")
+            );
+            assert_eq!(message.matrix_version, 2);
+        });
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use log::{error, info};
+use premium_core::config::FlagSetting;
+use premium_core::{channels, commission, flags, products, quote_cache, rate_test};
+use serde::Serialize;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+
+use crate::config::{Config, LimitsConfig};
+use crate::logging;
+use crate::middleware::Limits;
+
+/// The settings a reload applied.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reloaded {
+    pub log_level: Option<String>,
+    pub limits: LimitsConfig,
+    pub premium_cache_ttl_secs: Option<u64>,
+    /// Products with settings, such as tax rates, in the registry.
+    pub products: usize,
+    pub flags: BTreeMap<&'static str, FlagSetting>,
+}
+
+/// Re-reads the config file and applies what can change while serving: the
+/// log level, request limits, premium cache, product registry, rate test,
+/// feature flags, commission rates and sales channel factors.
+/// Nothing is applied when the file does not parse; other settings need a
+/// restart.
+pub fn reload(limits: &Limits) -> anyhow::Result<Reloaded> {
+    apply(Config::load()?, limits)
+}
+
+fn apply(config: Config, limits: &Limits) -> anyhow::Result<Reloaded> {
+    if let Some(level) = &config.log_level {
+        logging::set_level(level)?;
+    }
+    let products = products::load(&config.products, &config.matrix)?;
+    limits.set(&config.limits);
+    quote_cache::configure(&config.premium_cache);
+    rate_test::configure(&config.rate_test);
+    flags::configure(&config.flags);
+    commission::configure(&config.commission);
+    channels::configure(&config.channel_pricing);
+    info!("configuration reloaded");
+    Ok(Reloaded {
+        log_level: config.log_level,
+        limits: config.limits,
+        premium_cache_ttl_secs: Some(config.premium_cache.ttl_secs)
+            .filter(|_| config.premium_cache.enabled),
+        products,
+        flags: flags::settings(),
+    })
+}
+
+/// Reloads on every SIGHUP until the process exits.
+pub fn on_sighup(limits: Limits) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    for _ in signals.forever() {
+        info!("SIGHUP received, reloading configuration");
+        if let Err(err) = reload(&limits) {
+            error!("configuration reload failed {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use premium_core::config::PremiumCacheConfig;
+
+    #[test]
+    fn test_apply_reloaded_settings() {
+        let limits = Limits::new(&LimitsConfig::default());
+        let mut config = Config {
+            log_level: Some("premium_rs=debug".to_string()),
+            limits: LimitsConfig {
+                max_body_bytes: 8,
+                ..LimitsConfig::default()
+            },
+            premium_cache: PremiumCacheConfig {
+                enabled: true,
+                ttl_secs: 30,
+                ..PremiumCacheConfig::default()
+            },
+            ..Config::default()
+        };
+        let reloaded = apply(config.clone(), &limits).unwrap();
+        assert_eq!(reloaded.log_level.as_deref(), Some("premium_rs=debug"));
+        assert_eq!(reloaded.limits.max_body_bytes, 8);
+        assert_eq!(reloaded.premium_cache_ttl_secs, Some(30));
+        assert_eq!(reloaded.products, 0);
+        assert!(quote_cache::stats().enabled);
+
+        // a bad log filter fails the reload before anything is applied
+        config.log_level = Some("premium_rs=loudest".to_string());
+        config.premium_cache.enabled = false;
+        assert!(apply(config, &limits).is_err());
+        assert!(quote_cache::stats().enabled);
+
+        let reloaded = apply(Config::default(), &limits).unwrap();
+        assert_eq!(reloaded.premium_cache_ttl_secs, None);
+        assert!(!quote_cache::stats().enabled);
+    }
+}
@@ -0,0 +1,607 @@
+use std::sync::Once;
+
+use async_std::task;
+use chrono::{Local, Months};
+use serde_json::{json, Value};
+use tide::http::auth::BasicAuth;
+use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+use tide::StatusCode;
+
+use premium_core::config::{ChannelCommission, ChannelFactors, StorageBackend};
+
+use crate::config::Config;
+use crate::{app, configure, contract, state, State};
+
+const PREMIUMS: &str = "/api/v1/healths/premiums";
+const TENANT: &str = "suite";
+const CHANNEL: &str = "BANCA01";
+const DISTRIBUTOR_KEY: &str = "suite-distributor";
+const OPERATOR: (&str, &str) = ("ops", "suite-secret");
+
+static CONFIGURED: Once = Once::new();
+
+/// The service as `serve` builds it, on the in-memory store with the
+/// bundled `premium_tables.xlsx` loaded, so the suite runs without redis.
+/// Loads and unloads go to the `suite` tenant to leave that matrix alone.
+fn service() -> tide::Server<State> {
+    let mut config = Config::default();
+    config.storage.backend = StorageBackend::Memory;
+    config.tenants = vec![TENANT.to_string()];
+    config
+        .admin
+        .users
+        .insert(OPERATOR.0.to_string(), OPERATOR.1.to_string());
+    config.commission.channels.insert(
+        CHANNEL.to_string(),
+        ChannelCommission {
+            percent: 12.5,
+            ..ChannelCommission::default()
+        },
+    );
+    config
+        .distributors
+        .insert(DISTRIBUTOR_KEY.to_string(), CHANNEL.to_string());
+    config.channel_pricing.channels.insert(
+        "online".to_string(),
+        ChannelFactors {
+            percent: Some(90),
+            ..ChannelFactors::default()
+        },
+    );
+    CONFIGURED.call_once(|| task::block_on(configure(&config)).unwrap());
+    app(state(&config), &config)
+}
+
+/// A date of birth `age` years and a season ago, so the age band does not
+/// move as the calendar does.
+fn born(age: u32) -> String {
+    let date = Local::now().date_naive() - Months::new(age * 12 + 3);
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn quote(age: u32) -> Value {
+    json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(age)})
+}
+
+fn request(method: Method, path: &str) -> HttpRequest {
+    HttpRequest::new(
+        method,
+        Url::parse(&format!("http://localhost{}", path)).unwrap(),
+    )
+}
+
+fn json_request(method: Method, path: &str, body: &Value) -> HttpRequest {
+    let mut request = request(method, path);
+    request.insert_header("Content-Type", "application/json");
+    request.set_body(body.to_string());
+    request
+}
+
+/// The request with the operator's basic credentials, for admin routes.
+fn operator(mut request: HttpRequest) -> HttpRequest {
+    BasicAuth::new(OPERATOR.0, OPERATOR.1).apply(&mut request);
+    request
+}
+
+/// The status and body, `Null` when the body is not JSON.
+async fn send(app: &tide::Server<State>, request: HttpRequest) -> (StatusCode, Value) {
+    let mut response: HttpResponse = app.respond(request).await.unwrap();
+    let body = response.body_string().await.unwrap();
+    (
+        response.status(),
+        serde_json::from_str(&body).unwrap_or(Value::Null),
+    )
+}
+
+/// The request a contract interaction describes, with only its own headers.
+fn contract_request(interaction: &contract::ContractRequest) -> HttpRequest {
+    let method: Method = interaction.method.parse().unwrap();
+    let mut request = request(method, &interaction.path);
+    for (name, value) in &interaction.headers {
+        request.insert_header(name.as_str(), value.as_str());
+    }
+    if let Some(body) = &interaction.body {
+        request.set_body(body.to_string());
+        request.remove_header("Content-Type");
+        if let Some(content_type) = interaction.headers.get("Content-Type") {
+            request.insert_header("Content-Type", content_type.as_str());
+        }
+    }
+    request
+}
+
+/// Every interaction of the published pact, including those that need the
+/// matrix loaded.
+#[test]
+fn test_contract() {
+    let app = &service();
+    task::block_on(async {
+        let failures = contract::verify(&contract::quote_api_pact(), |interaction| {
+            let request = contract_request(&interaction);
+            async move {
+                let (status, body) = send(app, request).await;
+                Ok(contract::Outcome {
+                    status: status.into(),
+                    body: Some(body).filter(|body| !body.is_null()),
+                })
+            }
+        })
+        .await;
+        assert!(failures.is_empty(), "{:?}", failures);
+    });
+}
+
+#[test]
+fn test_probes() {
+    let app = service();
+    task::block_on(async {
+        for path in ["/", "/readyz", "/version", "/metrics"] {
+            let (status, _) = send(&app, request(Method::Get, path)).await;
+            assert_eq!(status, StatusCode::Ok, "{}", path);
+        }
+        let (_, version) = send(&app, request(Method::Get, "/version")).await;
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["gitSha"], env!("GIT_SHA"));
+        assert!(version["builtAt"]
+            .as_str()
+            .is_some_and(|at| at.ends_with("+00:00")));
+        assert!(version["matrixVersion"].is_u64(), "{}", version);
+        let (status, schema) =
+            send(&app, request(Method::Get, &format!("{}/schema", PREMIUMS))).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(schema["required"].is_array());
+    });
+}
+
+#[test]
+fn test_quotes() {
+    let app = service();
+    task::block_on(async {
+        for (age, premium) in [
+            (25, "250"),
+            (40, "500"),
+            (50, "750"),
+            (58, "950"),
+            (80, "12000"),
+        ] {
+            let (status, body) =
+                send(&app, json_request(Method::Post, PREMIUMS, &quote(age))).await;
+            assert_eq!(status, StatusCode::Ok, "{} {}", age, body);
+            assert_eq!(body["premium"], premium, "age {}", age);
+            assert_eq!(body["matrixVersion"], 1);
+        }
+
+        let (status, body) = send(
+            &app,
+            json_request(Method::Post, "/api/v2/healths/premiums", &quote(40)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body["premium"].is_object(), "{}", body);
+
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::Post,
+                PREMIUMS,
+                &json!({"code": "1A", "sumInsured": "100000", "age": 40}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert_eq!(body["premium"], "500");
+
+        let mut unisex = quote(40);
+        unisex["gender"] = json!("female");
+        let (status, body) = send(&app, json_request(Method::Post, PREMIUMS, &unisex)).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert_eq!(body["premium"], "500");
+
+        let path = format!(
+            "{}?code=1A&sumInsured=100000&dateOfBirth={}",
+            PREMIUMS,
+            born(40)
+        );
+        let (status, body) = send(&app, request(Method::Get, &path)).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(body["premium"], "500");
+    });
+}
+
+#[test]
+fn test_rejections() {
+    let app = service();
+    task::block_on(async {
+        let mut unsupported = json_request(Method::Post, PREMIUMS, &quote(40));
+        unsupported.insert_header("Content-Type", "text/plain");
+        let mut malformed = json_request(Method::Post, PREMIUMS, &quote(40));
+        malformed.set_body("{\"code\":");
+        let mut problem = json_request(Method::Post, PREMIUMS, &json!({"code": "1A"}));
+        problem.insert_header("Accept", "application/problem+json");
+        let cases = [
+            (
+                request(Method::Post, PREMIUMS),
+                StatusCode::BadRequest,
+                "003",
+            ),
+            (unsupported, StatusCode::BadRequest, "003"),
+            (malformed, StatusCode::BadRequest, "002"),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000"}),
+                ),
+                StatusCode::BadRequest,
+                "011",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "123", "dateOfBirth": born(40)}),
+                ),
+                StatusCode::BadRequest,
+                "013",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "9Z", "sumInsured": "100000", "dateOfBirth": born(40)}),
+                ),
+                StatusCode::BadRequest,
+                "004",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(10)}),
+                ),
+                StatusCode::BadRequest,
+                "009",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": "2023-02-29"}),
+                ),
+                StatusCode::BadRequest,
+                "026",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(40), "age": 50}),
+                ),
+                StatusCode::BadRequest,
+                "025",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(40), "gender": "x"}),
+                ),
+                StatusCode::BadRequest,
+                "011",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(40), "discountCodes": ["NOPE"]}),
+                ),
+                StatusCode::BadRequest,
+                "010",
+            ),
+            (
+                json_request(
+                    Method::Post,
+                    PREMIUMS,
+                    &json!({"code": "1A", "sumInsured": "100000", "dateOfBirth": born(40), "maternityCover": true}),
+                ),
+                StatusCode::BadRequest,
+                "027",
+            ),
+            (
+                request(Method::Get, &format!("{}/bulk/unknown", PREMIUMS)),
+                StatusCode::NotFound,
+                "020",
+            ),
+            (
+                operator(request(
+                    Method::Post,
+                    &format!("{}/versions/99/activate", PREMIUMS),
+                )),
+                StatusCode::NotFound,
+                "005",
+            ),
+            (
+                request(Method::Post, &format!("/tenants/other{}", PREMIUMS)),
+                StatusCode::BadRequest,
+                "015",
+            ),
+            (problem, StatusCode::BadRequest, "011"),
+        ];
+        for (request, expected, code) in cases {
+            let path = request.url().path().to_string();
+            let (status, body) = send(&app, request).await;
+            assert_eq!(status, expected, "{} {}", path, body);
+            assert_eq!(body["code"], code, "{} {}", path, body);
+        }
+    });
+}
+
+#[test]
+fn test_quote_routes() {
+    let app = service();
+    task::block_on(async {
+        let cases = [
+            ("compare", json!({"code": "1A", "dateOfBirth": born(40)})),
+            ("explain", quote(40)),
+            ("decisions", quote(40)),
+            (
+                "groups",
+                json!({"code": "1A", "members": [
+                    {"memberId": "E1", "sumInsured": "100000", "dateOfBirth": born(25)},
+                    {"memberId": "E2", "sumInsured": "100000", "dateOfBirth": born(50)}
+                ]}),
+            ),
+            (
+                "endorsements",
+                json!({
+                    "original": quote(40),
+                    "changed": quote(50),
+                    "policyStartDate": "2024-01-01",
+                    "policyEndDate": "2024-12-31",
+                    "effectiveDate": "2024-07-01"
+                }),
+            ),
+            (
+                "revalidations",
+                json!({
+                    "request": quote(40),
+                    "premium": "500",
+                    "matrixVersion": 1,
+                    "expiresAt": "2999-01-01T00:00:00Z"
+                }),
+            ),
+        ];
+        for (route, body) in cases {
+            let path = format!("{}/{}", PREMIUMS, route);
+            let (status, body) = send(&app, json_request(Method::Post, &path, &body)).await;
+            assert_eq!(status, StatusCode::Ok, "{} {}", route, body);
+        }
+        let path = format!("{}/rules/tests", PREMIUMS);
+        let (status, body) = send(
+            &app,
+            operator(json_request(Method::Post, &path, &quote(40))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+
+        let path = format!("{}/bulk", PREMIUMS);
+        let mut bulk = request(Method::Post, &path);
+        bulk.insert_header("Content-Type", "text/csv");
+        bulk.set_body(format!(
+            "code,sumInsured,dateOfBirth\n1A,100000,{}\n",
+            born(40)
+        ));
+        let (status, job) = send(&app, bulk).await;
+        assert_eq!(status, StatusCode::Accepted, "{}", job);
+        let id = job["id"].as_str().unwrap();
+        let (status, _) = send(&app, request(Method::Get, &format!("{}/{}", path, id))).await;
+        assert_eq!(status, StatusCode::Ok);
+    });
+}
+
+#[test]
+fn test_matrix_routes() {
+    let app = service();
+    task::block_on(async {
+        for path in [
+            "checks".to_string(),
+            "versions".to_string(),
+            "rules".to_string(),
+            "maintenance".to_string(),
+            "audits".to_string(),
+            "export".to_string(),
+            "versions/1/diff/1".to_string(),
+            "history?code=1A&sumInsured=100000&band=36-45".to_string(),
+        ] {
+            let (status, body) = send(
+                &app,
+                operator(request(Method::Get, &format!("{}/{}", PREMIUMS, path))),
+            )
+            .await;
+            assert_eq!(status, StatusCode::Ok, "{} {}", path, body);
+        }
+    });
+}
+
+#[test]
+fn test_audits_need_credentials() {
+    let app = service();
+    task::block_on(async {
+        let path = format!("{}/audits", PREMIUMS);
+        let (status, body) = send(&app, request(Method::Get, &path)).await;
+        assert_eq!(status, StatusCode::Unauthorized);
+        assert_eq!(body["code"], "022");
+    });
+}
+
+#[test]
+fn test_diagnostics() {
+    let app = service();
+    task::block_on(async {
+        let (status, body) = send(&app, operator(request(Method::Get, "/admin/diagnostics"))).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(body["build"]["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["build"]["gitSha"].is_string());
+        assert!(body["matrix"]["activeVersion"].is_u64(), "{}", body);
+        assert!(body["matrix"]["activeLoadedAt"].is_string());
+        assert!(body["premiumCache"]["hits"].is_u64());
+        assert!(body["flags"]["shadowPricing"]["enabled"].is_boolean());
+        // The memory store has no connections to report.
+        assert!(body.get("pool").is_none());
+    });
+}
+
+#[test]
+fn test_flags() {
+    let app = service();
+    task::block_on(async {
+        let (status, _) = send(
+            &app,
+            operator(json_request(
+                Method::Put,
+                "/admin/flags/interpolation",
+                &json!({}),
+            )),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NotFound);
+
+        // Only for the suite tenant, so quotes of other tests keep the v1 shape.
+        let setting = json!({"enabled": true, "percent": 100, "tenants": [TENANT]});
+        let (status, body) = send(
+            &app,
+            operator(json_request(Method::Put, "/admin/flags/v2Quotes", &setting)),
+        )
+        .await;
+        assert_eq!((status, &body), (StatusCode::Ok, &setting));
+        let (_, flags) = send(&app, operator(request(Method::Get, "/admin/flags"))).await;
+        assert_eq!(flags["v2Quotes"], setting);
+        assert_eq!(flags["genderedRates"]["enabled"], true);
+        let (status, body) = send(&app, json_request(Method::Post, PREMIUMS, &quote(40))).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body["premium"].is_string(), "{}", body);
+
+        let (status, _) = send(
+            &app,
+            operator(json_request(
+                Method::Put,
+                "/admin/flags/v2Quotes",
+                &json!({"enabled": false}),
+            )),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok);
+    });
+}
+
+#[test]
+fn test_commission() {
+    let app = service();
+    task::block_on(async {
+        let mut sold = quote(40);
+        sold["channel"] = json!(CHANNEL);
+        let (status, body) = send(&app, json_request(Method::Post, PREMIUMS, &sold)).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert!(body.get("commission").is_none(), "{}", body);
+
+        let mut request = json_request(Method::Post, PREMIUMS, &sold);
+        request.insert_header("X-Api-Key", DISTRIBUTOR_KEY);
+        let (_, body) = send(&app, request).await;
+        assert_eq!(
+            body["commission"],
+            json!({"channel": CHANNEL, "percent": 12.5, "base": "500", "amount": "62.50"})
+        );
+
+        sold["channel"] = json!("AGENCY99");
+        let (status, body) = send(&app, json_request(Method::Post, PREMIUMS, &sold)).await;
+        assert_eq!(status, StatusCode::BadRequest);
+        assert_eq!(body["code"], "025");
+    });
+}
+
+#[test]
+fn test_commission_is_not_cached() {
+    let app = service();
+    task::block_on(async {
+        let mut sold = quote(40);
+        sold["channel"] = json!(CHANNEL);
+        let respond = |request: HttpRequest| app.respond::<_, HttpResponse>(request);
+        let public = respond(json_request(Method::Post, PREMIUMS, &sold))
+            .await
+            .unwrap();
+        let mut shown = respond(operator(json_request(Method::Post, PREMIUMS, &sold)))
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&shown.body_string().await.unwrap()).unwrap();
+        assert_eq!(body["commission"]["amount"], "62.50", "{}", body);
+        assert_eq!(shown["Cache-Control"], "private, no-store");
+        let vary: Vec<&str> = shown["Vary"].iter().map(|value| value.as_str()).collect();
+        assert!(vary.contains(&"Authorization, X-Api-Key"), "{:?}", vary);
+        assert_ne!(shown["ETag"].as_str(), public["ETag"].as_str());
+        assert_ne!(
+            public.header("Cache-Control").map(|value| value.as_str()),
+            Some("private, no-store")
+        );
+
+        // The operator's tag does not revalidate an anonymous request.
+        let mut revalidate = json_request(Method::Post, PREMIUMS, &sold);
+        revalidate.insert_header("If-None-Match", shown["ETag"].as_str());
+        let revalidated = respond(revalidate).await.unwrap();
+        assert_eq!(revalidated.status(), StatusCode::Ok);
+    });
+}
+
+#[test]
+fn test_sales_channels() {
+    let app = service();
+    task::block_on(async {
+        for (channel, premium) in [("online", "450"), ("ONLINE", "450"), ("agency", "500")] {
+            let mut request = json_request(Method::Post, PREMIUMS, &quote(40));
+            request.insert_header("X-Channel", channel);
+            let (status, body) = send(&app, request).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            assert_eq!(body["premium"], premium, "{}", channel);
+        }
+    });
+}
+
+#[test]
+fn test_tenant_load_and_unload() {
+    let app = service();
+    let tenant = format!("/tenants/{}{}", TENANT, PREMIUMS);
+    task::block_on(async {
+        let (status, body) = send(&app, json_request(Method::Post, &tenant, &quote(40))).await;
+        assert_eq!(status, StatusCode::BadRequest, "{}", body);
+        assert_eq!(body["code"], "004");
+
+        let (status, report) = send(
+            &app,
+            operator(request(Method::Post, &format!("{}/loads", tenant))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", report);
+        assert_eq!(report["valid"], true);
+        let version = report["version"].clone();
+
+        let (status, body) = send(&app, json_request(Method::Post, &tenant, &quote(40))).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert_eq!(body["premium"], "500");
+        assert_eq!(body["matrixVersion"], version);
+
+        let (status, check) = send(&app, request(Method::Get, &format!("{}/checks", tenant))).await;
+        assert_eq!(status, StatusCode::Ok, "{}", check);
+        assert_eq!(check["consistent"], true);
+
+        let (status, _) = send(
+            &app,
+            operator(request(Method::Post, &format!("{}/unloads", tenant))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok);
+        let (status, body) = send(&app, json_request(Method::Post, &tenant, &quote(40))).await;
+        assert_eq!(status, StatusCode::BadRequest, "{}", body);
+
+        let (status, body) = send(&app, json_request(Method::Post, PREMIUMS, &quote(40))).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+    });
+}
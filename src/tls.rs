@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use async_rustls::server::TlsStream;
+use async_rustls::TlsAcceptor;
+use async_std::net::TcpStream;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig, Session,
+};
+use tide_rustls::{CustomTlsAcceptor, TlsListener};
+
+use crate::config::TlsConfig;
+
+/// Connections remembered before the unverified ones are forgotten.
+const MAX_PEERS: usize = 4096;
+
+/// Whether each open connection presented a verified client certificate, by
+/// peer address. The listener hands requests to tide without the TLS
+/// session, so this is how `AdminAuth` tells them apart.
+static PEERS: Mutex<Option<HashMap<String, bool>>> = Mutex::new(None);
+
+fn record(peer: SocketAddr, verified: bool) {
+    let mut peers = PEERS.lock().unwrap_or_else(|err| err.into_inner());
+    let peers = peers.get_or_insert_with(HashMap::new);
+    if peers.len() >= MAX_PEERS {
+        peers.retain(|_, verified| *verified);
+        if peers.len() >= MAX_PEERS {
+            peers.clear();
+        }
+    }
+    peers.insert(peer.to_string(), verified);
+}
+
+/// Whether the connection of `peer_addr` presented a client certificate the
+/// configured CA verified.
+pub fn client_verified(peer_addr: Option<&str>) -> bool {
+    let peers = PEERS.lock().unwrap_or_else(|err| err.into_inner());
+    match (peers.as_ref(), peer_addr) {
+        (Some(peers), Some(peer)) => peers.get(peer).copied().unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Accepts TLS connections and records whether each came with a client
+/// certificate.
+struct ClientCertAcceptor(TlsAcceptor);
+
+#[tide::utils::async_trait]
+impl CustomTlsAcceptor for ClientCertAcceptor {
+    async fn accept(&self, stream: TcpStream) -> io::Result<Option<TlsStream<TcpStream>>> {
+        let peer = stream.peer_addr()?;
+        let stream = self.0.accept(stream).await?;
+        record(peer, stream.get_ref().1.get_peer_certificates().is_some());
+        Ok(Some(stream))
+    }
+}
+
+/// A listener on `listen` serving HTTPS with the configured certificate.
+/// With a client CA, clients may present a certificate; one that does not
+/// verify fails the handshake.
+pub fn listener<State: Clone + Send + Sync + 'static>(
+    config: &TlsConfig,
+    listen: &str,
+) -> io::Result<TlsListener<State>> {
+    let verifier = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add_pem_file(&mut BufReader::new(File::open(path)?))
+                .map_err(|_| invalid(format!("no CA certificates in {}", path)))?;
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+    let chain = certs(&mut BufReader::new(File::open(&config.cert_path)?))
+        .map_err(|_| invalid(format!("no certificates in {}", config.cert_path)))?;
+    let mut server = ServerConfig::new(verifier);
+    server
+        .set_single_cert(chain, private_key(&config.key_path)?)
+        .map_err(|err| invalid(err.to_string()))?;
+    let builder = TlsListener::build().addrs(listen);
+    match config.client_ca_path {
+        Some(_) => builder.tls_acceptor(Arc::new(ClientCertAcceptor(TlsAcceptor::from(Arc::new(
+            server,
+        ))))),
+        None => builder.config(server),
+    }
+    .finish()
+}
+
+/// The first PKCS#8 or, failing that, PKCS#1 RSA key in `path`.
+fn private_key(path: &str) -> io::Result<rustls::PrivateKey> {
+    let read = |parse: fn(&mut dyn io::BufRead) -> Result<Vec<rustls::PrivateKey>, ()>| {
+        let mut reader = BufReader::new(File::open(path)?);
+        Ok::<_, io::Error>(parse(&mut reader).unwrap_or_default())
+    };
+    read(pkcs8_private_keys)?
+        .into_iter()
+        .chain(read(rsa_private_keys)?)
+        .next()
+        .ok_or_else(|| invalid(format!("no private key in {}", path)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_verified() {
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        assert!(!client_verified(Some("10.0.0.1:40000")));
+        record(peer, true);
+        assert!(client_verified(Some("10.0.0.1:40000")));
+        assert!(!client_verified(None));
+        record(peer, false);
+        assert!(!client_verified(Some("10.0.0.1:40000")));
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use async_std::task;
+use log::{error, info};
+use notify::{Event, RecursiveMode, Watcher};
+
+use premium_core::config::MatrixConfig;
+use premium_core::premium::load;
+
+/// Reloads the matrix whenever the workbook at `config.path` changes, once
+/// no further change has been seen for the debounce interval. Loads validate
+/// before swapping versions, so a bad file leaves the active matrix in place.
+/// Blocks the calling thread.
+pub fn watch(config: MatrixConfig) -> notify::Result<()> {
+    let path = PathBuf::from(&config.path);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // Editors and copies often replace the file, so watch its directory.
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    info!("watching {} for matrix changes", config.path);
+
+    let debounce = Duration::from_millis(config.watch_debounce_ms);
+    debounced(&receiver, &path, debounce, || {
+        if !path.exists() {
+            return;
+        }
+        match task::block_on(load(&config, None)) {
+            Ok(report) if report.valid => info!(
+                "reloaded {} rows from {} as version {:?}",
+                report.rows_loaded, config.path, report.version
+            ),
+            Ok(report) => error!(
+                "changed {} has {} invalid and {} duplicate rows, keeping active matrix",
+                config.path,
+                report.parse_errors.len(),
+                report.duplicate_keys.len()
+            ),
+            Err(err) => error!("Error while reloading {} {}", config.path, err),
+        }
+    });
+    Ok(())
+}
+
+/// Calls `reload` each time changes to `path` among the watcher's events
+/// have settled for `debounce`, until the watcher goes away.
+fn debounced(
+    receiver: &Receiver<notify::Result<Event>>,
+    path: &Path,
+    debounce: Duration,
+    mut reload: impl FnMut(),
+) {
+    let file_name = path.file_name();
+    let touches = |event: &Event| {
+        !event.kind.is_access()
+            && event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name() == file_name)
+    };
+    loop {
+        match receiver.recv() {
+            Ok(Ok(event)) if touches(&event) => {}
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => {
+                error!("Error while watching {} {}", path.display(), err);
+                continue;
+            }
+            Err(_) => return,
+        }
+        loop {
+            match receiver.recv_timeout(debounce) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        reload();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, EventKind, ModifyKind};
+    use std::thread;
+
+    #[test]
+    fn test_reloads_once_changes_settle() {
+        let workbook = Path::new("/srv/matrix/premium_tables.xlsx");
+        let event = |kind, path: &str| Ok(Event::new(kind).add_path(PathBuf::from(path)));
+        let (sender, receiver) = channel();
+        let writer = thread::spawn(move || {
+            let modify = EventKind::Modify(ModifyKind::Any);
+            // Neither another file nor reading the workbook reloads it.
+            sender.send(event(modify, "/srv/matrix/notes.txt")).unwrap();
+            let access = EventKind::Access(AccessKind::Any);
+            sender
+                .send(event(access, "/srv/matrix/premium_tables.xlsx"))
+                .unwrap();
+            // A copy is a burst of events, reloaded once.
+            let create = EventKind::Create(CreateKind::File);
+            sender
+                .send(event(create, "/srv/matrix/premium_tables.xlsx"))
+                .unwrap();
+            for _ in 0..3 {
+                sender
+                    .send(event(modify, "/srv/matrix/premium_tables.xlsx"))
+                    .unwrap();
+            }
+            thread::sleep(Duration::from_millis(200));
+            sender
+                .send(event(modify, "/srv/matrix/premium_tables.xlsx"))
+                .unwrap();
+            thread::sleep(Duration::from_millis(200));
+        });
+        let mut reloads = 0;
+        debounced(&receiver, workbook, Duration::from_millis(50), || {
+            reloads += 1
+        });
+        writer.join().unwrap();
+        assert_eq!(reloads, 2);
+    }
+}
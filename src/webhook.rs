@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use async_std::task;
+use hmac::{Hmac, Mac};
+use log::{error, info};
+use serde::Serialize;
+use sha2::Sha256;
+
+use premium_core::config::MatrixConfig;
+
+use crate::config::WebhookConfig;
+use premium_core::matrix::LoadReport;
+use premium_core::premium::{load, ErrorResponse};
+
+#[derive(Serialize, Debug)]
+pub struct LoadEvent {
+    pub status: String,
+    #[serde(flatten)]
+    pub report: Option<LoadReport>,
+    pub error: Option<ErrorResponse>,
+}
+
+/// Loads the matrix and POSTs the outcome to `callback_url`, signing the body
+/// with the configured secret in the `X-Premium-Signature` header.
+pub async fn load_and_notify(
+    callback_url: String,
+    source_url: Option<String>,
+    matrix: MatrixConfig,
+    config: WebhookConfig,
+) {
+    let result = load(&matrix, source_url.as_deref()).await;
+    let event = match result {
+        Ok(report) => LoadEvent {
+            status: if report.valid { "completed" } else { "failed" }.to_string(),
+            report: Some(report),
+            error: None,
+        },
+        Err(err) => LoadEvent {
+            status: "failed".to_string(),
+            report: None,
+            error: Some(ErrorResponse::from(&err)),
+        },
+    };
+
+    let body = match serde_json::to_string(&event) {
+        Ok(body) => body,
+        Err(err) => {
+            error!("Error while converting load event {}", err);
+            return;
+        }
+    };
+    let result = task::spawn_blocking(move || {
+        let mut request = ureq::post(&callback_url)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .set("Content-Type", "application/json");
+        if let Some(secret) = &config.secret {
+            request = request.set("X-Premium-Signature", &sign(secret, &body));
+        }
+        request
+            .send_string(&body)
+            .map(|_| callback_url)
+            .map_err(|err| err.to_string())
+    })
+    .await;
+    match result {
+        Ok(url) => info!("load callback delivered to {}", url),
+        Err(err) => error!("Error while delivering load callback {}", err),
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
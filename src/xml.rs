@@ -0,0 +1,144 @@
+use log::error;
+use premium_core::premium::PremiumError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tide::http::mime::Mime;
+use tide::{Body, Request, Response};
+
+const XML: &str = "application/xml";
+
+fn is_xml(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    essence == XML || essence == "text/xml" || essence.ends_with("+xml")
+}
+
+/// Whether the request body is XML rather than JSON.
+pub fn has_xml_body<State>(req: &Request<State>) -> bool {
+    req.header("Content-Type")
+        .is_some_and(|value| is_xml(value.as_str()))
+}
+
+/// Whether the caller asked for XML: an `Accept` naming XML and not JSON,
+/// or an XML body with no preference given.
+pub fn wants_xml<State>(req: &Request<State>) -> bool {
+    match req.header("Accept").map(|value| value.as_str()) {
+        Some(accept) if accept.split(',').any(is_xml) => !accept
+            .split(',')
+            .any(|mime| mime.trim().starts_with("application/json")),
+        Some(accept) if !accept.trim().starts_with("*/*") => false,
+        _ => has_xml_body(req),
+    }
+}
+
+/// A `<HealthRequest>` document before it is checked against the schema.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct XmlHealthRequest {
+    code: Option<String>,
+    sum_insured: Option<String>,
+    date_of_birth: Option<String>,
+    policy_start_date: Option<String>,
+    policy_end_date: Option<String>,
+    discount_codes: Vec<String>,
+    payment_frequency: Option<String>,
+    zone: Option<String>,
+    deductible: Option<String>,
+    gender: Option<String>,
+    maternity_cover: Option<bool>,
+    opd_cover: Option<bool>,
+    restore_benefit: Option<bool>,
+    declared_conditions: Vec<String>,
+    height_cm: Option<f64>,
+    weight_kg: Option<f64>,
+    as_of: Option<String>,
+    channel: Option<String>,
+}
+
+/// Reads a `<HealthRequest>` document, its elements named like the JSON
+/// fields with one `<discountCodes>` element per code, as the JSON body it
+/// stands for.
+pub fn parse_request(body: &str) -> anyhow::Result<Value, PremiumError> {
+    let request: XmlHealthRequest = quick_xml::de::from_str(body).map_err(|err| {
+        error!("Invalid xml in health request {}", err);
+        PremiumError::InvalidInput
+    })?;
+    let mut value = json!({
+        "code": request.code,
+        "sumInsured": request.sum_insured,
+        "dateOfBirth": request.date_of_birth,
+        "policyStartDate": request.policy_start_date,
+        "policyEndDate": request.policy_end_date,
+        "discountCodes": request.discount_codes,
+        "paymentFrequency": request.payment_frequency,
+        "zone": request.zone,
+        "deductible": request.deductible,
+        "gender": request.gender,
+        "maternityCover": request.maternity_cover,
+        "opdCover": request.opd_cover,
+        "restoreBenefit": request.restore_benefit,
+        "declaredConditions": Some(request.declared_conditions).filter(|codes| !codes.is_empty()),
+        "heightCm": request.height_cm,
+        "weightKg": request.weight_kg,
+        "asOf": request.as_of,
+        "channel": request.channel,
+    });
+    if let Some(fields) = value.as_object_mut() {
+        fields.retain(|_, field| !field.is_null());
+    }
+    Ok(value)
+}
+
+/// Re-encodes a JSON response as XML, rooted at `HealthResponse` or, for
+/// errors, `ErrorResponse`. Responses without a body pass unchanged.
+pub async fn encode(mut response: Response) -> tide::Result {
+    if response.is_empty() == Some(true) {
+        return Ok(response);
+    }
+    let value: Value = response.take_body().into_json().await?;
+    let root = match response.status().is_success() {
+        true => "HealthResponse",
+        false => "ErrorResponse",
+    };
+    let xml = quick_xml::se::to_string_with_root(root, &value)?;
+    response.set_body(Body::from_string(xml));
+    response.set_content_type(XML.parse::<Mime>()?);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use tide::StatusCode;
+
+    #[test]
+    fn test_xml_round_trip() {
+        let request = parse_request(
+            "<HealthRequest><code>1A</code><sumInsured>100000</sumInsured>\
+             <dateOfBirth>1977-09-14</dateOfBirth><discountCodes>A</discountCodes>\
+             <discountCodes>B</discountCodes></HealthRequest>",
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "code": "1A",
+                "sumInsured": "100000",
+                "dateOfBirth": "1977-09-14",
+                "discountCodes": ["A", "B"]
+            })
+        );
+        assert!(parse_request("<HealthRequest>").is_err());
+
+        task::block_on(async {
+            let mut response = Response::new(StatusCode::BadRequest);
+            response.set_body(Body::from_json(&json!({"code": "002"})).unwrap());
+            let mut response = encode(response).await.unwrap();
+            assert_eq!(response.content_type().unwrap().essence(), XML);
+            assert_eq!(
+                response.take_body().into_string().await.unwrap(),
+                "<ErrorResponse><code>002</code></ErrorResponse>"
+            );
+        });
+    }
+}